@@ -0,0 +1,483 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::lisp_expression::{LispExpression, Span, SpanTree};
+use crate::lisp_error::{LispError, LispResult};
+use crate::evaluate::LispOutput;
+use crate::functions::{Parameters, KEYWORD_PREFIX};
+use crate::library::{parse_import_set, parse_library_definition, ImportSet, LibraryDefinition};
+use crate::testing;
+use crate::types::{self, Type};
+
+
+/// A syntax tree whose special forms have been checked and resolved ahead of
+/// evaluation. Sub-trees are shared so that closures and the continuation
+/// frames of the evaluator can hold on to the code they still have to run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Constant(LispOutput),
+    Variable(String, Reference),
+    Define(String, Rc<Node>),
+    Lambda(Rc<Lambda>),
+    If(Rc<Node>, Rc<Node>, Rc<Node>),
+    And(Vec<Rc<Node>>),
+    Or(Vec<Rc<Node>>),
+    Del(String),
+    Let {
+        names: Vec<String>,
+        inits: Vec<Rc<Node>>,
+        body: Rc<Node>,
+        slots: Slots,
+    },
+    NamedLet {
+        name: String,
+        lambda: Rc<Lambda>,
+        inits: Vec<Rc<Node>>,
+        slots: Slots,
+    },
+    /// Every binding has a frame of its own.
+    LetStar {
+        bindings: Vec<(String, Rc<Node>)>,
+        body: Rc<Node>,
+        slots: Vec<Slots>,
+    },
+    Letrec {
+        bindings: Vec<(String, Rc<Node>)>,
+        body: Rc<Node>,
+        sequential: bool,
+        slots: Slots,
+    },
+    SetBang(String, Reference, Rc<Node>),
+    TheEnvironment,
+    /// Binds the names of the import sets in the top-level frame.
+    Import(Rc<[ImportSet]>),
+    DefineLibrary(Rc<LibraryDefinition>),
+    /// Clauses are tried in order until a test holds, in a frame binding `var`.
+    Guard {
+        var: String,
+        clauses: Vec<GuardClause>,
+        body: Rc<Node>,
+        slots: Slots,
+    },
+    /// The operator followed by the operands, and where the call was written.
+    Application(Vec<Rc<Node>>, Option<Span>),
+}
+
+/// A test, or `None` for `else`, and the expression to evaluate when it holds.
+pub type GuardClause = (Option<Rc<Node>>, Rc<Node>);
+
+/// The names of the slots of a frame created by a `lambda` or `let` form: its
+/// bindings, then the internal definitions the `resolve` pass found for it.
+pub type Slots = Rc<[String]>;
+
+/// How a variable is found at run time, as worked out by `resolve`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reference {
+    /// Looked up by name, frame by frame.
+    Dynamic,
+    /// In slot `index` of the frame `depth` frames out. While the slot is
+    /// unbound the variable is looked up by name from there.
+    Local { depth: usize, index: usize },
+    /// Not bound by any enclosing form, so looked up by name after skipping
+    /// the `depth` frames created by those forms.
+    Free { depth: usize },
+}
+
+/// The code of a `lambda`, shared by every closure created from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lambda {
+    /// The name it was given by `define` or a named `let`, for backtraces.
+    pub name: Option<String>,
+    pub parameters: Parameters,
+    pub body: Rc<Node>,
+    /// The frame of a call: the parameters, then internal definitions.
+    pub slots: Slots,
+}
+
+impl Lambda {
+    /// Builds the procedure of `parameters`, some of which may be annotated
+    /// with their types, and `body`, whose value has the type `result` if it
+    /// is given. Annotations become contracts checked by the body.
+    pub fn build(
+        name: Option<&String>,
+        parameters: &LispExpression,
+        result: Option<Type>,
+        body: &LispExpression,
+        body_spans: Option<&SpanTree>,
+    ) -> LispResult<Rc<Self>> {
+        let (parameters, contracts) = types::split_parameters(parameters)?;
+        let parameters = Parameters::parse(&parameters)?;
+        let body = analyze_with_spans(body, body_spans)?;
+        return Ok(Rc::new(Lambda {
+            name: name.cloned(),
+            slots: parameters.names().cloned().collect(),
+            parameters,
+            body: types::with_contracts(name, contracts, result, body),
+        }));
+    }
+
+    /// Builds the procedure of `(lambda parameters body)`, where the type of
+    /// the result may be annotated, `(lambda parameters : Type body)`.
+    fn analyze(name: Option<&String>, lambda: &[LispExpression], spans: Option<&SpanTree>) -> LispResult<Rc<Self>> {
+        let annotated = types::split_result(lambda, spans)?;
+        let (lambda, spans, result) = match annotated {
+            Some(ref annotated) => (&annotated.form[..], annotated.spans.as_ref(), Some(annotated.result.clone())),
+            None => (lambda, spans, None),
+        };
+        check_arguments(lambda, REQUIRED_LAMBDA_ARGUMENTS, "lambda")?;
+        return Lambda::build(name, &lambda[1], result, &lambda[2], child(spans, 2));
+    }
+}
+
+
+fn child(spans: Option<&SpanTree>, index: usize) -> Option<&SpanTree> {
+    return spans.and_then(|spans| spans.child(index));
+}
+
+
+fn check_arguments(args: &[LispExpression], number_of_args: usize, form: &str) -> LispResult<()> {
+    if args.len() != number_of_args {
+        return Err(LispError::syntax(format!(
+            "special form {form} was not supplied with correct number of arguments: got {}, expected {}",
+            args.len() - 1,
+            number_of_args - 1,
+        )));
+    }
+    return Ok(());
+}
+
+fn expect_symbol<'a>(expr: &'a LispExpression, message: &str) -> LispResult<&'a String> {
+    match expr {
+        LispExpression::Symbol(symbol) => Ok(symbol),
+        _ => Err(LispError::syntax(message)),
+    }
+}
+
+/// Splits a binding list such as `((x 1) (y 2))` into its names and
+/// initializers, rejecting anything that is not a `(symbol expression)` pair.
+fn parse_bindings(
+    definitions: &LispExpression,
+    spans: Option<&SpanTree>,
+    form: &str,
+) -> LispResult<Vec<(String, Rc<Node>)>> {
+    let definitions = match definitions {
+        LispExpression::List(definitions) => definitions,
+        _ => return Err(LispError::syntax(format!("expecting list of bindings in {form}"))),
+    };
+
+    let mut bindings = Vec::new();
+    for (index, def) in definitions.iter().enumerate() {
+        match def {
+            LispExpression::List(binding) if binding.len() == 2 => {
+                let message = format!("expecting first element of binding in {form} to be symbol!");
+                let var = expect_symbol(&binding[0], &message)?;
+                bindings.push((var.clone(), analyze_with_spans(&binding[1], child(child(spans, index), 1))?));
+            },
+            _ => return Err(LispError::syntax(format!("each binding in {form} should be a list of a symbol and an expression!"))),
+        }
+    }
+    return Ok(bindings);
+}
+
+/// Checks that the part of a let form where its bindings go, if it has one,
+/// is a list, before its parts are counted.
+fn check_binding_list(expressions: &[LispExpression], form: &str) -> LispResult<()> {
+    match expressions.get(1) {
+        None | Some(LispExpression::List(_)) => return Ok(()),
+        Some(_) => return Err(LispError::syntax(format!("expecting list of bindings in {form}"))),
+    }
+}
+
+/// Whether `expressions` is a named let, `(let name bindings body)`. A name
+/// with nothing after it is a let missing its bindings instead.
+fn is_named_let(expressions: &[LispExpression]) -> bool {
+    return expressions.len() > 2 && matches!(expressions[1], LispExpression::Symbol(_));
+}
+
+fn check_duplicate_bindings(bindings: &[(String, Rc<Node>)], form: &str) -> LispResult<()> {
+    let mut seen = HashSet::new();
+    for (var, _) in bindings {
+        if !seen.insert(var) {
+            return Err(LispError::syntax(format!("duplicate binding for {var} in {form}")));
+        }
+    }
+    return Ok(());
+}
+
+/// Reads the `(var clause ...)` part of `(guard (var clause ...) body)`.
+fn parse_guard_clauses(spec: &LispExpression, spans: Option<&SpanTree>) -> LispResult<(String, Vec<GuardClause>)> {
+    let spec = match spec {
+        LispExpression::List(spec) if !spec.is_empty() => spec,
+        _ => return Err(LispError::syntax("expecting guard to start with a variable and its clauses")),
+    };
+    let var = expect_symbol(&spec[0], "expecting guard variable to be a symbol")?;
+
+    let mut clauses = Vec::new();
+    for (index, clause) in spec.iter().enumerate().skip(1) {
+        let clause = match clause {
+            LispExpression::List(clause) if clause.len() == 2 => clause,
+            _ => return Err(LispError::syntax("each guard clause should be a list of a test and an expression!")),
+        };
+        let test = match &clause[0] {
+            LispExpression::Symbol(symbol) if symbol == "else" => {
+                if index != spec.len() - 1 {
+                    return Err(LispError::syntax("else clause must be the last clause of guard"));
+                }
+                None
+            },
+            test => Some(analyze_with_spans(test, child(child(spans, index), 0))?),
+        };
+        clauses.push((test, analyze_with_spans(&clause[1], child(child(spans, index), 1))?));
+    }
+    return Ok((var.clone(), clauses));
+}
+
+fn is_lambda(expressions: &[LispExpression]) -> bool {
+    return matches!(expressions.first(), Some(LispExpression::Symbol(symbol)) if symbol == "lambda");
+}
+
+const REQUIRED_DEFINE_ARGUMENTS: usize = 3;
+const REQUIRED_LAMBDA_ARGUMENTS: usize = 3;
+const REQUIRED_IF_ARGUMENTS: usize = 4;
+const REQUIRED_DEL_ARGUMENTS: usize = 2;
+const REQUIRED_LET_ARGUMENTS: usize = 3;
+const REQUIRED_NAMED_LET_ARGUMENTS: usize = 4;
+const REQUIRED_SET_BANG_ARGUMENTS: usize = 3;
+const REQUIRED_QUOTE_ARGUMENTS: usize = 2;
+const REQUIRED_THE_ENVIRONMENT_ARGUMENTS: usize = 1;
+const REQUIRED_GUARD_ARGUMENTS: usize = 3;
+
+/// Checks that `expressions`, a special form with a fixed number of parts,
+/// has them all, as analyzing it does before anything else. The annotation
+/// of the result of a procedure is not counted.
+pub fn check_special_form(expressions: &[LispExpression]) -> LispResult<()> {
+    if let Some(annotated) = types::split_result(expressions, None)? {
+        return check_special_form(&annotated.form);
+    }
+    let Some(LispExpression::Symbol(form)) = expressions.first() else { return Ok(()) };
+    let (number_of_args, form) = match &form[..] {
+        "define" => (REQUIRED_DEFINE_ARGUMENTS, "define"),
+        "lambda" => (REQUIRED_LAMBDA_ARGUMENTS, "lambda"),
+        "if" => (REQUIRED_IF_ARGUMENTS, "if"),
+        "del" => (REQUIRED_DEL_ARGUMENTS, "del"),
+        "let" if is_named_let(expressions) => (REQUIRED_NAMED_LET_ARGUMENTS, "named let"),
+        form @ ("let" | "let*" | "letrec" | "letrec*") => {
+            check_binding_list(expressions, form)?;
+            (REQUIRED_LET_ARGUMENTS, form)
+        },
+        "quote" => (REQUIRED_QUOTE_ARGUMENTS, "quote"),
+        "the-environment" => (REQUIRED_THE_ENVIRONMENT_ARGUMENTS, "the-environment"),
+        "guard" => (REQUIRED_GUARD_ARGUMENTS, "guard"),
+        "set!" => (REQUIRED_SET_BANG_ARGUMENTS, "set!"),
+        _ => return Ok(()),
+    };
+    return check_arguments(expressions, number_of_args, form);
+}
+
+pub fn analyze(tree: &LispExpression) -> LispResult<Rc<Node>> {
+    return analyze_with_spans(tree, None);
+}
+
+/// Analyzes `tree`, recording the spans of calls from the matching `spans`.
+pub fn analyze_with_spans(tree: &LispExpression, spans: Option<&SpanTree>) -> LispResult<Rc<Node>> {
+    let expressions = match tree {
+        LispExpression::Integer(num) => return Ok(Rc::new(Node::Constant(LispOutput::Integer(*num)))),
+        LispExpression::String(string) => return Ok(Rc::new(Node::Constant(LispOutput::String(string.clone())))),
+        LispExpression::Symbol(var) => return match var.strip_prefix(KEYWORD_PREFIX) {
+            Some(keyword) => Ok(Rc::new(Node::Constant(LispOutput::Keyword(keyword.to_string())))),
+            None => Ok(Rc::new(Node::Variable(var.clone(), Reference::Dynamic))),
+        },
+        LispExpression::List(expressions) => expressions,
+    };
+
+    if expressions.is_empty() {
+        return Err(LispError::syntax("list of expression cannot be empty!"));
+    }
+
+    let special_form = match &expressions[0] {
+        LispExpression::Symbol(symbol) => &symbol[..],
+        _ => "",
+    };
+
+    let analyze_child = |index: usize| analyze_with_spans(&expressions[index], child(spans, index));
+
+    let node = match special_form {
+        "define" => {
+            // the result of a procedure may be annotated, (define (name . parameters) : Type body)
+            let (annotated, result) = match types::split_result(expressions, spans)? {
+                Some(annotated) => (Some((annotated.form, annotated.spans)), Some(annotated.result)),
+                None => (None, None),
+            };
+            let (expressions, spans) = match &annotated {
+                Some((form, form_spans)) => (&form[..], form_spans.as_ref()),
+                None => (&expressions[..], spans),
+            };
+            check_arguments(expressions, REQUIRED_DEFINE_ARGUMENTS, "define")?;
+            match &expressions[1] {
+                LispExpression::Symbol(var) => {
+                    let value = match &expressions[2] {
+                        // (define name (lambda ...)) names the procedure
+                        LispExpression::List(lambda) if is_lambda(lambda) => {
+                            Rc::new(Node::Lambda(Lambda::analyze(Some(var), lambda, child(spans, 2))?))
+                        },
+                        _ => analyze_with_spans(&expressions[2], child(spans, 2))?,
+                    };
+                    Node::Define(var.clone(), value)
+                },
+                // (define (name . parameters) body) is shorthand for
+                // (define name (lambda parameters body))
+                LispExpression::List(signature) => {
+                    let name = match signature.first() {
+                        Some(LispExpression::Symbol(name)) => name,
+                        _ => return Err(LispError::syntax("expecting function name to be LispExpression Symbol")),
+                    };
+                    let parameters = LispExpression::List(signature[1..].to_vec());
+                    let lambda = Lambda::build(Some(name), &parameters, result, &expressions[2], child(spans, 2))?;
+                    Node::Define(name.clone(), Rc::new(Node::Lambda(lambda)))
+                },
+                _ => return Err(LispError::syntax("var must be LispExpression Symbol")),
+            }
+        },
+        "lambda" => Node::Lambda(Lambda::analyze(None, expressions, spans)?),
+        // (: name Type) declares the type of a variable for `lisp check`
+        ":" => {
+            match &expressions[..] {
+                [_, LispExpression::Symbol(_), annotation] => Type::parse(annotation)?,
+                _ => return Err(LispError::syntax("expecting a declaration to be written (: name Type)")),
+            };
+            Node::Constant(LispOutput::Void)
+        },
+        "if" => {
+            check_arguments(expressions, REQUIRED_IF_ARGUMENTS, "if")?;
+            Node::If(analyze_child(1)?, analyze_child(2)?, analyze_child(3)?)
+        },
+        "and" => Node::And((1..expressions.len()).map(analyze_child).collect::<LispResult<_>>()?),
+        "or" => Node::Or((1..expressions.len()).map(analyze_child).collect::<LispResult<_>>()?),
+        "del" => {
+            check_arguments(expressions, REQUIRED_DEL_ARGUMENTS, "del")?;
+            Node::Del(expect_symbol(&expressions[1], "expecting a symbol when removing a binding!")?.clone())
+        },
+        "let" if is_named_let(expressions) => {
+            check_arguments(expressions, REQUIRED_NAMED_LET_ARGUMENTS, "named let")?;
+            let name = expect_symbol(&expressions[1], "expecting named let to have a symbol name")?;
+            let bindings = parse_bindings(&expressions[2], child(spans, 2), "named let")?;
+            check_duplicate_bindings(&bindings, "named let")?;
+
+            let (names, inits): (Vec<String>, Vec<Rc<Node>>) = bindings.into_iter().unzip();
+            let parameters = LispExpression::List(names.into_iter().map(LispExpression::Symbol).collect());
+            Node::NamedLet {
+                name: name.clone(),
+                lambda: Lambda::build(Some(name), &parameters, None, &expressions[3], child(spans, 3))?,
+                inits,
+                slots: Rc::new([name.clone()]),
+            }
+        },
+        "let" => {
+            check_binding_list(expressions, "let")?;
+            check_arguments(expressions, REQUIRED_LET_ARGUMENTS, "let")?;
+            let bindings = parse_bindings(&expressions[1], child(spans, 1), "let")?;
+            check_duplicate_bindings(&bindings, "let")?;
+
+            let (names, inits): (Vec<String>, _) = bindings.into_iter().unzip();
+            Node::Let {
+                slots: names.iter().cloned().collect(),
+                names,
+                inits,
+                body: analyze_child(2)?,
+            }
+        },
+        "let*" => {
+            check_binding_list(expressions, "let*")?;
+            check_arguments(expressions, REQUIRED_LET_ARGUMENTS, "let*")?;
+            let bindings = parse_bindings(&expressions[1], child(spans, 1), "let*")?;
+            Node::LetStar {
+                slots: bindings.iter().map(|(var, _)| Rc::from([var.clone()])).collect(),
+                bindings,
+                body: analyze_child(2)?,
+            }
+        },
+        form @ ("letrec" | "letrec*") => {
+            check_binding_list(expressions, form)?;
+            check_arguments(expressions, REQUIRED_LET_ARGUMENTS, form)?;
+            let bindings = parse_bindings(&expressions[1], child(spans, 1), form)?;
+            check_duplicate_bindings(&bindings, form)?;
+            Node::Letrec {
+                slots: bindings.iter().map(|(var, _)| var.clone()).collect(),
+                bindings,
+                body: analyze_child(2)?,
+                sequential: form == "letrec*",
+            }
+        },
+        "quote" => {
+            check_arguments(expressions, REQUIRED_QUOTE_ARGUMENTS, "quote")?;
+            Node::Constant(LispOutput::from_datum(&expressions[1]))
+        },
+        "the-environment" => {
+            check_arguments(expressions, REQUIRED_THE_ENVIRONMENT_ARGUMENTS, "the-environment")?;
+            Node::TheEnvironment
+        },
+        "guard" => {
+            check_arguments(expressions, REQUIRED_GUARD_ARGUMENTS, "guard")?;
+            let (var, clauses) = parse_guard_clauses(&expressions[1], child(spans, 1))?;
+            Node::Guard {
+                slots: Rc::new([var.clone()]),
+                var,
+                clauses,
+                body: analyze_child(2)?,
+            }
+        },
+        "import" => {
+            if expressions.len() < 2 {
+                return Err(LispError::syntax("expecting import to be given at least one import set"));
+            }
+            Node::Import(expressions[1..].iter().map(parse_import_set).collect::<LispResult<_>>()?)
+        },
+        "define-library" => Node::DefineLibrary(Rc::new(parse_library_definition(expressions, spans)?)),
+        "define-test" => testing::analyze_define_test(expressions, spans)?,
+        "test-group" => testing::analyze_test_group(expressions, spans)?,
+        "assert-error" => testing::analyze_assert_error(expressions, spans)?,
+        "set!" => {
+            check_arguments(expressions, REQUIRED_SET_BANG_ARGUMENTS, "set!")?;
+            let variable = expect_symbol(&expressions[1], "expecting variable to be String type!")?;
+            Node::SetBang(variable.clone(), Reference::Dynamic, analyze_child(2)?)
+        },
+        _ => Node::Application(
+            (0..expressions.len()).map(analyze_child).collect::<LispResult<_>>()?,
+            spans.map(|spans| spans.span),
+        ),
+    };
+
+    return Ok(Rc::new(node));
+}
+
+
+/// Adds the internal definitions made in the frame `node` is evaluated in to
+/// `names`, leaving out those of the frames it creates itself.
+pub fn collect_definitions<'a>(node: &'a Node, names: &mut Vec<&'a String>) {
+    match node {
+        Node::Define(var, value) => {
+            names.push(var);
+            collect_definitions(value, names);
+        },
+        Node::If(condition, consequent, alternative) => {
+            for node in [condition, consequent, alternative] {
+                collect_definitions(node, names);
+            }
+        },
+        Node::And(nodes) | Node::Or(nodes) | Node::Application(nodes, _) => {
+            nodes.iter().for_each(|node| collect_definitions(node, names));
+        },
+        Node::SetBang(_, _, value) => collect_definitions(value, names),
+        Node::Let { inits, .. } | Node::NamedLet { inits, .. } => {
+            inits.iter().for_each(|node| collect_definitions(node, names));
+        },
+        Node::LetStar { bindings, body, .. } => match bindings.first() {
+            None => collect_definitions(body, names),
+            Some((_, init)) => collect_definitions(init, names),
+        },
+        // the body of a guard runs in the frame around it
+        Node::Guard { body, .. } => collect_definitions(body, names),
+        Node::Constant(_) | Node::Variable(..) | Node::Lambda(_) | Node::Del(_)
+            | Node::TheEnvironment | Node::Letrec { .. } | Node::Import(_) | Node::DefineLibrary(_) => {},
+    }
+}
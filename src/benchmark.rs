@@ -0,0 +1,105 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::evaluate::{evaluate, evaluate_compiled, Engine, Environment, LispOutput};
+use crate::lisp_error::LispResult;
+use crate::parser::parse;
+use crate::tokenizer::tokenize;
+
+
+/// A program to time: some definitions, then an expression using them whose
+/// `{n}` is replaced by the size of the run.
+struct Workload {
+    name: &'static str,
+    definitions: &'static [&'static str],
+    expression: &'static str,
+    size: i64,
+}
+
+const WORKLOADS: [Workload; 3] = [
+    Workload {
+        name: "fib",
+        definitions: &["(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))"],
+        expression: "(fib {n})",
+        size: 20,
+    },
+    Workload {
+        name: "tak",
+        definitions: &["(define (tak x y z) (if (< y x) (tak (tak (- x 1) y z) (tak (- y 1) z x) (tak (- z 1) x y)) z))"],
+        expression: "(tak {n} 12 6)",
+        size: 18,
+    },
+    Workload {
+        name: "lists",
+        definitions: &[
+            "(define (range n) (let loop ((i n) (acc nil)) (if (< i 1) acc (loop (- i 1) (append (list i) acc)))))",
+            "(define (even? x) (equal? x (* 2 (/ x 2))))",
+        ],
+        expression: "(reduce (map (filter (range {n}) even?) (lambda (x) (* x x))) + 0)",
+        size: 3000,
+    },
+];
+
+fn run_workload(workload: &Workload, size: i64, engine: Engine) -> LispResult<(LispOutput, Duration)> {
+    let mut env = Rc::new(RefCell::new(Environment::global_env()));
+    for definition in workload.definitions {
+        engine(&parse(&tokenize(definition)), &mut env)?;
+    }
+
+    let expression = parse(&tokenize(&workload.expression.replace("{n}", &size.to_string())));
+    let start = Instant::now();
+    let value = engine(&expression, &mut env)?;
+    return Ok((value, start.elapsed()));
+}
+
+/// Times every workload on the tree-walking evaluator and on the bytecode
+/// virtual machine, for `--bench`.
+pub fn run() {
+    println!("{:<10} {:>14} {:>14} {:>9}", "workload", "tree-walker", "vm", "speedup");
+    for workload in &WORKLOADS {
+        let tree = run_workload(workload, workload.size, evaluate);
+        let compiled = run_workload(workload, workload.size, evaluate_compiled);
+        match (tree, compiled) {
+            (Ok((tree_value, tree_time)), Ok((value, time))) if tree_value == value => {
+                let speedup = tree_time.as_secs_f64() / time.as_secs_f64();
+                println!(
+                    "{:<10} {:>11.1} ms {:>11.1} ms {:>8.1}x{}",
+                    workload.name,
+                    tree_time.as_secs_f64() * 1000.0,
+                    time.as_secs_f64() * 1000.0,
+                    speedup,
+                    if speedup < 1.0 { "  (slower on the vm)" } else { "" },
+                );
+            },
+            (Ok((tree_value, _)), Ok((value, _))) => {
+                println!("{:<10} engines disagree: {tree_value} and {value}", workload.name);
+            },
+            (Err(err), _) | (_, Err(err)) => println!("{:<10} failed: {err}", workload.name),
+        }
+    }
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engines_agree_on_workloads() {
+        let expected = [55, 7, 11480];
+        for (workload, expected) in WORKLOADS.iter().zip(expected) {
+            let size = match workload.name {
+                "fib" => 10,
+                "tak" => 14,
+                _ => 40,
+            };
+            let (tree_value, _) = run_workload(workload, size, evaluate).unwrap();
+            let (value, _) = run_workload(workload, size, evaluate_compiled).unwrap();
+            assert_eq!(LispOutput::Integer(expected), tree_value, "{}", workload.name);
+            assert_eq!(tree_value, value, "{}", workload.name);
+        }
+    }
+}
@@ -1,317 +1,533 @@
 use std::rc::Rc;
-use std::boxed::Box;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use crate::evaluate::{LispOutput, LispList};
-use crate::functions::{LispFunction, BuiltInFunction, LispFunctionCall};
+use crate::evaluate::{LispOutput, LispList, Environment, EnvironmentRef};
+use crate::lisp_error::{ErrorObject, LispError, LispErrorKind, LispResult};
+use crate::capability::{Capabilities, Capability};
+use crate::gc;
+use crate::testing;
+use crate::port::{self, PortRef};
+use crate::convert::{IntoLisp, NativeFunction, Optional, Rest, Symbol};
+use crate::functions::{LispFunction, BuiltInFunction, LispFunctionCall, Intrinsic};
 
 
-const MINIMUM_REQUIRED_DIVISION_ARGUMENTS: usize = 2;
-const REQUIRED_CAR_ARGUMENTS: usize = 1;
-const REQUIRED_CDR_ARGUMENTS: usize = 1;
-const REQUIRED_IS_LIST_ARGUMENTS: usize = 1;
-const REQUIRED_LIST_LENGTH_ARGUMENTS: usize = 1;
-const REQUIRED_LIST_REF_ARGUMENTS: usize = 2;
-const REQUIRED_MAP_ARGUMENTS: usize = 2;
-const REQUIRED_FILTER_ARGUMENTS: usize = 2;
-const REQUIRED_REDUCE_ARGUMENTS: usize = 3;
+// ============== ARITHMETIC BUILT-INS ===============
 
+fn add(numbers: Rest<i64>) -> i64 {
+    return numbers.0.iter().sum();
+}
 
-fn unwrap_lisp_outputs(args: Vec<LispOutput>) -> impl Iterator<Item = i64> {
-    return args.into_iter().map(|output| {
-        if let LispOutput::Integer(num) = output {
-            return num;
-        };
-        panic!("Only expecting integer arguments");
-    });
+fn sub(first_val: i64, rest: Rest<i64>) -> i64 {
+    return match rest.0.as_slice() {
+        [] => -first_val,
+        rest => first_val - rest.iter().sum::<i64>(),
+    };
 }
 
-fn check_output_arguments(args: &Vec<LispOutput>, number_of_args: usize) {
-    if args.len() != number_of_args {
-        panic!("incorrect nubmer of arguements: got {}, expected {}", args.len(), number_of_args);
+fn mul(numbers: Rest<i64>) -> i64 {
+    return numbers.0.iter().product();
+}
+
+fn div(dividend: i64, first_divisor: i64, rest: Rest<i64>) -> LispResult<i64> {
+    let divisor: i64 = first_divisor * rest.0.iter().product::<i64>();
+    if divisor == 0 {
+        return Err(LispError::new(LispErrorKind::DivisionByZero, "attempted to divide by zero"));
     }
+    return Ok(dividend / divisor);
 }
 
 
-// ============== ARITHMETIC BUILT-INS ===============
+// ============== LOGIC BUILT-INS ===============
+
+/// Whether all the values are the same, comparing lists element by element.
+fn equal_func(values: Rest<LispOutput>) -> bool {
+    return values.0.windows(2).all(|pair| pair[0] == pair[1]);
+}
+
+fn comparator(func: fn(i64, i64) -> bool) -> impl Fn(Rest<i64>) -> bool {
+    return move |numbers| numbers.0.windows(2).all(|pair| func(pair[0], pair[1]));
+}
+
+// ============== LIST BUILT-INS ===============
+
+fn cons_func(car: LispOutput, cdr: LispList) -> LispList {
+    return LispList::cons(car, Rc::new(cdr));
+}
+
+fn make_list(items: Rest<LispOutput>) -> LispList {
+    return LispList::build(items.0.into_iter());
+}
+
+fn car_func(list: LispList) -> LispResult {
+    return list.get_car();
+}
+
+fn cdr_func(list: LispList) -> LispResult {
+    return list.get_cdr();
+}
 
-fn add(args: Vec<LispOutput>) -> LispOutput {
-    return LispOutput::Integer(unwrap_lisp_outputs(args).sum());
+fn is_list_func(value: LispOutput) -> bool {
+    return matches!(value, LispOutput::List(_));
 }
 
-fn sub(args: Vec<LispOutput>) -> LispOutput {
-    if args.len() == 1 {
-        return match &args[0] {
-            LispOutput::Integer(num) => LispOutput::Integer(-num),
-            _ => panic!("Only expecting integer arugments"),
-        };
+fn list_length_func(list: LispList) -> LispResult {
+    return Ok(list.length());
+}
+
+fn list_ref_func(list: LispList, index: i64) -> LispResult {
+    if index < 0 {
+        return Err(LispError::new(LispErrorKind::IndexOutOfBounds, "negative indicies are not supported!"));
     }
+    return list.get(index);
+}
 
-    let mut numbers = unwrap_lisp_outputs(args);
-    let first_val = numbers.next().unwrap();
-    return LispOutput::Integer(
-        first_val - numbers.sum::<i64>()
-    );
+fn append_func(lists: Rest<LispList>) -> LispList {
+    return LispList::append(lists.0);
 }
 
-fn mul(args: Vec<LispOutput>) -> LispOutput {
-    return LispOutput::Integer(
-        unwrap_lisp_outputs(args).fold(1, |acc, next| acc * next)
-    );
+fn map_func(list: LispList, function: LispFunction) -> LispResult<LispList> {
+    let mapped = list.iter().map(|item| function.call(vec![item.clone()])).collect::<LispResult<Vec<_>>>()?;
+    return Ok(LispList::build(mapped.into_iter()));
 }
 
-fn div(args: Vec<LispOutput>) -> LispOutput {
-    let length = args.len();
+fn filter_func(list: LispList, function: LispFunction) -> LispResult<LispList> {
+    let mut kept = Vec::new();
+    for item in list.iter() {
+        match function.call(vec![item.clone()])? {
+            LispOutput::Bool(true) => kept.push(item.clone()),
+            LispOutput::Bool(false) => {},
+            _ => return Err(LispError::type_mismatch("expecting element to evaluate to boolean!")),
+        }
+    }
+    return Ok(LispList::build(kept.into_iter()));
+}
 
-    if length < MINIMUM_REQUIRED_DIVISION_ARGUMENTS {
-        panic!("Need two or more arguments to apply division function");
+fn reduce_func(list: LispList, function: LispFunction, initial_val: LispOutput) -> LispResult {
+    let mut value = initial_val;
+    for item in list.iter() {
+        value = function.call(vec![value, item.clone()])?;
     }
+    return Ok(value);
+}
 
-    let mut numbers = unwrap_lisp_outputs(args);
-    let first_val = numbers.next().unwrap();
-    return LispOutput::Integer(
-        first_val / numbers.fold(1, |acc, next| acc * next)
-    );
-    
+fn begin_func(first: LispOutput, rest: Rest<LispOutput>) -> LispOutput {
+    return rest.0.into_iter().last().unwrap_or(first);
 }
 
+/// `begin`, for special forms that evaluate a sequence of expressions.
+pub(crate) fn begin_procedure() -> LispOutput {
+    return convert_to_built_in(begin_func);
+}
 
-// ============== LOGIC BUILT-INS ===============
 
-fn comparator(func: Rc<dyn Fn(i64, i64) -> bool>) -> Rc<dyn Fn(Vec<LispOutput>) -> LispOutput> {
+// ============== PROCEDURE BUILT-INS ===============
 
-    let apply_func = move |args| {
-        let numbers: Vec<i64> = unwrap_lisp_outputs(args).collect();
+/// Returns `(min max)`, where `max` is `#f` for procedures accepting any number
+/// of trailing arguments.
+fn procedure_arity_func(function: LispFunction) -> (usize, Option<usize>) {
+    let arity = function.arity();
+    return (arity.min, arity.max);
+}
 
-        for i in 0..numbers.len() - 1 {
-            let current = numbers[i];
-            let next = numbers[i + 1];
 
-            if !func(current, next) {
-                return LispOutput::Bool(false);
-            }
-        }
-        return LispOutput::Bool(true);
+// ============== ENVIRONMENT BUILT-INS ===============
+
+/// `(make-environment parent)` creates an empty frame below `parent`; without
+/// a parent the new frame only sees the built-ins, with the same capabilities
+/// as those of the caller.
+fn make_environment_func(parent: Optional<EnvironmentRef>, capabilities: Capabilities) -> EnvironmentRef {
+    let parent = match parent.0 {
+        Some(parent) => parent.0,
+        None => Environment::built_ins_env_with(capabilities),
     };
+    return EnvironmentRef(gc::allocate(Environment::build(HashMap::new(), Some(parent))));
+}
 
-    return Rc::new(apply_func);
+fn is_environment_func(value: LispOutput) -> bool {
+    return matches!(value, LispOutput::Environment(_));
 }
 
-fn equal_compare(args: Vec<LispOutput>) -> LispOutput {
-    return comparator(Rc::new(|a, b| a == b))(args);
+fn environment_bound_func(env: EnvironmentRef, symbol: Symbol) -> bool {
+    return env.0.borrow().is_bound(&symbol.0);
 }
 
-fn less_than_compare(args: Vec<LispOutput>) -> LispOutput {
-    return comparator(Rc::new(|a, b| a < b))(args);
+fn environment_ref_func(env: EnvironmentRef, symbol: Symbol) -> LispResult {
+    return env.0.borrow().get(&symbol.0);
 }
 
-fn less_than_or_equal_compare(args: Vec<LispOutput>) -> LispOutput {
-    return comparator(Rc::new(|a, b| a <= b))(args);
+
+// ============== ERROR BUILT-INS ===============
+
+/// `(error message irritant ...)` raises a new error object.
+fn error_func(message: String, irritants: Rest<LispOutput>) -> LispResult {
+    return Err(LispError::raise(LispOutput::ErrorObject(Rc::new(ErrorObject {
+        kind: LispErrorKind::User,
+        message,
+        irritants: irritants.0,
+        backtrace: Vec::new(),
+    }))));
 }
 
-fn greater_than_compare(args: Vec<LispOutput>) -> LispOutput {
-    return comparator(Rc::new(|a, b| a > b))(args);
+fn is_error_object_func(value: LispOutput) -> bool {
+    return matches!(value, LispOutput::ErrorObject(_));
 }
 
-fn greater_than_or_equal_compare(args: Vec<LispOutput>) -> LispOutput {
-    return comparator(Rc::new(|a, b| a >= b))(args);
+fn error_object_message_func(error: Rc<ErrorObject>) -> String {
+    return error.message.clone();
 }
 
-// ============== LIST BUILT-INS ===============
+fn error_object_irritants_func(error: Rc<ErrorObject>) -> Vec<LispOutput> {
+    return error.irritants.clone();
+}
 
-fn make_list(args: Vec<LispOutput>) -> LispOutput {
-    return LispOutput::List(Box::new(LispList::build(args.into_iter())));
+/// The kind of error as a symbol, such as `type` or `unbound-variable`; errors
+/// from `error` are of kind `user`.
+fn error_object_kind_func(error: Rc<ErrorObject>) -> Symbol {
+    return Symbol(error.kind.name().to_string());
 }
 
-fn car_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_CAR_ARGUMENTS);
-    match &args[0] {
-        LispOutput::List(cons_cell) => cons_cell.get_car(),
-        _ => panic!("expecting a cons cell!"),
-    }
+
+/// One frame of `error-object-backtrace`.
+type BacktraceEntry = (Option<Symbol>, Vec<LispOutput>, Option<usize>, Option<usize>);
+
+/// A list with one `(name (argument ...) line column)` entry per call that
+/// was in progress when the error was raised, innermost first. Unknown names
+/// and positions are `#f`.
+fn error_object_backtrace_func(error: Rc<ErrorObject>) -> Vec<BacktraceEntry> {
+    return error.backtrace.iter().map(|frame| (
+        frame.name.clone().map(Symbol),
+        frame.arguments.clone(),
+        frame.span.map(|span| span.line),
+        frame.span.map(|span| span.column),
+    )).collect();
 }
 
-fn cdr_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_CDR_ARGUMENTS);
 
-    match &args[0] {
-        LispOutput::List(cons_cell) => cons_cell.get_cdr(),
-        _ => panic!("expecting a cons cell!"),
-    }
+// ============== PORT BUILT-INS ===============
+// Procedures taking an optional port use the current input or output port
+// without one. Reading at the end of the input returns the eof object, and
+// characters are read as strings of one character.
+
+fn input_port(port: Optional<PortRef>) -> PortRef {
+    return port.0.unwrap_or_else(port::current_input);
 }
 
-fn is_list_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_IS_LIST_ARGUMENTS);
+fn output_port(port: Optional<PortRef>) -> PortRef {
+    return port.0.unwrap_or_else(port::current_output);
+}
 
-    match args[0] {
-        LispOutput::List(_) => LispOutput::Bool(true),
-        _ => LispOutput::Bool(false),
-    }
+fn or_eof<T: IntoLisp>(read: Option<T>) -> LispResult {
+    return read.map_or(Ok(LispOutput::Eof), T::into_lisp);
 }
 
-fn list_length_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_LIST_LENGTH_ARGUMENTS);
+/// `(display value port)` writes `value` for people to read, with strings
+/// as their bare text.
+fn display_func(value: LispOutput, port: Optional<PortRef>) -> LispResult<()> {
+    return output_port(port).0.write_str(&value.display_string());
+}
 
-    match &args[0] {
-        LispOutput::List(cons_cell) => cons_cell.length(),
-        _ => panic!("expecting lisp list to get length"),
-    }
+/// `(write value port)` writes `value` the way it would be written in source.
+fn write_func(value: LispOutput, port: Optional<PortRef>) -> LispResult<()> {
+    return output_port(port).0.write_str(&value.to_string());
 }
 
-fn list_ref_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_LIST_REF_ARGUMENTS);
+fn newline_func(port: Optional<PortRef>) -> LispResult<()> {
+    return output_port(port).0.write_str("\n");
+}
 
-    
-    let index = match args[1] {
-        LispOutput::Integer(num) => num,
-        _ => panic!("expecting an integer to use as index in list")
-    };
-    
-    if index < 0 {
-        panic!("negative indicies are not supported!");
-    }
+fn read_line_func(port: Optional<PortRef>) -> LispResult {
+    return or_eof(input_port(port).0.read_line()?);
+}
 
-    match &args[0] {
-        LispOutput::List(cons_cell) => cons_cell.get(index),
-        _ => panic!("expecting a cons cell to index into"),
-    }
+fn read_char_func(port: Optional<PortRef>) -> LispResult {
+    return or_eof(input_port(port).0.read_char()?.map(String::from));
 }
 
-fn append_func(args: Vec<LispOutput>) -> LispOutput {
-    let lists = args.into_iter().map(|output| {
-        match output {
-            LispOutput::List(list) => *list,
-            _ => panic!("expecting only lisp lists for append built-in!"),
-        }
-    }).collect();
-    return LispOutput::List(Box::new(LispList::append(lists)));
+fn peek_char_func(port: Optional<PortRef>) -> LispResult {
+    return or_eof(input_port(port).0.peek_char()?.map(String::from));
 }
 
-fn map_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_MAP_ARGUMENTS);
+/// `(read port)` parses the next datum, returning it unevaluated.
+fn read_func(port: Optional<PortRef>) -> LispResult {
+    return or_eof(input_port(port).0.read()?);
+}
 
-    fn apply_map(list: LispList, func: impl LispFunctionCall) -> LispList {
-        match list {
-            LispList::Nil => LispList::Nil,
-            LispList::Cons(car, cdr) => LispList::Cons(
-                func.call(vec![car.clone()]), 
-                Box::new(apply_map(*cdr, func))
-            )
-        }
-    }
+fn open_input_file_func(path: String) -> LispResult<PortRef> {
+    return PortRef::open_input_file(Path::new(&path));
+}
 
-    let function = match &args[1] {
-        LispOutput::Lambda(func) => func.clone(),
-        _ => panic!("expecting second argument to be lisp function!"),
-    };
+fn open_output_file_func(path: String) -> LispResult<PortRef> {
+    return PortRef::open_output_file(Path::new(&path));
+}
 
-    match &args[0] {
-        LispOutput::List(list) => LispOutput::List(Box::new(apply_map(*list.clone(), function))),
-        _ => panic!("expecting first argument to be lisp list!"),
-    }
+fn open_input_string_func(text: String) -> PortRef {
+    return PortRef::input_string(&text);
 }
 
-fn filter_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_FILTER_ARGUMENTS);
+/// `(call-with-output-file path procedure)` calls `procedure` with a port
+/// writing to the file at `path`, which is closed once it returns.
+fn call_with_output_file_func(path: String, function: LispFunction) -> LispResult {
+    let port = PortRef::open_output_file(Path::new(&path))?;
+    let result = function.call(vec![LispOutput::Port(port.clone())]);
+    let closed = port.0.close();
+    let value = result?;
+    closed?;
+    return Ok(value);
+}
 
-    fn apply_filter(list: LispList, func: impl LispFunctionCall) -> LispList {
-        match list {
-            LispList::Nil => LispList::Nil,
-            LispList::Cons(car, cdr) => {
-                if let LispOutput::Bool(should_keep) = func.call(vec![car.clone()]) {
-                    if !should_keep {
-                        return apply_filter(*cdr, func);
-                    }
+/// `(with-output-to-string thunk)` calls `thunk` with the current output port
+/// collecting what it writes, and returns that.
+fn with_output_to_string_func(thunk: LispFunction) -> LispResult<String> {
+    let (result, written) = port::with_output_to_string(|| thunk.call(Vec::new()));
+    result?;
+    return Ok(written);
+}
 
-                    return LispList::Cons(
-                        car, 
-                        Box::new(apply_filter(*cdr, func))
-                    );
-                }
+fn close_port_func(port: PortRef) -> LispResult<()> {
+    return port.0.close();
+}
 
-                panic!("expecting element to evaluate to boolean!");
-            },
-        }
-    }
+fn current_input_port_func() -> PortRef {
+    return port::current_input();
+}
 
-    let function = match &args[1] {
-        LispOutput::Lambda(func) => func.clone(),
-        _ => panic!("expecting second argument to be lisp function!"),
-    };
+fn current_output_port_func() -> PortRef {
+    return port::current_output();
+}
+
+fn is_eof_object_func(value: LispOutput) -> bool {
+    return matches!(value, LispOutput::Eof);
+}
+
+
+// ============== PROCESS BUILT-INS ===============
+
+fn command_line_func() -> Vec<String> {
+    return std::env::args().collect();
+}
+
+/// The value of the environment variable `name`, or `#f` if it is not set.
+fn get_environment_variable_func(name: String) -> Option<String> {
+    return std::env::var(name).ok();
+}
+
+
+// ============== TIME BUILT-INS ===============
+
+const JIFFIES_PER_SECOND: i64 = 1_000_000;
+
+thread_local! {
+    // jiffies are counted from the first time they are asked for
+    static JIFFY_EPOCH: Instant = Instant::now();
+}
+
+/// The number of whole seconds since the Unix epoch.
+fn current_second_func() -> i64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64);
+}
+
+fn current_jiffy_func() -> i64 {
+    return JIFFY_EPOCH.with(|epoch| epoch.elapsed().as_micros() as i64);
+}
 
-    match &args[0] {
-        LispOutput::List(list) => LispOutput::List(Box::new(apply_filter(*list.clone(), function))),
-        _ => panic!("expecting first argument to be lisp list!"),
+
+// ============== RANDOM BUILT-INS ===============
+
+thread_local! {
+    static RANDOM_STATE: Cell<u64> = Cell::new(random_seed());
+}
+
+fn random_seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+    // the state of xorshift must never be zero
+    return nanos | 1;
+}
+
+/// `(random limit)` returns a pseudo-random integer from 0 up to but not
+/// including `limit`. The numbers are not suitable for cryptography.
+fn random_func(limit: i64) -> LispResult<i64> {
+    if limit <= 0 {
+        return Err(LispError::type_mismatch(format!("expecting a positive limit, got {limit}")));
     }
+    let next = RANDOM_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        return x;
+    });
+    return Ok((next % limit as u64) as i64);
 }
 
-fn reduce_func(args: Vec<LispOutput>) -> LispOutput {
-    check_output_arguments(&args, REQUIRED_REDUCE_ARGUMENTS);
 
-    fn apply_reduce(list: LispList, func: impl LispFunctionCall, initial_val: LispOutput) -> LispOutput {
-        match list {
-            LispList::Nil => initial_val,
-            LispList::Cons(car, cdr) => {
-                let new_val = func.call(vec![initial_val, car]);
-                apply_reduce(*cdr, func, new_val)
-            },
+// ============== MEMORY BUILT-INS ===============
+
+/// `(gc)` runs a collection and returns the number of objects it freed.
+fn gc_func() -> usize {
+    return gc::collect();
+}
+
+/// `(gc-stats)` returns `((collections n) (allocated n) (collected n) (live n))`.
+fn gc_stats_func() -> Vec<(Symbol, usize)> {
+    let stats = gc::stats();
+    return [
+        ("collections", stats.collections),
+        ("allocated", stats.allocated),
+        ("collected", stats.collected),
+        ("live", stats.live),
+    ].into_iter().map(|(name, count)| (Symbol(name.to_string()), count)).collect();
+}
+
+
+// ============== FUNCTION BUILDINGS FUNCTIONS ===============
+
+fn convert_to_built_in<Args>(func: impl NativeFunction<Args>) -> LispOutput {
+    return LispOutput::Lambda(LispFunction::BuiltInFunction(BuiltInFunction::native(func)));
+}
+
+fn convert_to_intrinsic(intrinsic: Intrinsic) -> LispOutput {
+    return LispOutput::Lambda(LispFunction::Intrinsic(intrinsic));
+}
+
+/// The built-ins of every capability, with those of the capabilities not in
+/// `capabilities` replaced by procedures that fail when called.
+pub fn built_in_function_bindings(capabilities: Capabilities) -> HashMap<String, LispOutput> {
+    let groups = [
+        (Capability::Pure, pure_bindings(capabilities)),
+        (Capability::IoConsole, console_bindings()),
+        (Capability::IoFilesystem, filesystem_bindings()),
+        (Capability::Process, process_bindings()),
+        (Capability::Time, time_bindings()),
+        (Capability::Random, random_bindings()),
+    ];
+    let mut bindings = HashMap::new();
+    for (capability, group) in groups {
+        for (name, value) in group {
+            let value = match capabilities.contains(capability) {
+                true => value,
+                false => denied(name, capability),
+            };
+            bindings.insert(name.to_string(), value);
         }
     }
+    return bindings;
+}
 
-    let function = match &args[1] {
-        LispOutput::Lambda(func) => func.clone(),
-        _ => panic!("expecting second argument to be lisp function!"),
-    };
+/// A procedure standing in for the built-in `name` when `capability` is
+/// denied, so that calling it says why it is unavailable.
+fn denied(name: &'static str, capability: Capability) -> LispOutput {
+    return convert_to_built_in(move |_: Rest<LispOutput>| -> LispResult {
+        return Err(LispError::new(
+            LispErrorKind::CapabilityDenied,
+            format!("{name} needs the {capability} capability"),
+        ));
+    });
+}
 
-    let initial_val = args[2].clone();
+fn pure_bindings(capabilities: Capabilities) -> Vec<(&'static str, LispOutput)> {
+    return vec![
+        ("+", convert_to_built_in(add)),
+        ("-", convert_to_built_in(sub)),
+        ("*", convert_to_built_in(mul)),
+        ("/", convert_to_built_in(div)),
+        ("equal?", convert_to_built_in(equal_func)),
+        ("<", convert_to_built_in(comparator(|a, b| a < b))),
+        ("<=", convert_to_built_in(comparator(|a, b| a <= b))),
+        (">", convert_to_built_in(comparator(|a, b| a > b))),
+        (">=", convert_to_built_in(comparator(|a, b| a >= b))),
+        ("#t", LispOutput::Bool(true)),
+        ("#f", LispOutput::Bool(false)),
+        ("nil", LispOutput::List(Box::new(LispList::Nil))),
+        ("list", convert_to_built_in(make_list)),
+        ("cons", convert_to_built_in(cons_func)),
+        ("car", convert_to_built_in(car_func)),
+        ("cdr", convert_to_built_in(cdr_func)),
+        ("list?", convert_to_built_in(is_list_func)),
+        ("length", convert_to_built_in(list_length_func)),
+        ("list-ref", convert_to_built_in(list_ref_func)),
+        ("append", convert_to_built_in(append_func)),
+        ("map", convert_to_built_in(map_func)),
+        ("filter", convert_to_built_in(filter_func)),
+        ("reduce", convert_to_built_in(reduce_func)),
+        ("begin", convert_to_built_in(begin_func)),
+        ("procedure-arity", convert_to_built_in(procedure_arity_func)),
+        ("apply", convert_to_intrinsic(Intrinsic::Apply)),
+        ("eval", convert_to_intrinsic(Intrinsic::Eval)),
+        ("interaction-environment", convert_to_intrinsic(Intrinsic::InteractionEnvironment)),
+        ("call-with-current-continuation", convert_to_intrinsic(Intrinsic::CallWithCurrentContinuation)),
+        ("call/cc", convert_to_intrinsic(Intrinsic::CallWithCurrentContinuation)),
+        ("call-with-escape-continuation", convert_to_intrinsic(Intrinsic::CallWithEscapeContinuation)),
+        ("call/ec", convert_to_intrinsic(Intrinsic::CallWithEscapeContinuation)),
+        ("dynamic-wind", convert_to_intrinsic(Intrinsic::DynamicWind)),
+        ("raise", convert_to_intrinsic(Intrinsic::Raise)),
+        ("raise-continuable", convert_to_intrinsic(Intrinsic::RaiseContinuable)),
+        ("with-exception-handler", convert_to_intrinsic(Intrinsic::WithExceptionHandler)),
+        ("break", convert_to_intrinsic(Intrinsic::Break)),
+        ("error", convert_to_built_in(error_func)),
+        ("error-object?", convert_to_built_in(is_error_object_func)),
+        ("error-object-message", convert_to_built_in(error_object_message_func)),
+        ("error-object-irritants", convert_to_built_in(error_object_irritants_func)),
+        ("error-object-kind", convert_to_built_in(error_object_kind_func)),
+        ("error-object-backtrace", convert_to_built_in(error_object_backtrace_func)),
+        ("make-environment", convert_to_built_in(move |parent| make_environment_func(parent, capabilities))),
+        ("environment?", convert_to_built_in(is_environment_func)),
+        ("environment-bound?", convert_to_built_in(environment_bound_func)),
+        ("environment-ref", convert_to_built_in(environment_ref_func)),
+        ("assert-equal", convert_to_built_in(testing::assert_equal_func)),
+        ("assert-true", convert_to_built_in(testing::assert_true_func)),
+        ("gc", convert_to_built_in(gc_func)),
+        ("gc-stats", convert_to_built_in(gc_stats_func)),
+    ];
+}
 
-    match &args[0] {
-        LispOutput::List(list) => apply_reduce(*list.clone(), function, initial_val),
-        _ => panic!("expecting first argument to be lisp list!"),
-    }    
+fn console_bindings() -> Vec<(&'static str, LispOutput)> {
+    return vec![
+        ("display", convert_to_built_in(display_func)),
+        ("write", convert_to_built_in(write_func)),
+        ("newline", convert_to_built_in(newline_func)),
+        ("read-line", convert_to_built_in(read_line_func)),
+        ("read-char", convert_to_built_in(read_char_func)),
+        ("peek-char", convert_to_built_in(peek_char_func)),
+        ("read", convert_to_built_in(read_func)),
+        ("open-input-string", convert_to_built_in(open_input_string_func)),
+        ("with-output-to-string", convert_to_built_in(with_output_to_string_func)),
+        ("close-port", convert_to_built_in(close_port_func)),
+        ("current-input-port", convert_to_built_in(current_input_port_func)),
+        ("current-output-port", convert_to_built_in(current_output_port_func)),
+        ("eof-object?", convert_to_built_in(is_eof_object_func)),
+    ];
 }
 
-fn begin_func(args: Vec<LispOutput>) -> LispOutput {
-    let number_of_args = args.len();
-    if number_of_args == 0 {
-        panic!("expecting arguments to begin function to be non-zero");
-    }
-    return args[number_of_args - 1].clone();
+fn filesystem_bindings() -> Vec<(&'static str, LispOutput)> {
+    return vec![
+        ("open-input-file", convert_to_built_in(open_input_file_func)),
+        ("open-output-file", convert_to_built_in(open_output_file_func)),
+        ("call-with-output-file", convert_to_built_in(call_with_output_file_func)),
+        ("load", convert_to_intrinsic(Intrinsic::Load)),
+    ];
 }
 
+fn process_bindings() -> Vec<(&'static str, LispOutput)> {
+    return vec![
+        ("command-line", convert_to_built_in(command_line_func)),
+        ("get-environment-variable", convert_to_built_in(get_environment_variable_func)),
+    ];
+}
 
-// ============== FUNCTION BUILDINGS FUNCTIONS ===============
+fn time_bindings() -> Vec<(&'static str, LispOutput)> {
+    return vec![
+        ("current-second", convert_to_built_in(current_second_func)),
+        ("current-jiffy", convert_to_built_in(current_jiffy_func)),
+        ("jiffies-per-second", convert_to_built_in(|| JIFFIES_PER_SECOND)),
+    ];
+}
 
-fn convert_to_built_in(func: Rc<dyn Fn(Vec<LispOutput>) -> LispOutput>) -> LispOutput {
-    return LispOutput::Lambda(LispFunction::BuiltInFunction(BuiltInFunction::new(func)));
-}
-
-pub fn built_in_function_bindings() -> HashMap<String, LispOutput> {
-    return HashMap::from([
-        ("+".to_string(), convert_to_built_in(Rc::new(add))),
-        ("-".to_string(), convert_to_built_in(Rc::new(sub))),
-        ("*".to_string(), convert_to_built_in(Rc::new(mul))),
-        ("/".to_string(), convert_to_built_in(Rc::new(div))),
-        ("equal?".to_string(), convert_to_built_in(Rc::new(equal_compare))),
-        ("<".to_string(), convert_to_built_in(Rc::new(less_than_compare))),
-        ("<=".to_string(), convert_to_built_in(Rc::new(less_than_or_equal_compare))),
-        (">".to_string(), convert_to_built_in(Rc::new(greater_than_compare))),
-        (">=".to_string(), convert_to_built_in(Rc::new(greater_than_or_equal_compare))),
-        ("#t".to_string(), LispOutput::Bool(true)),
-        ("#f".to_string(), LispOutput::Bool(false)),
-        ("nil".to_string(), LispOutput::List(Box::new(LispList::Nil))),
-        ("list".to_string(), convert_to_built_in(Rc::new(make_list))),
-        ("car".to_string(), convert_to_built_in(Rc::new(car_func))),
-        ("cdr".to_string(), convert_to_built_in(Rc::new(cdr_func))),
-        ("list?".to_string(), convert_to_built_in(Rc::new(is_list_func))),
-        ("length".to_string(), convert_to_built_in(Rc::new(list_length_func))),
-        ("list-ref".to_string(), convert_to_built_in(Rc::new(list_ref_func))),
-        ("append".to_string(), convert_to_built_in(Rc::new(append_func))),
-        ("map".to_string(), convert_to_built_in(Rc::new(map_func))),
-        ("filter".to_string(), convert_to_built_in(Rc::new(filter_func))),
-        ("reduce".to_string(), convert_to_built_in(Rc::new(reduce_func))),
-        ("begin".to_string(), convert_to_built_in(Rc::new(begin_func))),
-    ]);
-
-
-}
\ No newline at end of file
+fn random_bindings() -> Vec<(&'static str, LispOutput)> {
+    return vec![
+        ("random", convert_to_built_in(random_func)),
+    ];
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+
+
+/// A group of built-in procedures that can be withheld from untrusted code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Computing with values: arithmetic, lists, procedures, environments and
+    /// errors.
+    Pure,
+    /// Reading and writing through ports, including standard input and output.
+    IoConsole,
+    /// Opening files and loading code from them.
+    IoFilesystem,
+    /// The command line and environment variables of the process.
+    Process,
+    /// The clock.
+    Time,
+    /// Random numbers.
+    Random,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::Pure,
+        Capability::IoConsole,
+        Capability::IoFilesystem,
+        Capability::Process,
+        Capability::Time,
+        Capability::Random,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Pure => "pure",
+            Capability::IoConsole => "io-console",
+            Capability::IoFilesystem => "io-filesystem",
+            Capability::Process => "process",
+            Capability::Time => "time",
+            Capability::Random => "random",
+        }
+    }
+
+    /// The capability with the given name, the inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        return Self::ALL.into_iter().find(|capability| capability.name() == name);
+    }
+
+    fn bit(&self) -> u8 {
+        return 1 << *self as u8;
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+
+/// The capabilities an environment is given. Built-ins of the others are
+/// still bound, but calling them fails with a `CapabilityDenied` error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(u8);
+
+/// Every capability.
+impl Default for Capabilities {
+    fn default() -> Self {
+        return Self::all();
+    }
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        return Self::only(&Capability::ALL);
+    }
+
+    /// No capabilities at all, not even `Pure`.
+    pub fn none() -> Self {
+        return Capabilities(0);
+    }
+
+    pub fn only(capabilities: &[Capability]) -> Self {
+        return capabilities.iter().fold(Self::none(), |set, capability| set.with(*capability));
+    }
+
+    pub fn with(self, capability: Capability) -> Self {
+        return Capabilities(self.0 | capability.bit());
+    }
+
+    pub fn without(self, capability: Capability) -> Self {
+        return Capabilities(self.0 & !capability.bit());
+    }
+
+    pub fn contains(&self, capability: Capability) -> bool {
+        return self.0 & capability.bit() != 0;
+    }
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::evaluate::{evaluate, evaluate_compiled, Environment, LispOutput};
+    use crate::lisp_error::{LispErrorKind, LispResult};
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    /// Evaluates each of `sources` with each engine in an environment with
+    /// `capabilities`, handing `check` the results and the engine's name.
+    fn eval_with(capabilities: Capabilities, sources: &[&str], check: impl Fn(&[LispResult], &str)) {
+        for compiled in [false, true] {
+            let mut env = Rc::new(RefCell::new(Environment::global_env_with(capabilities)));
+            let results: Vec<LispResult> = sources.iter().map(|source| {
+                let tree = parse(&tokenize(source));
+                match compiled {
+                    true => evaluate_compiled(&tree, &mut env),
+                    false => evaluate(&tree, &mut env),
+                }
+            }).collect();
+            check(&results, if compiled { "compiled" } else { "tree-walking" });
+        }
+    }
+
+    #[test]
+    fn capability_sets() {
+        let sandbox = Capabilities::only(&[Capability::Pure, Capability::IoConsole]);
+        assert!(sandbox.contains(Capability::IoConsole));
+        assert!(!sandbox.contains(Capability::IoFilesystem));
+        assert!(!sandbox.without(Capability::IoConsole).contains(Capability::IoConsole));
+        assert!(Capability::ALL.iter().all(|capability| Capabilities::all().contains(*capability)));
+        assert!(Capability::ALL.iter().all(|capability| !Capabilities::none().contains(*capability)));
+        assert_eq!(Some(Capability::IoFilesystem), Capability::from_name("io-filesystem"));
+        assert_eq!(None, Capability::from_name("network"));
+    }
+
+    #[test]
+    fn denied_built_ins_say_why() {
+        let pure = Capabilities::only(&[Capability::Pure]);
+        eval_with(pure, &[
+            "(display 1)",
+            "(environment-bound? (the-environment) 'open-input-file)",
+            "(guard (e (#t (error-object-kind e))) (random 10))",
+            "(map (range 1 4) (lambda (x) (* x x)))",
+        ], |results, engine| {
+            let err = results[0].clone().unwrap_err();
+            assert_eq!(LispErrorKind::CapabilityDenied, err.kind, "{engine}");
+            assert_eq!("capability-denied error: display needs the io-console capability", err.to_string(), "{engine}");
+            assert_eq!(Ok(LispOutput::Bool(true)), results[1], "{engine}");
+            assert_eq!(Ok(LispOutput::Symbol("capability-denied".to_string())), results[2], "{engine}");
+            assert_eq!(Ok(LispOutput::from_datum(&parse(&tokenize("(1 4 9)")))), results[3], "{engine}");
+        });
+
+        eval_with(Capabilities::none(), &["(+ 1 2)", "(load \"file.lisp\")"], |results, engine| {
+            assert_eq!(LispErrorKind::CapabilityDenied, results[0].clone().unwrap_err().kind, "{engine}");
+            let err = results[1].clone().unwrap_err();
+            assert_eq!("capability-denied error: load needs the io-filesystem capability", err.to_string(), "{engine}");
+        });
+    }
+
+    #[test]
+    fn new_environments_keep_the_capabilities() {
+        eval_with(Capabilities::only(&[Capability::Pure]), &["(eval '(current-second) (make-environment))"], |results, engine| {
+            assert_eq!(LispErrorKind::CapabilityDenied, results[0].clone().unwrap_err().kind, "{engine}");
+        });
+        eval_with(Capabilities::all(), &["(eval '(random 1) (make-environment))"], |results, engine| {
+            assert_eq!(Ok(LispOutput::Integer(0)), results[0], "{engine}");
+        });
+    }
+
+    #[test]
+    fn process_time_and_random_built_ins() {
+        eval_with(Capabilities::all(), &[
+            "(list? (command-line))",
+            "(get-environment-variable \"LISP_SURELY_NOT_SET\")",
+            "(> (current-second) 1600000000)",
+            "(let ((start (current-jiffy))) (<= start (current-jiffy)))",
+            "(jiffies-per-second)",
+            "(every (map (range 0 50) (lambda (n) (random 6))) (lambda (n) (if (>= n 0) (< n 6) #f)))",
+            "(random 0)",
+        ], |results, engine| {
+            for index in [0, 2, 3, 5] {
+                assert_eq!(Ok(LispOutput::Bool(true)), results[index], "{engine}: {index}");
+            }
+            assert_eq!(Ok(LispOutput::Bool(false)), results[1], "{engine}");
+            assert_eq!(Ok(LispOutput::Integer(1_000_000)), results[4], "{engine}");
+            assert_eq!(LispErrorKind::Type, results[6].clone().unwrap_err().kind, "{engine}");
+        });
+    }
+}
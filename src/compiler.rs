@@ -0,0 +1,663 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::analyze::{collect_definitions, Lambda, Node};
+use crate::evaluate::LispOutput;
+use crate::lisp_expression::Span;
+
+
+/// A variable captured by a closure, shared with the frame it was bound in.
+/// It is empty while an internal definition has not run yet.
+pub type Upvalue = Rc<RefCell<Option<LispOutput>>>;
+
+/// One instruction of the virtual machine. Operands index into the tables of
+/// the `Proto` the instruction belongs to, or are positions in its code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(usize),
+    Pop,
+    /// Variables that are not bound by any enclosing `lambda` or `let` are
+    /// looked up by name in the environment of the frame.
+    LoadName(usize),
+    DefineName(usize),
+    SetName(usize),
+    DelName(usize),
+    LoadLocal(usize),
+    /// Stores the value on top of the stack in a slot, leaving it there.
+    StoreLocal(usize),
+    /// Pops a value into a fresh binding of a slot.
+    BindLocal(usize),
+    /// Like `BindLocal`, for slots that are captured or assigned to later.
+    BindCell(usize),
+    /// Gives a slot a fresh, empty cell, for internal definitions.
+    DeclareCell(usize),
+    LoadUpvalue(usize),
+    StoreUpvalue(usize),
+    JumpIfLocalBound(usize, usize),
+    JumpIfUpvalueBound(usize, usize),
+    MakeClosure(usize),
+    Jump(usize),
+    /// Pops a value and jumps unless it is `#t`, like `if`.
+    JumpIfNotTrue(usize),
+    /// Pops a value and jumps if it is `#f`, like `and`.
+    JumpIfFalse(usize),
+    /// Pops a value and jumps if it is `#t`, like `or`.
+    JumpIfTrue(usize),
+    /// Calls the function below the given number of arguments. The second
+    /// operand indexes the spans of the calls.
+    Call(usize, usize),
+    TailCall(usize, usize),
+    Return,
+    /// Starts the body of a `guard`, whose handler code begins at the operand.
+    PushGuard(usize),
+    PopGuard,
+    /// Passes the condition on top of the stack to the next handler out, when
+    /// no clause of a `guard` matched it.
+    Reraise,
+    /// Evaluates a node with the tree-walking evaluator in tail position.
+    Interpret(usize),
+}
+
+/// Where a closure finds a captured variable when it is created: in a slot of
+/// the enclosing frame or among the upvalues of the enclosing closure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Local(usize),
+    Upvalue(usize),
+}
+
+/// The compiled code of a `lambda`, or of a top-level expression.
+#[derive(Debug, Default, PartialEq)]
+pub struct Proto {
+    pub lambda: Option<Rc<Lambda>>,
+    pub code: Vec<Op>,
+    pub constants: Vec<LispOutput>,
+    pub names: Vec<String>,
+    pub spans: Vec<Option<Span>>,
+    pub protos: Vec<Rc<Proto>>,
+    pub nodes: Vec<Rc<Node>>,
+    pub captures: Vec<Capture>,
+    pub upvalue_names: Vec<String>,
+    pub slot_names: Vec<String>,
+    /// Which parameters live in cells; parameters take the first slots.
+    pub boxed_parameters: Vec<bool>,
+}
+
+
+// -------------- SCOPES --------------
+struct Local {
+    name: String,
+    slot: usize,
+    /// Introduced by an internal `define`, so it may not be bound yet, in
+    /// which case the variable of the same name further out is used.
+    declared: bool,
+}
+
+#[derive(Default)]
+struct FunctionState {
+    code: Vec<Op>,
+    constants: Vec<LispOutput>,
+    names: Vec<String>,
+    spans: Vec<Option<Span>>,
+    protos: Vec<Rc<Proto>>,
+    captures: Vec<Capture>,
+    upvalue_names: Vec<String>,
+    slot_names: Vec<String>,
+    /// Slots that are captured by a closure or assigned after being bound.
+    boxed: Vec<bool>,
+    scopes: Vec<Vec<Local>>,
+}
+
+impl FunctionState {
+    fn finish(mut self, lambda: Option<Rc<Lambda>>) -> Proto {
+        for op in &mut self.code {
+            if let Op::BindLocal(slot) = *op {
+                if self.boxed[slot] {
+                    *op = Op::BindCell(slot);
+                }
+            }
+        }
+        let parameter_count = lambda.as_ref().map_or(0, |lambda| lambda.parameters.names().count());
+        return Proto {
+            lambda,
+            code: self.code,
+            constants: self.constants,
+            names: self.names,
+            spans: self.spans,
+            protos: self.protos,
+            nodes: Vec::new(),
+            captures: self.captures,
+            upvalue_names: self.upvalue_names,
+            slot_names: self.slot_names,
+            boxed_parameters: self.boxed[..parameter_count].to_vec(),
+        };
+    }
+}
+
+enum Location {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
+
+/// A resolved variable, and where to look instead while it is not bound.
+struct Resolved {
+    location: Location,
+    fallback: Option<Box<Resolved>>,
+}
+
+
+// -------------- COMPILER --------------
+/// Compiles analyzed code to bytecode for the virtual machine in `machine`.
+///
+/// Variables bound by `lambda` and the `let` forms live in numbered slots of
+/// the frame and are captured by closures as shared cells; everything else is
+/// looked up by name in the environment the code runs in. Expressions that
+/// need named frames, such as `the-environment` or `del` of a local variable,
+/// are left to the tree-walking evaluator.
+pub fn compile(node: &Rc<Node>) -> Rc<Proto> {
+    let mut compiler = Compiler {
+        functions: vec![FunctionState::default()],
+        needs_environments: false,
+    };
+    compiler.expression(node, true);
+    compiler.emit(Op::Return);
+
+    if compiler.needs_environments {
+        return Rc::new(Proto {
+            code: vec![Op::Interpret(0)],
+            nodes: vec![node.clone()],
+            ..Proto::default()
+        });
+    }
+    return Rc::new(compiler.functions.pop().unwrap().finish(None));
+}
+
+struct Compiler {
+    /// The function being compiled and the ones it is nested in, outermost
+    /// first.
+    functions: Vec<FunctionState>,
+    needs_environments: bool,
+}
+
+impl Compiler {
+    fn state(&mut self) -> &mut FunctionState {
+        return self.functions.last_mut().unwrap();
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.state().code;
+        code.push(op);
+        return code.len() - 1;
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.state().code.len();
+        let op = &mut self.state().code[at];
+        *op = match *op {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfNotTrue(_) => Op::JumpIfNotTrue(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfTrue(_) => Op::JumpIfTrue(target),
+            Op::JumpIfLocalBound(slot, _) => Op::JumpIfLocalBound(slot, target),
+            Op::JumpIfUpvalueBound(index, _) => Op::JumpIfUpvalueBound(index, target),
+            Op::PushGuard(_) => Op::PushGuard(target),
+            op => unreachable!("{op:?} is not a jump"),
+        };
+    }
+
+    fn constant(&mut self, value: LispOutput) {
+        let constants = &mut self.state().constants;
+        constants.push(value);
+        let index = constants.len() - 1;
+        self.emit(Op::Constant(index));
+    }
+
+    fn name(&mut self, var: &str) -> usize {
+        let names = &mut self.state().names;
+        return match names.iter().position(|name| name == var) {
+            Some(index) => index,
+            None => {
+                names.push(var.to_string());
+                names.len() - 1
+            },
+        };
+    }
+
+    fn add_local(&mut self, var: &str, declared: bool) -> usize {
+        let state = self.state();
+        let slot = state.slot_names.len();
+        state.slot_names.push(var.to_string());
+        state.boxed.push(false);
+        state.scopes.last_mut().unwrap().push(Local { name: var.to_string(), slot, declared });
+        return slot;
+    }
+
+    /// Declares the internal definitions made in the scope just opened.
+    fn declare_definitions(&mut self, nodes: &[&Node]) {
+        let mut names = Vec::new();
+        for node in nodes {
+            collect_definitions(node, &mut names);
+        }
+        for name in names {
+            let existing = self.state().scopes.last().unwrap().iter()
+                .find(|local| &local.name == name)
+                .map(|local| local.slot);
+            match existing {
+                Some(slot) => self.state().boxed[slot] = true,
+                None => {
+                    let slot = self.add_local(name, true);
+                    self.emit(Op::DeclareCell(slot));
+                },
+            }
+        }
+    }
+
+    /// Finds `var` in the first `scope_limit` scopes of `function` or further
+    /// out, capturing it into every function in between.
+    fn resolve(&mut self, function: usize, scope_limit: usize, var: &str) -> Resolved {
+        let state = &self.functions[function];
+        for scope in (0..scope_limit.min(state.scopes.len())).rev() {
+            if let Some(local) = state.scopes[scope].iter().rev().find(|local| local.name == var) {
+                let (slot, declared) = (local.slot, local.declared);
+                let fallback = declared.then(|| Box::new(self.resolve(function, scope, var)));
+                return Resolved { location: Location::Local(slot), fallback };
+            }
+        }
+
+        if function == 0 {
+            return Resolved { location: Location::Global, fallback: None };
+        }
+        let outer_scopes = self.functions[function - 1].scopes.len();
+        let outer = self.resolve(function - 1, outer_scopes, var);
+        return self.capture(function, var, outer);
+    }
+
+    fn capture(&mut self, function: usize, var: &str, outer: Resolved) -> Resolved {
+        let capture = match outer.location {
+            Location::Global => return outer,
+            Location::Local(slot) => {
+                self.functions[function - 1].boxed[slot] = true;
+                Capture::Local(slot)
+            },
+            Location::Upvalue(index) => Capture::Upvalue(index),
+        };
+
+        let state = &mut self.functions[function];
+        let index = match state.captures.iter().position(|existing| *existing == capture) {
+            Some(index) => index,
+            None => {
+                state.captures.push(capture);
+                state.upvalue_names.push(var.to_string());
+                state.captures.len() - 1
+            },
+        };
+        let fallback = outer.fallback.map(|fallback| Box::new(self.capture(function, var, *fallback)));
+        return Resolved { location: Location::Upvalue(index), fallback };
+    }
+
+    fn resolve_here(&mut self, var: &str) -> Resolved {
+        return self.resolve(self.functions.len() - 1, usize::MAX, var);
+    }
+
+    /// Emits `access` for a resolved variable, trying its fallbacks while it
+    /// is not bound.
+    fn access(&mut self, resolved: &Resolved, var: &str, access: fn(&mut Self, &Location, &str)) {
+        let fallback = match &resolved.fallback {
+            Some(fallback) => fallback,
+            None => return access(self, &resolved.location, var),
+        };
+        let check = match resolved.location {
+            Location::Local(slot) => self.emit(Op::JumpIfLocalBound(slot, 0)),
+            Location::Upvalue(index) => self.emit(Op::JumpIfUpvalueBound(index, 0)),
+            Location::Global => unreachable!("global variables have no fallback"),
+        };
+        self.access(fallback, var, access);
+        let end = self.emit(Op::Jump(0));
+        self.patch_jump(check);
+        access(self, &resolved.location, var);
+        self.patch_jump(end);
+    }
+
+    fn load(&mut self, location: &Location, var: &str) {
+        let op = match *location {
+            Location::Local(slot) => Op::LoadLocal(slot),
+            Location::Upvalue(index) => Op::LoadUpvalue(index),
+            Location::Global => Op::LoadName(self.name(var)),
+        };
+        self.emit(op);
+    }
+
+    fn store(&mut self, location: &Location, var: &str) {
+        let op = match *location {
+            Location::Local(slot) => {
+                self.state().boxed[slot] = true;
+                Op::StoreLocal(slot)
+            },
+            Location::Upvalue(index) => Op::StoreUpvalue(index),
+            Location::Global => Op::SetName(self.name(var)),
+        };
+        self.emit(op);
+    }
+
+    /// Compiles `node`, leaving its value on the stack or, in tail position,
+    /// returning it.
+    fn expression(&mut self, node: &Rc<Node>, tail: bool) {
+        match &**node {
+            Node::Constant(value) => self.constant(value.clone()),
+            Node::Variable(var, _) => {
+                let resolved = self.resolve_here(var);
+                self.access(&resolved, var, Self::load);
+            },
+            Node::Define(var, value) => {
+                self.expression(value, false);
+                self.define(var);
+            },
+            Node::Lambda(lambda) => self.lambda(lambda),
+            Node::If(condition, consequent, alternative) => {
+                self.expression(condition, false);
+                let to_alternative = self.emit(Op::JumpIfNotTrue(0));
+                self.expression(consequent, tail);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch_jump(to_alternative);
+                self.expression(alternative, tail);
+                self.patch_jump(to_end);
+            },
+            Node::And(clauses) => self.junction(clauses, true),
+            Node::Or(clauses) => self.junction(clauses, false),
+            // only a top-level del reaches the global frame, the others look in
+            // the frame they are in, which is kept for the interpreter
+            Node::Del(var) => match self.resolve_here(var).location {
+                Location::Global if self.functions.len() == 1 && self.state().scopes.is_empty() => {
+                    let index = self.name(var);
+                    self.emit(Op::DelName(index));
+                },
+                _ => self.needs_environments = true,
+            },
+            Node::Let { names, inits, body, .. } => {
+                for init in inits {
+                    self.expression(init, false);
+                }
+                self.state().scopes.push(Vec::new());
+                let slots: Vec<usize> = names.iter().map(|name| self.add_local(name, false)).collect();
+                for slot in slots.into_iter().rev() {
+                    self.emit(Op::BindLocal(slot));
+                }
+                self.declare_definitions(&[body]);
+                self.expression(body, tail);
+                self.state().scopes.pop();
+            },
+            Node::NamedLet { name, lambda, inits, .. } => {
+                // the loop procedure is bound in a scope of its own, which
+                // the initializers do not see
+                self.state().scopes.push(Vec::new());
+                let slot = self.add_local(name, false);
+                self.emit(Op::DeclareCell(slot));
+                self.lambda(lambda);
+                self.emit(Op::StoreLocal(slot));
+                self.state().scopes.pop();
+
+                for init in inits {
+                    self.expression(init, false);
+                }
+                self.call(inits.len(), None, tail);
+            },
+            Node::LetStar { bindings, body, .. } => {
+                for (index, (var, init)) in bindings.iter().enumerate() {
+                    self.expression(init, false);
+                    self.state().scopes.push(Vec::new());
+                    let slot = self.add_local(var, false);
+                    self.emit(Op::BindLocal(slot));
+                    match bindings.get(index + 1) {
+                        Some((_, next)) => self.declare_definitions(&[next]),
+                        None => self.declare_definitions(&[body]),
+                    }
+                }
+                self.expression(body, tail);
+                for _ in bindings {
+                    self.state().scopes.pop();
+                }
+            },
+            Node::Letrec { bindings, body, sequential, .. } => {
+                self.state().scopes.push(Vec::new());
+                let mut slots = Vec::new();
+                for (var, _) in bindings {
+                    let slot = self.add_local(var, false);
+                    self.state().boxed[slot] = true;
+                    self.constant(LispOutput::Void);
+                    self.emit(Op::BindLocal(slot));
+                    slots.push(slot);
+                }
+                let scope_nodes: Vec<&Node> = bindings.iter()
+                    .map(|(_, init)| &**init)
+                    .chain([&**body])
+                    .collect();
+                self.declare_definitions(&scope_nodes);
+
+                for ((_, init), slot) in bindings.iter().zip(&slots) {
+                    self.expression(init, false);
+                    if *sequential {
+                        self.emit(Op::StoreLocal(*slot));
+                        self.emit(Op::Pop);
+                    }
+                }
+                if !*sequential {
+                    for slot in slots.iter().rev() {
+                        self.emit(Op::StoreLocal(*slot));
+                        self.emit(Op::Pop);
+                    }
+                }
+                self.expression(body, tail);
+                self.state().scopes.pop();
+            },
+            Node::SetBang(var, _, value) => {
+                self.expression(value, false);
+                let resolved = self.resolve_here(var);
+                self.access(&resolved, var, Self::store);
+            },
+            Node::TheEnvironment | Node::Import(_) | Node::DefineLibrary(_) => {
+                self.needs_environments = true;
+                self.constant(LispOutput::Void);
+            },
+            Node::Guard { var, clauses, body, .. } => self.guard(var, clauses, body, tail),
+            Node::Application(nodes, span) => {
+                for node in nodes {
+                    self.expression(node, false);
+                }
+                self.call(nodes.len() - 1, *span, tail);
+            },
+        }
+    }
+
+    fn define(&mut self, var: &str) {
+        let local = self.state().scopes.last()
+            .map(|scope| scope.iter().rev().find(|local| local.name == var).map(|local| local.slot));
+        let op = match local {
+            None => Op::DefineName(self.name(var)),
+            Some(Some(slot)) => Op::StoreLocal(slot),
+            Some(None) => {
+                let slot = self.add_local(var, true);
+                Op::StoreLocal(slot)
+            },
+        };
+        if let Op::StoreLocal(slot) = op {
+            self.state().boxed[slot] = true;
+        }
+        self.emit(op);
+    }
+
+    fn lambda(&mut self, lambda: &Rc<Lambda>) {
+        self.functions.push(FunctionState::default());
+        self.state().scopes.push(Vec::new());
+        for name in lambda.parameters.names() {
+            self.add_local(name, false);
+        }
+
+        let defaults = lambda.parameters.defaults();
+        let scope_nodes: Vec<&Node> = defaults.iter()
+            .flatten()
+            .map(|default| &***default)
+            .chain([&*lambda.body])
+            .collect();
+        self.declare_definitions(&scope_nodes);
+
+        for (slot, default) in defaults.iter().enumerate() {
+            if let Some(default) = default {
+                let skip = self.emit(Op::JumpIfLocalBound(slot, 0));
+                self.expression(default, false);
+                self.emit(Op::StoreLocal(slot));
+                self.emit(Op::Pop);
+                self.patch_jump(skip);
+            }
+        }
+        self.expression(&lambda.body, true);
+        self.emit(Op::Return);
+
+        let proto = self.functions.pop().unwrap().finish(Some(lambda.clone()));
+        let protos = &mut self.state().protos;
+        protos.push(Rc::new(proto));
+        let index = protos.len() - 1;
+        self.emit(Op::MakeClosure(index));
+    }
+
+    /// `and` stops at the first `#f` and `or` at the first `#t`; both only
+    /// ever give a boolean.
+    fn junction(&mut self, clauses: &[Rc<Node>], is_and: bool) {
+        let mut exits = Vec::new();
+        for clause in clauses {
+            self.expression(clause, false);
+            exits.push(self.emit(if is_and { Op::JumpIfFalse(0) } else { Op::JumpIfTrue(0) }));
+        }
+        self.constant(LispOutput::Bool(is_and));
+        if exits.is_empty() {
+            return;
+        }
+
+        let to_end = self.emit(Op::Jump(0));
+        for exit in exits {
+            self.patch_jump(exit);
+        }
+        self.constant(LispOutput::Bool(!is_and));
+        self.patch_jump(to_end);
+    }
+
+    fn call(&mut self, argc: usize, span: Option<Span>, tail: bool) {
+        let spans = &mut self.state().spans;
+        spans.push(span);
+        let span = spans.len() - 1;
+        self.emit(if tail { Op::TailCall(argc, span) } else { Op::Call(argc, span) });
+    }
+
+    /// The body runs with the guard in effect; when something is raised the
+    /// frame is restored as it was at `PushGuard` and resumes at the handler
+    /// code with the condition on the stack.
+    fn guard(&mut self, var: &str, clauses: &[(Option<Rc<Node>>, Rc<Node>)], body: &Rc<Node>, tail: bool) {
+        let push_guard = self.emit(Op::PushGuard(0));
+        self.expression(body, false);
+        self.emit(Op::PopGuard);
+        let mut ends = vec![self.emit(Op::Jump(0))];
+
+        self.patch_jump(push_guard);
+        self.state().scopes.push(Vec::new());
+        let slot = self.add_local(var, false);
+        self.emit(Op::BindLocal(slot));
+        let scope_nodes: Vec<&Node> = clauses.iter()
+            .flat_map(|(test, expr)| test.iter().chain([expr]))
+            .map(|node| &**node)
+            .collect();
+        self.declare_definitions(&scope_nodes);
+
+        for (test, expr) in clauses {
+            let next = match test {
+                Some(test) => {
+                    self.expression(test, false);
+                    Some(self.emit(Op::JumpIfNotTrue(0)))
+                },
+                None => None,
+            };
+            self.expression(expr, tail);
+            ends.push(self.emit(Op::Jump(0)));
+            if let Some(next) = next {
+                self.patch_jump(next);
+            }
+        }
+        if !matches!(clauses.last(), Some((None, _))) {
+            self.emit(Op::LoadLocal(slot));
+            self.emit(Op::Reraise);
+        }
+        self.state().scopes.pop();
+
+        for end in ends {
+            self.patch_jump(end);
+        }
+    }
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::analyze;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn compile_source(source: &str) -> Rc<Proto> {
+        return compile(&analyze(&parse(&tokenize(source))).unwrap());
+    }
+
+    #[test]
+    fn parameters_live_in_slots() {
+        let proto = compile_source("(lambda (x y) y)");
+        assert_eq!(vec![Op::MakeClosure(0), Op::Return], proto.code);
+        assert_eq!(vec![Op::LoadLocal(1), Op::Return], proto.protos[0].code);
+    }
+
+    #[test]
+    fn free_variables_are_looked_up_by_name() {
+        let proto = compile_source("(lambda (x) (+ x 1))");
+        assert_eq!(vec!["+".to_string()], proto.protos[0].names);
+        assert_eq!(Op::LoadName(0), proto.protos[0].code[0]);
+    }
+
+    #[test]
+    fn calls_in_tail_position() {
+        let proto = compile_source("(lambda (f) (if (f 1) (f 2) 3))");
+        let code = &proto.protos[0].code;
+        assert!(matches!(code[2], Op::Call(1, _)));
+        assert!(code.iter().any(|op| matches!(op, Op::TailCall(1, _))));
+    }
+
+    #[test]
+    fn captured_variables_are_boxed() {
+        let proto = compile_source("(lambda (x y) (lambda () x))");
+        let outer = &proto.protos[0];
+        assert_eq!(vec![true, false], outer.boxed_parameters);
+        assert_eq!(vec![Capture::Local(0)], outer.protos[0].captures);
+        assert_eq!(vec![Op::LoadUpvalue(0), Op::Return], outer.protos[0].code);
+    }
+
+    #[test]
+    fn let_bindings_are_boxed_once_captured() {
+        let proto = compile_source("(let ((a 1) (b 2)) (lambda () a))");
+        assert!(proto.code.contains(&Op::BindCell(0)));
+        assert!(proto.code.contains(&Op::BindLocal(1)));
+    }
+
+    #[test]
+    fn internal_definitions_fall_back_until_defined() {
+        let proto = compile_source("(lambda () (begin x (define x 1)))");
+        let code = &proto.protos[0].code;
+        assert_eq!(Op::DeclareCell(0), code[0]);
+        assert!(code.contains(&Op::JumpIfLocalBound(0, 5)));
+        assert!(code.contains(&Op::StoreLocal(0)));
+    }
+
+    #[test]
+    fn first_class_environments_are_interpreted() {
+        let proto = compile_source("(let ((x 1)) (the-environment))");
+        assert_eq!(vec![Op::Interpret(0)], proto.code);
+    }
+}
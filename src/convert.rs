@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::evaluate::{EnvironmentRef, LispList, LispOutput};
+use crate::functions::{Arity, BuiltInFunction, LispFunction};
+use crate::lisp_error::{ErrorObject, LispError, LispErrorKind, LispResult};
+use crate::port::PortRef;
+
+
+// -------------- CONVERSIONS --------------
+/// Rust values that can be made from Lisp values, failing with a type error
+/// when the value is of the wrong kind.
+pub trait FromLisp: Sized {
+    fn from_lisp(value: LispOutput) -> LispResult<Self>;
+}
+
+/// Rust values that can be turned into Lisp values. Conversions fail for
+/// values Lisp has no way to represent, such as fractional numbers.
+pub trait IntoLisp {
+    fn into_lisp(self) -> LispResult;
+}
+
+fn expected(what: &str, value: &LispOutput) -> LispError {
+    return LispError::type_mismatch(format!("expecting {what}, got {value}"));
+}
+
+/// A symbol, as opposed to a string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol(pub String);
+
+impl FromLisp for LispOutput {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        return Ok(value);
+    }
+}
+
+impl IntoLisp for LispOutput {
+    fn into_lisp(self) -> LispResult {
+        return Ok(self);
+    }
+}
+
+impl IntoLisp for () {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::Void);
+    }
+}
+
+impl FromLisp for i64 {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Integer(num) => Ok(num),
+            _ => Err(expected("an integer", &value)),
+        }
+    }
+}
+
+impl IntoLisp for i64 {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::Integer(self));
+    }
+}
+
+impl FromLisp for usize {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Integer(num) if num >= 0 => Ok(num as usize),
+            _ => Err(expected("a non-negative integer", &value)),
+        }
+    }
+}
+
+impl IntoLisp for usize {
+    fn into_lisp(self) -> LispResult {
+        return match i64::try_from(self) {
+            Ok(num) => Ok(LispOutput::Integer(num)),
+            Err(_) => Err(LispError::type_mismatch(format!("{self} is too large for an integer"))),
+        };
+    }
+}
+
+/// Lisp only has integers, so floating point numbers convert to and from
+/// whole numbers.
+impl FromLisp for f64 {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Integer(num) => Ok(num as f64),
+            _ => Err(expected("a number", &value)),
+        }
+    }
+}
+
+impl IntoLisp for f64 {
+    fn into_lisp(self) -> LispResult {
+        if self.fract() != 0.0 || self < i64::MIN as f64 || self > i64::MAX as f64 {
+            return Err(LispError::type_mismatch(format!("{self} can not be represented as an integer")));
+        }
+        return Ok(LispOutput::Integer(self as i64));
+    }
+}
+
+impl FromLisp for bool {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Bool(bool_val) => Ok(bool_val),
+            _ => Err(expected("a boolean", &value)),
+        }
+    }
+}
+
+impl IntoLisp for bool {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::Bool(self));
+    }
+}
+
+impl FromLisp for String {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::String(string) => Ok(string),
+            _ => Err(expected("a string", &value)),
+        }
+    }
+}
+
+impl IntoLisp for String {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::String(self));
+    }
+}
+
+impl IntoLisp for &str {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::String(self.to_string()));
+    }
+}
+
+impl FromLisp for Symbol {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Symbol(symbol) => Ok(Symbol(symbol)),
+            _ => Err(expected("a symbol", &value)),
+        }
+    }
+}
+
+impl IntoLisp for Symbol {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::Symbol(self.0));
+    }
+}
+
+impl FromLisp for LispList {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::List(list) => Ok(*list),
+            _ => Err(expected("a list", &value)),
+        }
+    }
+}
+
+impl IntoLisp for LispList {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::List(Box::new(self)));
+    }
+}
+
+impl FromLisp for LispFunction {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Lambda(function) => Ok(function),
+            _ => Err(expected("a procedure", &value)),
+        }
+    }
+}
+
+impl IntoLisp for LispFunction {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::Lambda(self));
+    }
+}
+
+impl FromLisp for EnvironmentRef {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Environment(env) => Ok(env),
+            _ => Err(expected("an environment", &value)),
+        }
+    }
+}
+
+impl IntoLisp for EnvironmentRef {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::Environment(self));
+    }
+}
+
+impl FromLisp for Rc<ErrorObject> {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::ErrorObject(error) => Ok(error),
+            _ => Err(expected("an error object", &value)),
+        }
+    }
+}
+
+impl IntoLisp for Rc<ErrorObject> {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::ErrorObject(self));
+    }
+}
+
+impl FromLisp for PortRef {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Port(port) => Ok(port),
+            _ => Err(expected("a port", &value)),
+        }
+    }
+}
+
+impl IntoLisp for PortRef {
+    fn into_lisp(self) -> LispResult {
+        return Ok(LispOutput::Port(self));
+    }
+}
+
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        return LispList::from_lisp(value)?.to_vec().into_iter().map(T::from_lisp).collect();
+    }
+}
+
+impl<T: IntoLisp> IntoLisp for Vec<T> {
+    fn into_lisp(self) -> LispResult {
+        let items = self.into_iter().map(T::into_lisp).collect::<LispResult<Vec<LispOutput>>>()?;
+        return LispList::build(items.into_iter()).into_lisp();
+    }
+}
+
+/// `None` is `#f`, so `Some(false)` can not be told apart from it.
+impl<T: FromLisp> FromLisp for Option<T> {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        match value {
+            LispOutput::Bool(false) => Ok(None),
+            _ => Ok(Some(T::from_lisp(value)?)),
+        }
+    }
+}
+
+impl<T: IntoLisp> IntoLisp for Option<T> {
+    fn into_lisp(self) -> LispResult {
+        match self {
+            Some(value) => value.into_lisp(),
+            None => Ok(LispOutput::Bool(false)),
+        }
+    }
+}
+
+/// Maps are association lists with a `(key value)` list per entry.
+impl<K: FromLisp + Eq + Hash, V: FromLisp> FromLisp for HashMap<K, V> {
+    fn from_lisp(value: LispOutput) -> LispResult<Self> {
+        return Vec::<(K, V)>::from_lisp(value).map(|entries| entries.into_iter().collect());
+    }
+}
+
+impl<K: IntoLisp, V: IntoLisp> IntoLisp for HashMap<K, V> {
+    fn into_lisp(self) -> LispResult {
+        return self.into_iter().collect::<Vec<(K, V)>>().into_lisp();
+    }
+}
+
+/// Tuples are lists of exactly their length.
+macro_rules! tuple_conversions {
+    ($length:literal; $($item:ident),+) => {
+        impl<$($item: FromLisp),+> FromLisp for ($($item,)+) {
+            fn from_lisp(value: LispOutput) -> LispResult<Self> {
+                let items = match &value {
+                    LispOutput::List(list) => list.to_vec(),
+                    _ => Vec::new(),
+                };
+                if items.len() != $length {
+                    return Err(expected(concat!("a list of ", $length), &value));
+                }
+                let mut items = items.into_iter();
+                return Ok(($($item::from_lisp(items.next().unwrap())?,)+));
+            }
+        }
+
+        impl<$($item: IntoLisp),+> IntoLisp for ($($item,)+) {
+            #[allow(non_snake_case)]
+            fn into_lisp(self) -> LispResult {
+                let ($($item,)+) = self;
+                let items = vec![$($item.into_lisp()?),+];
+                return LispList::build(items.into_iter()).into_lisp();
+            }
+        }
+    };
+}
+
+tuple_conversions!(1; A);
+tuple_conversions!(2; A, B);
+tuple_conversions!(3; A, B, C);
+tuple_conversions!(4; A, B, C, D);
+
+
+// -------------- NATIVE FUNCTIONS --------------
+/// The parameters of a native function. A type that converts from a Lisp
+/// value takes one argument; `Optional` and `Rest` take any that are left.
+pub trait LispArgument: Sized {
+    /// The fewest and the most arguments this takes.
+    const ARITY: (usize, Option<usize>);
+
+    fn take(args: &mut std::vec::IntoIter<LispOutput>) -> LispResult<Self>;
+}
+
+/// An argument that may be left out, as the last or next to last parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Optional<T>(pub Option<T>);
+
+/// All the remaining arguments, as the last parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rest<T>(pub Vec<T>);
+
+impl<T: FromLisp> LispArgument for T {
+    const ARITY: (usize, Option<usize>) = (1, Some(1));
+
+    fn take(args: &mut std::vec::IntoIter<LispOutput>) -> LispResult<Self> {
+        let arg = args.next().ok_or_else(|| LispError::arity("missing argument"))?;
+        return T::from_lisp(arg);
+    }
+}
+
+impl<T: FromLisp> LispArgument for Optional<T> {
+    const ARITY: (usize, Option<usize>) = (0, Some(1));
+
+    fn take(args: &mut std::vec::IntoIter<LispOutput>) -> LispResult<Self> {
+        return Ok(Optional(args.next().map(T::from_lisp).transpose()?));
+    }
+}
+
+impl<T: FromLisp> LispArgument for Rest<T> {
+    const ARITY: (usize, Option<usize>) = (0, None);
+
+    fn take(args: &mut std::vec::IntoIter<LispOutput>) -> LispResult<Self> {
+        return Ok(Rest(args.map(T::from_lisp).collect::<LispResult<Vec<T>>>()?));
+    }
+}
+
+/// What a native function may return: a value that converts into Lisp, or a
+/// `Result` of one with an error that converts into a `LispError`.
+pub trait IntoLispResult {
+    fn into_lisp_result(self) -> LispResult;
+}
+
+impl<T: IntoLisp> IntoLispResult for T {
+    fn into_lisp_result(self) -> LispResult {
+        return self.into_lisp();
+    }
+}
+
+impl<T: IntoLisp, E: Into<LispError>> IntoLispResult for Result<T, E> {
+    fn into_lisp_result(self) -> LispResult {
+        return self.map_err(Into::into)?.into_lisp();
+    }
+}
+
+/// Errors from native functions given as a message are user errors, like
+/// those of the `error` procedure.
+impl From<String> for LispError {
+    fn from(message: String) -> Self {
+        return LispError::new(LispErrorKind::User, message);
+    }
+}
+
+impl From<&str> for LispError {
+    fn from(message: &str) -> Self {
+        return LispError::new(LispErrorKind::User, message);
+    }
+}
+
+/// Rust functions that can be called from Lisp, such as
+/// `Fn(i64, String) -> Result<Vec<i64>, E>`. `Args` is the tuple of their
+/// parameter types; the arity and the conversion of the arguments follow from
+/// it.
+pub trait NativeFunction<Args> {
+    fn arity() -> Arity;
+
+    fn into_built_in(self) -> BuiltInFunction;
+}
+
+macro_rules! native_function {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg: LispArgument),*> NativeFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoLispResult,
+        {
+            #[allow(unused_mut)]
+            fn arity() -> Arity {
+                let (mut min, mut max) = (0, Some(0));
+                $(
+                    let (arg_min, arg_max) = $arg::ARITY;
+                    min += arg_min;
+                    max = max.zip(arg_max).map(|(max, arg_max)| max + arg_max);
+                )*
+                return Arity { min, max };
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_built_in(self) -> BuiltInFunction {
+                let function = move |args: Vec<LispOutput>| {
+                    let mut args = args.into_iter();
+                    $(let $arg = $arg::take(&mut args)?;)*
+                    return self($($arg),*).into_lisp_result();
+                };
+                return BuiltInFunction::with_arity(Rc::new(function), Self::arity());
+            }
+        }
+    };
+}
+
+native_function!();
+native_function!(A);
+native_function!(A, B);
+native_function!(A, B, C);
+native_function!(A, B, C, D);
+native_function!(A, B, C, D, E);
+native_function!(A, B, C, D, E, G);
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::LispFunctionCall;
+
+    fn integer_list(items: &[i64]) -> LispOutput {
+        return LispOutput::List(Box::new(LispList::build(items.iter().map(|item| LispOutput::Integer(*item)))));
+    }
+
+    fn round_trip<T: FromLisp + IntoLisp + Clone + PartialEq + std::fmt::Debug>(value: T) {
+        let lisp = value.clone().into_lisp().unwrap();
+        assert_eq!(Ok(value), T::from_lisp(lisp));
+    }
+
+    #[test]
+    fn values_round_trip() {
+        round_trip(42i64);
+        round_trip(7usize);
+        round_trip(2.0f64);
+        round_trip(true);
+        round_trip("hello".to_string());
+        round_trip(Symbol("name".to_string()));
+        round_trip(vec![1i64, 2, 3]);
+        round_trip(Some(5i64));
+        round_trip(None::<i64>);
+        round_trip((1i64, "one".to_string(), false));
+        round_trip(HashMap::from([("a".to_string(), 1i64), ("b".to_string(), 2)]));
+    }
+
+    #[test]
+    fn conversions_check_types() {
+        let err = i64::from_lisp(LispOutput::String("1".to_string())).unwrap_err();
+        assert_eq!(LispErrorKind::Type, err.kind);
+        assert_eq!("expecting an integer, got \"1\"", err.message);
+        assert!(Vec::<i64>::from_lisp(LispOutput::Integer(1)).is_err());
+        assert!(<(i64, i64)>::from_lisp(integer_list(&[1, 2, 3])).is_err());
+        assert!(usize::from_lisp(LispOutput::Integer(-1)).is_err());
+        assert!(1.5f64.into_lisp().is_err());
+    }
+
+    #[test]
+    fn native_functions_convert_arguments_and_results() {
+        let repeat = BuiltInFunction::native(|num: i64, times: usize| -> Result<Vec<i64>, String> {
+            match times {
+                0 => Err("nothing to repeat".to_string()),
+                _ => Ok(vec![num; times]),
+            }
+        });
+        assert_eq!(Arity::exactly(2), repeat.arity());
+        assert_eq!(Ok(integer_list(&[7, 7])), repeat.call(vec![LispOutput::Integer(7), LispOutput::Integer(2)]));
+
+        let err = repeat.call(vec![LispOutput::Integer(7), LispOutput::Integer(0)]).unwrap_err();
+        assert_eq!((LispErrorKind::User, "nothing to repeat".to_string()), (err.kind, err.message));
+        let err = repeat.call(vec![LispOutput::Integer(7), LispOutput::Bool(true)]).unwrap_err();
+        assert_eq!(LispErrorKind::Type, err.kind);
+        let err = repeat.call(vec![LispOutput::Integer(7)]).unwrap_err();
+        assert_eq!(LispErrorKind::Arity, err.kind);
+    }
+
+    #[test]
+    fn optional_and_rest_arguments() {
+        let sum = BuiltInFunction::native(|first: i64, second: Optional<i64>, rest: Rest<i64>| {
+            first + second.0.unwrap_or(0) + rest.0.iter().sum::<i64>()
+        });
+        assert_eq!(Arity::at_least(1), sum.arity());
+        assert_eq!(Ok(LispOutput::Integer(1)), sum.call(vec![LispOutput::Integer(1)]));
+        assert_eq!(Ok(LispOutput::Integer(10)), sum.call((1..=4).map(LispOutput::Integer).collect()));
+
+        let at_most_two = BuiltInFunction::native(|_: i64, _: Optional<i64>| ());
+        assert_eq!(Arity::between(1, 2), at_most_two.arity());
+        assert_eq!(Ok(LispOutput::Void), at_most_two.call(vec![LispOutput::Integer(1)]));
+    }
+}
@@ -0,0 +1,460 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::debugger::{Debugger, Pause, Reason, Resume};
+use crate::evaluate::{Environment, LispOutput};
+use crate::functions::LispFunction;
+use crate::interpreter::Interpreter;
+use crate::json::{read_message, write_message, Json};
+use crate::lisp_error::LispResult;
+use crate::lisp_expression::Span;
+use crate::port::PortRef;
+
+
+type Env = Rc<RefCell<Environment>>;
+
+/// Lisp code runs on a single thread, which is all the editor is told about.
+const THREAD_ID: i64 = 1;
+
+/// What a `variablesReference` given to the editor stands for. References
+/// are numbered from 1 and are only good until evaluation resumes.
+enum Container {
+    /// The variables of a frame of an environment.
+    Frame(Env),
+    /// The elements of a list.
+    List(Vec<LispOutput>),
+    /// The arguments of a call further up the stack, whose environment is
+    /// gone from sight.
+    Arguments(Vec<LispOutput>),
+}
+
+/// A Debug Adapter Protocol server debugging one program, which it runs on
+/// the thread it is serving from. Requests are read while the program is
+/// paused, and before and after it runs.
+pub struct DapServer {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: i64,
+    /// The lines with breakpoints, by the path of their source.
+    breakpoints: HashMap<String, BTreeSet<usize>>,
+    program: Option<String>,
+    /// The program's source, split into lines of characters.
+    source: Vec<Vec<char>>,
+    configured: bool,
+    stop_on_entry: Cell<bool>,
+    /// Set when the pause about to happen is the one asked for by
+    /// `stopOnEntry`.
+    entering: Cell<bool>,
+    /// The line of the last call made in the program. A breakpoint pauses
+    /// when evaluation gets to its line, rather than at every call on it.
+    last_line: Cell<Option<usize>>,
+    containers: Vec<Container>,
+    /// Where the program's output goes, and how many characters of it have
+    /// been passed on to the editor.
+    program_output: PortRef,
+    forwarded: usize,
+    disconnected: bool,
+}
+
+/// Serves the Debug Adapter Protocol on `input` and `output` until the editor
+/// disconnects or the input ends.
+pub fn serve(input: impl BufRead + 'static, output: impl Write + 'static) -> io::Result<()> {
+    let server = Rc::new(RefCell::new(DapServer::new(input, output)));
+    let Some(program) = server.borrow_mut().configure()? else { return Ok(()) };
+
+    let program_output = server.borrow().program_output.clone();
+    let mut interpreter = Interpreter::new().with_output(program_output).with_debugger(server.clone());
+    if let Some(directory) = Path::new(&program).parent() {
+        interpreter.add_library_path(directory);
+    }
+    let result = interpreter.eval_file(&program);
+    return server.borrow_mut().finish(result);
+}
+
+/// `path` spelled the same however the editor and the launch request spell
+/// it, if it exists.
+fn canonical(path: &str) -> String {
+    return fs::canonicalize(path).map_or(path.to_string(), |path| path.display().to_string());
+}
+
+impl DapServer {
+    pub fn new(input: impl BufRead + 'static, output: impl Write + 'static) -> Self {
+        return DapServer {
+            input: Box::new(input),
+            output: Box::new(output),
+            seq: 0,
+            breakpoints: HashMap::new(),
+            program: None,
+            source: Vec::new(),
+            configured: false,
+            stop_on_entry: Cell::new(false),
+            entering: Cell::new(false),
+            last_line: Cell::new(None),
+            containers: Vec::new(),
+            program_output: PortRef::output_string(),
+            forwarded: 0,
+            disconnected: false,
+        };
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message.set("seq", self.seq.into());
+        return write_message(&mut self.output, &message);
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        return self.send(Json::object([("type", "event".into()), ("event", event.into()), ("body", body)]));
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> io::Result<()> {
+        let mut response = Json::object([
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").clone()),
+            ("command", request.get("command").clone()),
+        ]);
+        match result {
+            Ok(body) => {
+                response.set("success", true.into());
+                if !body.is_null() {
+                    response.set("body", body);
+                }
+            },
+            Err(message) => {
+                response.set("success", false.into());
+                response.set("message", message.into());
+            },
+        }
+        return self.send(response);
+    }
+
+    /// Handles requests until the program has been launched and the editor
+    /// is done setting it up, returning the path of the program, or `None`
+    /// if the editor went away first.
+    fn configure(&mut self) -> io::Result<Option<String>> {
+        while !(self.configured && self.program.is_some()) {
+            let Some(request) = read_message(&mut self.input)? else { return Ok(None) };
+            self.handle(&request, None)?;
+            if self.disconnected {
+                return Ok(None);
+            }
+        }
+        return Ok(self.program.clone());
+    }
+
+    /// Reports how the program ended, then handles requests until the
+    /// editor disconnects.
+    fn finish(&mut self, result: LispResult) -> io::Result<()> {
+        self.forward_output()?;
+        if let Err(err) = &result {
+            if !self.disconnected {
+                self.event("output", Json::object([("category", "stderr".into()), ("output", format!("{}\n", err.report()).into())]))?;
+            }
+        }
+        let exit_code: i64 = if result.is_ok() { 0 } else { 1 };
+        self.event("exited", Json::object([("exitCode", exit_code.into())]))?;
+        self.event("terminated", Json::object([]))?;
+        while !self.disconnected {
+            let Some(request) = read_message(&mut self.input)? else { break };
+            self.handle(&request, None)?;
+        }
+        return Ok(());
+    }
+
+    /// Passes on what the program has written since the last time.
+    fn forward_output(&mut self) -> io::Result<()> {
+        let written = self.program_output.0.written().unwrap_or_default();
+        let new: String = written.chars().skip(self.forwarded).collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+        self.forwarded += new.chars().count();
+        return self.event("output", Json::object([("category", "stdout".into()), ("output", new.into())]));
+    }
+
+    /// Whether `span` is that of a list in the program. Spans do not say
+    /// which source they are in, and the procedures of the prelude and of
+    /// libraries have spans into theirs.
+    fn in_program(&self, span: Span) -> bool {
+        let at = |line: usize, column: usize| {
+            let line = self.source.get(line.checked_sub(1)?)?;
+            return line.get(column.checked_sub(1)?).copied();
+        };
+        return at(span.line, span.column) == Some('(')
+            && at(span.end_line, span.end_column.saturating_sub(1)) == Some(')');
+    }
+
+    fn program_lines(&self) -> Option<&BTreeSet<usize>> {
+        return self.breakpoints.get(&canonical(self.program.as_deref()?));
+    }
+
+    /// Lines a breakpoint can be set on: those where a list starts.
+    fn has_list(path: &str, line: usize) -> bool {
+        let Ok(source) = fs::read_to_string(path) else { return false };
+        return source.lines().nth(line.saturating_sub(1)).is_some_and(|text| {
+            let code = text.split(';').next().unwrap_or_default();
+            return code.contains('(');
+        });
+    }
+
+    fn contain(&mut self, container: Container) -> usize {
+        self.containers.push(container);
+        return self.containers.len();
+    }
+
+    /// The reference of the variables inside `value`, or 0 if there are
+    /// none.
+    fn reference(&mut self, value: &LispOutput) -> usize {
+        let container = match value {
+            LispOutput::List(list) if !list.to_vec().is_empty() => Container::List(list.to_vec()),
+            LispOutput::Lambda(LispFunction::Function(function)) => Container::Frame(function.enclosing_frame().clone()),
+            LispOutput::Lambda(LispFunction::Closure(closure)) => Container::Frame(closure.env().clone()),
+            LispOutput::Environment(env) => Container::Frame(env.0.clone()),
+            _ => return 0,
+        };
+        return self.contain(container);
+    }
+
+    fn variable(&mut self, name: String, value: &LispOutput) -> Json {
+        let reference = self.reference(value);
+        return Json::object([
+            ("name", name.into()),
+            ("value", value.to_string().into()),
+            ("variablesReference", reference.into()),
+        ]);
+    }
+
+    fn source_json(&self) -> Json {
+        let path = self.program.clone().unwrap_or_default();
+        let name = Path::new(&path).file_name().map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+        return Json::object([("name", name.into()), ("path", path.into())]);
+    }
+
+    /// The stack as the editor sees it: the procedures in progress,
+    /// innermost first, each where it is at, then the top level.
+    fn stack_frames(&self, pause: &Pause) -> Vec<Json> {
+        let mut frames = Vec::new();
+        let mut at = pause.call.span;
+        for index in 0..=pause.backtrace.len() {
+            let name = match pause.backtrace.get(index) {
+                Some(frame) => frame.name.clone().unwrap_or_else(|| "<anonymous>".to_string()),
+                None => "<top level>".to_string(),
+            };
+            let mut frame = Json::object([("id", index.into()), ("name", name.into())]);
+            match at.filter(|span| self.in_program(*span)) {
+                Some(span) => {
+                    frame.set("source", self.source_json());
+                    frame.set("line", span.line.into());
+                    frame.set("column", span.column.into());
+                    frame.set("endLine", span.end_line.into());
+                    frame.set("endColumn", span.end_column.into());
+                },
+                None => {
+                    frame.set("line", 0_usize.into());
+                    frame.set("column", 0_usize.into());
+                    frame.set("presentationHint", "subtle".into());
+                },
+            }
+            frames.push(frame);
+            at = pause.backtrace.get(index).and_then(|frame| frame.span);
+        }
+        return frames;
+    }
+
+    fn scopes(&mut self, pause: &Pause, frame_id: usize) -> Result<Json, String> {
+        let mut scopes = Vec::new();
+        let env_frames = pause.frames();
+        if frame_id == 0 {
+            let last = env_frames.len() - 1;
+            for (index, env) in env_frames.into_iter().enumerate() {
+                let name = match index {
+                    _ if index == last => "Globals",
+                    0 => "Locals",
+                    _ => "Enclosing",
+                };
+                let reference = self.contain(Container::Frame(env));
+                scopes.push(Json::object([
+                    ("name", name.into()),
+                    ("variablesReference", reference.into()),
+                    ("expensive", (index == last).into()),
+                ]));
+            }
+        } else if let Some(frame) = pause.backtrace.get(frame_id) {
+            let reference = self.contain(Container::Arguments(frame.arguments.clone()));
+            scopes.push(Json::object([
+                ("name", "Arguments".into()),
+                ("variablesReference", reference.into()),
+                ("expensive", false.into()),
+            ]));
+        } else if frame_id == pause.backtrace.len() {
+            let reference = self.contain(Container::Frame(env_frames.last().unwrap().clone()));
+            scopes.push(Json::object([
+                ("name", "Globals".into()),
+                ("variablesReference", reference.into()),
+                ("expensive", true.into()),
+            ]));
+        } else {
+            return Err(format!("there is no frame {frame_id}"));
+        }
+        return Ok(Json::object([("scopes", scopes.into())]));
+    }
+
+    fn variables(&mut self, reference: usize) -> Result<Json, String> {
+        let named: Vec<(String, LispOutput)> = match reference.checked_sub(1).and_then(|index| self.containers.get(index)) {
+            Some(Container::Frame(env)) => env.borrow().local_bindings(),
+            Some(Container::List(values) | Container::Arguments(values)) => {
+                values.iter().enumerate().map(|(index, value)| (index.to_string(), value.clone())).collect()
+            },
+            None => return Err(format!("there are no variables {reference}")),
+        };
+        let variables: Vec<Json> = named.into_iter().map(|(name, value)| self.variable(name, &value)).collect();
+        return Ok(Json::object([("variables", variables.into())]));
+    }
+
+    fn evaluate(&mut self, pause: &Pause, arguments: &Json) -> Result<Json, String> {
+        let expression = arguments.get("expression").as_str().unwrap_or_default();
+        // only the innermost frame has an environment; the others evaluate
+        // at the top level
+        let frame = match arguments.get("frameId").as_i64() {
+            None | Some(0) => 0,
+            Some(_) => pause.frames().len() - 1,
+        };
+        let value = pause.eval(expression, frame).map_err(|err| err.to_string())?;
+        let reference = self.reference(&value);
+        return Ok(Json::object([("result", value.to_string().into()), ("variablesReference", reference.into())]));
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Json {
+        let path = arguments.get("source").get("path").as_str().unwrap_or_default().to_string();
+        let lines: BTreeSet<usize> = arguments.get("breakpoints").as_array().iter()
+            .filter_map(|breakpoint| breakpoint.get("line").as_i64())
+            .map(|line| line as usize)
+            .collect();
+        let breakpoints: Vec<Json> = lines.iter()
+            .map(|line| Json::object([("verified", Self::has_list(&path, *line).into()), ("line", (*line).into())]))
+            .collect();
+        self.breakpoints.insert(canonical(&path), lines);
+        return Json::object([("breakpoints", breakpoints.into())]);
+    }
+
+    /// Carries out `request`, returning how to resume when it is one of the
+    /// requests that end a pause.
+    fn handle(&mut self, request: &Json, pause: Option<&Pause>) -> io::Result<Option<Resume>> {
+        let arguments = request.get("arguments");
+        let command = request.get("command").as_str().unwrap_or_default();
+        let (result, resume) = match (command, pause) {
+            ("initialize", _) => {
+                let capabilities = Json::object([
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                    ("supportsTerminateRequest", true.into()),
+                ]);
+                self.respond(request, Ok(capabilities))?;
+                self.event("initialized", Json::object([]))?;
+                return Ok(None);
+            },
+            ("launch", _) => match arguments.get("program").as_str() {
+                Some(program) => match fs::read_to_string(program) {
+                    Ok(source) => {
+                        self.source = source.lines().map(|line| line.chars().collect()).collect();
+                        self.program = Some(program.to_string());
+                        self.stop_on_entry.set(arguments.get("stopOnEntry").as_bool() == Some(true));
+                        (Ok(Json::Null), None)
+                    },
+                    Err(err) => (Err(format!("can not read {program}: {err}")), None),
+                },
+                None => (Err("launch needs the path of a program".to_string()), None),
+            },
+            ("setBreakpoints", _) => (Ok(self.set_breakpoints(arguments)), None),
+            ("setExceptionBreakpoints", _) => (Ok(Json::object([("breakpoints", Json::Array(Vec::new()))])), None),
+            ("configurationDone", _) => {
+                self.configured = true;
+                (Ok(Json::Null), None)
+            },
+            ("threads", _) => {
+                let thread = Json::object([("id", THREAD_ID.into()), ("name", "main".into())]);
+                (Ok(Json::object([("threads", vec![thread].into())])), None)
+            },
+            ("disconnect" | "terminate", _) => {
+                self.disconnected = true;
+                (Ok(Json::Null), pause.map(|_| Resume::Abort))
+            },
+            ("stackTrace", Some(pause)) => {
+                let frames = self.stack_frames(pause);
+                let total = frames.len();
+                (Ok(Json::object([("stackFrames", frames.into()), ("totalFrames", total.into())])), None)
+            },
+            ("scopes", Some(pause)) => {
+                let frame_id = arguments.get("frameId").as_i64().unwrap_or_default() as usize;
+                (self.scopes(pause, frame_id), None)
+            },
+            ("variables", Some(_)) => {
+                let reference = arguments.get("variablesReference").as_i64().unwrap_or_default() as usize;
+                (self.variables(reference), None)
+            },
+            ("evaluate", Some(pause)) => (self.evaluate(pause, arguments), None),
+            ("continue", Some(_)) => (Ok(Json::object([("allThreadsContinued", true.into())])), Some(Resume::Continue)),
+            ("next", Some(_)) => (Ok(Json::Null), Some(Resume::StepOver)),
+            ("stepIn", Some(_)) => (Ok(Json::Null), Some(Resume::StepInto)),
+            ("stepOut", Some(_)) => (Ok(Json::Null), Some(Resume::StepOut)),
+            (
+                "stackTrace" | "scopes" | "variables" | "evaluate" | "continue" | "next" | "stepIn" | "stepOut",
+                None,
+            ) => (Err(format!("{command} needs the program to be paused")), None),
+            _ => (Err(format!("unsupported request {command}")), None),
+        };
+        self.respond(request, result)?;
+        return Ok(resume);
+    }
+
+    fn pause(&mut self, pause: &Pause) -> io::Result<Resume> {
+        self.containers.clear();
+        self.forward_output()?;
+        let reason = match pause.reason {
+            _ if self.entering.replace(false) => "entry",
+            Reason::Step => "step",
+            Reason::Break | Reason::Breakpoint => "breakpoint",
+        };
+        self.event("stopped", Json::object([
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ]))?;
+        loop {
+            let Some(request) = read_message(&mut self.input)? else {
+                self.disconnected = true;
+                return Ok(Resume::Abort);
+            };
+            if let Some(resume) = self.handle(&request, Some(pause))? {
+                return Ok(resume);
+            }
+        }
+    }
+}
+
+impl Debugger for DapServer {
+    fn breaks_at(&self, _name: Option<&str>, span: Option<Span>) -> bool {
+        if self.stop_on_entry.replace(false) {
+            self.entering.set(true);
+            return true;
+        }
+        let Some(span) = span.filter(|span| self.in_program(*span)) else { return false };
+        let arrived = self.last_line.replace(Some(span.line)) != Some(span.line);
+        return arrived && self.program_lines().is_some_and(|lines| lines.contains(&span.line));
+    }
+
+    fn paused(&mut self, pause: &Pause) -> Resume {
+        if self.disconnected {
+            return Resume::Abort;
+        }
+        // the editor is gone if it can not be talked to
+        return self.pause(pause).unwrap_or_else(|_| {
+            self.disconnected = true;
+            Resume::Abort
+        });
+    }
+}
@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::evaluate::{evaluate, evaluate_compiled, Environment, LispOutput};
+use crate::functions::{Intrinsic, LispFunction};
+use crate::limits;
+use crate::lisp_error::{LispError, LispErrorKind, LispResult, TraceFrame};
+use crate::lisp_expression::Span;
+use crate::parser::parse_all_with_spans;
+use crate::tokenizer::try_tokenize_with_spans;
+
+pub mod repl;
+
+
+type Env = Rc<RefCell<Environment>>;
+
+/// A debugger shared between the code that installs it and the evaluation it
+/// watches.
+pub type DebuggerRef = Rc<RefCell<dyn Debugger>>;
+
+thread_local! {
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+}
+
+/// Decides where evaluation pauses and what happens once it has.
+///
+/// Evaluation can only pause just before a call written in the code, after
+/// its arguments have been evaluated: at `(break)`, at a call `breaks_at`
+/// agrees to, and at every call while stepping.
+pub trait Debugger {
+    /// Whether to pause before calling the procedure named `name`, when it has
+    /// one, written at `span`, when the code came with spans.
+    fn breaks_at(&self, name: Option<&str>, span: Option<Span>) -> bool;
+
+    /// Called while evaluation is paused; evaluation goes on once it returns.
+    fn paused(&mut self, pause: &Pause) -> Resume;
+}
+
+/// Why evaluation paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// `(break)` was called.
+    Break,
+    /// `Debugger::breaks_at` asked for it.
+    Breakpoint,
+    /// The previous pause asked to step.
+    Step,
+}
+
+impl Reason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reason::Break => "break",
+            Reason::Breakpoint => "breakpoint",
+            Reason::Step => "step",
+        }
+    }
+}
+
+/// How evaluation goes on after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Until the next `(break)` or breakpoint.
+    Continue,
+    /// Pausing again at the next call, wherever it is.
+    StepInto,
+    /// Pausing again at the next call that is not made from inside the call
+    /// paused at.
+    StepOver,
+    /// Pausing again at the next call made once the procedure paused in has
+    /// returned.
+    StepOut,
+    /// Stopping the evaluation with an `Interrupted` error.
+    Abort,
+}
+
+/// The state of evaluation while it is paused.
+pub struct Pause {
+    pub reason: Reason,
+    /// The call about to be made, with its arguments.
+    pub call: TraceFrame,
+    /// The calls in progress, innermost first.
+    pub backtrace: Vec<TraceFrame>,
+    env: Env,
+    compiled: bool,
+}
+
+impl Pause {
+    /// The frames of the environment the call is made in, innermost first,
+    /// up to the global frame. Compiled code keeps the variables of
+    /// procedures in registers rather than frames, so only those of the
+    /// frames enclosing them show up here.
+    pub fn frames(&self) -> Vec<Env> {
+        let mut frames = vec![self.env.clone()];
+        loop {
+            let parent = match &frames.last().unwrap().borrow().parent_env {
+                // the outermost frame holds the built-ins
+                Some(parent) if parent.borrow().parent_env.is_some() => parent.clone(),
+                _ => break,
+            };
+            frames.push(parent);
+        }
+        return frames;
+    }
+
+    /// Evaluates the expressions of `source` in frame `frame` of `frames`,
+    /// with the engine that was running, and returns the value of the last.
+    /// Evaluation does not pause in here.
+    pub fn eval(&self, source: &str, frame: usize) -> LispResult {
+        let mut env = match self.frames().get(frame) {
+            Some(env) => env.clone(),
+            None => return Err(LispError::new(LispErrorKind::IndexOutOfBounds, format!("there is no frame {frame}"))),
+        };
+        let (tokens, spans) = try_tokenize_with_spans(source)?;
+        let mut value = LispOutput::Void;
+        for (tree, _) in parse_all_with_spans(&tokens, &spans)? {
+            value = match self.compiled {
+                true => evaluate_compiled(&tree, &mut env),
+                false => evaluate(&tree, &mut env),
+            }?;
+        }
+        return Ok(value);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stepping {
+    Off,
+    Into,
+    /// Pauses at calls made with at most this many calls in progress.
+    UpTo(usize),
+}
+
+struct Session {
+    debugger: DebuggerRef,
+    stepping: Stepping,
+}
+
+/// Runs `evaluation` watched by `debugger`, or by none at all. Pausing is
+/// done on this thread, so the debugger can block for as long as it likes.
+pub fn debugging<T>(debugger: Option<DebuggerRef>, evaluation: impl FnOnce() -> LispResult<T>) -> LispResult<T> {
+    let session = debugger.map(|debugger| Session { debugger, stepping: Stepping::Off });
+    let outer = SESSION.with(|current| current.replace(session));
+    let result = evaluation();
+    SESSION.with(|current| current.replace(outer));
+    return result;
+}
+
+/// The name `function` was defined with, or else the first name it is bound
+/// to in `env`.
+fn name_of(function: &LispFunction, env: &Env) -> Option<String> {
+    let name = match function {
+        LispFunction::Function(function) => function.lambda().name.clone(),
+        LispFunction::Closure(closure) => closure.lambda().name.clone(),
+        _ => None,
+    };
+    if name.is_some() {
+        return name;
+    }
+    let mut current = Some(env.clone());
+    while let Some(frame) = current {
+        let bindings = frame.borrow().local_bindings();
+        let found = bindings.into_iter().find(|(_, value)| match value {
+            LispOutput::Lambda(bound) => bound == function,
+            _ => false,
+        });
+        if let Some((name, _)) = found {
+            return Some(name);
+        }
+        current = frame.borrow().parent_env.clone();
+    }
+    return None;
+}
+
+/// Called by the `machine` before a call written in the code at `span` is
+/// made in `env`, to pause there if the debugger wants to.
+pub(crate) fn before_call(
+    function: &LispFunction,
+    args: &[LispOutput],
+    env: &Env,
+    span: Option<Span>,
+    compiled: bool,
+    backtrace: impl FnOnce() -> Vec<TraceFrame>,
+) -> LispResult<()> {
+    let depth = limits::depth();
+    let reason = SESSION.with(|current| {
+        let current = current.borrow();
+        let session = current.as_ref()?;
+        if *function == LispFunction::Intrinsic(Intrinsic::Break) {
+            return Some(Reason::Break);
+        }
+        let name = match function {
+            LispFunction::Function(function) => function.lambda().name.as_deref(),
+            LispFunction::Closure(closure) => closure.lambda().name.as_deref(),
+            _ => None,
+        };
+        if session.debugger.borrow().breaks_at(name, span) {
+            return Some(Reason::Breakpoint);
+        }
+        return match session.stepping {
+            Stepping::Into => Some(Reason::Step),
+            Stepping::UpTo(max_depth) if depth <= max_depth => Some(Reason::Step),
+            _ => None,
+        };
+    });
+    let Some(reason) = reason else { return Ok(()) };
+
+    let pause = Pause {
+        reason,
+        call: TraceFrame { name: name_of(function, env), span, arguments: args.to_vec() },
+        backtrace: backtrace(),
+        env: env.clone(),
+        compiled,
+    };
+    // the session is put aside while paused, so that what the debugger
+    // evaluates runs as it would without one
+    let Some(session) = SESSION.with(|current| current.take()) else { return Ok(()) };
+    let resume = session.debugger.borrow_mut().paused(&pause);
+    let stepping = match resume {
+        Resume::Continue | Resume::Abort => Stepping::Off,
+        Resume::StepInto => Stepping::Into,
+        Resume::StepOver => Stepping::UpTo(depth),
+        Resume::StepOut => Stepping::UpTo(depth.saturating_sub(1)),
+    };
+    SESSION.with(|current| current.replace(Some(Session { stepping, ..session })));
+
+    if resume == Resume::Abort {
+        return Err(LispError::new(LispErrorKind::Interrupted, "evaluation stopped from the debugger"));
+    }
+    return Ok(());
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate::Engine;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    const ENGINES: [Engine; 2] = [evaluate, evaluate_compiled];
+
+    /// Pauses at the procedures named in `breakpoints` and resumes as told
+    /// by `resumes`, noting down every pause.
+    #[derive(Default)]
+    struct Scripted {
+        breakpoints: Vec<&'static str>,
+        resumes: Vec<Resume>,
+        /// Evaluated in the innermost frame at every pause.
+        probe: Option<&'static str>,
+        pauses: Vec<String>,
+        backtraces: Vec<Vec<Option<String>>>,
+        probes: Vec<LispResult>,
+    }
+
+    impl Debugger for Scripted {
+        fn breaks_at(&self, name: Option<&str>, _span: Option<Span>) -> bool {
+            return name.is_some_and(|name| self.breakpoints.contains(&name));
+        }
+
+        fn paused(&mut self, pause: &Pause) -> Resume {
+            self.pauses.push(format!("{} {}", pause.reason.name(), pause.call));
+            self.backtraces.push(pause.backtrace.iter().map(|frame| frame.name.clone()).collect());
+            if let Some(probe) = self.probe {
+                self.probes.push(pause.eval(probe, 0));
+            }
+            return match self.resumes.is_empty() {
+                true => Resume::Continue,
+                false => self.resumes.remove(0),
+            };
+        }
+    }
+
+    /// Evaluates each of `sources` with `engine`, the last one watched by
+    /// `debugger`.
+    fn debug(engine: Engine, sources: &[&str], debugger: &Rc<RefCell<Scripted>>) -> LispResult {
+        let mut env = Rc::new(RefCell::new(Environment::global_env()));
+        let (last, definitions) = sources.split_last().unwrap();
+        for source in definitions {
+            engine(&parse(&tokenize(source)), &mut env).unwrap();
+        }
+        return debugging(Some(debugger.clone()), || engine(&parse(&tokenize(last)), &mut env));
+    }
+
+    #[test]
+    fn break_pauses_where_it_is_called() {
+        let sources = ["(define (f x) (begin (break) (+ x 1)))", "(f 41)"];
+        for engine in ENGINES {
+            let debugger = Rc::new(RefCell::new(Scripted { probe: Some("(* x 2)"), ..Scripted::default() }));
+            assert_eq!(Ok(LispOutput::Integer(42)), debug(engine, &sources, &debugger));
+            assert_eq!(vec!["break (break)"], debugger.borrow().pauses);
+            assert_eq!(vec![vec![Some("f".to_string())]], debugger.borrow().backtraces);
+        }
+        // compiled code keeps `x` in a register, out of the debugger's sight
+        let debugger = Rc::new(RefCell::new(Scripted { probe: Some("(* x 2)"), ..Scripted::default() }));
+        debug(evaluate, &sources, &debugger).unwrap();
+        assert_eq!(vec![Ok(LispOutput::Integer(82))], debugger.borrow().probes);
+    }
+
+    #[test]
+    fn break_does_nothing_without_a_debugger() {
+        for engine in ENGINES {
+            let mut env = Rc::new(RefCell::new(Environment::global_env()));
+            assert_eq!(Ok(LispOutput::Void), engine(&parse(&tokenize("(break)")), &mut env));
+        }
+    }
+
+    #[test]
+    fn stepping_into_over_and_out_of_calls() {
+        let sources = [
+            "(define (square x) (* x x))",
+            "(define (sum-squares a b) (+ (square a) (square b)))",
+            "(sum-squares 2 3)",
+        ];
+        for engine in ENGINES {
+            let debugger = Rc::new(RefCell::new(Scripted {
+                breakpoints: vec!["sum-squares"],
+                resumes: vec![Resume::StepInto, Resume::StepInto, Resume::StepOut, Resume::StepOver],
+                ..Scripted::default()
+            }));
+            assert_eq!(Ok(LispOutput::Integer(13)), debug(engine, &sources, &debugger));
+            let expected = vec!["breakpoint (sum-squares 2 3)", "step (square 2)", "step (* 2 2)", "step (square 3)", "step (+ 4 9)"];
+            assert_eq!(expected, debugger.borrow().pauses);
+            let innermost: Vec<Option<String>> = vec![Some("square".to_string()), Some("sum-squares".to_string())];
+            assert_eq!(innermost, debugger.borrow().backtraces[2]);
+        }
+    }
+
+    #[test]
+    fn aborting_stops_the_evaluation() {
+        for engine in ENGINES {
+            let debugger = Rc::new(RefCell::new(Scripted { resumes: vec![Resume::Abort], ..Scripted::default() }));
+            let result = debug(engine, &["(guard (e (#t 'caught)) (begin (break) 1))"], &debugger);
+            assert_eq!(LispErrorKind::Interrupted, result.unwrap_err().kind);
+        }
+    }
+
+    #[test]
+    fn evaluating_while_paused_does_not_pause() {
+        let debugger = Rc::new(RefCell::new(Scripted { probe: Some("(begin (break) (list 1 2))"), ..Scripted::default() }));
+        debug(evaluate, &["(break)"], &debugger).unwrap();
+        assert_eq!(1, debugger.borrow().pauses.len());
+        assert_eq!("(1 2)", debugger.borrow().probes[0].clone().unwrap().to_string());
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use super::{Debugger, Pause, Resume};
+use crate::lisp_expression::Span;
+
+
+const HELP: &str = "\
+:step, :s        go on until the next call
+:next, :n        go on until the next call that is not inside this one
+:finish, :f      go on until the next call after this procedure returns
+:continue, :c    go on until the next break or breakpoint
+:quit, :q        stop evaluating
+:locals, :l      the variables of the selected frame
+:up, :u          select the frame enclosing the selected one
+:down, :d        select the frame the selected one encloses
+:where, :w       the calls in progress
+:show            the call paused at
+:break NAME      pause whenever the procedure NAME is called
+:unbreak NAME    stop pausing at calls to NAME
+:breakpoints     the procedures paused at
+anything else is evaluated in the selected frame";
+
+/// A debugger driven by commands typed at a `debug>` prompt, for the REPL.
+pub struct DebugRepl {
+    read_line: Box<dyn FnMut() -> Option<String>>,
+    output: Box<dyn Write>,
+    /// The names of the procedures to pause at.
+    breakpoints: BTreeSet<String>,
+    /// The code being evaluated, for showing where a pause happened.
+    source: String,
+}
+
+impl DebugRepl {
+    /// A debugger reading commands with `read_line`, which returns `None` at
+    /// the end of its input, and writing to `output`.
+    pub fn new(read_line: impl FnMut() -> Option<String> + 'static, output: impl Write + 'static) -> Self {
+        return DebugRepl {
+            read_line: Box::new(read_line),
+            output: Box::new(output),
+            breakpoints: BTreeSet::new(),
+            source: String::new(),
+        };
+    }
+
+    /// A debugger on standard input and output.
+    pub fn console() -> Self {
+        let read_line = || {
+            let mut line = String::new();
+            let read = io::stdin().read_line(&mut line).ok()?;
+            return (read > 0).then_some(line);
+        };
+        return Self::new(read_line, io::stdout());
+    }
+
+    /// Sets the code about to be evaluated, whose spans pauses point into.
+    pub fn set_source(&mut self, source: &str) {
+        self.source = source.to_string();
+    }
+
+    /// Pauses before every call to the procedure `name`. Returns whether it
+    /// was not paused at already.
+    pub fn add_breakpoint(&mut self, name: &str) -> bool {
+        return self.breakpoints.insert(name.to_string());
+    }
+
+    /// Returns whether there was a breakpoint on `name` to remove.
+    pub fn remove_breakpoint(&mut self, name: &str) -> bool {
+        return self.breakpoints.remove(name);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &String> {
+        return self.breakpoints.iter();
+    }
+
+    /// Carries out `line` if it is `:break`, `:unbreak` or `:breakpoints`,
+    /// returning what to tell the user. Returns `None` for anything else.
+    pub fn breakpoint_command(&mut self, line: &str) -> Option<String> {
+        let mut words = line.split_whitespace();
+        let command = words.next()?;
+        let name = words.next();
+        let message = match (command, name) {
+            (":break", Some(name)) => match self.add_breakpoint(name) {
+                true => format!("breakpoint set on {name}"),
+                false => format!("already a breakpoint on {name}"),
+            },
+            (":unbreak", Some(name)) => match self.remove_breakpoint(name) {
+                true => format!("breakpoint on {name} removed"),
+                false => format!("no breakpoint on {name}"),
+            },
+            (":break" | ":unbreak", None) => format!("{command} needs the name of a procedure"),
+            (":breakpoints", _) if self.breakpoints.is_empty() => "no breakpoints".to_string(),
+            (":breakpoints", _) => self.breakpoints().cloned().collect::<Vec<String>>().join("\n"),
+            _ => return None,
+        };
+        return Some(message);
+    }
+
+    /// The line of `source` that `span` starts on, with carets under the
+    /// span. Spans do not say which source they are in, so this is `None`
+    /// unless the span covers a call in `source`; a procedure defined by an
+    /// earlier input has spans into that one.
+    fn excerpt(&self, span: Span) -> Option<String> {
+        let line: Vec<char> = self.source.lines().nth(span.line.checked_sub(1)?)?.chars().collect();
+        let start = span.column.checked_sub(1)?;
+        let end = match span.end_line == span.line {
+            true => span.end_column.checked_sub(1)?,
+            false => line.len(),
+        };
+        if start >= end || end > line.len() || line[start] != '(' {
+            return None;
+        }
+        if span.end_line == span.line && line[end - 1] != ')' {
+            return None;
+        }
+        let line: String = line.into_iter().collect();
+        let carets = "^".repeat(end - start);
+        let number = span.line.to_string();
+        return Some(format!(
+            "{number} | {line}\n{} | {}{carets}",
+            " ".repeat(number.len()),
+            " ".repeat(span.column.saturating_sub(1)),
+        ));
+    }
+
+    fn show(&mut self, pause: &Pause) {
+        let _ = writeln!(self.output, "{}: {}", pause.reason.name(), pause.call);
+        if let Some(excerpt) = pause.call.span.and_then(|span| self.excerpt(span)) {
+            let _ = writeln!(self.output, "{excerpt}");
+        }
+    }
+
+    fn show_frame(&mut self, pause: &Pause, index: usize) {
+        let frames = pause.frames();
+        let names: Vec<String> = frames[index].borrow().local_bindings().into_iter().map(|(name, _)| name).collect();
+        let global = if index == frames.len() - 1 { " (global)" } else { "" };
+        let _ = writeln!(self.output, "frame {index}{global}: {}", names.join(" "));
+    }
+}
+
+impl Debugger for DebugRepl {
+    fn breaks_at(&self, name: Option<&str>, _span: Option<Span>) -> bool {
+        return name.is_some_and(|name| self.breakpoints.contains(name));
+    }
+
+    fn paused(&mut self, pause: &Pause) -> Resume {
+        let frame_count = pause.frames().len();
+        let mut selected = 0;
+        self.show(pause);
+        loop {
+            let _ = write!(self.output, "debug> ");
+            let _ = self.output.flush();
+            let Some(line) = (self.read_line)() else { return Resume::Continue };
+            let line = line.trim();
+            if let Some(message) = self.breakpoint_command(line) {
+                let _ = writeln!(self.output, "{message}");
+                continue;
+            }
+            match line {
+                "" => {},
+                ":step" | ":s" => return Resume::StepInto,
+                ":next" | ":n" => return Resume::StepOver,
+                ":finish" | ":f" => return Resume::StepOut,
+                ":continue" | ":c" => return Resume::Continue,
+                ":quit" | ":q" => return Resume::Abort,
+                ":locals" | ":l" => {
+                    let bindings = pause.frames()[selected].borrow().local_bindings();
+                    if bindings.is_empty() {
+                        let _ = writeln!(self.output, "no variables");
+                    }
+                    for (name, value) in bindings {
+                        let _ = writeln!(self.output, "{name} = {value}");
+                    }
+                },
+                ":up" | ":u" if selected + 1 == frame_count => {
+                    let _ = writeln!(self.output, "already in the global frame");
+                },
+                ":up" | ":u" => {
+                    selected += 1;
+                    self.show_frame(pause, selected);
+                },
+                ":down" | ":d" if selected == 0 => {
+                    let _ = writeln!(self.output, "already in the innermost frame");
+                },
+                ":down" | ":d" => {
+                    selected -= 1;
+                    self.show_frame(pause, selected);
+                },
+                ":where" | ":w" => {
+                    if pause.backtrace.is_empty() {
+                        let _ = writeln!(self.output, "no calls in progress");
+                    }
+                    for (index, frame) in pause.backtrace.iter().enumerate() {
+                        let _ = writeln!(self.output, "{index}: {frame}");
+                    }
+                },
+                ":show" => self.show(pause),
+                ":help" | ":h" => {
+                    let _ = writeln!(self.output, "{HELP}");
+                },
+                _ if line.starts_with(':') => {
+                    let _ = writeln!(self.output, "unknown command {line}, :help lists them");
+                },
+                _ => {
+                    let _ = match pause.eval(line, selected) {
+                        Ok(value) => writeln!(self.output, "{value}"),
+                        Err(err) => writeln!(self.output, "{}", err.report()),
+                    };
+                },
+            }
+        }
+    }
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::{Interpreter, LispOutput};
+
+    /// Output that stays readable after the debugger has been handed it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            return self.0.borrow_mut().write(buf);
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    fn scripted(lines: &[&str]) -> (Rc<RefCell<DebugRepl>>, Shared) {
+        let mut lines: Vec<String> = lines.iter().rev().map(|line| format!("{line}\n")).collect();
+        let output = Shared::default();
+        let debugger = DebugRepl::new(move || lines.pop(), output.clone());
+        return (Rc::new(RefCell::new(debugger)), output);
+    }
+
+    fn transcript(output: &Shared) -> String {
+        return String::from_utf8(output.0.borrow().clone()).unwrap();
+    }
+
+    #[test]
+    fn breakpoint_commands() {
+        let (debugger, _) = scripted(&[]);
+        let mut debugger = debugger.borrow_mut();
+        assert_eq!(Some("no breakpoints".to_string()), debugger.breakpoint_command(":breakpoints"));
+        assert_eq!(Some("breakpoint set on fact".to_string()), debugger.breakpoint_command(":break fact"));
+        assert_eq!(Some("already a breakpoint on fact".to_string()), debugger.breakpoint_command(":break fact"));
+        debugger.breakpoint_command(":break go");
+        assert_eq!(Some("fact\ngo".to_string()), debugger.breakpoint_command(":breakpoints"));
+        assert_eq!(Some("breakpoint on go removed".to_string()), debugger.breakpoint_command(":unbreak go"));
+        assert_eq!(Some("no breakpoint on go".to_string()), debugger.breakpoint_command(":unbreak go"));
+        assert_eq!(Some(":break needs the name of a procedure".to_string()), debugger.breakpoint_command(":break"));
+        assert_eq!(None, debugger.breakpoint_command("(break)"));
+        assert!(debugger.breaks_at(Some("fact"), None));
+        assert!(!debugger.breaks_at(None, None));
+    }
+
+    #[test]
+    fn a_debugging_session() {
+        let (debugger, output) = scripted(&[
+            ":locals", ":s", "(* n 10)", ":where", ":up", ":up", ":l", ":down", ":bogus", ":n", ":c",
+        ]);
+        let mut interpreter = Interpreter::new().with_debugger(debugger.clone());
+        interpreter.eval_str("(define (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))").unwrap();
+        debugger.borrow_mut().add_breakpoint("fact");
+        let source = "(define limit 3)\n(+ 100 (fact limit))";
+        debugger.borrow_mut().set_source(source);
+        assert_eq!(Ok(LispOutput::Integer(106)), interpreter.eval_str(source));
+
+        let expected = "\
+breakpoint: (fact 3) at line 2, column 8
+2 | (+ 100 (fact limit))
+  |        ^^^^^^^^^^^^
+debug> fact = #[procedure]
+limit = 3
+debug> step: (< 3 2) at line 1, column 22
+debug> 30
+debug> 0: (fact 3) at line 2, column 8
+debug> frame 1 (global): fact limit
+debug> already in the global frame
+debug> fact = #[procedure]
+limit = 3
+debug> frame 0: n
+debug> unknown command :bogus, :help lists them
+debug> step: (- 3 1) at line 1, column 43
+debug> breakpoint: (fact 2) at line 1, column 37
+debug> breakpoint: (fact 1) at line 1, column 37
+debug> ";
+        assert_eq!(expected, transcript(&output));
+    }
+
+    #[test]
+    fn quitting_stops_the_evaluation() {
+        let (debugger, output) = scripted(&[":q"]);
+        let mut interpreter = Interpreter::new().compiled(true).with_debugger(debugger);
+        let err = interpreter.eval_str("(define x 1) (break) (set! x 2)").unwrap_err();
+        assert_eq!("interrupted error: evaluation stopped from the debugger", err.to_string());
+        assert_eq!(Some(LispOutput::Integer(1)), interpreter.get_global("x"));
+        assert_eq!("break: (break) at line 1, column 14\ndebug> ", transcript(&output));
+    }
+}
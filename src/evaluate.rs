@@ -3,9 +3,18 @@ use std::rc::Rc;
 use std::boxed::Box;
 use std::cell::RefCell;
 
-use crate::lisp_expression::LispExpression;
+use crate::analyze::{analyze, analyze_with_spans, Reference, Slots};
+use crate::lisp_expression::{LispExpression, SpanTree};
+use crate::lisp_error::{ErrorObject, LispError, LispErrorKind, LispResult};
 use crate::built_in_functions::built_in_function_bindings;
-use crate::functions::{LispFunction, LispFunctionCall, Function};
+use crate::functions::{LispFunction, KEYWORD_PREFIX};
+use crate::capability::Capabilities;
+use crate::compiler::compile;
+use crate::gc;
+use crate::port::PortRef;
+use crate::prelude;
+use crate::machine::{execute, execute_compiled};
+use crate::resolve::resolve;
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -13,1534 +22,530 @@ pub enum LispOutput {
     Void,
     Integer(i64),
     Bool(bool),
+    String(String),
     Lambda(LispFunction),
     List(Box<LispList>),
+    Keyword(String),
+    Symbol(String),
+    Environment(EnvironmentRef),
+    ErrorObject(Rc<ErrorObject>),
+    Port(PortRef),
+    /// What reading from a port returns at the end of its input.
+    Eof,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum LispList {
-    Cons(LispOutput, Box<LispList>),
-    Nil,
-}
-
-impl LispList {
-    pub fn build(mut args: impl Iterator<Item=LispOutput>) -> Self {
-        let first = args.next();
-        match first {
-            Some(val) => LispList::Cons(val, Box::new(LispList::build(args))),
-            None => LispList::Nil,
+impl LispOutput {
+    /// Converts a quoted expression into the data it denotes.
+    pub fn from_datum(expr: &LispExpression) -> Self {
+        match expr {
+            LispExpression::Integer(num) => LispOutput::Integer(*num),
+            LispExpression::String(string) => LispOutput::String(string.clone()),
+            LispExpression::Symbol(symbol) => match &symbol[..] {
+                "#t" => LispOutput::Bool(true),
+                "#f" => LispOutput::Bool(false),
+                _ => match symbol.strip_prefix(KEYWORD_PREFIX) {
+                    Some(keyword) => LispOutput::Keyword(keyword.to_string()),
+                    None => LispOutput::Symbol(symbol.clone()),
+                },
+            },
+            LispExpression::List(expressions) => LispOutput::List(Box::new(
+                LispList::build(expressions.iter().map(LispOutput::from_datum))
+            )),
         }
     }
 
-    pub fn get_car(&self) -> LispOutput {
+    /// Converts data back into an expression that can be evaluated.
+    pub fn to_expression(&self) -> LispResult<LispExpression> {
         match self {
-            LispList::Cons(car, _) => car.clone(),
-            LispList::Nil => panic!("lisp list is empty!"),
+            LispOutput::Integer(num) => Ok(LispExpression::Integer(*num)),
+            LispOutput::String(string) => Ok(LispExpression::String(string.clone())),
+            LispOutput::Bool(true) => Ok(LispExpression::Symbol("#t".to_string())),
+            LispOutput::Bool(false) => Ok(LispExpression::Symbol("#f".to_string())),
+            LispOutput::Symbol(symbol) => Ok(LispExpression::Symbol(symbol.clone())),
+            LispOutput::Keyword(keyword) => Ok(LispExpression::Symbol(format!("{KEYWORD_PREFIX}{keyword}"))),
+            LispOutput::List(list) => Ok(LispExpression::List(
+                list.to_vec().iter().map(LispOutput::to_expression).collect::<LispResult<_>>()?
+            )),
+            _ => Err(LispError::type_mismatch(format!("cannot convert {:?} into an expression", self))),
         }
     }
+}
 
-    pub fn get_cdr(&self) -> LispOutput {
+impl LispOutput {
+    /// Prints the value for people to read, as `display` does: like the
+    /// `Display` implementation, but with strings as their bare text.
+    pub fn display_string(&self) -> String {
         match self {
-            LispList::Cons(_, cdr) => LispOutput::List(cdr.clone()),
-            LispList::Nil => panic!("lisp list is empty!"),
+            LispOutput::String(string) => string.clone(),
+            LispOutput::List(list) => {
+                let items: Vec<String> = list.to_vec().iter().map(LispOutput::display_string).collect();
+                format!("({})", items.join(" "))
+            },
+            _ => self.to_string(),
         }
     }
+}
 
-    pub fn length(&self) -> LispOutput {
-        fn get_length(list: &LispList) -> i64 {
-            match list {
-                LispList::Nil => 0,
-                LispList::Cons(_, cdr) => get_length(cdr) + 1,
-            }
-        }
-        return LispOutput::Integer(get_length(self));
-    }
-
-    pub fn get(&self, index: i64) -> LispOutput {
+/// Prints values the way they would be written in source, for error messages.
+impl std::fmt::Display for LispOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LispList::Nil => panic!("index out of bounds!"),
-            LispList::Cons(car, cdr) => {
-                if index == 0 {
-                    return car.clone();
-                }
-
-                cdr.get(index - 1)
-            }
+            LispOutput::Void => write!(f, "#!void"),
+            LispOutput::Integer(num) => write!(f, "{num}"),
+            LispOutput::Bool(true) => write!(f, "#t"),
+            LispOutput::Bool(false) => write!(f, "#f"),
+            LispOutput::String(string) => write!(f, "{string:?}"),
+            LispOutput::Lambda(_) => write!(f, "#[procedure]"),
+            LispOutput::List(list) => {
+                let items: Vec<String> = list.to_vec().iter().map(|item| item.to_string()).collect();
+                write!(f, "({})", items.join(" "))
+            },
+            LispOutput::Keyword(keyword) => write!(f, "{KEYWORD_PREFIX}{keyword}"),
+            LispOutput::Symbol(symbol) => write!(f, "{symbol}"),
+            LispOutput::Environment(_) => write!(f, "#[environment]"),
+            LispOutput::ErrorObject(error) => write!(f, "#[{} error: {}]", error.kind.name(), error.message),
+            LispOutput::Port(port) => write!(f, "{}", port.0),
+            LispOutput::Eof => write!(f, "#[eof]"),
         }
     }
+}
 
-    pub fn append(lists: Vec<LispList>) -> LispList {
-        if lists.len() == 0 {
-            return LispList::Nil;
-        }
+/// An environment used as a first-class value. Two references are equal when
+/// they point at the same frame.
+#[derive(Clone)]
+pub struct EnvironmentRef(pub Rc<RefCell<Environment>>);
 
-        match &lists[0] {
-            LispList::Nil => LispList::append(lists[1..].to_vec()),
-            LispList::Cons(car, cdr) => {
-                let mut new_args = vec![*cdr.clone()];
-                new_args.append(&mut lists[1..].to_vec());
-                let rest = Box::new(LispList::append(new_args));
-                
-                return LispList::Cons(car.clone(), rest);
-            }
-        }
+impl std::fmt::Debug for EnvironmentRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Environment")
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Environment {
-    pub bindings: HashMap<String, LispOutput>,
-    pub parent_env: Option<Rc<RefCell<Environment>>>,
-}
-
-impl Environment {
-    pub fn new() -> Self {
-        Environment {
-            bindings: HashMap::new(),
-            parent_env: None,
-        }
+impl PartialEq for EnvironmentRef {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
     }
+}
 
-    pub fn build(
-        bindings: HashMap<String, LispOutput>, 
-        parent_env: Option<Rc<RefCell<Environment>>>) -> Self {
-            return Environment {
-                bindings,
-                parent_env,
-            }
-    }
+/// A list, whose tails are shared between the lists made from it, so that
+/// copying one copies only its first cell. Lists are walked, compared and
+/// dropped a cell at a time, since they may be far longer than the stack is
+/// deep. The cells alive are counted against the `max_cells` limit, so new
+/// ones are made with `LispList::cons`.
+pub enum LispList {
+    Cons(LispOutput, Rc<LispList>),
+    Nil,
+}
 
-    pub fn built_ins_env() -> Self {
-        return Self::build(
-            built_in_function_bindings(),
-            None,
-        );
-    }
+thread_local! {
+    /// Put in place of the tails taken while dropping a list.
+    static NIL: Rc<LispList> = Rc::new(LispList::Nil);
+}
 
-    pub fn global_env() -> Self {
-        return Self::build(
-            HashMap::new(),
-            Some(Rc::new(RefCell::new(Self::built_ins_env()))),
-        );
+impl Clone for LispList {
+    fn clone(&self) -> Self {
+        match self {
+            LispList::Cons(car, cdr) => LispList::cons(car.clone(), cdr.clone()),
+            LispList::Nil => LispList::Nil,
+        }
     }
+}
 
-    fn get(&self, var: &String) -> LispOutput {
-        
-        let val = self.bindings.get(var);
-
-        if val == None {
-            if self.parent_env == None {
-                panic!("variable not found in any environment");
+impl Drop for LispList {
+    fn drop(&mut self) {
+        let LispList::Cons(_, tail) = self else { return };
+        gc::list_cell_freed();
+        let mut next = std::mem::replace(tail, NIL.with(Rc::clone));
+        // a tail still shared with another list is left for that one to drop
+        while let Ok(mut list) = Rc::try_unwrap(next) {
+            match &mut list {
+                LispList::Cons(_, tail) => next = std::mem::replace(tail, NIL.with(Rc::clone)),
+                LispList::Nil => break,
             }
-            return self.parent_env.as_ref().unwrap().borrow().get(var);
         }
-        return val.unwrap().clone();
-    }
-
-    fn set(&mut self, var: &String, val: &LispOutput) {
-        self.bindings.insert(var.clone(), val.clone());
     }
+}
 
-    fn del(&mut self, var: &String) -> LispOutput {
-        if !self.bindings.contains_key(var) {
-            panic!("variable not found in environment!");
+impl PartialEq for LispList {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut left, mut right) = (self, other);
+        loop {
+            match (left, right) {
+                (LispList::Nil, LispList::Nil) => return true,
+                (LispList::Cons(left_car, left_cdr), LispList::Cons(right_car, right_cdr)) => {
+                    if left_car != right_car {
+                        return false;
+                    }
+                    if Rc::ptr_eq(left_cdr, right_cdr) {
+                        return true;
+                    }
+                    (left, right) = (left_cdr, right_cdr);
+                },
+                _ => return false,
+            }
         }
-        return self.bindings.remove(var).unwrap();
     }
+}
 
-    fn set_bang(&mut self, var: &String, val: LispOutput) -> LispOutput {
-        if self.bindings.contains_key(var) {
-            self.bindings.insert(var.clone(), val.clone());
-            return val;
+/// Written as it would be derived, `Cons(car, Cons(...))`.
+impl std::fmt::Debug for LispList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut cells = 0;
+        for item in self.iter() {
+            write!(f, "Cons({item:?}, ")?;
+            cells += 1;
         }
-
-        match &self.parent_env {
-            Some(env) => env.borrow_mut().set_bang(var, val),
-            None => panic!("variable does not exist in any environment!"),
+        write!(f, "Nil")?;
+        for _ in 0..cells {
+            write!(f, ")")?;
         }
+        return Ok(());
     }
 }
 
-fn check_arguments(args: &Vec<LispExpression>, number_of_args: usize) {
-    if args.len() != number_of_args {
-        panic!("special form was not supplied with correct number of arugments");
-    }
-}
-
-const REQUIRED_DEFINE_ARGUMENTS: usize = 3;
-const REQUIRED_LAMBDA_ARGUMENTS: usize = 3;
-const REQUIRED_IF_ARGUMENTS: usize = 4;
-const REQUIRED_DEL_ARGUMENTS: usize = 2;
-const REQUIRED_LET_ARGUMENTS: usize = 3;
-const REQUIRED_SET_BANG_ARGUMENTS: usize = 3;
-
-pub fn evaluate(tree: &LispExpression, env: &mut Rc<RefCell<Environment>>) -> LispOutput {
-    match tree {
-        LispExpression::Integer(num) => LispOutput::Integer(num.clone()),
-        LispExpression::Symbol(var) => env.borrow_mut().get(&var),
-        LispExpression::List(expressions) => {
-            if expressions.len() == 0 {
-                panic!("list of expression cannot be empty!");
-            }
-
-            if let LispExpression::Symbol(built_in) = &expressions[0] {
-                match &built_in[..] {
-                    "define" => {
-                        check_arguments(&expressions, REQUIRED_DEFINE_ARGUMENTS);
-                        let var = match &expressions[1] {
-                            LispExpression::Symbol(symbol) => symbol,
-                            _ => panic!("var must be LispExpression Symbol"),
-                        };
-        
-                        let val = evaluate(&expressions[2], env);
-
-                        env.borrow_mut().set(&var, &val);
-        
-                        return val;
-                    },
-                    "lambda" => {
-                        check_arguments(&expressions, REQUIRED_LAMBDA_ARGUMENTS);
-                        let parameters = &expressions[1];
-                        let body = &expressions[2];
-
-                        return LispOutput::Lambda(
-                            LispFunction::Function(
-                                Function::build(parameters.clone(), body.clone(), env.clone())
-                            )
-                        );
-                    },
-                    "if" => {
-                        check_arguments(&expressions, REQUIRED_IF_ARGUMENTS);
-                        let condition = &expressions[1];
-                        
-                        if evaluate(condition, env) == LispOutput::Bool(true) {
-                            let true_expr = &expressions[2];
-                            return evaluate(true_expr, env);
-                        } else {
-                            let false_expr = &expressions[3];
-                            return evaluate(false_expr, env);
-                        }
-                    },
-                    "and" => {
-                        for expr in &expressions[1..] {
-                            let clause_bool = evaluate(expr, env);
-                            if clause_bool == LispOutput::Bool(false) {
-                                return clause_bool;
-                            }
-                        }
-                        return LispOutput::Bool(true);
-                    },
-                    "or" => {
-                        for expr in &expressions[1..] {
-                            let clause_bool = evaluate(expr, env);
-                            if clause_bool == LispOutput::Bool(true) {
-                                return clause_bool;
-                            }
-                        }
-                        return LispOutput::Bool(false);
-                    },
-                    "del" => {
-                        check_arguments(&expressions, REQUIRED_DEL_ARGUMENTS);
-                        if let LispExpression::Symbol(symbol) = &expressions[1] {
-                            return env.borrow_mut().del(&symbol);
-                        }
-                        panic!("expecting a symbol when removing a binding!");
-                    },
-                    "let" => {
-                        check_arguments(&expressions, REQUIRED_LET_ARGUMENTS);
-
-                        let mut bindings = HashMap::new();
-
-                        if let LispExpression::List(definitions) = &expressions[1] {
-                            for def in definitions {
-                                if let LispExpression::List(binding) = &def {
-                                    let var = match &binding[0] {
-                                        LispExpression::Symbol(symbol) => symbol,
-                                        _ => panic!("expecting first element of binding to be symbol!"),
-                                    };
-                                    let expr = &binding[1];
-
-                                    bindings.insert(var.clone(), evaluate(expr, env));
-                                } else {
-                                    panic!("each binding should be a LispExpression List!");
-                                }
-                            }
-                        } else {
-                            panic!("expecting list of bindings");
-                        }
-
-                        let mut new_env = Rc::new(RefCell::new(Environment::build(
-                            bindings,
-                            Some(env.clone()),
-                        )));
-
-                        return evaluate(&expressions[2], &mut new_env);
-                    },
-                    "set!" => {
-                        check_arguments(&expressions, REQUIRED_SET_BANG_ARGUMENTS);
-                        let variable = match &expressions[1] {
-                            LispExpression::Symbol(variable) => variable,
-                            _ => panic!("expecting variable to be String type!"),
-                        };
-                        let value = evaluate(&expressions[2], env);
-                        return env.borrow_mut().set_bang(variable, value);
-                    },
-                    _ => {},
-                }
-            }
-
-            let mut expr_iterator = expressions.iter();
-            let function = match evaluate(
-                expr_iterator.next().unwrap(), 
-                env) {
-                    LispOutput::Lambda(output) => output,
-                    _ => panic!("expected function for first expression of list"),
-            };
-            let args = expr_iterator.map(|expr| evaluate(expr, env)).collect();
-            return function.call(args);
-        },
-    }
-}
-
-
-// ============== TESTS ===============
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn create_empty_environment() -> Rc<RefCell<Environment>> {
-        return Rc::new(RefCell::new(Environment::new()));
-    }
-
-    fn create_global_environment() -> Rc<RefCell<Environment>> {
-        return Rc::new(RefCell::new(Environment::global_env()));
-    }
-
-    #[test]
-    fn single_integer() {
-        let lisp_integer = LispExpression::Integer(1);
-        let mut env = create_empty_environment();
-
-        let expected = LispOutput::Integer(1);
-        let result = evaluate(&lisp_integer, &mut env);
-        
-        assert_eq!(expected, result);
-    }
-
-    #[test]
-    fn simple_defintion() {
-        let mut env = create_empty_environment();
-
-        let lisp_definition = LispExpression::List(vec![
-            LispExpression::Symbol("define".to_string()),
-            LispExpression::Symbol("x".to_string()),
-            LispExpression::Integer(2),
-        ]);
-
-        let expected = LispOutput::Integer(2);
-        let defintion_result = evaluate(&lisp_definition, &mut env);
-
-        assert_eq!(expected, defintion_result);
-
-        let lisp_x = LispExpression::Symbol("x".to_string());
-        let x_result = evaluate(&lisp_x, &mut env);
-
-        assert_eq!(expected, x_result);
-    }
-
-    #[test]
-    #[should_panic]
-    fn variable_not_found() {
-        let mut env = create_empty_environment();
-        let nonexistent_variable = LispExpression::Symbol("x".to_string());
-        evaluate(&nonexistent_variable, &mut env);
-    }
-
-    #[test]
-    fn simple_lambda() {
-        let mut env = create_global_environment();
-        let add_one = LispExpression::List(vec![
-            LispExpression::Symbol("define".to_string()),
-            LispExpression::Symbol("add_one".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("lambda".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("x".to_string()),
-                ]),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("+".to_string()),
-                    LispExpression::Symbol("x".to_string()),
-                    LispExpression::Integer(1),
-                ]),
-            ]),
-        ]);
-
-        evaluate(&add_one, &mut env);
-
-        let two_plus_one = LispExpression::List(vec![
-            LispExpression::Symbol("add_one".to_string()),
-            LispExpression::Integer(2),
-        ]);
-
-        let result = evaluate(&two_plus_one, &mut env);
-        let expected = LispOutput::Integer(3);
-
-        assert_eq!(expected, result);
-    }
-
-    #[test]
-    fn simple_if_statement() {
-        let mut env = create_global_environment();
-        let always_true_expression = LispExpression::List(vec![
-            LispExpression::Symbol("if".to_string()),
-            LispExpression::Symbol("#t".to_string()),
-            LispExpression::Integer(1),
-            LispExpression::Integer(0),
-        ]);
-
-        let always_false_expression = LispExpression::List(vec![
-            LispExpression::Symbol("if".to_string()),
-            LispExpression::Symbol("#f".to_string()),
-            LispExpression::Integer(1),
-            LispExpression::Integer(0),
-        ]);
-
-        let true_result = evaluate(&always_true_expression, &mut env);
-        let false_result = evaluate(&always_false_expression, &mut env);
-
-        assert_eq!(LispOutput::Integer(1), true_result);
-        assert_eq!(LispOutput::Integer(0), false_result);
-    }
-
-    #[test]
-    fn simple_and_statement() {
-        let mut env = create_global_environment();
-        let single_true_expression = LispExpression::List(vec![
-            LispExpression::Symbol("and".to_string()),
-            LispExpression::Symbol("#t".to_string()),
-        ]);
-
-        let single_false_expression = LispExpression::List(vec![
-            LispExpression::Symbol("and".to_string()),
-            LispExpression::Symbol("#f".to_string()),
-        ]);
-
-        let nested_and_expression = LispExpression::List(vec![
-            LispExpression::Symbol("and".to_string()),
-            LispExpression::Symbol("#t".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("equal?".to_string()),
-                LispExpression::Integer(10),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("+".to_string()),
-                    LispExpression::Integer(1),
-                    LispExpression::Integer(2),
-                    LispExpression::Integer(3),
-                    LispExpression::Integer(4),
-                ]),
-            ]),
-        ]);
-
-        let true_result = evaluate(&single_true_expression, &mut env);
-        let false_result = evaluate(&single_false_expression, &mut env);
-        let nested_result = evaluate(&nested_and_expression, &mut env);
-
-        assert_eq!(LispOutput::Bool(true), true_result);
-        assert_eq!(LispOutput::Bool(false), false_result);
-        assert_eq!(LispOutput::Bool(true), nested_result);
-    }
-
-    #[test]
-    fn short_circuiting_and() {
-        let mut env = create_global_environment();
-        let nested_and_expression = LispExpression::List(vec![
-            LispExpression::Symbol("and".to_string()),
-            LispExpression::Symbol("#f".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("define".to_string()),
-                LispExpression::Symbol("add_one".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("lambda".to_string()),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("x".to_string()),
-                    ]),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("+".to_string()),
-                        LispExpression::Symbol("x".to_string()),
-                        LispExpression::Integer(1),
-                    ]),
-                ]),
-            ]),
-        ]);
-
-        let nested_result = evaluate(&nested_and_expression, &mut env);
-
-        // add_one function should not be defined, since it is expected that
-        // the and short circuiting occurred at the first true expression
-        let borrowed_env = env.borrow();
-        let add_one_func = borrowed_env.bindings.get("add_one");
-        
-        match add_one_func {
-            Some(_) => panic!("function should not be defined!"),
-            None => {},
-        };
-
-        assert_eq!(LispOutput::Bool(false), nested_result);
+impl LispList {
+    pub fn cons(car: LispOutput, cdr: Rc<LispList>) -> Self {
+        gc::list_cell_made();
+        return LispList::Cons(car, cdr);
     }
 
-    #[test]
-    fn non_short_circuiting_and() {
-        let mut env = create_global_environment();
-        let nested_and_expression = LispExpression::List(vec![
-            LispExpression::Symbol("and".to_string()),
-            LispExpression::Symbol("#t".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("define".to_string()),
-                LispExpression::Symbol("add_one".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("lambda".to_string()),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("x".to_string()),
-                    ]),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("+".to_string()),
-                        LispExpression::Symbol("x".to_string()),
-                        LispExpression::Integer(1),
-                    ]),
-                ]),
-            ]),
-            LispExpression::List(vec![
-                LispExpression::Symbol("equal?".to_string()),
-                LispExpression::Integer(10),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("+".to_string()),
-                    LispExpression::Integer(1),
-                    LispExpression::Integer(2),
-                    LispExpression::Integer(3),
-                    LispExpression::Integer(4),
-                ]),
-            ]),
-        ]);
-
-        let nested_result = evaluate(&nested_and_expression, &mut env);
-
-        // add_one function should not be defined, since it is expected that
-        // the and short circuiting occurred at the first true expression
-        let borrowed_env = env.borrow();
-        let add_one_func = borrowed_env.bindings.get("add_one");
-        
-        match add_one_func {
-            Some(_) => {},
-            None => { panic!("function should not be defined!") },
-        };
-
-        assert_eq!(LispOutput::Bool(true), nested_result);
+    pub fn build(args: impl Iterator<Item=LispOutput>) -> Self {
+        let items: Vec<LispOutput> = args.collect();
+        return items.into_iter().rev().fold(LispList::Nil, |tail, item| LispList::cons(item, Rc::new(tail)));
     }
 
-    #[test]
-    fn short_circuiting_or() {
-        let mut env = create_global_environment();
-        let nested_and_expression = LispExpression::List(vec![
-            LispExpression::Symbol("or".to_string()),
-            LispExpression::Symbol("#t".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("define".to_string()),
-                LispExpression::Symbol("add_one".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("lambda".to_string()),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("x".to_string()),
-                    ]),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("+".to_string()),
-                        LispExpression::Symbol("x".to_string()),
-                        LispExpression::Integer(1),
-                    ]),
-                ]),
-            ]),
-        ]);
-
-        let nested_result = evaluate(&nested_and_expression, &mut env);
-
-        // add_one function should not be defined, since it is expected that
-        // the or short circuiting occurred at the first true expression
-        let borrowed_env = env.borrow();
-        let add_one_func = borrowed_env.bindings.get("add_one");
-        
-        match add_one_func {
-            Some(_) => panic!("function should not be defined!"),
-            None => {},
+    pub fn get_car(&self) -> LispResult {
+        match self {
+            LispList::Cons(car, _) => Ok(car.clone()),
+            LispList::Nil => Err(LispError::type_mismatch("lisp list is empty!")),
         }
-
-        assert_eq!(LispOutput::Bool(true), nested_result);
-    }
-
-    #[test]
-    fn empty_list() {
-        let mut env = create_global_environment();
-        let emtpy_list_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list".to_string()),
-        ]);
-
-        let expected = LispOutput::List(Box::new(LispList::Nil));
-        let result = evaluate(&emtpy_list_expression, &mut env);
-
-        assert_eq!(expected, result);
-    }
-
-    #[test]
-    fn single_element_list() {
-        let mut env = create_global_environment();
-        let list_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list".to_string()),
-            LispExpression::Integer(3),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(3),
-                    Box::new(LispList::Nil)
-                )
-            )
-        );
-
-        let result = evaluate(&list_expression, &mut env);
-
-        assert_eq!(expected, result);
     }
 
-    #[test]
-    fn multiple_element_list() {
-        let mut env = create_global_environment();
-        let list_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list".to_string()),
-            LispExpression::Integer(1),
-            LispExpression::Integer(2),
-            LispExpression::Integer(3),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(1),
-                    Box::new(
-                        LispList::Cons(
-                            LispOutput::Integer(2),
-                            Box::new(
-                                LispList::Cons(
-                                    LispOutput::Integer(3),
-                                    Box::new(LispList::Nil)
-                                )
-                            )
-                        )
-                    )
-                )
-            )
-        );
-
-        let result = evaluate(&list_expression, &mut env);
-        assert_eq!(expected, result);
-
-        let get_car_expression = LispExpression::List(vec![
-            LispExpression::Symbol("car".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-            ]),
-        ]);
-
-        let expected = LispOutput::Integer(1);
-        let result = evaluate(&get_car_expression, &mut env);
-        assert_eq!(expected, result);
-
-
-        let get_cdr_expression = LispExpression::List(vec![
-            LispExpression::Symbol("cdr".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-            ]),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(2),
-                    Box::new(
-                        LispList::Cons(
-                            LispOutput::Integer(3),
-                            Box::new(LispList::Nil)
-                        )
-                    )
-                )
-            )
-        );
-
-        let result = evaluate(&get_cdr_expression, &mut env);
-        assert_eq!(expected, result);
-    }
-
-    #[test]
-    fn is_list() {
-        let mut env = create_global_environment();
-        let list_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list?".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-            ]),
-        ]);
-        let list_expected = LispOutput::Bool(true);
-        
-        let function_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list?".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("define".to_string()),
-                LispExpression::Symbol("add_one".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("lambda".to_string()),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("x".to_string()),
-                    ]),
-                    LispExpression::List(vec![
-                        LispExpression::Symbol("+".to_string()),
-                        LispExpression::Symbol("x".to_string()),
-                        LispExpression::Integer(1),
-                    ]),
-                ]),
-            ]), 
-        ]);
-        let function_expected = LispOutput::Bool(false);
-                        
-        let integer_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list?".to_string()),
-            LispExpression::Integer(3),
-        ]);
-        let integer_expected = LispOutput::Bool(false);
-
-        let bool_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list?".to_string()),
-            LispExpression::Symbol("#t".to_string()),
-        ]);
-        let bool_expected = LispOutput::Bool(false);
-
-        assert_eq!(list_expected, evaluate(&list_expression, &mut env));
-        assert_eq!(function_expected, evaluate(&function_expression, &mut env));
-        assert_eq!(integer_expected, evaluate(&integer_expression, &mut env));
-        assert_eq!(bool_expected, evaluate(&bool_expression, &mut env));
-    }
-
-    #[test]
-    fn empty_list_length() {
-        let mut env = create_global_environment();
-        let empty_list_length_expression = LispExpression::List(vec![
-            LispExpression::Symbol("length".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-        ]);
-        
-        let expected = LispOutput::Integer(0);
-
-        assert_eq!(expected, evaluate(&empty_list_length_expression, &mut env));
-    }
-
-    #[test]
-    fn single_element_list_length() {
-        let mut env = create_global_environment();
-        let list_length_expression = LispExpression::List(vec![
-            LispExpression::Symbol("length".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(3),
-            ]),
-        ]);
-        
-        let expected = LispOutput::Integer(1);
-
-        assert_eq!(expected, evaluate(&list_length_expression, &mut env));
-    }
-
-    #[test]
-    fn multi_element_list_length() {
-        let mut env = create_global_environment();
-        let list_length_expression = LispExpression::List(vec![
-            LispExpression::Symbol("length".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Symbol("#t".to_string()),
-                LispExpression::Symbol("nil".to_string()),
-                LispExpression::Integer(4),
-                LispExpression::Integer(5),
-            ]),
-        ]);
-        
-        let expected = LispOutput::Integer(5);
-
-        assert_eq!(expected, evaluate(&list_length_expression, &mut env));
-    }
-
-    #[test]
-    fn indexing_into_single_element_list() {
-        let mut env = create_global_environment();
-        let list_ref_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list-ref".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-            ]),
-            LispExpression::Integer(0),
-        ]);
-
-        let expected = LispOutput::Integer(1);
-        let result = evaluate(&list_ref_expression, &mut env);
-
-        assert_eq!(expected, result);
-    }
-
-    #[test]
-    fn indexing_into_multi_element_list() {
-        let mut env = create_global_environment();
-        let list_ref_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list-ref".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-                LispExpression::Integer(4),
-                LispExpression::Integer(5),
-            ]),
-            LispExpression::Integer(3),
-        ]);
-
-        let expected = LispOutput::Integer(4);
-        let result = evaluate(&list_ref_expression, &mut env);
-
-        assert_eq!(expected, result);
+    pub fn get_cdr(&self) -> LispResult {
+        match self {
+            LispList::Cons(_, cdr) => Ok(LispOutput::List(Box::new(cdr.as_ref().clone()))),
+            LispList::Nil => Err(LispError::type_mismatch("lisp list is empty!")),
+        }
     }
 
-    #[test]
-    #[should_panic]
-    fn indexing_into_empty_list() {
-        let mut env = create_global_environment();
-        let list_ref_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list-ref".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Integer(0),
-        ]);
-
-        evaluate(&list_ref_expression, &mut env);
+    /// The elements, first to last.
+    pub fn iter(&self) -> impl Iterator<Item = &LispOutput> {
+        let mut list = self;
+        return std::iter::from_fn(move || match list {
+            LispList::Cons(car, cdr) => {
+                list = cdr;
+                Some(car)
+            },
+            LispList::Nil => None,
+        });
     }
 
-    #[test]
-    #[should_panic]
-    fn indexing_out_of_bounds_non_empty_list() {
-        let mut env = create_global_environment();
-        let list_ref_expression = LispExpression::List(vec![
-            LispExpression::Symbol("list-ref".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-            ]),
-            LispExpression::Integer(5),
-        ]);
-
-        evaluate(&list_ref_expression, &mut env);
+    pub fn to_vec(&self) -> Vec<LispOutput> {
+        return self.iter().cloned().collect();
     }
 
-    #[test]
-    fn appending_no_list() {
-        let mut env = create_global_environment();
-        let append_empty_expression = LispExpression::List(vec![
-            LispExpression::Symbol("append".to_string()),
-        ]);
-
-        let expected = LispOutput::List(Box::new(LispList::Nil));
-        let result = evaluate(&append_empty_expression, &mut env);
-
-        assert_eq!(expected, result);
+    pub fn length(&self) -> LispOutput {
+        return LispOutput::Integer(self.iter().count() as i64);
     }
 
-    #[test]
-    fn appending_single_empty_list() {
-        let mut env = create_global_environment();
-        let append_empty_expression = LispExpression::List(vec![
-            LispExpression::Symbol("append".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-        ]);
-
-        let expected = LispOutput::List(Box::new(LispList::Nil));
-        let result = evaluate(&append_empty_expression, &mut env);
-
-        assert_eq!(expected, result);
+    pub fn get(&self, index: i64) -> LispResult {
+        let item = usize::try_from(index).ok().and_then(|index| self.iter().nth(index));
+        return item.cloned().ok_or_else(|| LispError::new(LispErrorKind::IndexOutOfBounds, "index out of bounds!"));
     }
 
-    #[test]
-    fn appending_single_non_empty_list() {
-        let mut env = create_global_environment();
-        let append_empty_expression = LispExpression::List(vec![
-            LispExpression::Symbol("append".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-            ]),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(1),
-                    Box::new(
-                        LispList::Cons(
-                            LispOutput::Integer(2),
-                            Box::new(
-                                LispList::Cons(
-                                    LispOutput::Integer(3),
-                                    Box::new(LispList::Nil)
-                                )
-                            )
-                        )
-                    )
-                )
-            )
-        );
-
-        let result = evaluate(&append_empty_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// The elements of `lists` in one list, which shares the last of them.
+    pub fn append(lists: Vec<LispList>) -> LispList {
+        let mut lists = lists.into_iter().rev();
+        let Some(mut appended) = lists.next() else { return LispList::Nil };
+        for list in lists {
+            for item in list.to_vec().into_iter().rev() {
+                appended = LispList::cons(item, Rc::new(appended));
+            }
+        }
+        return appended;
     }
+}
 
-    #[test]
-    fn appending_non_empty_list_with_empty_lists() {
-        let mut env = create_global_environment();
-        let append_empty_expression = LispExpression::List(vec![
-            LispExpression::Symbol("append".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-            ]),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(1),
-                    Box::new(
-                        LispList::Cons(
-                            LispOutput::Integer(2),
-                            Box::new(
-                                LispList::Cons(
-                                    LispOutput::Integer(3),
-                                    Box::new(LispList::Nil)
-                                )
-                            )
-                        )
-                    )
-                )
-            )
-        );
-
-        let result = evaluate(&append_empty_expression, &mut env);
+/// A frame of variables. Frames created by `lambda` and the `let` forms keep
+/// their variables in slots, found by the lexical addresses of `resolve`; the
+/// global table and frames made by `make-environment` keep them by name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Environment {
+    /// Variables by name, including definitions that have no slot.
+    pub bindings: HashMap<String, LispOutput>,
+    names: Slots,
+    /// The value of each of `names`, or `None` while it is unbound.
+    slots: Vec<Option<LispOutput>>,
+    pub parent_env: Option<Rc<RefCell<Environment>>>,
+}
 
-        assert_eq!(expected, result);
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            bindings: HashMap::new(),
+            names: Rc::new([]),
+            slots: Vec::new(),
+            parent_env: None,
+        }
     }
 
-    #[test]
-    fn appending_two_non_empty_lists() {
-        let mut env = create_global_environment();
-        let append_empty_expression = LispExpression::List(vec![
-            LispExpression::Symbol("append".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-            ]),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(4),
-                LispExpression::Integer(5),
-            ]),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(1),
-                    Box::new(
-                        LispList::Cons(
-                            LispOutput::Integer(2),
-                            Box::new(
-                                LispList::Cons(
-                                    LispOutput::Integer(3),
-                                    Box::new(
-                                        LispList::Cons(
-                                            LispOutput::Integer(4),
-                                            Box::new(
-                                                LispList::Cons(
-                                                    LispOutput::Integer(5),
-                                                    Box::new(LispList::Nil)
-                                                )
-                                            )
-                                        )
-                                    )
-                                )
-                            )
-                        )
-                    )
-                )
-            )
-        );
-
-        let result = evaluate(&append_empty_expression, &mut env);
-
-        assert_eq!(expected, result);
+    pub fn build(
+        bindings: HashMap<String, LispOutput>, 
+        parent_env: Option<Rc<RefCell<Environment>>>) -> Self {
+            return Environment {
+                bindings,
+                names: Rc::new([]),
+                slots: Vec::new(),
+                parent_env,
+            }
     }
 
-    #[test]
-    fn appending_multiple_non_empty_lists_and_empty_lists() {
-        let mut env = create_global_environment();
-        let append_empty_expression = LispExpression::List(vec![
-            LispExpression::Symbol("append".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-            ]),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(3),
-            ]),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(4),
-                LispExpression::Integer(5),
-            ]),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(1),
-                    Box::new(
-                        LispList::Cons(
-                            LispOutput::Integer(2),
-                            Box::new(
-                                LispList::Cons(
-                                    LispOutput::Integer(3),
-                                    Box::new(
-                                        LispList::Cons(
-                                            LispOutput::Integer(4),
-                                            Box::new(
-                                                LispList::Cons(
-                                                    LispOutput::Integer(5),
-                                                    Box::new(LispList::Nil)
-                                                )
-                                            )
-                                        )
-                                    )
-                                )
-                            )
-                        )
-                    )
-                )
-            )
-        );
-
-        let result = evaluate(&append_empty_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// A frame with a slot for each of `names`, filled from `values` in order.
+    pub fn frame(
+        names: Slots,
+        values: impl IntoIterator<Item = LispOutput>,
+        parent_env: &Rc<RefCell<Environment>>,
+    ) -> Self {
+        let mut slots: Vec<Option<LispOutput>> = values.into_iter().map(Some).collect();
+        slots.resize(names.len(), None);
+        return Environment {
+            bindings: HashMap::new(),
+            names,
+            slots,
+            parent_env: Some(parent_env.clone()),
+        };
     }
 
-    #[test]
-    #[should_panic]
-    fn map_on_non_list() {
-        let mut env = create_global_environment();
-        let map_expression = LispExpression::List(vec![
-            LispExpression::Symbol("map".to_string()),
-            LispExpression::Integer(1),
-            LispExpression::Symbol("+".to_string()),
-        ]);
-
-        evaluate(&map_expression, &mut env);
+    /// The frame of the built-in procedures, both those implemented in Rust
+    /// and those of the `prelude`.
+    pub fn built_ins_env() -> Rc<RefCell<Environment>> {
+        return Self::built_ins_env_with(Capabilities::all());
     }
 
-    #[test]
-    fn map_on_empty_list() {
-        let mut env = create_global_environment();
-        let map_expression = LispExpression::List(vec![
-            LispExpression::Symbol("map".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("+".to_string()),
-        ]);
-
-        let expected = LispOutput::List(Box::new(LispList::Nil));
-        let result = evaluate(&map_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// The frame of the built-in procedures, where those of capabilities
+    /// missing from `capabilities` fail with a `CapabilityDenied` error.
+    pub fn built_ins_env_with(capabilities: Capabilities) -> Rc<RefCell<Environment>> {
+        // the procedures of the prelude refer back to this frame, so it is
+        // left to the collector
+        let env = gc::allocate(Self::build(built_in_function_bindings(capabilities), None));
+        prelude::evaluate_into(&env);
+        return env;
     }
 
-    #[test]
-    fn map_on_single_element_list() {
-        let mut env = create_global_environment();
-        let map_expression = LispExpression::List(vec![
-            LispExpression::Symbol("map".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(3),
-            ]),
-            LispExpression::Symbol("-".to_string()),
-        ]);
-
-        let expected = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(-3),
-                    Box::new(
-                        LispList::Nil,
-                    )
-                )
-            )
-        );
-        let result = evaluate(&map_expression, &mut env);
-
-        assert_eq!(expected, result);
+    pub fn global_env() -> Self {
+        return Self::global_env_with(Capabilities::all());
     }
 
-    #[test]
-    #[should_panic]
-    fn filter_on_non_list() {
-        let mut env = create_global_environment();
-        let filter_expression = LispExpression::List(vec![
-            LispExpression::Symbol("filter".to_string()),
-            LispExpression::Integer(1),
-            LispExpression::Symbol("+".to_string()),
-        ]);
-
-        evaluate(&filter_expression, &mut env);
+    /// An empty frame below the built-ins of `capabilities`.
+    pub fn global_env_with(capabilities: Capabilities) -> Self {
+        return Self::build(HashMap::new(), Some(Self::built_ins_env_with(capabilities)));
     }
 
-    #[test]
-    fn filter_on_empty_list() {
-        let mut env = create_global_environment();
-        let filter_expression = LispExpression::List(vec![
-            LispExpression::Symbol("filter".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("+".to_string()),
-        ]);
-
-        let expected = LispOutput::List(Box::new(LispList::Nil));
-        let result = evaluate(&filter_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// The frame user definitions live in: the outermost frame below the
+    /// built-ins.
+    pub fn toplevel(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut current = env.clone();
+        loop {
+            let parent = match &current.borrow().parent_env {
+                Some(parent) if parent.borrow().parent_env.is_some() => parent.clone(),
+                _ => return current.clone(),
+            };
+            current = parent;
+        }
     }
 
-    #[test]
-    fn filter_on_single_element_list() {
-        let mut env = create_global_environment();
-
-        let greater_than_one_func = LispExpression::List(vec![
-            LispExpression::Symbol("define".to_string()),
-            LispExpression::Symbol("greater_than_one".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("lambda".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("x".to_string()),
-                ]),
-                LispExpression::List(vec![
-                    LispExpression::Symbol(">".to_string()),
-                    LispExpression::Symbol("x".to_string()),
-                    LispExpression::Integer(1),
-                ])
-            ])
-        ]);
-
-        evaluate(&greater_than_one_func, &mut env);
-
-        let filter_expression_false = LispExpression::List(vec![
-            LispExpression::Symbol("filter".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(0),
-            ]),
-            LispExpression::Symbol("greater_than_one".to_string()),
-        ]);
-
-        let filter_expression_true = LispExpression::List(vec![
-            LispExpression::Symbol("filter".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(3),
-            ]),
-            LispExpression::Symbol("greater_than_one".to_string()),
-        ]);
-
-        let expected_filter_false = LispOutput::List(Box::new(LispList::Nil));
-        let expected_filter_true = LispOutput::List(
-            Box::new(
-                LispList::Cons(
-                    LispOutput::Integer(3),
-                    Box::new(
-                        LispList::Nil,
-                    )
-                )
-            )
-        );
-
-        let result_false = evaluate(&filter_expression_false, &mut env);
-        let result_true = evaluate(&filter_expression_true, &mut env);
-
-        assert_eq!(expected_filter_false, result_false);
-        assert_eq!(expected_filter_true, result_true);
+    fn slot_index(&self, var: &str) -> Option<usize> {
+        return self.names.iter().position(|name| name == var);
     }
 
-    #[test]
-    #[should_panic]
-    fn reduce_on_non_list() {
-        let mut env = create_global_environment();
-        let reduce_expression = LispExpression::List(vec![
-            LispExpression::Symbol("reduce".to_string()),
-            LispExpression::Integer(1),
-            LispExpression::Symbol("+".to_string()),
-            LispExpression::Integer(1),
-        ]);
-
-        evaluate(&reduce_expression, &mut env);
+    /// The value bound to `var` in this frame alone.
+    fn local(&self, var: &str) -> Option<&LispOutput> {
+        match self.slot_index(var) {
+            Some(index) if self.slots[index].is_some() => self.slots[index].as_ref(),
+            _ => self.bindings.get(var),
+        }
     }
 
-    #[test]
-    fn reduce_on_empty_list() {
-        let mut env = create_global_environment();
-        let reduce_expression = LispExpression::List(vec![
-            LispExpression::Symbol("reduce".to_string()),
-            LispExpression::Symbol("nil".to_string()),
-            LispExpression::Symbol("+".to_string()),
-            LispExpression::Integer(0),
-        ]);
-
-        let expected = LispOutput::Integer(0);
-        let result = evaluate(&reduce_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// The values bound in this frame alone.
+    pub fn values(&self) -> impl Iterator<Item = &LispOutput> {
+        return self.bindings.values().chain(self.slots.iter().flatten());
     }
 
-    #[test]
-    fn reduce_on_single_element_list() {
-        let mut env = create_global_environment();
-
-        let reduce_expression = LispExpression::List(vec![
-            LispExpression::Symbol("reduce".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-            ]),
-            LispExpression::Symbol("+".to_string()),
-            LispExpression::Integer(0),
-        ]);
-
-        let expected = LispOutput::Integer(1);
-
-        let result = evaluate(&reduce_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// The variables bound in this frame alone: those in slots in the order
+    /// of the slots, then the others by name.
+    pub fn local_bindings(&self) -> Vec<(String, LispOutput)> {
+        let mut bindings: Vec<(String, LispOutput)> = self.names.iter().zip(&self.slots)
+            .filter_map(|(name, value)| Some((name.clone(), value.clone()?)))
+            .collect();
+        let mut named: Vec<(String, LispOutput)> = self.bindings.iter()
+            .filter(|(name, _)| !bindings.iter().any(|(slot_name, _)| slot_name == *name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        named.sort_by(|(a, _), (b, _)| a.cmp(b));
+        bindings.extend(named);
+        return bindings;
     }
 
-    #[test]
-    fn reduce_on_multi_element_list() {
-        let mut env = create_global_environment();
-
-        let reduce_expression = LispExpression::List(vec![
-            LispExpression::Symbol("reduce".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("list".to_string()),
-                LispExpression::Integer(1),
-                LispExpression::Integer(2),
-                LispExpression::Integer(3),
-                LispExpression::Integer(4),
-                LispExpression::Integer(5),
-            ]),
-            LispExpression::Symbol("+".to_string()),
-            LispExpression::Integer(0),
-        ]);
-
-        let expected = LispOutput::Integer(15);
-
-        let result = evaluate(&reduce_expression, &mut env);
-
-        assert_eq!(expected, result);
+    fn parent(&self) -> &Rc<RefCell<Environment>> {
+        return self.parent_env.as_ref().expect("lexical address points past the outermost frame");
     }
 
-    #[test]
-    #[should_panic]
-    fn begin_empty_arguments() {
-        let mut env = create_global_environment();
-
-        let begin_expression = LispExpression::List(vec![
-            LispExpression::Symbol("begin".to_string()),
-        ]);
-        
-        evaluate(&begin_expression, &mut env);
+    pub fn is_bound(&self, var: &str) -> bool {
+        if self.local(var).is_some() {
+            return true;
+        }
+        match &self.parent_env {
+            Some(parent) => parent.borrow().is_bound(var),
+            None => false,
+        }
     }
 
-    #[test]
-    fn begin_single_argument() {
-        let mut env = create_global_environment();
-
-        let begin_expression = LispExpression::List(vec![
-            LispExpression::Symbol("begin".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("define".to_string()),
-                LispExpression::Symbol("x".to_string()),
-                LispExpression::Integer(2),
-            ]),
-            LispExpression::Symbol("x".to_string()),
-        ]);
-
-        let expected = LispOutput::Integer(2);
-
-        let result = evaluate(&begin_expression, &mut env);
-
-        assert_eq!(expected, result);
+    pub fn get(&self, var: &str) -> LispResult {
+        match self.local(var) {
+            Some(val) => Ok(val.clone()),
+            None => match &self.parent_env {
+                Some(parent) => parent.borrow().get(var),
+                None => Err(LispError::unbound_variable(var)),
+            },
+        }
     }
 
-    #[test]
-    fn begin_multiple_arguments() {
-        let mut env = create_global_environment();
-
-        let add_one_func = LispExpression::List(vec![
-            LispExpression::Symbol("define".to_string()),
-            LispExpression::Symbol("add_one".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("lambda".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("x".to_string()),
-                ]),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("+".to_string()),
-                    LispExpression::Symbol("x".to_string()),
-                    LispExpression::Integer(1),
-                ])
-            ])
-        ]);
-
-        // x = 2
-        // y = x + 3  - should evaluate to 5
-        // add_one(y) - should evaluate to 6
-        let begin_expression = LispExpression::List(vec![
-            LispExpression::Symbol("begin".to_string()),
-            LispExpression::List(vec![
-                LispExpression::Symbol("define".to_string()),
-                LispExpression::Symbol("x".to_string()),
-                LispExpression::Integer(2),
-            ]),
-            LispExpression::List(vec![
-                LispExpression::Symbol("define".to_string()),
-                LispExpression::Symbol("y".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("+".to_string()),
-                    LispExpression::Symbol("x".to_string()),
-                    LispExpression::Integer(3),
-                ]),
-            ]),
-            LispExpression::List(vec![
-                add_one_func,
-                LispExpression::Symbol("y".to_string()),
-            ])
-        ]);
-
-        let expected = LispOutput::Integer(6);
-
-        let result = evaluate(&begin_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// Looks `var` up the way `resolve` said to.
+    pub fn lookup(&self, var: &str, reference: Reference) -> LispResult {
+        match reference {
+            Reference::Local { depth: 0, index } => match &self.slots[index] {
+                Some(val) => Ok(val.clone()),
+                None => self.get(var),
+            },
+            Reference::Dynamic | Reference::Free { depth: 0 } => self.get(var),
+            Reference::Local { depth, index } => {
+                self.parent().borrow().lookup(var, Reference::Local { depth: depth - 1, index })
+            },
+            Reference::Free { depth } => self.parent().borrow().lookup(var, Reference::Free { depth: depth - 1 }),
+        }
     }
 
-    #[test]
-    #[should_panic]
-    fn del_non_existent_object() {
-        let mut env = create_global_environment();
-
-        let del_expression = LispExpression::List(vec![
-            LispExpression::Symbol("del".to_string()),
-            LispExpression::Symbol("add_one".to_string()),
-        ]);
-
-        evaluate(&del_expression, &mut env);
+    pub fn set(&mut self, var: &str, val: &LispOutput) {
+        match self.slot_index(var) {
+            Some(index) => self.slots[index] = Some(val.clone()),
+            None => {
+                self.bindings.insert(var.to_string(), val.clone());
+            },
+        }
     }
 
-    #[test]
-    fn del_variable_definition() {
-        let mut env = create_global_environment();
-
-        let define_var = LispExpression::List(vec![
-            LispExpression::Symbol("define".to_string()),
-            LispExpression::Symbol("x".to_string()),
-            LispExpression::Integer(2),
-        ]);
-
-        evaluate(&define_var, &mut env);
-
-        let del_expression = LispExpression::List(vec![
-            LispExpression::Symbol("del".to_string()),
-            LispExpression::Symbol("x".to_string()),
-        ]);
-
-        let expected = LispOutput::Integer(2);
-        let result = evaluate(&del_expression, &mut env);
-        
-        assert_eq!(expected, result);
+    pub fn set_slot(&mut self, index: usize, val: LispOutput) {
+        self.slots[index] = Some(val);
     }
 
-    #[test]
-    #[should_panic]
-    fn del_variable_definition_twice() {
-        let mut env = create_global_environment();
-
-        let define_var = LispExpression::List(vec![
-            LispExpression::Symbol("define".to_string()),
-            LispExpression::Symbol("x".to_string()),
-            LispExpression::Integer(2),
-        ]);
-
-        evaluate(&define_var, &mut env);
-
-        let del_expression = LispExpression::List(vec![
-            LispExpression::Symbol("del".to_string()),
-            LispExpression::Symbol("x".to_string()),
-        ]);
-
-        let expected = LispOutput::Integer(2);
-        let result = evaluate(&del_expression, &mut env);
-        
-        assert_eq!(expected, result);
-
-        evaluate(&del_expression, &mut env);
+    pub fn del(&mut self, var: &str) -> LispResult {
+        let removed = match self.slot_index(var) {
+            Some(index) if self.slots[index].is_some() => self.slots[index].take(),
+            _ => self.bindings.remove(var),
+        };
+        match removed {
+            Some(val) => Ok(val),
+            None => Err(LispError::new(
+                LispErrorKind::UnboundVariable,
+                format!("variable not found in environment: {var}"),
+            )),
+        }
     }
 
-    #[test]
-    fn let_simple_variable_definition() {
-        let mut env = create_global_environment();
-
-        let let_expression = LispExpression::List(vec![
-            LispExpression::Symbol("let".to_string()),
-            LispExpression::List(vec![
-                LispExpression::List(vec![
-                    LispExpression::Symbol("x".to_string()),
-                    LispExpression::Integer(2),
-                ])
-            ]),
-            LispExpression::Symbol("x".to_string()),
-        ]);
-
-        let expected = LispOutput::Integer(2);
-        let result = evaluate(&let_expression, &mut env);
+    pub fn set_bang(&mut self, var: &str, val: LispOutput) -> LispResult {
+        if self.local(var).is_some() {
+            self.set(var, &val);
+            return Ok(val);
+        }
 
-        assert_eq!(expected, result);
+        match &self.parent_env {
+            Some(env) => env.borrow_mut().set_bang(var, val),
+            None => Err(LispError::new(
+                LispErrorKind::UnboundVariable,
+                format!("variable does not exist in any environment: {var}"),
+            )),
+        }
     }
 
-    #[test]
-    fn let_binary_operations() {
-        let mut env = create_global_environment();
-
-        let let_expression = LispExpression::List(vec![
-            LispExpression::Symbol("let".to_string()),
-            LispExpression::List(vec![
-                LispExpression::List(vec![
-                    LispExpression::Symbol("x".to_string()),
-                    LispExpression::Integer(2),
-                ]),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("y".to_string()),
-                    LispExpression::Integer(3),
-                ]),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("z".to_string()),
-                    LispExpression::Integer(6),
-                ]),
-            ]),
-            LispExpression::List(vec![
-                LispExpression::Symbol("equal?".to_string()),
-                LispExpression::List(vec![
-                    LispExpression::Symbol("*".to_string()),
-                    LispExpression::Symbol("x".to_string()),
-                    LispExpression::Symbol("y".to_string()),
-                ]),
-                LispExpression::Symbol("z".to_string()),
-            ]),
-        ]);
-
-        let expected = LispOutput::Bool(true);
-        let result = evaluate(&let_expression, &mut env);
-
-        assert_eq!(expected, result);
+    /// Assigns to `var` the way `resolve` said to, like `set!`.
+    pub fn assign(&mut self, var: &str, reference: Reference, val: LispOutput) -> LispResult {
+        match reference {
+            Reference::Local { depth: 0, index } if self.slots[index].is_some() => {
+                self.slots[index] = Some(val.clone());
+                Ok(val)
+            },
+            Reference::Dynamic | Reference::Local { depth: 0, .. } | Reference::Free { depth: 0 } => {
+                self.set_bang(var, val)
+            },
+            Reference::Local { depth, index } => {
+                self.parent().borrow_mut().assign(var, Reference::Local { depth: depth - 1, index }, val)
+            },
+            Reference::Free { depth } => {
+                self.parent().borrow_mut().assign(var, Reference::Free { depth: depth - 1 }, val)
+            },
+        }
     }
+}
 
-    #[test]
-    #[should_panic]
-    fn set_bang_non_existent_variable() {
-        let mut env = create_global_environment();
-
-        let set_bang_expression = LispExpression::List(vec![
-            LispExpression::Symbol("set!".to_string()),
-            LispExpression::Symbol("x".to_string()),
-            LispExpression::Integer(2),
-        ]);
+/// Either of the engines, `evaluate` or `evaluate_compiled`.
+pub type Engine = fn(&LispExpression, &mut Rc<RefCell<Environment>>) -> LispResult;
 
-        evaluate(&set_bang_expression, &mut env);
-    }
+/// Evaluates `tree` in `env`.
+///
+/// The expression is first analyzed, which reports malformed special forms as
+/// syntax errors before anything runs, its variables are given lexical
+/// addresses by `resolve`, and then it is run by the `machine`.
+pub fn evaluate(tree: &LispExpression, env: &mut Rc<RefCell<Environment>>) -> LispResult {
+    return execute(resolve(&analyze(tree)?), env.clone());
+}
 
-    #[test]
-    fn set_bang_single_variable() {
-        let mut env = create_global_environment();
+/// Like `evaluate`, but backtraces of errors point at where calls were
+/// written, using the spans the parser found for `tree`.
+pub fn evaluate_with_spans(tree: &LispExpression, spans: &SpanTree, env: &mut Rc<RefCell<Environment>>) -> LispResult {
+    return execute(resolve(&analyze_with_spans(tree, Some(spans))?), env.clone());
+}
 
-        let define_x = LispExpression::List(vec![
-            LispExpression::Symbol("define".to_string()),
-            LispExpression::Symbol("x".to_string()),
-            LispExpression::Integer(2),
-        ]);
+/// Like `evaluate`, but compiles `tree` to bytecode and runs it on the virtual
+/// machine instead of walking it.
+pub fn evaluate_compiled(tree: &LispExpression, env: &mut Rc<RefCell<Environment>>) -> LispResult {
+    return execute_compiled(compile(&analyze(tree)?), env.clone());
+}
 
-        evaluate(&define_x, &mut env);
+/// `evaluate_compiled` with the spans the parser found for `tree`.
+pub fn evaluate_compiled_with_spans(
+    tree: &LispExpression,
+    spans: &SpanTree,
+    env: &mut Rc<RefCell<Environment>>,
+) -> LispResult {
+    return execute_compiled(compile(&analyze_with_spans(tree, Some(spans))?), env.clone());
+}
 
-        let get_x = LispExpression::Symbol("x".to_string());
-        let expected_x_before = LispOutput::Integer(2);
 
-        assert_eq!(expected_x_before, evaluate(&get_x, &mut env));
-        
-        let set_bang_expression = LispExpression::List(vec![
-            LispExpression::Symbol("set!".to_string()),
-            LispExpression::Symbol("x".to_string()),
-            LispExpression::Integer(5),
-        ]);
+// ============== TESTS ===============
 
-        let set_bang_result = evaluate(&set_bang_expression, &mut env);
-        let expected_x_after = LispOutput::Integer(5);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc;
+    use crate::limits::{with_limits, EvalLimits};
+    use crate::lisp_error::TraceFrame;
+    use crate::lisp_expression::Span;
+    use crate::parser::{parse, parse_with_spans};
+    use crate::tokenizer::{tokenize, tokenize_with_spans};
+
+    include!("evaluate_tests.rs");
+}
 
-        assert_eq!(expected_x_after, set_bang_result);
-        assert_eq!(expected_x_after, evaluate(&get_x, &mut env));
-    }
+#[cfg(test)]
+mod compiled_tests {
+    use super::*;
+    use super::{evaluate_compiled as evaluate, evaluate_compiled_with_spans as evaluate_with_spans};
+    use crate::gc;
+    use crate::limits::{with_limits, EvalLimits};
+    use crate::lisp_error::TraceFrame;
+    use crate::lisp_expression::Span;
+    use crate::parser::{parse, parse_with_spans};
+    use crate::tokenizer::{tokenize, tokenize_with_spans};
+
+    include!("evaluate_tests.rs");
 }
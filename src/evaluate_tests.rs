@@ -0,0 +1,2310 @@
+// Tests of the evaluator, included by `evaluate.rs` once for the tree-walking
+// evaluator and once for the bytecode virtual machine, with `evaluate` and
+// `evaluate_with_spans` standing for the engine under test.
+
+fn create_empty_environment() -> Rc<RefCell<Environment>> {
+    return Rc::new(RefCell::new(Environment::new()));
+}
+
+fn create_global_environment() -> Rc<RefCell<Environment>> {
+    return Rc::new(RefCell::new(Environment::global_env()));
+}
+
+fn evaluate_source(source: &str, env: &mut Rc<RefCell<Environment>>) -> LispResult {
+    return evaluate(&parse(&tokenize(source)), env);
+}
+
+#[test]
+fn single_integer() {
+    let lisp_integer = LispExpression::Integer(1);
+    let mut env = create_empty_environment();
+
+    let expected = LispOutput::Integer(1);
+    let result = evaluate(&lisp_integer, &mut env).unwrap();
+    
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn simple_defintion() {
+    let mut env = create_empty_environment();
+
+    let lisp_definition = LispExpression::List(vec![
+        LispExpression::Symbol("define".to_string()),
+        LispExpression::Symbol("x".to_string()),
+        LispExpression::Integer(2),
+    ]);
+
+    let expected = LispOutput::Integer(2);
+    let defintion_result = evaluate(&lisp_definition, &mut env).unwrap();
+
+    assert_eq!(expected, defintion_result);
+
+    let lisp_x = LispExpression::Symbol("x".to_string());
+    let x_result = evaluate(&lisp_x, &mut env).unwrap();
+
+    assert_eq!(expected, x_result);
+}
+
+#[test]
+#[should_panic]
+fn variable_not_found() {
+    let mut env = create_empty_environment();
+    let nonexistent_variable = LispExpression::Symbol("x".to_string());
+    evaluate(&nonexistent_variable, &mut env).unwrap();
+}
+
+#[test]
+fn simple_lambda() {
+    let mut env = create_global_environment();
+    let add_one = LispExpression::List(vec![
+        LispExpression::Symbol("define".to_string()),
+        LispExpression::Symbol("add_one".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("lambda".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("x".to_string()),
+            ]),
+            LispExpression::List(vec![
+                LispExpression::Symbol("+".to_string()),
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Integer(1),
+            ]),
+        ]),
+    ]);
+
+    evaluate(&add_one, &mut env).unwrap();
+
+    let two_plus_one = LispExpression::List(vec![
+        LispExpression::Symbol("add_one".to_string()),
+        LispExpression::Integer(2),
+    ]);
+
+    let result = evaluate(&two_plus_one, &mut env).unwrap();
+    let expected = LispOutput::Integer(3);
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn simple_if_statement() {
+    let mut env = create_global_environment();
+    let always_true_expression = LispExpression::List(vec![
+        LispExpression::Symbol("if".to_string()),
+        LispExpression::Symbol("#t".to_string()),
+        LispExpression::Integer(1),
+        LispExpression::Integer(0),
+    ]);
+
+    let always_false_expression = LispExpression::List(vec![
+        LispExpression::Symbol("if".to_string()),
+        LispExpression::Symbol("#f".to_string()),
+        LispExpression::Integer(1),
+        LispExpression::Integer(0),
+    ]);
+
+    let true_result = evaluate(&always_true_expression, &mut env).unwrap();
+    let false_result = evaluate(&always_false_expression, &mut env).unwrap();
+
+    assert_eq!(LispOutput::Integer(1), true_result);
+    assert_eq!(LispOutput::Integer(0), false_result);
+}
+
+#[test]
+fn simple_and_statement() {
+    let mut env = create_global_environment();
+    let single_true_expression = LispExpression::List(vec![
+        LispExpression::Symbol("and".to_string()),
+        LispExpression::Symbol("#t".to_string()),
+    ]);
+
+    let single_false_expression = LispExpression::List(vec![
+        LispExpression::Symbol("and".to_string()),
+        LispExpression::Symbol("#f".to_string()),
+    ]);
+
+    let nested_and_expression = LispExpression::List(vec![
+        LispExpression::Symbol("and".to_string()),
+        LispExpression::Symbol("#t".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("equal?".to_string()),
+            LispExpression::Integer(10),
+            LispExpression::List(vec![
+                LispExpression::Symbol("+".to_string()),
+                LispExpression::Integer(1),
+                LispExpression::Integer(2),
+                LispExpression::Integer(3),
+                LispExpression::Integer(4),
+            ]),
+        ]),
+    ]);
+
+    let true_result = evaluate(&single_true_expression, &mut env).unwrap();
+    let false_result = evaluate(&single_false_expression, &mut env).unwrap();
+    let nested_result = evaluate(&nested_and_expression, &mut env).unwrap();
+
+    assert_eq!(LispOutput::Bool(true), true_result);
+    assert_eq!(LispOutput::Bool(false), false_result);
+    assert_eq!(LispOutput::Bool(true), nested_result);
+}
+
+#[test]
+fn short_circuiting_and() {
+    let mut env = create_global_environment();
+    let nested_and_expression = LispExpression::List(vec![
+        LispExpression::Symbol("and".to_string()),
+        LispExpression::Symbol("#f".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("define".to_string()),
+            LispExpression::Symbol("add_one".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("lambda".to_string()),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("x".to_string()),
+                ]),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("+".to_string()),
+                    LispExpression::Symbol("x".to_string()),
+                    LispExpression::Integer(1),
+                ]),
+            ]),
+        ]),
+    ]);
+
+    let nested_result = evaluate(&nested_and_expression, &mut env).unwrap();
+
+    // add_one function should not be defined, since it is expected that
+    // the and short circuiting occurred at the first true expression
+    let borrowed_env = env.borrow();
+    let add_one_func = borrowed_env.bindings.get("add_one");
+    
+    if add_one_func.is_some() {
+        panic!("function should not be defined!");
+    }
+
+    assert_eq!(LispOutput::Bool(false), nested_result);
+}
+
+#[test]
+fn non_short_circuiting_and() {
+    let mut env = create_global_environment();
+    let nested_and_expression = LispExpression::List(vec![
+        LispExpression::Symbol("and".to_string()),
+        LispExpression::Symbol("#t".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("define".to_string()),
+            LispExpression::Symbol("add_one".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("lambda".to_string()),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("x".to_string()),
+                ]),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("+".to_string()),
+                    LispExpression::Symbol("x".to_string()),
+                    LispExpression::Integer(1),
+                ]),
+            ]),
+        ]),
+        LispExpression::List(vec![
+            LispExpression::Symbol("equal?".to_string()),
+            LispExpression::Integer(10),
+            LispExpression::List(vec![
+                LispExpression::Symbol("+".to_string()),
+                LispExpression::Integer(1),
+                LispExpression::Integer(2),
+                LispExpression::Integer(3),
+                LispExpression::Integer(4),
+            ]),
+        ]),
+    ]);
+
+    let nested_result = evaluate(&nested_and_expression, &mut env).unwrap();
+
+    // add_one function should not be defined, since it is expected that
+    // the and short circuiting occurred at the first true expression
+    let borrowed_env = env.borrow();
+    let add_one_func = borrowed_env.bindings.get("add_one");
+    
+    match add_one_func {
+        Some(_) => {},
+        None => { panic!("function should not be defined!") },
+    };
+
+    assert_eq!(LispOutput::Bool(true), nested_result);
+}
+
+#[test]
+fn short_circuiting_or() {
+    let mut env = create_global_environment();
+    let nested_and_expression = LispExpression::List(vec![
+        LispExpression::Symbol("or".to_string()),
+        LispExpression::Symbol("#t".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("define".to_string()),
+            LispExpression::Symbol("add_one".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("lambda".to_string()),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("x".to_string()),
+                ]),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("+".to_string()),
+                    LispExpression::Symbol("x".to_string()),
+                    LispExpression::Integer(1),
+                ]),
+            ]),
+        ]),
+    ]);
+
+    let nested_result = evaluate(&nested_and_expression, &mut env).unwrap();
+
+    // add_one function should not be defined, since it is expected that
+    // the or short circuiting occurred at the first true expression
+    let borrowed_env = env.borrow();
+    let add_one_func = borrowed_env.bindings.get("add_one");
+    
+    if add_one_func.is_some() {
+        panic!("function should not be defined!");
+    }
+
+    assert_eq!(LispOutput::Bool(true), nested_result);
+}
+
+#[test]
+fn empty_list() {
+    let mut env = create_global_environment();
+    let emtpy_list_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list".to_string()),
+    ]);
+
+    let expected = LispOutput::List(Box::new(LispList::Nil));
+    let result = evaluate(&emtpy_list_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn single_element_list() {
+    let mut env = create_global_environment();
+    let list_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list".to_string()),
+        LispExpression::Integer(3),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(3),
+                Rc::new(LispList::Nil)
+            )
+        )
+    );
+
+    let result = evaluate(&list_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn multiple_element_list() {
+    let mut env = create_global_environment();
+    let list_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list".to_string()),
+        LispExpression::Integer(1),
+        LispExpression::Integer(2),
+        LispExpression::Integer(3),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(1),
+                Rc::new(
+                    LispList::Cons(
+                        LispOutput::Integer(2),
+                        Rc::new(
+                            LispList::Cons(
+                                LispOutput::Integer(3),
+                                Rc::new(LispList::Nil)
+                            )
+                        )
+                    )
+                )
+            )
+        )
+    );
+
+    let result = evaluate(&list_expression, &mut env).unwrap();
+    assert_eq!(expected, result);
+
+    let get_car_expression = LispExpression::List(vec![
+        LispExpression::Symbol("car".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+        ]),
+    ]);
+
+    let expected = LispOutput::Integer(1);
+    let result = evaluate(&get_car_expression, &mut env).unwrap();
+    assert_eq!(expected, result);
+
+
+    let get_cdr_expression = LispExpression::List(vec![
+        LispExpression::Symbol("cdr".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+        ]),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(2),
+                Rc::new(
+                    LispList::Cons(
+                        LispOutput::Integer(3),
+                        Rc::new(LispList::Nil)
+                    )
+                )
+            )
+        )
+    );
+
+    let result = evaluate(&get_cdr_expression, &mut env).unwrap();
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn is_list() {
+    let mut env = create_global_environment();
+    let list_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list?".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+        ]),
+    ]);
+    let list_expected = LispOutput::Bool(true);
+    
+    let function_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list?".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("define".to_string()),
+            LispExpression::Symbol("add_one".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("lambda".to_string()),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("x".to_string()),
+                ]),
+                LispExpression::List(vec![
+                    LispExpression::Symbol("+".to_string()),
+                    LispExpression::Symbol("x".to_string()),
+                    LispExpression::Integer(1),
+                ]),
+            ]),
+        ]), 
+    ]);
+    let function_expected = LispOutput::Bool(false);
+                    
+    let integer_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list?".to_string()),
+        LispExpression::Integer(3),
+    ]);
+    let integer_expected = LispOutput::Bool(false);
+
+    let bool_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list?".to_string()),
+        LispExpression::Symbol("#t".to_string()),
+    ]);
+    let bool_expected = LispOutput::Bool(false);
+
+    assert_eq!(list_expected, evaluate(&list_expression, &mut env).unwrap());
+    assert_eq!(function_expected, evaluate(&function_expression, &mut env).unwrap());
+    assert_eq!(integer_expected, evaluate(&integer_expression, &mut env).unwrap());
+    assert_eq!(bool_expected, evaluate(&bool_expression, &mut env).unwrap());
+}
+
+#[test]
+fn empty_list_length() {
+    let mut env = create_global_environment();
+    let empty_list_length_expression = LispExpression::List(vec![
+        LispExpression::Symbol("length".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+    ]);
+    
+    let expected = LispOutput::Integer(0);
+
+    assert_eq!(expected, evaluate(&empty_list_length_expression, &mut env).unwrap());
+}
+
+#[test]
+fn single_element_list_length() {
+    let mut env = create_global_environment();
+    let list_length_expression = LispExpression::List(vec![
+        LispExpression::Symbol("length".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(3),
+        ]),
+    ]);
+    
+    let expected = LispOutput::Integer(1);
+
+    assert_eq!(expected, evaluate(&list_length_expression, &mut env).unwrap());
+}
+
+#[test]
+fn multi_element_list_length() {
+    let mut env = create_global_environment();
+    let list_length_expression = LispExpression::List(vec![
+        LispExpression::Symbol("length".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Symbol("#t".to_string()),
+            LispExpression::Symbol("nil".to_string()),
+            LispExpression::Integer(4),
+            LispExpression::Integer(5),
+        ]),
+    ]);
+    
+    let expected = LispOutput::Integer(5);
+
+    assert_eq!(expected, evaluate(&list_length_expression, &mut env).unwrap());
+}
+
+#[test]
+fn indexing_into_single_element_list() {
+    let mut env = create_global_environment();
+    let list_ref_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list-ref".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+        ]),
+        LispExpression::Integer(0),
+    ]);
+
+    let expected = LispOutput::Integer(1);
+    let result = evaluate(&list_ref_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn indexing_into_multi_element_list() {
+    let mut env = create_global_environment();
+    let list_ref_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list-ref".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+            LispExpression::Integer(4),
+            LispExpression::Integer(5),
+        ]),
+        LispExpression::Integer(3),
+    ]);
+
+    let expected = LispOutput::Integer(4);
+    let result = evaluate(&list_ref_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+#[should_panic]
+fn indexing_into_empty_list() {
+    let mut env = create_global_environment();
+    let list_ref_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list-ref".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Integer(0),
+    ]);
+
+    evaluate(&list_ref_expression, &mut env).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn indexing_out_of_bounds_non_empty_list() {
+    let mut env = create_global_environment();
+    let list_ref_expression = LispExpression::List(vec![
+        LispExpression::Symbol("list-ref".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+        ]),
+        LispExpression::Integer(5),
+    ]);
+
+    evaluate(&list_ref_expression, &mut env).unwrap();
+}
+
+#[test]
+fn appending_no_list() {
+    let mut env = create_global_environment();
+    let append_empty_expression = LispExpression::List(vec![
+        LispExpression::Symbol("append".to_string()),
+    ]);
+
+    let expected = LispOutput::List(Box::new(LispList::Nil));
+    let result = evaluate(&append_empty_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn appending_single_empty_list() {
+    let mut env = create_global_environment();
+    let append_empty_expression = LispExpression::List(vec![
+        LispExpression::Symbol("append".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+    ]);
+
+    let expected = LispOutput::List(Box::new(LispList::Nil));
+    let result = evaluate(&append_empty_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn appending_single_non_empty_list() {
+    let mut env = create_global_environment();
+    let append_empty_expression = LispExpression::List(vec![
+        LispExpression::Symbol("append".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+        ]),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(1),
+                Rc::new(
+                    LispList::Cons(
+                        LispOutput::Integer(2),
+                        Rc::new(
+                            LispList::Cons(
+                                LispOutput::Integer(3),
+                                Rc::new(LispList::Nil)
+                            )
+                        )
+                    )
+                )
+            )
+        )
+    );
+
+    let result = evaluate(&append_empty_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn appending_non_empty_list_with_empty_lists() {
+    let mut env = create_global_environment();
+    let append_empty_expression = LispExpression::List(vec![
+        LispExpression::Symbol("append".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+        ]),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(1),
+                Rc::new(
+                    LispList::Cons(
+                        LispOutput::Integer(2),
+                        Rc::new(
+                            LispList::Cons(
+                                LispOutput::Integer(3),
+                                Rc::new(LispList::Nil)
+                            )
+                        )
+                    )
+                )
+            )
+        )
+    );
+
+    let result = evaluate(&append_empty_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn appending_two_non_empty_lists() {
+    let mut env = create_global_environment();
+    let append_empty_expression = LispExpression::List(vec![
+        LispExpression::Symbol("append".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+        ]),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(4),
+            LispExpression::Integer(5),
+        ]),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(1),
+                Rc::new(
+                    LispList::Cons(
+                        LispOutput::Integer(2),
+                        Rc::new(
+                            LispList::Cons(
+                                LispOutput::Integer(3),
+                                Rc::new(
+                                    LispList::Cons(
+                                        LispOutput::Integer(4),
+                                        Rc::new(
+                                            LispList::Cons(
+                                                LispOutput::Integer(5),
+                                                Rc::new(LispList::Nil)
+                                            )
+                                        )
+                                    )
+                                )
+                            )
+                        )
+                    )
+                )
+            )
+        )
+    );
+
+    let result = evaluate(&append_empty_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn appending_multiple_non_empty_lists_and_empty_lists() {
+    let mut env = create_global_environment();
+    let append_empty_expression = LispExpression::List(vec![
+        LispExpression::Symbol("append".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+        ]),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(3),
+        ]),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(4),
+            LispExpression::Integer(5),
+        ]),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(1),
+                Rc::new(
+                    LispList::Cons(
+                        LispOutput::Integer(2),
+                        Rc::new(
+                            LispList::Cons(
+                                LispOutput::Integer(3),
+                                Rc::new(
+                                    LispList::Cons(
+                                        LispOutput::Integer(4),
+                                        Rc::new(
+                                            LispList::Cons(
+                                                LispOutput::Integer(5),
+                                                Rc::new(LispList::Nil)
+                                            )
+                                        )
+                                    )
+                                )
+                            )
+                        )
+                    )
+                )
+            )
+        )
+    );
+
+    let result = evaluate(&append_empty_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+#[should_panic]
+fn map_on_non_list() {
+    let mut env = create_global_environment();
+    let map_expression = LispExpression::List(vec![
+        LispExpression::Symbol("map".to_string()),
+        LispExpression::Integer(1),
+        LispExpression::Symbol("+".to_string()),
+    ]);
+
+    evaluate(&map_expression, &mut env).unwrap();
+}
+
+#[test]
+fn map_on_empty_list() {
+    let mut env = create_global_environment();
+    let map_expression = LispExpression::List(vec![
+        LispExpression::Symbol("map".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("+".to_string()),
+    ]);
+
+    let expected = LispOutput::List(Box::new(LispList::Nil));
+    let result = evaluate(&map_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn map_on_single_element_list() {
+    let mut env = create_global_environment();
+    let map_expression = LispExpression::List(vec![
+        LispExpression::Symbol("map".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(3),
+        ]),
+        LispExpression::Symbol("-".to_string()),
+    ]);
+
+    let expected = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(-3),
+                Rc::new(
+                    LispList::Nil,
+                )
+            )
+        )
+    );
+    let result = evaluate(&map_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+#[should_panic]
+fn filter_on_non_list() {
+    let mut env = create_global_environment();
+    let filter_expression = LispExpression::List(vec![
+        LispExpression::Symbol("filter".to_string()),
+        LispExpression::Integer(1),
+        LispExpression::Symbol("+".to_string()),
+    ]);
+
+    evaluate(&filter_expression, &mut env).unwrap();
+}
+
+#[test]
+fn filter_on_empty_list() {
+    let mut env = create_global_environment();
+    let filter_expression = LispExpression::List(vec![
+        LispExpression::Symbol("filter".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("+".to_string()),
+    ]);
+
+    let expected = LispOutput::List(Box::new(LispList::Nil));
+    let result = evaluate(&filter_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn filter_on_single_element_list() {
+    let mut env = create_global_environment();
+
+    let greater_than_one_func = LispExpression::List(vec![
+        LispExpression::Symbol("define".to_string()),
+        LispExpression::Symbol("greater_than_one".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("lambda".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("x".to_string()),
+            ]),
+            LispExpression::List(vec![
+                LispExpression::Symbol(">".to_string()),
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Integer(1),
+            ])
+        ])
+    ]);
+
+    evaluate(&greater_than_one_func, &mut env).unwrap();
+
+    let filter_expression_false = LispExpression::List(vec![
+        LispExpression::Symbol("filter".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(0),
+        ]),
+        LispExpression::Symbol("greater_than_one".to_string()),
+    ]);
+
+    let filter_expression_true = LispExpression::List(vec![
+        LispExpression::Symbol("filter".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(3),
+        ]),
+        LispExpression::Symbol("greater_than_one".to_string()),
+    ]);
+
+    let expected_filter_false = LispOutput::List(Box::new(LispList::Nil));
+    let expected_filter_true = LispOutput::List(
+        Box::new(
+            LispList::Cons(
+                LispOutput::Integer(3),
+                Rc::new(
+                    LispList::Nil,
+                )
+            )
+        )
+    );
+
+    let result_false = evaluate(&filter_expression_false, &mut env).unwrap();
+    let result_true = evaluate(&filter_expression_true, &mut env).unwrap();
+
+    assert_eq!(expected_filter_false, result_false);
+    assert_eq!(expected_filter_true, result_true);
+}
+
+#[test]
+#[should_panic]
+fn reduce_on_non_list() {
+    let mut env = create_global_environment();
+    let reduce_expression = LispExpression::List(vec![
+        LispExpression::Symbol("reduce".to_string()),
+        LispExpression::Integer(1),
+        LispExpression::Symbol("+".to_string()),
+        LispExpression::Integer(1),
+    ]);
+
+    evaluate(&reduce_expression, &mut env).unwrap();
+}
+
+#[test]
+fn reduce_on_empty_list() {
+    let mut env = create_global_environment();
+    let reduce_expression = LispExpression::List(vec![
+        LispExpression::Symbol("reduce".to_string()),
+        LispExpression::Symbol("nil".to_string()),
+        LispExpression::Symbol("+".to_string()),
+        LispExpression::Integer(0),
+    ]);
+
+    let expected = LispOutput::Integer(0);
+    let result = evaluate(&reduce_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn reduce_on_single_element_list() {
+    let mut env = create_global_environment();
+
+    let reduce_expression = LispExpression::List(vec![
+        LispExpression::Symbol("reduce".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+        ]),
+        LispExpression::Symbol("+".to_string()),
+        LispExpression::Integer(0),
+    ]);
+
+    let expected = LispOutput::Integer(1);
+
+    let result = evaluate(&reduce_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn reduce_on_multi_element_list() {
+    let mut env = create_global_environment();
+
+    let reduce_expression = LispExpression::List(vec![
+        LispExpression::Symbol("reduce".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("list".to_string()),
+            LispExpression::Integer(1),
+            LispExpression::Integer(2),
+            LispExpression::Integer(3),
+            LispExpression::Integer(4),
+            LispExpression::Integer(5),
+        ]),
+        LispExpression::Symbol("+".to_string()),
+        LispExpression::Integer(0),
+    ]);
+
+    let expected = LispOutput::Integer(15);
+
+    let result = evaluate(&reduce_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+#[should_panic]
+fn begin_empty_arguments() {
+    let mut env = create_global_environment();
+
+    let begin_expression = LispExpression::List(vec![
+        LispExpression::Symbol("begin".to_string()),
+    ]);
+    
+    evaluate(&begin_expression, &mut env).unwrap();
+}
+
+#[test]
+fn begin_single_argument() {
+    let mut env = create_global_environment();
+
+    let begin_expression = LispExpression::List(vec![
+        LispExpression::Symbol("begin".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("define".to_string()),
+            LispExpression::Symbol("x".to_string()),
+            LispExpression::Integer(2),
+        ]),
+        LispExpression::Symbol("x".to_string()),
+    ]);
+
+    let expected = LispOutput::Integer(2);
+
+    let result = evaluate(&begin_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn begin_multiple_arguments() {
+    let mut env = create_global_environment();
+
+    let add_one_func = LispExpression::List(vec![
+        LispExpression::Symbol("define".to_string()),
+        LispExpression::Symbol("add_one".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("lambda".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("x".to_string()),
+            ]),
+            LispExpression::List(vec![
+                LispExpression::Symbol("+".to_string()),
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Integer(1),
+            ])
+        ])
+    ]);
+
+    // x = 2
+    // y = x + 3  - should evaluate to 5
+    // add_one(y) - should evaluate to 6
+    let begin_expression = LispExpression::List(vec![
+        LispExpression::Symbol("begin".to_string()),
+        LispExpression::List(vec![
+            LispExpression::Symbol("define".to_string()),
+            LispExpression::Symbol("x".to_string()),
+            LispExpression::Integer(2),
+        ]),
+        LispExpression::List(vec![
+            LispExpression::Symbol("define".to_string()),
+            LispExpression::Symbol("y".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("+".to_string()),
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Integer(3),
+            ]),
+        ]),
+        LispExpression::List(vec![
+            add_one_func,
+            LispExpression::Symbol("y".to_string()),
+        ])
+    ]);
+
+    let expected = LispOutput::Integer(6);
+
+    let result = evaluate(&begin_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+#[should_panic]
+fn del_non_existent_object() {
+    let mut env = create_global_environment();
+
+    let del_expression = LispExpression::List(vec![
+        LispExpression::Symbol("del".to_string()),
+        LispExpression::Symbol("add_one".to_string()),
+    ]);
+
+    evaluate(&del_expression, &mut env).unwrap();
+}
+
+#[test]
+fn del_variable_definition() {
+    let mut env = create_global_environment();
+
+    let define_var = LispExpression::List(vec![
+        LispExpression::Symbol("define".to_string()),
+        LispExpression::Symbol("x".to_string()),
+        LispExpression::Integer(2),
+    ]);
+
+    evaluate(&define_var, &mut env).unwrap();
+
+    let del_expression = LispExpression::List(vec![
+        LispExpression::Symbol("del".to_string()),
+        LispExpression::Symbol("x".to_string()),
+    ]);
+
+    let expected = LispOutput::Integer(2);
+    let result = evaluate(&del_expression, &mut env).unwrap();
+    
+    assert_eq!(expected, result);
+}
+
+#[test]
+#[should_panic]
+fn del_variable_definition_twice() {
+    let mut env = create_global_environment();
+
+    let define_var = LispExpression::List(vec![
+        LispExpression::Symbol("define".to_string()),
+        LispExpression::Symbol("x".to_string()),
+        LispExpression::Integer(2),
+    ]);
+
+    evaluate(&define_var, &mut env).unwrap();
+
+    let del_expression = LispExpression::List(vec![
+        LispExpression::Symbol("del".to_string()),
+        LispExpression::Symbol("x".to_string()),
+    ]);
+
+    let expected = LispOutput::Integer(2);
+    let result = evaluate(&del_expression, &mut env).unwrap();
+    
+    assert_eq!(expected, result);
+
+    evaluate(&del_expression, &mut env).unwrap();
+}
+
+#[test]
+fn del_only_removes_from_its_own_frame() {
+    let mut env = create_global_environment();
+    evaluate_source("(define z 1)", &mut env).unwrap();
+    for source in ["((lambda () (del z)))", "(let ((a 1)) (del z))"] {
+        let err = evaluate_source(source, &mut env).unwrap_err();
+        assert_eq!("unbound-variable error: variable not found in environment: z", err.to_string(), "{source}");
+    }
+    assert_eq!(Ok(LispOutput::Integer(1)), evaluate_source("z", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(2)), evaluate_source("(let ((a 2)) (del a))", &mut env));
+}
+
+#[test]
+fn let_simple_variable_definition() {
+    let mut env = create_global_environment();
+
+    let let_expression = LispExpression::List(vec![
+        LispExpression::Symbol("let".to_string()),
+        LispExpression::List(vec![
+            LispExpression::List(vec![
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Integer(2),
+            ])
+        ]),
+        LispExpression::Symbol("x".to_string()),
+    ]);
+
+    let expected = LispOutput::Integer(2);
+    let result = evaluate(&let_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn let_binary_operations() {
+    let mut env = create_global_environment();
+
+    let let_expression = LispExpression::List(vec![
+        LispExpression::Symbol("let".to_string()),
+        LispExpression::List(vec![
+            LispExpression::List(vec![
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Integer(2),
+            ]),
+            LispExpression::List(vec![
+                LispExpression::Symbol("y".to_string()),
+                LispExpression::Integer(3),
+            ]),
+            LispExpression::List(vec![
+                LispExpression::Symbol("z".to_string()),
+                LispExpression::Integer(6),
+            ]),
+        ]),
+        LispExpression::List(vec![
+            LispExpression::Symbol("equal?".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Symbol("*".to_string()),
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Symbol("y".to_string()),
+            ]),
+            LispExpression::Symbol("z".to_string()),
+        ]),
+    ]);
+
+    let expected = LispOutput::Bool(true);
+    let result = evaluate(&let_expression, &mut env).unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+#[should_panic]
+fn set_bang_non_existent_variable() {
+    let mut env = create_global_environment();
+
+    let set_bang_expression = LispExpression::List(vec![
+        LispExpression::Symbol("set!".to_string()),
+        LispExpression::Symbol("x".to_string()),
+        LispExpression::Integer(2),
+    ]);
+
+    evaluate(&set_bang_expression, &mut env).unwrap();
+}
+
+#[test]
+fn set_bang_single_variable() {
+    let mut env = create_global_environment();
+
+    let define_x = LispExpression::List(vec![
+        LispExpression::Symbol("define".to_string()),
+        LispExpression::Symbol("x".to_string()),
+        LispExpression::Integer(2),
+    ]);
+
+    evaluate(&define_x, &mut env).unwrap();
+
+    let get_x = LispExpression::Symbol("x".to_string());
+    let expected_x_before = LispOutput::Integer(2);
+
+    assert_eq!(expected_x_before, evaluate(&get_x, &mut env).unwrap());
+    
+    let set_bang_expression = LispExpression::List(vec![
+        LispExpression::Symbol("set!".to_string()),
+        LispExpression::Symbol("x".to_string()),
+        LispExpression::Integer(5),
+    ]);
+
+    let set_bang_result = evaluate(&set_bang_expression, &mut env).unwrap();
+    let expected_x_after = LispOutput::Integer(5);
+
+    assert_eq!(expected_x_after, set_bang_result);
+    assert_eq!(expected_x_after, evaluate(&get_x, &mut env).unwrap());
+}
+
+#[test]
+fn let_star_sequential_bindings() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(let* ((x 2) (y (* x 3))) (+ x y))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(8)), result);
+
+    let result = evaluate_source("(let* ((x 1) (x (+ x 1))) x)", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(2)), result);
+}
+
+#[test]
+fn let_bindings_do_not_see_each_other() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(let ((x 2) (y x)) y)", &mut env);
+    assert_eq!(LispErrorKind::UnboundVariable, result.unwrap_err().kind);
+}
+
+#[test]
+fn letrec_mutually_recursive_functions() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("
+        (letrec ((is-even (lambda (n) (if (equal? n 0) #t (is-odd (- n 1)))))
+                 (is-odd (lambda (n) (if (equal? n 0) #f (is-even (- n 1))))))
+            (is-even 100))", &mut env);
+
+    assert_eq!(Ok(LispOutput::Bool(true)), result);
+}
+
+#[test]
+fn letrec_function_outlives_its_frame() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("((letrec ((add-one (lambda (x) (+ x 1)))) add-one) 2)", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(3)), result);
+}
+
+#[test]
+fn letrec_star_sequential_bindings() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(letrec* ((x 1) (y (+ x 1))) (list x y))", &mut env);
+    let expected = LispOutput::List(Box::new(LispList::build(
+        vec![LispOutput::Integer(1), LispOutput::Integer(2)].into_iter()
+    )));
+
+    assert_eq!(Ok(expected), result);
+}
+
+#[test]
+fn long_lists_do_not_overflow_the_stack() {
+    let mut env = create_global_environment();
+    let long = || LispOutput::List(Box::new(LispList::build((0..100000).map(LispOutput::Integer))));
+    env.borrow_mut().set("long", &long());
+
+    let result = evaluate_source("(length (filter (map long (lambda (x) (+ x 1))) (lambda (x) (> x 50000))))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(50000)), result);
+    assert_eq!(Ok(LispOutput::Integer(100000)), evaluate_source("(length long)", &mut env));
+    assert_eq!(Ok(long()), evaluate_source("long", &mut env));
+    assert_eq!(100000, format!("{:?}", long()).matches("Cons").count());
+}
+
+#[test]
+fn named_let_loop() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(let loop ((i 0)) (if (< i 10) (loop (+ i 1)) i))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(10)), result);
+
+    let result = evaluate_source("
+        (let sum ((i 5) (total 0))
+            (if (equal? i 0) total (sum (- i 1) (+ total i))))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(15)), result);
+}
+
+#[test]
+fn named_let_name_not_visible_outside() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(let loop ((i 0)) i)", &mut env).unwrap();
+    let result = evaluate_source("loop", &mut env);
+
+    assert_eq!(LispErrorKind::UnboundVariable, result.unwrap_err().kind);
+}
+
+#[test]
+fn tail_calls_run_in_constant_stack() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(let loop ((i 0)) (if (< i 100000) (loop (+ i 1)) i))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(100000)), result);
+
+    evaluate_source("(define count-down (lambda (n) (if (equal? n 0) 0 (count-down (- n 1)))))", &mut env).unwrap();
+    let result = evaluate_source("(count-down 100000)", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(0)), result);
+}
+
+#[test]
+fn duplicate_let_bindings() {
+    let mut env = create_global_environment();
+
+    for source in [
+        "(let ((x 1) (x 2)) x)",
+        "(letrec ((x 1) (x 2)) x)",
+        "(letrec* ((x 1) (x 2)) x)",
+        "(let loop ((x 1) (x 2)) x)",
+    ] {
+        let result = evaluate_source(source, &mut env);
+        assert_eq!(LispErrorKind::Syntax, result.unwrap_err().kind, "{source}");
+    }
+}
+
+#[test]
+fn malformed_let_bindings() {
+    let mut env = create_global_environment();
+
+    for source in [
+        "(let ((x)) x)",
+        "(let ((x 1 2)) x)",
+        "(let ((1 2)) 1)",
+        "(let (x 1) x)",
+        "(let x x)",
+        "(let* (x) x)",
+        "(letrec 1 1)",
+        "(let ((x 1)))",
+        "(let x)",
+    ] {
+        let result = evaluate_source(source, &mut env);
+        assert_eq!(LispErrorKind::Syntax, result.unwrap_err().kind, "{source}");
+    }
+    // a name alone is not a named let
+    let err = evaluate_source("(let x)", &mut env).unwrap_err();
+    assert_eq!("expecting list of bindings in let", err.message);
+}
+
+fn integer_list(numbers: Vec<i64>) -> LispOutput {
+    return LispOutput::List(Box::new(LispList::build(
+        numbers.into_iter().map(LispOutput::Integer)
+    )));
+}
+
+#[test]
+fn define_function_shorthand() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (add x y) (+ x y))", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(add 1 2)", &mut env));
+
+    evaluate_source("(define (five) 5)", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(5)), evaluate_source("(five)", &mut env));
+}
+
+#[test]
+fn rest_parameters() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("((lambda (a . rest) rest) 1 2 3)", &mut env);
+    assert_eq!(Ok(integer_list(vec![2, 3])), result);
+
+    let result = evaluate_source("((lambda args args) 1 2 3)", &mut env);
+    assert_eq!(Ok(integer_list(vec![1, 2, 3])), result);
+
+    let result = evaluate_source("((lambda (a #!rest rest) rest) 1)", &mut env);
+    assert_eq!(Ok(integer_list(vec![])), result);
+
+    evaluate_source("(define (count first . others) (+ 1 (length others)))", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(4)), evaluate_source("(count 1 2 3 4)", &mut env));
+}
+
+#[test]
+fn optional_parameters() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (scale x #!optional (factor 2) (offset factor)) (+ (* x factor) offset))", &mut env).unwrap();
+
+    assert_eq!(Ok(LispOutput::Integer(12)), evaluate_source("(scale 5)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(18)), evaluate_source("(scale 5 3)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(16)), evaluate_source("(scale 5 3 1)", &mut env));
+
+    let result = evaluate_source("((lambda (#!optional x) x))", &mut env);
+    assert_eq!(Ok(LispOutput::Void), result);
+}
+
+#[test]
+fn keyword_parameters() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (shift x #!key (by 1) (times 1)) (* (+ x by) times))", &mut env).unwrap();
+
+    assert_eq!(Ok(LispOutput::Integer(2)), evaluate_source("(shift 1)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(11)), evaluate_source("(shift 1 #:by 10)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(22)), evaluate_source("(shift 1 #:times 2 #:by 10)", &mut env));
+
+    let result = evaluate_source("(shift 1 #:amount 10)", &mut env);
+    assert_eq!(LispErrorKind::Arity, result.unwrap_err().kind);
+
+    let result = evaluate_source("(shift 1 #:by)", &mut env);
+    assert_eq!(LispErrorKind::Arity, result.unwrap_err().kind);
+}
+
+#[test]
+fn keywords_evaluate_to_themselves() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("#:name", &mut env);
+    assert_eq!(Ok(LispOutput::Keyword("name".to_string())), result);
+}
+
+#[test]
+fn strict_arity_checking() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (add x y) (+ x y))", &mut env).unwrap();
+
+    for source in [
+        "(add 1)",
+        "(add 1 2 3)",
+        "((lambda (a . rest) a))",
+        "((lambda (a #!optional b) a) 1 2 3)",
+        "(car (list 1) (list 2))",
+        "(car)",
+    ] {
+        let err = evaluate_source(source, &mut env).unwrap_err();
+        assert_eq!(LispErrorKind::Arity, err.kind, "{source}");
+    }
+
+    let err = evaluate_source("(add 1)", &mut env).unwrap_err();
+    assert_eq!("procedure expects 2 arguments, got 1", err.message);
+}
+
+#[test]
+fn malformed_parameter_lists() {
+    let mut env = create_global_environment();
+
+    for source in [
+        "(lambda (x x) x)",
+        "(lambda (x . y z) x)",
+        "(lambda (x .) x)",
+        "(lambda (1) 1)",
+        "(lambda (#!optional (x)) x)",
+        "(define (1 x) x)",
+    ] {
+        let err = evaluate_source(source, &mut env).unwrap_err();
+        assert_eq!(LispErrorKind::Syntax, err.kind, "{source}");
+    }
+}
+
+#[test]
+fn procedure_arity() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(procedure-arity (lambda (x y) x))", &mut env);
+    assert_eq!(Ok(integer_list(vec![2, 2])), result);
+
+    let result = evaluate_source("(procedure-arity (lambda (x #!optional y z) x))", &mut env);
+    assert_eq!(Ok(integer_list(vec![1, 3])), result);
+
+    let result = evaluate_source("(procedure-arity car)", &mut env);
+    assert_eq!(Ok(integer_list(vec![1, 1])), result);
+
+    let expected = LispOutput::List(Box::new(LispList::build(
+        vec![LispOutput::Integer(1), LispOutput::Bool(false)].into_iter()
+    )));
+    let result = evaluate_source("(procedure-arity (lambda (x . rest) x))", &mut env);
+    assert_eq!(Ok(expected), result);
+}
+
+fn symbol_list(symbols: Vec<&str>) -> LispOutput {
+    return LispOutput::List(Box::new(LispList::build(
+        symbols.into_iter().map(|symbol| LispOutput::Symbol(symbol.to_string()))
+    )));
+}
+
+#[test]
+fn quote_expressions() {
+    let mut env = create_global_environment();
+
+    assert_eq!(Ok(LispOutput::Symbol("x".to_string())), evaluate_source("'x", &mut env));
+    assert_eq!(Ok(symbol_list(vec!["a", "b"])), evaluate_source("(quote (a b))", &mut env));
+
+    let expected = LispOutput::List(Box::new(LispList::build(vec![
+        LispOutput::Integer(1),
+        LispOutput::Bool(true),
+        symbol_list(vec!["x"]),
+    ].into_iter())));
+    assert_eq!(Ok(expected), evaluate_source("'(1 #t (x))", &mut env));
+}
+
+#[test]
+fn apply_spreads_last_argument() {
+    let mut env = create_global_environment();
+
+    assert_eq!(Ok(LispOutput::Integer(10)), evaluate_source("(apply + 1 2 '(3 4))", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(0)), evaluate_source("(apply + '())", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(apply apply (list + (list 1 2)))", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(6)), evaluate_source("(apply (lambda (a #!optional (b 5)) (+ a b)) '(1))", &mut env));
+
+    let result = evaluate_source("(apply + 1 2)", &mut env);
+    assert_eq!(LispErrorKind::Type, result.unwrap_err().kind);
+
+    let result = evaluate_source("(apply car '(1 2))", &mut env);
+    assert_eq!(LispErrorKind::Arity, result.unwrap_err().kind);
+}
+
+#[test]
+fn apply_in_tail_position() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (count-down n) (if (equal? n 0) 0 (apply count-down (list (- n 1)))))", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(0)), evaluate_source("(count-down 100000)", &mut env));
+}
+
+#[test]
+fn eval_quoted_expressions() {
+    let mut env = create_global_environment();
+
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(eval '(+ 1 2))", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(eval (list '+ 1 2))", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(5)), evaluate_source("(eval ''5)", &mut env));
+
+    // without an environment argument eval uses the interaction environment,
+    // even when called from inside a function
+    evaluate_source("(define (define-y) (eval '(define y 5)))", &mut env).unwrap();
+    evaluate_source("(define-y)", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(5)), evaluate_source("y", &mut env));
+
+    let result = evaluate_source("(eval (list car '(1)))", &mut env);
+    assert_eq!(LispErrorKind::Type, result.unwrap_err().kind);
+}
+
+#[test]
+fn the_environment_captures_local_frame() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (make-counter) (let ((n 0)) (the-environment)))", &mut env).unwrap();
+    evaluate_source("(define counter (make-counter))", &mut env).unwrap();
+    evaluate_source("(eval '(set! n (+ n 1)) counter)", &mut env).unwrap();
+    evaluate_source("(eval '(set! n (+ n 1)) counter)", &mut env).unwrap();
+
+    assert_eq!(Ok(LispOutput::Integer(2)), evaluate_source("(environment-ref counter 'n)", &mut env));
+    assert_eq!(Ok(LispOutput::Bool(false)), evaluate_source("(environment-bound? (interaction-environment) 'n)", &mut env));
+    assert_eq!(Ok(LispOutput::Bool(true)), evaluate_source("(environment? counter)", &mut env));
+    assert_eq!(Ok(LispOutput::Bool(false)), evaluate_source("(environment? 'counter)", &mut env));
+}
+
+#[test]
+fn interaction_environment_is_the_global_frame() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(let ((x 1)) (interaction-environment))", &mut env);
+    assert_eq!(Ok(LispOutput::Environment(EnvironmentRef(env.clone()))), result);
+}
+
+#[test]
+fn make_environment_with_parent() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define x 1)", &mut env).unwrap();
+    evaluate_source("(define child (make-environment (interaction-environment)))", &mut env).unwrap();
+    evaluate_source("(eval '(define z (+ x 2)) child)", &mut env).unwrap();
+
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(environment-ref child 'z)", &mut env));
+    assert_eq!(Ok(LispOutput::Bool(true)), evaluate_source("(environment-bound? child 'x)", &mut env));
+    assert_eq!(Ok(LispOutput::Bool(false)), evaluate_source("(environment-bound? (interaction-environment) 'z)", &mut env));
+
+    let result = evaluate_source("(environment-ref (interaction-environment) 'z)", &mut env);
+    assert_eq!(LispErrorKind::UnboundVariable, result.unwrap_err().kind);
+}
+
+#[test]
+fn make_environment_without_parent() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define x 1)", &mut env).unwrap();
+    evaluate_source("(define fresh (make-environment))", &mut env).unwrap();
+
+    assert_eq!(Ok(LispOutput::Bool(true)), evaluate_source("(environment-bound? fresh 'car)", &mut env));
+    assert_eq!(Ok(LispOutput::Bool(false)), evaluate_source("(environment-bound? fresh 'x)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(2)), evaluate_source("(eval '(car (list 2)) fresh)", &mut env));
+}
+
+fn create_tracing_environment() -> Rc<RefCell<Environment>> {
+    let mut env = create_global_environment();
+    evaluate_source("(define trace '())", &mut env).unwrap();
+    evaluate_source("(define (note x) (set! trace (append trace (list x))))", &mut env).unwrap();
+    return env;
+}
+
+#[test]
+fn contracts_are_checked_on_every_call() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (add-one [x : Int]) : Int (+ x 1))", &mut env).unwrap();
+    evaluate_source("(define (twice [x : Int]) : Int (add-one (add-one x)))", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(twice 1)", &mut env));
+
+    let err = evaluate_source("(add-one 'a)", &mut env).unwrap_err();
+    assert_eq!("contract violation: add-one expects x to be Int, got a", err.message);
+    // a value of type Any passed on by annotated code is checked all the same
+    evaluate_source("(define (first-plus-one [items : (List Any)]) : Int (add-one (car items)))", &mut env).unwrap();
+    let err = evaluate_source("(first-plus-one '(a))", &mut env).unwrap_err();
+    assert_eq!("contract violation: add-one expects x to be Int, got a", err.message);
+
+    evaluate_source("(define (broken [x : Int]) : Int (car (list 'a)))", &mut env).unwrap();
+    let err = evaluate_source("(broken 1)", &mut env).unwrap_err();
+    assert_eq!("contract violation: broken promised a result of type Int, got a", err.message);
+}
+
+#[test]
+fn parameter_contracts_keep_tail_calls() {
+    let mut env = create_global_environment();
+    let limits = EvalLimits::default().with_max_depth(100);
+
+    evaluate_source("(define (count-down [n : Int] [done : Bool]) (if (equal? n 0) done (count-down (- n 1) done)))", &mut env).unwrap();
+    let result = with_limits(&limits, || evaluate_source("(count-down 1000 #f)", &mut env));
+    assert_eq!(Ok(LispOutput::Bool(false)), result);
+    let err = evaluate_source("(count-down 3 1)", &mut env).unwrap_err();
+    assert_eq!("contract violation: count-down expects done to be Bool, got 1", err.message);
+
+    // checking the result takes the body out of tail position
+    evaluate_source("(define (count-to-zero [n : Int]) : Int (if (equal? n 0) n (count-to-zero (- n 1))))", &mut env).unwrap();
+    assert_eq!(LispErrorKind::DepthExceeded, limit_error("(count-to-zero 1000)", limits, &mut env));
+}
+
+#[test]
+fn call_cc_returns_normally() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(+ 1 (call/cc (lambda (k) 3)))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(4)), result);
+}
+
+#[test]
+fn call_cc_early_exit() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(+ 1 (call-with-current-continuation (lambda (k) (+ 10 (k 5)))))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(6)), result);
+}
+
+#[test]
+fn call_cc_escapes_from_built_in_callbacks() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("
+        (call/cc (lambda (return)
+            (map '(1 -2 3) (lambda (x) (if (< x 0) (return x) (* x 2))))))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(-2)), result);
+
+    let result = evaluate_source("(call/cc (lambda (k) (map '(1 2) k)))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(1)), result);
+}
+
+#[test]
+fn call_cc_reentry() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("
+        (let ((saved #f))
+            (let ((x (call/cc (lambda (c) (begin (set! saved c) 0)))))
+                (if (< x 3) (saved (+ x 1)) x)))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(3)), result);
+}
+
+#[test]
+fn call_cc_reentry_after_evaluation_finished() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define k #f)", &mut env).unwrap();
+    let result = evaluate_source("(define result (+ 100 (call/cc (lambda (c) (begin (set! k c) 0)))))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(100)), result);
+
+    assert_eq!(Ok(LispOutput::Integer(105)), evaluate_source("(k 5)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(105)), evaluate_source("result", &mut env));
+}
+
+#[test]
+fn call_cc_reentry_into_a_finished_callback() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define k #f)", &mut env).unwrap();
+    let result = evaluate_source("(map '(1 2) (lambda (x) (call/cc (lambda (c) (begin (set! k c) x)))))", &mut env);
+    assert_eq!(Ok(integer_list(vec![1, 2])), result);
+
+    let result = evaluate_source("(+ 1 (k 5))", &mut env);
+    assert_eq!(LispErrorKind::Continuation, result.unwrap_err().kind);
+}
+
+#[test]
+fn intrinsic_errors_use_the_lisp_name() {
+    let mut env = create_global_environment();
+
+    let err = evaluate_source("(with-exception-handler 1 (lambda () 2))", &mut env).unwrap_err();
+    assert_eq!("expecting first argument to with-exception-handler to be lisp function!", err.message);
+    let err = evaluate_source("(dynamic-wind (lambda () 1) 2 (lambda () 3))", &mut env).unwrap_err();
+    assert_eq!("expecting second argument to dynamic-wind to be lisp function!", err.message);
+    let err = evaluate_source("(call/cc 1)", &mut env).unwrap_err();
+    assert_eq!("expecting first argument to call-with-current-continuation to be lisp function!", err.message);
+}
+
+#[test]
+fn call_cc_generator() {
+    let mut env = create_global_environment();
+
+    evaluate_source("
+        (define (make-generator items)
+            (let ((return #f) (resume #f) (started #f))
+                (lambda ()
+                    (call/cc (lambda (r)
+                        (begin
+                            (set! return r)
+                            (if started
+                                (resume 'next)
+                                (begin
+                                    (set! started #t)
+                                    (let loop ((rest items))
+                                        (if (equal? (length rest) 0)
+                                            (return 'done)
+                                            (begin
+                                                (call/cc (lambda (c) (begin (set! resume c) (return (car rest)))))
+                                                (loop (cdr rest)))))))))))))", &mut env).unwrap();
+
+    evaluate_source("(define gen (make-generator '(1 2 3)))", &mut env).unwrap();
+    let result = evaluate_source("(list (gen) (gen))", &mut env);
+    assert_eq!(Ok(integer_list(vec![1, 2])), result);
+
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(gen)", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("done".to_string())), evaluate_source("(gen)", &mut env));
+}
+
+#[test]
+fn dynamic_wind_order() {
+    let mut env = create_tracing_environment();
+
+    let result = evaluate_source("
+        (dynamic-wind
+            (lambda () (note 'before))
+            (lambda () (begin (note 'during) 7))
+            (lambda () (note 'after)))", &mut env);
+
+    assert_eq!(Ok(LispOutput::Integer(7)), result);
+    assert_eq!(Ok(symbol_list(vec!["before", "during", "after"])), evaluate_source("trace", &mut env));
+}
+
+#[test]
+fn dynamic_wind_escaping_runs_after() {
+    let mut env = create_tracing_environment();
+
+    let result = evaluate_source("
+        (call/cc (lambda (k)
+            (dynamic-wind
+                (lambda () (note 'in))
+                (lambda () (begin (k 'escaped) (note 'unreachable)))
+                (lambda () (note 'out)))))", &mut env);
+
+    assert_eq!(Ok(LispOutput::Symbol("escaped".to_string())), result);
+    assert_eq!(Ok(symbol_list(vec!["in", "out"])), evaluate_source("trace", &mut env));
+}
+
+#[test]
+fn dynamic_wind_reentry_runs_before() {
+    let mut env = create_tracing_environment();
+
+    let result = evaluate_source("
+        (let ((k #f) (n 0))
+            (begin
+                (dynamic-wind
+                    (lambda () (note 'in))
+                    (lambda () (call/cc (lambda (c) (set! k c))))
+                    (lambda () (note 'out)))
+                (set! n (+ n 1))
+                (if (< n 3) (k 'again) n)))", &mut env);
+
+    assert_eq!(Ok(LispOutput::Integer(3)), result);
+    assert_eq!(
+        Ok(symbol_list(vec!["in", "out", "in", "out", "in", "out"])),
+        evaluate_source("trace", &mut env)
+    );
+}
+
+#[test]
+fn dynamic_wind_errors_run_after() {
+    let mut env = create_tracing_environment();
+
+    let result = evaluate_source("
+        (dynamic-wind
+            (lambda () (note 'in))
+            (lambda () (car 1))
+            (lambda () (note 'out)))", &mut env);
+
+    assert_eq!(LispErrorKind::Type, result.unwrap_err().kind);
+    assert_eq!(Ok(symbol_list(vec!["in", "out"])), evaluate_source("trace", &mut env));
+}
+
+#[test]
+fn escape_continuations() {
+    let mut env = create_global_environment();
+
+    assert_eq!(Ok(LispOutput::Integer(42)), evaluate_source("(call/ec (lambda (k) (+ 1 (k 42))))", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(call-with-escape-continuation (lambda (k) 3))", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(7)), evaluate_source("(call/ec (lambda (k) (map '(7 8) k)))", &mut env));
+}
+
+#[test]
+fn escape_continuation_outside_extent() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define saved #f)", &mut env).unwrap();
+    evaluate_source("(call/ec (lambda (k) (set! saved k)))", &mut env).unwrap();
+
+    let result = evaluate_source("(saved 1)", &mut env);
+    assert_eq!(LispErrorKind::Continuation, result.unwrap_err().kind);
+}
+
+#[test]
+fn deep_recursion_does_not_use_rust_stack() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (count n) (if (equal? n 0) 0 (+ 1 (count (- n 1)))))", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(100000)), evaluate_source("(count 100000)", &mut env));
+}
+
+#[test]
+fn string_literals() {
+    let mut env = create_global_environment();
+
+    assert_eq!(Ok(LispOutput::String("hello world".to_string())), evaluate_source("\"hello world\"", &mut env));
+    assert_eq!(
+        Ok(LispOutput::List(Box::new(LispList::build(vec![LispOutput::String("a b".to_string())].into_iter())))),
+        evaluate_source("'(\"a b\")", &mut env)
+    );
+}
+
+#[test]
+fn guard_catches_errors() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(guard (e (#t (error-object-message e))) (error \"boom\" 1 2))", &mut env);
+    assert_eq!(Ok(LispOutput::String("boom".to_string())), result);
+
+    let result = evaluate_source("(guard (e ((error-object? e) (error-object-irritants e))) (error \"boom\" 1 2))", &mut env);
+    assert_eq!(Ok(integer_list(vec![1, 2])), result);
+
+    let result = evaluate_source("(guard (e (#t 'unused)) (+ 1 2))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(3)), result);
+}
+
+#[test]
+fn guard_clauses() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(guard (e ((equal? e 1) 'one) ((equal? e 42) 'answer) (else 'other)) (raise 42))", &mut env);
+    assert_eq!(Ok(LispOutput::Symbol("answer".to_string())), result);
+
+    let result = evaluate_source("(guard (e ((equal? e 1) 'one) (else (list 'other e))) (raise 7))", &mut env);
+    assert_eq!(Ok(LispOutput::List(Box::new(LispList::build(vec![
+        LispOutput::Symbol("other".to_string()),
+        LispOutput::Integer(7),
+    ].into_iter())))), result);
+}
+
+#[test]
+fn guard_without_matching_clause_reraises() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(guard (e ((error-object? e) 'error)) (raise 5))", &mut env);
+    assert_eq!(LispErrorKind::Raise, result.unwrap_err().kind);
+
+    let result = evaluate_source("
+        (guard (outer (#t (list 'outer outer)))
+            (guard (inner ((error-object? inner) 'inner))
+                (raise 5)))", &mut env);
+    assert_eq!(Ok(LispOutput::List(Box::new(LispList::build(vec![
+        LispOutput::Symbol("outer".to_string()),
+        LispOutput::Integer(5),
+    ].into_iter())))), result);
+}
+
+#[test]
+fn guard_syntax_errors() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(guard (e (else 1) (#t 2)) 3)", &mut env);
+    assert_eq!(LispErrorKind::Syntax, result.unwrap_err().kind);
+
+    let result = evaluate_source("(guard (1 (#t 2)) 3)", &mut env);
+    assert_eq!(LispErrorKind::Syntax, result.unwrap_err().kind);
+
+    let result = evaluate_source("(guard (e (#t)) 3)", &mut env);
+    assert_eq!(LispErrorKind::Syntax, result.unwrap_err().kind);
+}
+
+#[test]
+fn runtime_errors_are_error_objects() {
+    let mut env = create_global_environment();
+    let kind_of = |source: &str, env: &mut Rc<RefCell<Environment>>| {
+        evaluate_source(&format!("(guard (e ((error-object? e) (error-object-kind e))) {source})"), env)
+    };
+
+    assert_eq!(Ok(LispOutput::Symbol("type".to_string())), kind_of("(car 1)", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("type".to_string())), kind_of("(+ 1 'a)", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("unbound-variable".to_string())), kind_of("undefined-variable", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("index-out-of-bounds".to_string())), kind_of("(list-ref (list 1) 3)", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("division-by-zero".to_string())), kind_of("(/ 1 0)", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("arity".to_string())), kind_of("((lambda (x) x))", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("user".to_string())), kind_of("(error \"custom\")", &mut env));
+    assert_eq!(Ok(LispOutput::Symbol("type".to_string())), kind_of("(map '(1 2) (lambda (x) (car x)))", &mut env));
+}
+
+#[test]
+fn uncaught_error_objects() {
+    let mut env = create_global_environment();
+
+    let err = evaluate_source("(error \"bad thing\" 1 \"two\" 'three)", &mut env).unwrap_err();
+    assert_eq!(LispErrorKind::User, err.kind);
+    assert_eq!("user error: bad thing 1 \"two\" three", err.to_string());
+
+    let err = evaluate_source("(raise 'oops)", &mut env).unwrap_err();
+    assert_eq!(LispErrorKind::Raise, err.kind);
+    assert_eq!("raise error: non-condition object raised: oops", err.to_string());
+}
+
+#[test]
+fn with_exception_handler_continuable() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("
+        (with-exception-handler
+            (lambda (c) 10)
+            (lambda () (+ 1 (raise-continuable 'oops))))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(11)), result);
+
+    let result = evaluate_source("(with-exception-handler (lambda (c) 10) (lambda () 5))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(5)), result);
+}
+
+#[test]
+fn with_exception_handler_returning_from_raise() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("(with-exception-handler (lambda (c) 10) (lambda () (raise 'oops)))", &mut env);
+    assert_eq!(LispErrorKind::Raise, result.unwrap_err().kind);
+
+    // the secondary error goes to the outer handler, not back to the first
+    let result = evaluate_source("
+        (guard (e ((error-object? e) 'secondary))
+            (with-exception-handler (lambda (c) 10) (lambda () (car 1))))", &mut env);
+    assert_eq!(Ok(LispOutput::Symbol("secondary".to_string())), result);
+}
+
+#[test]
+fn with_exception_handler_escaping() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("
+        (call/cc (lambda (k)
+            (with-exception-handler
+                (lambda (e) (k (error-object-message e)))
+                (lambda () (error \"bad\")))))", &mut env);
+    assert_eq!(Ok(LispOutput::String("bad".to_string())), result);
+}
+
+#[test]
+fn nested_exception_handlers() {
+    let mut env = create_global_environment();
+
+    let result = evaluate_source("
+        (with-exception-handler
+            (lambda (c) (+ c 1))
+            (lambda ()
+                (with-exception-handler
+                    (lambda (c) (raise-continuable (* c 10)))
+                    (lambda () (raise-continuable 2)))))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(21)), result);
+}
+
+#[test]
+fn guard_leaves_dynamic_wind() {
+    let mut env = create_tracing_environment();
+
+    let result = evaluate_source("
+        (guard (e (#t 'caught))
+            (dynamic-wind
+                (lambda () (note 'in))
+                (lambda () (raise 'x))
+                (lambda () (note 'out))))", &mut env);
+
+    assert_eq!(Ok(LispOutput::Symbol("caught".to_string())), result);
+    assert_eq!(Ok(symbol_list(vec!["in", "out"])), evaluate_source("trace", &mut env));
+}
+
+fn evaluate_source_with_spans(source: &str, env: &mut Rc<RefCell<Environment>>) -> LispResult {
+    let (tokens, spans) = tokenize_with_spans(source);
+    let (tree, span_tree) = parse_with_spans(&tokens, &spans);
+    return evaluate_with_spans(&tree, &span_tree, env);
+}
+
+fn create_backtrace_environment() -> Rc<RefCell<Environment>> {
+    let mut env = create_global_environment();
+    evaluate_source_with_spans("(define (inner x) (car x))", &mut env).unwrap();
+    evaluate_source_with_spans("(define outer (lambda (x)\n  (+ 1 (inner x))))", &mut env).unwrap();
+    return env;
+}
+
+#[test]
+fn backtrace_names_calls() {
+    let mut env = create_backtrace_environment();
+
+    let err = evaluate_source_with_spans("(outer 5)", &mut env).unwrap_err();
+    assert_eq!(LispErrorKind::Type, err.kind);
+    assert_eq!(vec![
+        TraceFrame {
+            name: Some("inner".to_string()),
+            span: Some(Span { line: 2, column: 8, end_line: 2, end_column: 17 }),
+            arguments: vec![LispOutput::Integer(5)],
+        },
+        TraceFrame {
+            name: Some("outer".to_string()),
+            span: Some(Span { line: 1, column: 1, end_line: 1, end_column: 10 }),
+            arguments: vec![LispOutput::Integer(5)],
+        },
+    ], err.backtrace);
+
+    assert_eq!(
+        "type error: expecting a list, got 5\n    in (inner 5) at line 2, column 8\n    in (outer 5) at line 1, column 1",
+        err.report()
+    );
+}
+
+#[test]
+fn backtrace_without_spans() {
+    let mut env = create_global_environment();
+
+    let err = evaluate_source("((lambda (x) (car x)) 'a)", &mut env).unwrap_err();
+    assert_eq!("type error: expecting a list, got a\n    in (<anonymous> a)", err.report());
+}
+
+#[test]
+fn long_backtraces_are_collapsed_in_reports() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (count n) (if (equal? n 0) (car n) (+ 1 (count (- n 1)))))", &mut env).unwrap();
+    let err = evaluate_source("(count 100)", &mut env).unwrap_err();
+    assert_eq!(101, err.backtrace.len());
+
+    let report = err.report();
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(22, lines.len());
+    assert_eq!("    in (count 0)", lines[1]);
+    assert_eq!("    in (count 9)", lines[10]);
+    assert_eq!("    … 81 more calls", lines[11]);
+    assert_eq!("    in (count 91)", lines[12]);
+    assert_eq!("    in (count 100)", lines[21]);
+}
+
+#[test]
+fn backtrace_tail_calls_replace_their_caller() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (countdown n) (if (equal? n 0) (car n) (countdown (- n 1))))", &mut env).unwrap();
+    evaluate_source("(define (start) (+ 1 (countdown 1000)))", &mut env).unwrap();
+
+    let err = evaluate_source("(start)", &mut env).unwrap_err();
+    let names: Vec<Option<String>> = err.backtrace.iter().map(|frame| frame.name.clone()).collect();
+    assert_eq!(vec![Some("countdown".to_string()), Some("start".to_string())], names);
+    assert_eq!(vec![LispOutput::Integer(0)], err.backtrace[0].arguments);
+
+    let err = evaluate_source("(let loop ((n 2)) (if (equal? n 0) (car n) (loop (- n 1))))", &mut env).unwrap_err();
+    assert_eq!(Some("loop".to_string()), err.backtrace[0].name);
+}
+
+#[test]
+fn backtrace_through_built_in_callbacks() {
+    let mut env = create_global_environment();
+
+    evaluate_source("(define (check x) (car x))", &mut env).unwrap();
+    evaluate_source("(define (check-all xs) (map xs check))", &mut env).unwrap();
+
+    let err = evaluate_source("(check-all '(1))", &mut env).unwrap_err();
+    let names: Vec<Option<String>> = err.backtrace.iter().map(|frame| frame.name.clone()).collect();
+    assert_eq!(vec![Some("check".to_string()), Some("check-all".to_string())], names);
+}
+
+#[test]
+fn error_object_backtrace() {
+    let mut env = create_backtrace_environment();
+
+    let result = evaluate_source_with_spans("(guard (e (#t (error-object-backtrace e))) (outer 5))", &mut env);
+    let frame = |name: &str, line: i64, column: i64| LispOutput::List(Box::new(LispList::build(vec![
+        LispOutput::Symbol(name.to_string()),
+        integer_list(vec![5]),
+        LispOutput::Integer(line),
+        LispOutput::Integer(column),
+    ].into_iter())));
+    assert_eq!(
+        Ok(LispOutput::List(Box::new(LispList::build(vec![frame("inner", 2, 8), frame("outer", 1, 44)].into_iter())))),
+        result
+    );
+
+    evaluate_source("(define (fail) (error \"failed\"))", &mut env).unwrap();
+    let result = evaluate_source("(guard (e (#t (length (error-object-backtrace e)))) (fail))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(1)), result);
+}
+
+#[test]
+fn internal_definition_is_not_visible_before_it_runs() {
+    let mut env = create_global_environment();
+    evaluate_source("(define x 1)", &mut env).unwrap();
+    evaluate_source("(define (f) (begin (define y x) (define x 2) (list y x)))", &mut env).unwrap();
+    assert_eq!(Ok(integer_list(vec![1, 2])), evaluate_source("(f)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(1)), evaluate_source("x", &mut env));
+}
+
+#[test]
+fn deleting_a_parameter_uncovers_the_outer_variable() {
+    let mut env = create_global_environment();
+    evaluate_source("(define x 1)", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(1)), evaluate_source("((lambda (x) (begin (del x) x)) 5)", &mut env));
+}
+
+#[test]
+fn set_bang_reaches_through_closures() {
+    let mut env = create_global_environment();
+    evaluate_source("(define (make-counter) (let ((n 0)) (lambda () (begin (set! n (+ n 1)) n))))", &mut env).unwrap();
+    evaluate_source("(define counter (make-counter))", &mut env).unwrap();
+    evaluate_source("(counter)", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(2)), evaluate_source("(counter)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(1)), evaluate_source("((make-counter))", &mut env));
+}
+
+fn gc_stat(name: &str, env: &mut Rc<RefCell<Environment>>) -> i64 {
+    let stats = evaluate_source("(gc-stats)", env).unwrap();
+    let LispOutput::List(stats) = stats else { panic!("expected a list, got {stats:?}") };
+    for entry in stats.to_vec() {
+        let LispOutput::List(entry) = entry else { panic!("expected an entry, got {entry:?}") };
+        if entry.get_car() == Ok(LispOutput::Symbol(name.to_string())) {
+            return match entry.get(1) {
+                Ok(LispOutput::Integer(count)) => count,
+                other => panic!("expected a count, got {other:?}"),
+            };
+        }
+    }
+    panic!("no {name} in gc-stats");
+}
+
+#[test]
+fn memory_stays_bounded_in_loops_making_cyclic_closures() {
+    let mut env = create_global_environment();
+    // every call leaves a frame that holds a procedure closing over it
+    evaluate_source("(define (make-cycle) (begin (define (self) self) self))", &mut env).unwrap();
+    let allocated = gc_stat("allocated", &mut env);
+
+    let result = evaluate_source("(let loop ((i 0)) (if (< i 20000) (loop (+ i (begin (make-cycle) 1))) i))", &mut env);
+    assert_eq!(Ok(LispOutput::Integer(20000)), result);
+    assert!(gc_stat("allocated", &mut env) - allocated >= 20000);
+    assert!(gc_stat("live", &mut env) < 10000);
+
+    evaluate_source("(gc)", &mut env).unwrap();
+    assert!(gc_stat("live", &mut env) < 100);
+}
+
+#[test]
+fn gc_keeps_closures_that_are_still_referenced() {
+    let mut env = create_global_environment();
+    evaluate_source("(define (make-counter) (let ((n 0)) (lambda () (begin (set! n (+ n 1)) n))))", &mut env).unwrap();
+    evaluate_source("(define counter (make-counter))", &mut env).unwrap();
+    evaluate_source("(counter)", &mut env).unwrap();
+    evaluate_source("(make-counter)", &mut env).unwrap();
+    evaluate_source("(gc)", &mut env).unwrap();
+    assert_eq!(Ok(LispOutput::Integer(2)), evaluate_source("(counter)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(3)), evaluate_source("(begin (gc) (counter))", &mut env));
+}
+
+fn limit_error(source: &str, limits: EvalLimits, env: &mut Rc<RefCell<Environment>>) -> LispErrorKind {
+    match with_limits(&limits, || evaluate_source(source, env)) {
+        Err(err) => return err.kind,
+        Ok(value) => panic!("expected {source} to exceed a limit, got {value}"),
+    }
+}
+
+#[test]
+fn running_out_of_fuel() {
+    let mut env = create_global_environment();
+    evaluate_source("(define f (lambda () (f)))", &mut env).unwrap();
+    let limits = EvalLimits::default().with_fuel(10000);
+    assert_eq!(LispErrorKind::OutOfFuel, limit_error("(f)", limits, &mut env));
+    assert_eq!(Ok(LispOutput::Integer(3)), with_limits(&limits, || evaluate_source("(+ 1 2)", &mut env)));
+}
+
+#[test]
+fn exceeding_the_call_depth() {
+    let mut env = create_global_environment();
+    evaluate_source("(define (deep n) (+ 1 (deep n)))", &mut env).unwrap();
+    // calls made by built-ins count as well
+    evaluate_source("(define (nested x) (map (list x) nested))", &mut env).unwrap();
+    let limits = EvalLimits::default().with_max_depth(100);
+    assert_eq!(LispErrorKind::DepthExceeded, limit_error("(deep 1)", limits, &mut env));
+    assert_eq!(LispErrorKind::DepthExceeded, limit_error("(nested 1)", limits, &mut env));
+
+    evaluate_source("(define (count n) (if (equal? n 0) 0 (+ 1 (count (- n 1)))))", &mut env).unwrap();
+    let result = with_limits(&limits, || evaluate_source("(count 90)", &mut env));
+    assert_eq!(Ok(LispOutput::Integer(90)), result);
+}
+
+#[test]
+fn recursion_through_built_ins_does_not_overflow_the_stack() {
+    let mut env = create_global_environment();
+    let source = "(define (f n) (if (equal? n 0) 0 (car (map (list 1) (lambda (x) (f (- n 1)))))))";
+    evaluate_source(source, &mut env).unwrap();
+    // the nesting is bounded even without limits
+    match evaluate_source("(f 1000000)", &mut env) {
+        Err(err) => assert_eq!(LispErrorKind::DepthExceeded, err.kind),
+        Ok(value) => panic!("expected (f 1000000) to fail, got {value}"),
+    }
+    assert_eq!(LispErrorKind::DepthExceeded, limit_error("(f 1000000)", EvalLimits::default(), &mut env));
+    assert_eq!(Ok(LispOutput::Integer(0)), evaluate_source("(f 20)", &mut env));
+}
+
+#[test]
+fn exceeding_the_memory_limit() {
+    let mut env = create_global_environment();
+    // every closure keeps the frame holding the previous one alive
+    evaluate_source("(define (grow f) (grow (lambda () f)))", &mut env).unwrap();
+    let limits = EvalLimits::default().with_max_cells(gc::size() + 1000);
+    assert_eq!(LispErrorKind::OutOfMemory, limit_error("(grow 0)", limits, &mut env));
+    assert_eq!(Ok(LispOutput::Integer(3)), with_limits(&limits, || evaluate_source("(+ 1 2)", &mut env)));
+}
+
+#[test]
+fn list_cells_count_against_the_memory_limit() {
+    let mut env = create_global_environment();
+    let build = "(let loop ((i 0) (acc nil)) (if (< i 5000) (loop (+ i 1) (cons i acc)) (length acc)))";
+    let limits = EvalLimits::default().with_max_cells(gc::size() + 1000);
+    assert_eq!(LispErrorKind::OutOfMemory, limit_error(build, limits, &mut env));
+
+    let limits = EvalLimits::default().with_max_cells(gc::size() + 10000);
+    assert_eq!(Ok(LispOutput::Integer(5000)), with_limits(&limits, || evaluate_source(build, &mut env)));
+}
+
+#[test]
+fn running_past_the_deadline() {
+    let mut env = create_global_environment();
+    evaluate_source("(define (spin) (spin))", &mut env).unwrap();
+    let limits = EvalLimits::default().with_timeout(std::time::Duration::from_millis(20));
+    assert_eq!(LispErrorKind::Timeout, limit_error("(spin)", limits, &mut env));
+}
+
+#[test]
+fn limits_cannot_be_caught() {
+    let mut env = create_global_environment();
+    evaluate_source("(define (spin) (spin))", &mut env).unwrap();
+    let limits = EvalLimits::default().with_fuel(1000);
+    let source = "(guard (e (#t 0)) (with-exception-handler (lambda (e) 0) (lambda () (spin))))";
+    assert_eq!(LispErrorKind::OutOfFuel, limit_error(source, limits, &mut env));
+}
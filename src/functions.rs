@@ -1,23 +1,71 @@
-use std::fmt::Debug;
-
-use std::iter::zip;
-use std::rc::{Rc, Weak};
+use std::rc::Rc;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use crate::analyze::{analyze, Lambda, Node};
+use crate::compiler::{Proto, Upvalue};
+use crate::convert::NativeFunction;
 use crate::lisp_expression::LispExpression;
-use crate::evaluate::{LispOutput, Environment, evaluate};
+use crate::lisp_error::{LispError, LispResult};
+use crate::evaluate::{LispOutput, LispList, Environment};
+use crate::gc;
+use crate::machine::{call_function, execute, Continuation};
 
 
 pub trait LispFunctionCall {
-    fn call(&self, args: Vec<LispOutput>) -> LispOutput;
+    fn call(&self, args: Vec<LispOutput>) -> LispResult;
+}
+
+
+// -------------- ARITY --------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(count: usize) -> Self {
+        return Arity { min: count, max: Some(count) };
+    }
+
+    pub fn at_least(count: usize) -> Self {
+        return Arity { min: count, max: None };
+    }
+
+    pub fn between(min: usize, max: usize) -> Self {
+        return Arity { min, max: Some(max) };
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        return count >= self.min && self.max.is_none_or(|max| count <= max);
+    }
+
+    pub fn check(&self, count: usize) -> LispResult<()> {
+        if self.accepts(count) {
+            return Ok(());
+        }
+        return Err(LispError::arity(format!("procedure expects {self}, got {count}")));
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = |count: usize| if count == 1 { "argument" } else { "arguments" };
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{} {}", max, plural(max)),
+            Some(max) => write!(f, "between {} and {} arguments", self.min, max),
+            None => write!(f, "at least {} {}", self.min, plural(self.min)),
+        }
+    }
 }
 
 
 // -------------- BUILT IN FUNCTION --------------
 #[derive(Clone)]
 pub struct BuiltInFunction {
-    function: Rc<dyn Fn(Vec<LispOutput>) -> LispOutput>,
+    function: Rc<dyn Fn(Vec<LispOutput>) -> LispResult>,
+    arity: Arity,
 }
 
 
@@ -34,79 +82,469 @@ impl PartialEq for BuiltInFunction {
 }
 
 impl LispFunctionCall for BuiltInFunction {
-    fn call(&self, args: Vec<LispOutput>) -> LispOutput {
+    fn call(&self, args: Vec<LispOutput>) -> LispResult {
+        self.arity.check(args.len())?;
         return (self.function)(args);
     }
 }
 
 impl BuiltInFunction {
-    pub fn new(built_in_func: Rc<dyn Fn(Vec<LispOutput>) -> LispOutput>) -> Self {
+    pub fn new(built_in_func: Rc<dyn Fn(Vec<LispOutput>) -> LispResult>) -> Self {
+        return Self::with_arity(built_in_func, Arity::at_least(0));
+    }
+
+    pub fn with_arity(built_in_func: Rc<dyn Fn(Vec<LispOutput>) -> LispResult>, arity: Arity) -> Self {
         return BuiltInFunction {
             function: built_in_func,
+            arity,
+        }
+    }
+
+    /// A built-in implemented by a Rust function taking and returning values
+    /// that convert to and from Lisp, with its arity worked out from its
+    /// parameters.
+    pub fn native<Args>(function: impl NativeFunction<Args>) -> Self {
+        return function.into_built_in();
+    }
+
+    pub fn arity(&self) -> Arity {
+        return self.arity;
+    }
+}
+
+
+// -------------- PARAMETER LISTS --------------
+const OPTIONAL_MARKER: &str = "#!optional";
+const REST_MARKER: &str = "#!rest";
+const KEY_MARKER: &str = "#!key";
+const DOTTED_REST_MARKER: &str = ".";
+
+/// Prefix of keyword symbols such as `#:scale`, which evaluate to themselves.
+pub const KEYWORD_PREFIX: &str = "#:";
+
+/// A parsed lambda list.
+///
+/// Supports plain required parameters `(a b)`, a rest parameter written either
+/// `(a . rest)`, `(a #!rest rest)` or as a bare symbol `args`, optional
+/// parameters after `#!optional` and keyword parameters after `#!key`. Optional
+/// and keyword parameters may be written `(name default)`; their defaults are
+/// evaluated at call time with the earlier parameters in scope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameters {
+    required: Vec<String>,
+    optional: Vec<(String, Option<Rc<Node>>)>,
+    rest: Option<String>,
+    keywords: Vec<(String, Option<Rc<Node>>)>,
+}
+
+#[derive(PartialEq)]
+enum ParameterSection {
+    Required,
+    Optional,
+    Rest,
+    Keyword,
+}
+
+impl Parameters {
+    pub fn parse(parameters: &LispExpression) -> LispResult<Self> {
+        let mut parsed = Parameters {
+            required: vec![],
+            optional: vec![],
+            rest: None,
+            keywords: vec![],
+        };
+
+        let param_expressions = match parameters {
+            LispExpression::Symbol(rest) => {
+                parsed.rest = Some(rest.clone());
+                return Ok(parsed);
+            },
+            LispExpression::List(param_expressions) => param_expressions,
+            _ => return Err(LispError::syntax("parameters should be a list or a symbol")),
+        };
+
+        let mut section = ParameterSection::Required;
+        for param_expr in param_expressions {
+            if let LispExpression::Symbol(marker) = param_expr {
+                let next_section = match &marker[..] {
+                    OPTIONAL_MARKER => Some(ParameterSection::Optional),
+                    REST_MARKER | DOTTED_REST_MARKER => Some(ParameterSection::Rest),
+                    KEY_MARKER => Some(ParameterSection::Keyword),
+                    _ => None,
+                };
+                if let Some(next_section) = next_section {
+                    if parsed.rest.is_some() || next_section == section {
+                        return Err(LispError::syntax(format!("misplaced {marker} in parameter list")));
+                    }
+                    section = next_section;
+                    continue;
+                }
+            }
+
+            match section {
+                ParameterSection::Required => parsed.required.push(Self::parse_name(param_expr)?),
+                ParameterSection::Optional => parsed.optional.push(Self::parse_with_default(param_expr)?),
+                ParameterSection::Keyword => parsed.keywords.push(Self::parse_with_default(param_expr)?),
+                ParameterSection::Rest => {
+                    if parsed.rest.is_some() {
+                        return Err(LispError::syntax("only one rest parameter is allowed"));
+                    }
+                    parsed.rest = Some(Self::parse_name(param_expr)?);
+                },
+            }
+        }
+
+        if section == ParameterSection::Rest && parsed.rest.is_none() {
+            return Err(LispError::syntax("missing rest parameter name"));
+        }
+
+        let mut seen = HashSet::new();
+        for name in parsed.names() {
+            if !seen.insert(name) {
+                return Err(LispError::syntax(format!("duplicate parameter {name}")));
+            }
+        }
+
+        return Ok(parsed);
+    }
+
+    /// Whether `symbol` starts a section of a parameter list rather than
+    /// naming a parameter.
+    pub fn is_marker(symbol: &str) -> bool {
+        return matches!(symbol, OPTIONAL_MARKER | REST_MARKER | KEY_MARKER | DOTTED_REST_MARKER);
+    }
+
+    fn parse_name(param_expr: &LispExpression) -> LispResult<String> {
+        match param_expr {
+            LispExpression::Symbol(param) => Ok(param.clone()),
+            _ => Err(LispError::syntax("one or more parameters is not a LispExpression symbol")),
+        }
+    }
+
+    fn parse_with_default(param_expr: &LispExpression) -> LispResult<(String, Option<Rc<Node>>)> {
+        match param_expr {
+            LispExpression::List(pair) if pair.len() == 2 => {
+                Ok((Self::parse_name(&pair[0])?, Some(analyze(&pair[1])?)))
+            },
+            LispExpression::List(_) => Err(LispError::syntax("parameter defaults should be written (name default)")),
+            _ => Ok((Self::parse_name(param_expr)?, None)),
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        return self.required.iter()
+            .chain(self.optional.iter().map(|(name, _)| name))
+            .chain(self.rest.iter())
+            .chain(self.keywords.iter().map(|(name, _)| name));
+    }
+
+    /// Whether calls may pass `#:name value` pairs besides the positional
+    /// arguments.
+    pub fn takes_keywords(&self) -> bool {
+        return !self.keywords.is_empty();
+    }
+
+    /// Arity of the positional parameters; keyword arguments are not counted.
+    pub fn arity(&self) -> Arity {
+        let min = self.required.len();
+        return match self.rest {
+            Some(_) => Arity::at_least(min),
+            None => Arity::between(min, min + self.optional.len()),
+        };
+    }
+
+    /// Separates `#:name value` pairs from positional arguments. Only functions
+    /// declaring `#!key` parameters treat keywords specially.
+    fn split_keyword_arguments(
+        &self,
+        args: Vec<LispOutput>,
+    ) -> LispResult<(Vec<LispOutput>, HashMap<String, LispOutput>)> {
+        if self.keywords.is_empty() {
+            return Ok((args, HashMap::new()));
+        }
+
+        let mut positional = vec![];
+        let mut keyword_args = HashMap::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let keyword = match arg {
+                LispOutput::Keyword(keyword) => keyword,
+                other => {
+                    positional.push(other);
+                    continue;
+                },
+            };
+
+            if !self.keywords.iter().any(|(name, _)| *name == keyword) {
+                return Err(LispError::arity(format!("unknown keyword argument {KEYWORD_PREFIX}{keyword}")));
+            }
+            let value = match args.next() {
+                Some(value) => value,
+                None => return Err(LispError::arity(format!("missing value for keyword argument {KEYWORD_PREFIX}{keyword}"))),
+            };
+            if keyword_args.insert(keyword.clone(), value).is_some() {
+                return Err(LispError::arity(format!("keyword argument {KEYWORD_PREFIX}{keyword} supplied twice")));
+            }
+        }
+        return Ok((positional, keyword_args));
+    }
+
+    /// Default expressions, in the same order as `names`.
+    pub fn defaults(&self) -> Vec<Option<&Rc<Node>>> {
+        return self.required.iter().map(|_| None)
+            .chain(self.optional.iter().map(|(_, default)| default.as_ref()))
+            .chain(self.rest.iter().map(|_| None))
+            .chain(self.keywords.iter().map(|(_, default)| default.as_ref()))
+            .collect();
+    }
+
+    /// Matches `args` against the parameters, giving one value per name in the
+    /// order of `names`. Missing arguments are `Void`, unless the parameter has
+    /// a default, in which case they are left for the caller to evaluate.
+    pub fn match_arguments(&self, args: Vec<LispOutput>) -> LispResult<Vec<Option<LispOutput>>> {
+        let (positional, mut keyword_args) = self.split_keyword_arguments(args)?;
+        self.arity().check(positional.len())?;
+
+        let missing = |default: &Option<Rc<Node>>| match default {
+            Some(_) => None,
+            None => Some(LispOutput::Void),
+        };
+
+        let mut values = Vec::new();
+        let mut positional = positional.into_iter();
+        for _ in &self.required {
+            values.push(positional.next());
+        }
+
+        for (_, default) in &self.optional {
+            values.push(positional.next().or_else(|| missing(default)));
+        }
+
+        if self.rest.is_some() {
+            values.push(Some(LispOutput::List(Box::new(LispList::build(positional)))));
+        }
+
+        for (name, default) in &self.keywords {
+            values.push(keyword_args.remove(name).or_else(|| missing(default)));
+        }
+
+        return Ok(values);
+    }
+
+    /// Binds `args` in the first slots of `env`, evaluating defaults for
+    /// missing optional and keyword arguments in `env` itself.
+    pub fn bind(&self, args: Vec<LispOutput>, env: &Rc<RefCell<Environment>>) -> LispResult<()> {
+        let values = self.match_arguments(args)?;
+        for (index, (value, default)) in values.into_iter().zip(self.defaults()).enumerate() {
+            let value = match (value, default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => execute(default.clone(), env.clone())?,
+                (None, None) => LispOutput::Void,
+            };
+            env.borrow_mut().set_slot(index, value);
         }
+        return Ok(());
+    }
+
+    /// The same parameters with `resolve` applied to their defaults.
+    pub fn map_defaults(&self, mut resolve: impl FnMut(&Rc<Node>) -> Rc<Node>) -> Self {
+        let mut resolve_all = |parameters: &[(String, Option<Rc<Node>>)]| {
+            parameters.iter()
+                .map(|(name, default)| (name.clone(), default.as_ref().map(&mut resolve)))
+                .collect()
+        };
+        return Parameters {
+            required: self.required.clone(),
+            optional: resolve_all(&self.optional),
+            rest: self.rest.clone(),
+            keywords: resolve_all(&self.keywords),
+        };
     }
 }
 
 
 // -------------- USER FUNCTION --------------
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Function {
-    parameters: Vec<String>,
-    body: LispExpression,
-    enclosing_frame: Weak<RefCell<Environment>>,
+    lambda: Rc<Lambda>,
+    enclosing_frame: Rc<RefCell<Environment>>,
 }
 
 
+impl std::fmt::Debug for Function {
+    // the enclosing frame usually contains the function itself, so it is left
+    // out to avoid recursing forever
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("parameters", &self.lambda.parameters)
+            .field("body", &self.lambda.body)
+            .finish()
+    }
+}
+
 impl PartialEq for Function {
     fn eq(&self, other: &Self) -> bool {
-        self.parameters == other.parameters
-            && self.body == other.body
-            && Rc::ptr_eq(&self.enclosing_frame.upgrade().unwrap(), &other.enclosing_frame.upgrade().unwrap())
+        self.lambda == other.lambda
+            && Rc::ptr_eq(&self.enclosing_frame, &other.enclosing_frame)
     }
 }
 
 impl LispFunctionCall for Function {
-    fn call(&self, args: Vec<LispOutput>) -> LispOutput {
-        
-        let mut bindings = HashMap::new();
+    fn call(&self, args: Vec<LispOutput>) -> LispResult {
+        return call_function(LispFunction::Function(self.clone()), args);
+    }
+}
 
-        for (param, arg) in zip(&self.parameters, args) {
-            bindings.insert(param.clone(), arg);
-        }
+impl Function {
+    pub fn new(lambda: Rc<Lambda>, enclosing_frame: Rc<RefCell<Environment>>) -> Self {
+        return Self {
+            lambda,
+            enclosing_frame,
+        };
+    }
 
-        let mut new_env = Rc::new(RefCell::new(
-            Environment {
-                parent_env: Some(self.enclosing_frame.upgrade().unwrap().clone()),
-                bindings,
-            }
-        ));
+    pub fn lambda(&self) -> &Rc<Lambda> {
+        return &self.lambda;
+    }
+
+    pub fn enclosing_frame(&self) -> &Rc<RefCell<Environment>> {
+        return &self.enclosing_frame;
+    }
+
+    pub fn body(&self) -> Rc<Node> {
+        return self.lambda.body.clone();
+    }
+
+    pub fn arity(&self) -> Arity {
+        return self.lambda.parameters.arity();
+    }
 
-        return evaluate(&self.body, &mut new_env);
+    /// Creates the frame a call to this function evaluates its body in.
+    pub fn bind_arguments(&self, args: Vec<LispOutput>) -> LispResult<Rc<RefCell<Environment>>> {
+        let new_env = gc::allocate(Environment::frame(self.lambda.slots.clone(), [], &self.enclosing_frame));
+        self.lambda.parameters.bind(args, &new_env)?;
+        return Ok(new_env);
     }
 }
 
-impl Function {
-    pub fn build(
-        parameters: LispExpression, 
-        body: LispExpression, 
-        enclosing_frame: Rc<RefCell<Environment>>
-    ) -> Self {
-            let mut params = vec![];
-            if let LispExpression::List(param_expressions) = parameters {
-                for param_expr in &param_expressions {
-                    match &param_expr {
-                        LispExpression::Symbol(param) => params.push(param.clone()),
-                        _ => panic!("one or more parameters is not a LispExpression symbol"),
-                    };
-                }
-            } else {
-                panic!("parameters should be a list");
-            }
-            return Self {
-                parameters: params,
-                body,
-                enclosing_frame: Rc::downgrade(&enclosing_frame),
-            };
+
+// -------------- COMPILED FUNCTION --------------
+/// A procedure made by compiled code: the compiled body of its `lambda`, the
+/// variables it captured and the environment its free variables are looked up
+/// in.
+#[derive(Clone)]
+pub struct Closure {
+    proto: Rc<Proto>,
+    upvalues: Rc<[Upvalue]>,
+    env: Rc<RefCell<Environment>>,
+}
+
+impl std::fmt::Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Closure")
+            .field("parameters", &self.lambda().parameters)
+            .field("body", &self.lambda().body)
+            .finish()
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.proto, &other.proto)
+            && Rc::ptr_eq(&self.upvalues, &other.upvalues)
+            && Rc::ptr_eq(&self.env, &other.env)
+    }
+}
+
+impl LispFunctionCall for Closure {
+    fn call(&self, args: Vec<LispOutput>) -> LispResult {
+        return call_function(LispFunction::Closure(self.clone()), args);
+    }
+}
+
+impl Closure {
+    pub fn new(proto: Rc<Proto>, upvalues: Rc<[Upvalue]>, env: Rc<RefCell<Environment>>) -> Self {
+        return Self {
+            proto,
+            upvalues,
+            env,
+        };
+    }
+
+    pub fn lambda(&self) -> &Rc<Lambda> {
+        return self.proto.lambda.as_ref().expect("closures are made from lambdas");
+    }
+
+    pub fn proto(&self) -> &Rc<Proto> {
+        return &self.proto;
+    }
+
+    pub fn upvalues(&self) -> &Rc<[Upvalue]> {
+        return &self.upvalues;
+    }
+
+    pub fn env(&self) -> &Rc<RefCell<Environment>> {
+        return &self.env;
+    }
+
+    pub fn arity(&self) -> Arity {
+        return self.lambda().parameters.arity();
+    }
+}
+
+
+// -------------- INTRINSIC FUNCTION --------------
+/// Procedures that need the evaluator itself rather than just their arguments,
+/// such as the current environment, the current continuation or the ability to
+/// continue evaluation in tail position. They are applied by the `machine`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic {
+    Apply,
+    Eval,
+    Load,
+    InteractionEnvironment,
+    CallWithCurrentContinuation,
+    CallWithEscapeContinuation,
+    DynamicWind,
+    Raise,
+    RaiseContinuable,
+    WithExceptionHandler,
+    /// Pauses evaluation in the `debugger`, if there is one.
+    Break,
+}
+
+impl Intrinsic {
+    pub fn arity(&self) -> Arity {
+        match self {
+            Intrinsic::Apply => Arity::at_least(2),
+            Intrinsic::Eval => Arity::between(1, 2),
+            Intrinsic::Load => Arity::between(1, 2),
+            Intrinsic::InteractionEnvironment => Arity::exactly(0),
+            Intrinsic::CallWithCurrentContinuation => Arity::exactly(1),
+            Intrinsic::CallWithEscapeContinuation => Arity::exactly(1),
+            Intrinsic::DynamicWind => Arity::exactly(3),
+            Intrinsic::Raise => Arity::exactly(1),
+            Intrinsic::RaiseContinuable => Arity::exactly(1),
+            Intrinsic::WithExceptionHandler => Arity::exactly(2),
+            Intrinsic::Break => Arity::exactly(0),
+        }
+    }
+
+    /// The name the intrinsic is bound to in the global environment.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Intrinsic::Apply => "apply",
+            Intrinsic::Eval => "eval",
+            Intrinsic::Load => "load",
+            Intrinsic::InteractionEnvironment => "interaction-environment",
+            Intrinsic::CallWithCurrentContinuation => "call-with-current-continuation",
+            Intrinsic::CallWithEscapeContinuation => "call-with-escape-continuation",
+            Intrinsic::DynamicWind => "dynamic-wind",
+            Intrinsic::Raise => "raise",
+            Intrinsic::RaiseContinuable => "raise-continuable",
+            Intrinsic::WithExceptionHandler => "with-exception-handler",
+            Intrinsic::Break => "break",
+        }
     }
 }
 
@@ -116,13 +554,28 @@ impl Function {
 pub enum LispFunction {
     BuiltInFunction(BuiltInFunction),
     Function(Function),
+    Closure(Closure),
+    Intrinsic(Intrinsic),
+    Continuation(Continuation),
+}
+
+impl LispFunction {
+    pub fn arity(&self) -> Arity {
+        match self {
+            LispFunction::BuiltInFunction(function) => function.arity(),
+            LispFunction::Function(function) => function.arity(),
+            LispFunction::Closure(closure) => closure.arity(),
+            LispFunction::Intrinsic(intrinsic) => intrinsic.arity(),
+            LispFunction::Continuation(_) => Arity::between(0, 1),
+        }
+    }
 }
 
 impl LispFunctionCall for LispFunction {
-    fn call(&self, args: Vec<LispOutput>) -> LispOutput {
+    fn call(&self, args: Vec<LispOutput>) -> LispResult {
         match self {
             LispFunction::BuiltInFunction(function) => function.call(args),
-            LispFunction::Function(function) => function.call(args),
+            _ => call_function(self.clone(), args),
         }
     }
-}
\ No newline at end of file
+}
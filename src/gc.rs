@@ -0,0 +1,422 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::compiler::Upvalue;
+use crate::evaluate::{Environment, LispList, LispOutput};
+use crate::functions::LispFunction;
+
+
+/// Collections run once this many objects have been allocated since the last
+/// one, or twice the number of objects that survived it and list cells it
+/// walked if that is more.
+const MINIMUM_THRESHOLD: usize = 4096;
+
+type Env = Rc<RefCell<Environment>>;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::new());
+    // list cells are freed by reference counting alone, so they are only counted
+    static LIST_CELLS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counters reported by `(gc-stats)`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    /// Objects allocated since the interpreter started.
+    pub allocated: usize,
+    /// Objects freed by collections.
+    pub collected: usize,
+    /// Objects currently alive.
+    pub live: usize,
+}
+
+/// The objects values can form cycles through: environment frames, and the
+/// cells compiled closures share their captured variables in. Everything else
+/// a value holds is either owned outright or immutable, so reference counting
+/// frees it; this heap only keeps weak references, to find the cycles that
+/// reference counting cannot free.
+struct Heap {
+    environments: Vec<Weak<RefCell<Environment>>>,
+    cells: Vec<Weak<RefCell<Option<LispOutput>>>>,
+    since_collection: usize,
+    threshold: usize,
+    stats: GcStats,
+}
+
+impl Heap {
+    fn new() -> Self {
+        return Heap {
+            environments: Vec::new(),
+            cells: Vec::new(),
+            since_collection: 0,
+            threshold: MINIMUM_THRESHOLD,
+            stats: GcStats::default(),
+        };
+    }
+
+    /// Counts an allocation, returning whether a collection is due.
+    fn allocated(&mut self) -> bool {
+        self.stats.allocated += 1;
+        self.since_collection += 1;
+        return self.since_collection >= self.threshold;
+    }
+
+    fn live(&self) -> usize {
+        let environments = self.environments.iter().filter(|env| env.strong_count() > 0).count();
+        let cells = self.cells.iter().filter(|cell| cell.strong_count() > 0).count();
+        return environments + cells;
+    }
+}
+
+
+// -------------- ALLOCATION --------------
+/// Puts `env` on the heap, collecting first if enough has been allocated.
+pub fn allocate(env: Environment) -> Env {
+    let env = Rc::new(RefCell::new(env));
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.push(Rc::downgrade(&env));
+        heap.allocated()
+    });
+    if due {
+        collect();
+    }
+    return env;
+}
+
+/// Puts a cell for a captured variable on the heap.
+pub fn allocate_cell(value: Option<LispOutput>) -> Upvalue {
+    let cell = Rc::new(RefCell::new(value));
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.cells.push(Rc::downgrade(&cell));
+        heap.allocated()
+    });
+    if due {
+        collect();
+    }
+    return cell;
+}
+
+/// Counts a list cell made.
+pub fn list_cell_made() {
+    LIST_CELLS.with(|cells| cells.set(cells.get() + 1));
+}
+
+/// Counts a list cell freed.
+pub fn list_cell_freed() {
+    LIST_CELLS.with(|cells| cells.set(cells.get().saturating_sub(1)));
+}
+
+/// The number of objects on the heap, including any that a collection would
+/// free, and of list cells.
+pub fn size() -> usize {
+    return HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.environments.len() + heap.cells.len()
+    }) + LIST_CELLS.with(Cell::get);
+}
+
+pub fn stats() -> GcStats {
+    return HEAP.with(|heap| {
+        let heap = heap.borrow();
+        GcStats { live: heap.live(), ..heap.stats }
+    });
+}
+
+
+// -------------- COLLECTION --------------
+#[derive(Clone)]
+enum Object {
+    Environment(Env),
+    Cell(Upvalue),
+    /// The captured variables of one or more copies of a compiled closure.
+    /// They are not allocated on the heap, but cycles can run through them.
+    Upvalues(Rc<[Upvalue]>),
+    /// The tail of a list that is shared with other lists, so that it is
+    /// walked once and what it refers to is kept while any of them is.
+    List(Rc<LispList>),
+}
+
+struct Node {
+    object: Object,
+    /// Strong references to the object, other than the collector's own.
+    references: usize,
+    /// References to the object from other objects on the heap.
+    internal: usize,
+    children: Vec<usize>,
+    /// Whether the object was in use, so its children could not be traced.
+    busy: bool,
+    marked: bool,
+}
+
+fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    return Rc::as_ptr(rc) as *const () as usize;
+}
+
+/// The heap as a graph, with an edge for every strong reference one object
+/// holds to another.
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    index: HashMap<usize, usize>,
+    /// The list cells walked, which collections are spaced out by too.
+    cells: usize,
+}
+
+impl Graph {
+    fn add(&mut self, address: usize, object: Object, references: usize) {
+        self.index.insert(address, self.nodes.len());
+        self.nodes.push(Node {
+            object,
+            references,
+            internal: 0,
+            children: Vec::new(),
+            busy: false,
+            marked: false,
+        });
+    }
+
+    fn trace(&mut self, index: usize) {
+        let mut children = Vec::new();
+        let busy = match self.nodes[index].object.clone() {
+            Object::Environment(env) => env.try_borrow().map(|env| {
+                for value in env.values() {
+                    self.value(value, &mut children);
+                }
+                if let Some(parent) = &env.parent_env {
+                    children.push(address(parent));
+                }
+            }).is_err(),
+            Object::Cell(cell) => cell.try_borrow().map(|value| {
+                if let Some(value) = &*value {
+                    self.value(value, &mut children);
+                }
+            }).is_err(),
+            Object::Upvalues(upvalues) => {
+                children.extend(upvalues.iter().map(address));
+                false
+            },
+            Object::List(list) => {
+                self.list(&list, &mut children);
+                false
+            },
+        };
+        self.nodes[index].children = children;
+        self.nodes[index].busy = busy;
+    }
+
+    /// Adds the heap objects `value` refers to to `children`. Continuations
+    /// and error objects are not looked into: what they refer to counts as
+    /// referenced from outside the heap, and so is never collected while
+    /// they are alive.
+    fn value(&mut self, value: &LispOutput, children: &mut Vec<usize>) {
+        match value {
+            LispOutput::Lambda(LispFunction::Function(function)) => {
+                children.push(address(function.enclosing_frame()));
+            },
+            LispOutput::Lambda(LispFunction::Closure(closure)) => {
+                let upvalues = closure.upvalues();
+                if !self.index.contains_key(&address(upvalues)) {
+                    // counted before the collector holds a reference itself
+                    let references = Rc::strong_count(upvalues);
+                    self.add(address(upvalues), Object::Upvalues(upvalues.clone()), references);
+                }
+                children.push(address(upvalues));
+                children.push(address(closure.env()));
+            },
+            LispOutput::Environment(env) => children.push(address(&env.0)),
+            LispOutput::List(list) => self.list(list, children),
+            _ => {},
+        }
+    }
+
+    /// Walks `list` up to the first tail it shares with another list, which
+    /// becomes a node of its own.
+    fn list(&mut self, list: &LispList, children: &mut Vec<usize>) {
+        let mut list = list;
+        while let LispList::Cons(car, cdr) = list {
+            self.value(car, children);
+            self.cells += 1;
+            if Rc::strong_count(cdr) > 1 {
+                if !self.index.contains_key(&address(cdr)) {
+                    let references = Rc::strong_count(cdr);
+                    self.add(address(cdr), Object::List(cdr.clone()), references);
+                }
+                children.push(address(cdr));
+                return;
+            }
+            list = cdr;
+        }
+    }
+}
+
+/// Frees the objects on the heap that nothing outside of it can reach, and
+/// returns how many there were.
+///
+/// The roots are the objects referenced from outside the heap: from the
+/// frames on the evaluator stack, from the global environment, or from values
+/// held by running built-ins. Rather than walking all of those, the collector
+/// counts the references each object gets from the rest of the heap; an object
+/// with more references than that is a root. Everything reachable from the
+/// roots is marked, and the frames and cells left unmarked are emptied, which
+/// breaks the cycles keeping them alive.
+pub fn collect() -> usize {
+    let mut graph = Graph::default();
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        for env in heap.environments.iter().filter_map(Weak::upgrade) {
+            let references = Rc::strong_count(&env) - 1;
+            graph.add(address(&env), Object::Environment(env), references);
+        }
+        for cell in heap.cells.iter().filter_map(Weak::upgrade) {
+            let references = Rc::strong_count(&cell) - 1;
+            graph.add(address(&cell), Object::Cell(cell), references);
+        }
+    });
+
+    // tracing can discover more nodes, so the length is checked every time
+    let mut index = 0;
+    while index < graph.nodes.len() {
+        graph.trace(index);
+        index += 1;
+    }
+    for index in 0..graph.nodes.len() {
+        for child in graph.nodes[index].children.clone() {
+            if let Some(&child) = graph.index.get(&child) {
+                graph.nodes[child].internal += 1;
+            }
+        }
+    }
+
+    let mut pending: Vec<usize> = graph.nodes.iter()
+        .enumerate()
+        .filter(|(_, node)| node.busy || node.references > node.internal)
+        .map(|(index, _)| index)
+        .collect();
+    while let Some(index) = pending.pop() {
+        if graph.nodes[index].marked {
+            continue;
+        }
+        graph.nodes[index].marked = true;
+        pending.extend(graph.nodes[index].children.iter().filter_map(|child| graph.index.get(child)));
+    }
+
+    // the contents are dropped only once no frame is borrowed
+    let mut environments = Vec::new();
+    let mut cells = Vec::new();
+    for node in graph.nodes.iter().filter(|node| !node.marked) {
+        match &node.object {
+            Object::Environment(env) => environments.push(std::mem::take(&mut *env.borrow_mut())),
+            Object::Cell(cell) => cells.push(cell.borrow_mut().take()),
+            Object::Upvalues(_) | Object::List(_) => {},
+        }
+    }
+    let collected = environments.len() + cells.len();
+    let list_cells = graph.cells;
+    drop(environments);
+    drop(cells);
+    drop(graph);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.retain(|env| env.strong_count() > 0);
+        heap.cells.retain(|cell| cell.strong_count() > 0);
+        heap.since_collection = 0;
+        heap.threshold = MINIMUM_THRESHOLD.max(2 * (heap.environments.len() + heap.cells.len() + list_cells));
+        heap.stats.collections += 1;
+        heap.stats.collected += collected;
+    });
+    return collected;
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate::{evaluate, evaluate_compiled};
+    use crate::functions::LispFunctionCall;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    const MAKE_CYCLE: &str = "(define (make-cycle) (begin (define (self) self) self))";
+
+    fn run(source: &str, env: &mut Env, compiled: bool) -> LispOutput {
+        let expr = parse(&tokenize(source));
+        let result = match compiled {
+            true => evaluate_compiled(&expr, env),
+            false => evaluate(&expr, env),
+        };
+        return result.unwrap();
+    }
+
+    #[test]
+    fn unreachable_cycles_are_collected() {
+        let mut env = Rc::new(RefCell::new(Environment::global_env()));
+        run(MAKE_CYCLE, &mut env, false);
+        let frame = match run("(make-cycle)", &mut env, false) {
+            LispOutput::Lambda(LispFunction::Function(function)) => Rc::downgrade(function.enclosing_frame()),
+            other => panic!("expected a function, got {other:?}"),
+        };
+
+        assert!(frame.upgrade().is_some());
+        collect();
+        assert!(frame.upgrade().is_none());
+    }
+
+    #[test]
+    fn compiled_cycles_are_collected() {
+        let mut env = Rc::new(RefCell::new(Environment::global_env()));
+        run(MAKE_CYCLE, &mut env, true);
+        let cell = match run("(make-cycle)", &mut env, true) {
+            LispOutput::Lambda(LispFunction::Closure(closure)) => Rc::downgrade(&closure.upvalues()[0]),
+            other => panic!("expected a closure, got {other:?}"),
+        };
+
+        assert!(cell.upgrade().is_some());
+        collect();
+        assert!(cell.upgrade().is_none());
+    }
+
+    #[test]
+    fn reachable_cycles_survive() {
+        let mut env = Rc::new(RefCell::new(Environment::global_env()));
+        run(MAKE_CYCLE, &mut env, false);
+        let kept = run("(make-cycle)", &mut env, false);
+        run("(define kept (make-cycle))", &mut env, false);
+
+        collect();
+        assert!(matches!(run("((kept))", &mut env, false), LispOutput::Lambda(_)));
+        match kept {
+            LispOutput::Lambda(function) => assert!(matches!(function.call(vec![]), Ok(LispOutput::Lambda(_)))),
+            other => panic!("expected a function, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn shared_list_tails_keep_what_they_hold() {
+        for compiled in [false, true] {
+            let mut env = Rc::new(RefCell::new(Environment::global_env()));
+            run("(define (keep l) (begin (define (self) (list self l)) self))", &mut env, compiled);
+            let held = match run("(list 1 2 (let ((x 42)) (lambda () x)))", &mut env, compiled) {
+                LispOutput::List(list) => list,
+                other => panic!("expected a list, got {other:?}"),
+            };
+            // a garbage cycle holding all but the first cell of the list
+            match run("keep", &mut env, compiled) {
+                LispOutput::Lambda(keep) => drop(keep.call(vec![held.get_cdr().unwrap()]).unwrap()),
+                other => panic!("expected a function, got {other:?}"),
+            }
+
+            collect();
+            match held.get(2) {
+                Ok(LispOutput::Lambda(function)) => assert_eq!(function.call(vec![]), Ok(LispOutput::Integer(42))),
+                other => panic!("expected a function, got {other:?}"),
+            }
+        }
+    }
+}
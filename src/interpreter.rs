@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::capability::Capabilities;
+use crate::convert::NativeFunction;
+use crate::debugger::{debugging, DebuggerRef};
+use crate::evaluate::{evaluate_compiled_with_spans, evaluate_with_spans, Environment, LispOutput};
+use crate::functions::{Arity, BuiltInFunction, LispFunction, LispFunctionCall};
+use crate::library::{read_source, with_libraries, Libraries};
+use crate::limits::{with_limits, EvalLimits};
+use crate::port::{with_io, FileAccess, Io, PortRef};
+use crate::lisp_error::{LispError, LispResult};
+use crate::parser::parse_all_with_spans;
+use crate::tokenizer::try_tokenize_with_spans;
+
+
+/// A Lisp interpreter for embedding in Rust programs. It owns a global
+/// environment that lives as long as it does, so definitions made by one
+/// evaluation are seen by the next.
+pub struct Interpreter {
+    env: Rc<RefCell<Environment>>,
+    compiled: bool,
+    limits: EvalLimits,
+    libraries: Rc<RefCell<Libraries>>,
+    io: Io,
+    debugger: Option<DebuggerRef>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with the built-ins and nothing else, walking the code
+    /// it is given.
+    pub fn new() -> Self {
+        return Self::sandboxed(Capabilities::all());
+    }
+
+    /// An interpreter whose code, including that of the libraries it imports,
+    /// can only use the built-ins of `capabilities`. Calling any of the
+    /// others fails with a `CapabilityDenied` error.
+    pub fn sandboxed(capabilities: Capabilities) -> Self {
+        let mut libraries = Libraries::default();
+        libraries.set_capabilities(capabilities);
+        return Interpreter {
+            env: Rc::new(RefCell::new(Environment::global_env_with(capabilities))),
+            compiled: false,
+            limits: EvalLimits::default(),
+            libraries: Rc::new(RefCell::new(libraries)),
+            io: Io::default(),
+            debugger: None,
+        };
+    }
+
+    /// Makes the interpreter compile code to bytecode and run it on the
+    /// virtual machine instead.
+    pub fn compiled(self, compiled: bool) -> Self {
+        return Interpreter { compiled, ..self };
+    }
+
+    /// Makes every later evaluation run under `limits`.
+    pub fn with_limits(self, limits: EvalLimits) -> Self {
+        return Interpreter { limits, ..self };
+    }
+
+    pub fn set_limits(&mut self, limits: EvalLimits) {
+        self.limits = limits;
+    }
+
+    /// Sets what Lisp code may do with files: open them with
+    /// `open-input-file` and the like, or `load` them. Access is unrestricted
+    /// by default.
+    pub fn with_file_access(mut self, file_access: FileAccess) -> Self {
+        self.set_file_access(file_access);
+        return self;
+    }
+
+    pub fn set_file_access(&mut self, file_access: FileAccess) {
+        self.io.file_access = file_access;
+    }
+
+    /// Only lets Lisp code open files inside `directory`, or inside its
+    /// subdirectories.
+    pub fn with_allowed_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.io.allowed_directory = Some(directory.into());
+        return self;
+    }
+
+    /// Makes `read` and the other procedures reading input use `port` by
+    /// default instead of standard input.
+    pub fn with_input(mut self, port: PortRef) -> Self {
+        self.io.input = port;
+        return self;
+    }
+
+    /// Makes `display` and the other procedures writing output use `port`
+    /// by default instead of standard output, for instance a string port
+    /// made by `PortRef::output_string` to capture what is printed.
+    pub fn with_output(mut self, port: PortRef) -> Self {
+        self.io.output = port;
+        return self;
+    }
+
+    /// Makes `import` also look for libraries in `directory`, after the
+    /// current directory and the directories added before it.
+    pub fn with_library_path(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.add_library_path(directory);
+        return self;
+    }
+
+    pub fn add_library_path(&mut self, directory: impl Into<PathBuf>) {
+        self.libraries.borrow_mut().add_search_path(directory);
+    }
+
+    /// The libraries imported so far and where others are looked for.
+    pub fn libraries(&self) -> &Rc<RefCell<Libraries>> {
+        return &self.libraries;
+    }
+
+    /// Makes evaluation pause in `debugger` at `(break)`, at the breakpoints
+    /// it sets and while it is stepping.
+    pub fn with_debugger(mut self, debugger: DebuggerRef) -> Self {
+        self.set_debugger(Some(debugger));
+        return self;
+    }
+
+    pub fn set_debugger(&mut self, debugger: Option<DebuggerRef>) {
+        self.debugger = debugger;
+    }
+
+    /// The global environment.
+    pub fn environment(&self) -> &Rc<RefCell<Environment>> {
+        return &self.env;
+    }
+
+    /// Evaluates every expression in `source` in turn, returning the value of
+    /// the last one, or `Void` if there are none. Evaluation stops at the
+    /// first error.
+    pub fn eval_str(&mut self, source: &str) -> LispResult {
+        let (tokens, spans) = try_tokenize_with_spans(source)?;
+        let expressions = parse_all_with_spans(&tokens, &spans)?;
+        let mut value = LispOutput::Void;
+        for (tree, span_tree) in expressions {
+            let compiled = self.compiled;
+            let env = &mut self.env;
+            let debugger = self.debugger.clone();
+            value = with_io(&self.io, || with_libraries(&self.libraries, || debugging(debugger, || with_limits(&self.limits, || match compiled {
+                true => evaluate_compiled_with_spans(&tree, &span_tree, env),
+                false => evaluate_with_spans(&tree, &span_tree, env),
+            }))))?;
+        }
+        return Ok(value);
+    }
+
+    /// Evaluates the expressions in the file at `path`, like `eval_str`.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> LispResult {
+        let source = read_source(path.as_ref())?;
+        return self.eval_str(&source);
+    }
+
+    /// Calls the procedure bound to `name` in the global environment.
+    pub fn call_function(&mut self, name: &str, args: Vec<LispOutput>) -> LispResult {
+        let function = match self.env.borrow().get(name)? {
+            LispOutput::Lambda(function) => function,
+            other => return Err(LispError::type_mismatch(format!("{name} is not a procedure: {other}"))),
+        };
+        let debugger = self.debugger.clone();
+        return with_io(&self.io, || with_libraries(&self.libraries, || debugging(debugger, || {
+            with_limits(&self.limits, || function.call(args))
+        })));
+    }
+
+    /// Binds `name` to `value` in the global environment, replacing any
+    /// earlier definition.
+    pub fn define_global(&mut self, name: &str, value: LispOutput) {
+        self.env.borrow_mut().set(name, &value);
+    }
+
+    /// The value bound to `name` in the global environment, including the
+    /// built-ins.
+    pub fn get_global(&self, name: &str) -> Option<LispOutput> {
+        return self.env.borrow().get(name).ok();
+    }
+
+    /// Defines `name` as a procedure implemented by `function`, which is only
+    /// called with a number of arguments accepted by `arity`.
+    pub fn register_builtin(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(Vec<LispOutput>) -> LispResult + 'static,
+    ) {
+        let built_in = BuiltInFunction::with_arity(Rc::new(function), arity);
+        self.define_global(name, LispOutput::Lambda(LispFunction::BuiltInFunction(built_in)));
+    }
+
+    /// Defines `name` as a procedure implemented by a Rust function with typed
+    /// parameters, such as `|n: i64, name: String| -> Result<Vec<i64>, String>`.
+    /// Its arity follows from its parameters, and arguments that do not
+    /// convert to their types are type errors.
+    pub fn register_function<Args>(&mut self, name: &str, function: impl NativeFunction<Args>) {
+        let built_in = BuiltInFunction::native(function);
+        self.define_global(name, LispOutput::Lambda(LispFunction::BuiltInFunction(built_in)));
+    }
+}
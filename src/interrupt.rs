@@ -0,0 +1,119 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::lisp_error::{LispError, LispErrorKind, LispResult};
+
+
+/// Set by Ctrl-C, or by `request` from another thread.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static INTERRUPTIBLE: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(unix)]
+mod sigint {
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: i32 = 2;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    // only async-signal-safe work is allowed here, which an atomic store is
+    extern "C" fn handle(_signum: i32) {
+        super::INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    pub fn install() {
+        // SAFETY: `handle` only stores to an atomic
+        unsafe {
+            signal(SIGINT, handle);
+        }
+    }
+}
+
+/// Makes Ctrl-C interrupt the evaluation in progress instead of ending the
+/// process. Does nothing on platforms other than Unix.
+pub fn install_handler() {
+    #[cfg(unix)]
+    sigint::install();
+}
+
+/// Asks the interruptible evaluation in progress to stop.
+pub fn request() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Runs `evaluation` so that an interrupt stops it with an error of kind
+/// `Interrupted`. Like exceeding one of the `EvalLimits`, the error cannot be
+/// caught by Lisp code. Interrupts made before the evaluation started are
+/// forgotten.
+pub fn interruptible<T>(evaluation: impl FnOnce() -> LispResult<T>) -> LispResult<T> {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let outer = INTERRUPTIBLE.with(|interruptible| interruptible.replace(true));
+    let result = evaluation();
+    INTERRUPTIBLE.with(|interruptible| interruptible.set(outer));
+    return result;
+}
+
+/// Runs `evaluation` so that interrupts wait until it has finished, for work
+/// done on behalf of the interpreter that must not be left half done.
+pub fn uninterruptible<T>(evaluation: impl FnOnce() -> T) -> T {
+    let outer = INTERRUPTIBLE.with(|interruptible| interruptible.replace(false));
+    let result = evaluation();
+    INTERRUPTIBLE.with(|interruptible| interruptible.set(outer));
+    return result;
+}
+
+/// Fails if the evaluation running on this thread has been interrupted.
+pub fn check() -> LispResult<()> {
+    if INTERRUPTIBLE.with(Cell::get) && INTERRUPTED.swap(false, Ordering::SeqCst) {
+        return Err(LispError::new(LispErrorKind::Interrupted, "evaluation interrupted"));
+    }
+    return Ok(());
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::evaluate::{evaluate, evaluate_compiled, Engine, Environment, LispOutput};
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    #[test]
+    fn interrupting_a_loop_keeps_the_environment() {
+        let engines: [Engine; 2] = [evaluate, evaluate_compiled];
+        for engine in engines {
+            let mut env = Rc::new(RefCell::new(Environment::global_env()));
+            engine(&parse(&tokenize("(define x 42)")), &mut env).unwrap();
+            engine(&parse(&tokenize("(define (spin) (spin))")), &mut env).unwrap();
+
+            let interrupter = thread::spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                request();
+            });
+            let result = interruptible(|| engine(&parse(&tokenize("(guard (e (#t 0)) (spin))")), &mut env));
+            interrupter.join().unwrap();
+
+            assert_eq!(LispErrorKind::Interrupted, result.unwrap_err().kind);
+            assert_eq!(Ok(LispOutput::Integer(42)), engine(&parse(&tokenize("x")), &mut env));
+        }
+    }
+
+    #[test]
+    fn evaluations_that_are_not_interruptible_ignore_interrupts() {
+        let mut env = Rc::new(RefCell::new(Environment::global_env()));
+        request();
+        assert_eq!(Ok(LispOutput::Integer(3)), evaluate(&parse(&tokenize("(+ 1 2)")), &mut env));
+    }
+}
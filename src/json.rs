@@ -0,0 +1,429 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+
+/// A JSON value, for the protocols spoken to editors. Objects keep their
+/// fields in the order they were written, though that order does not matter
+/// to equality.
+#[derive(Debug, Clone, Default)]
+pub enum Json {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+static NULL: Json = Json::Null;
+
+impl PartialEq for Json {
+    fn eq(&self, other: &Self) -> bool {
+        return match (self, other) {
+            (Json::Null, Json::Null) => true,
+            (Json::Bool(a), Json::Bool(b)) => a == b,
+            (Json::Number(a), Json::Number(b)) => a == b,
+            (Json::String(a), Json::String(b)) => a == b,
+            (Json::Array(a), Json::Array(b)) => a == b,
+            (Json::Object(a), Json::Object(b)) => {
+                a.len() == b.len() && a.iter().all(|field| b.contains(field))
+            },
+            _ => false,
+        };
+    }
+}
+
+impl Json {
+    pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Json)>) -> Self {
+        return Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect());
+    }
+
+    /// The field `key` of an object, or `Null` if there is no such field, so
+    /// that lookups can be chained.
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map_or(&NULL, |(_, value)| value),
+            _ => &NULL,
+        }
+    }
+
+    /// Sets the field `key` of an object, replacing any it had.
+    pub fn set(&mut self, key: &str, value: Json) {
+        if let Json::Object(fields) = self {
+            match fields.iter_mut().find(|(name, _)| name == key) {
+                Some((_, old)) => *old = value,
+                None => fields.push((key.to_string(), value)),
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(number) if number.fract() == 0.0 => Some(*number as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+
+    /// The elements of an array, or none for anything else.
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(elements) => elements,
+            _ => &[],
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        return *self == Json::Null;
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), index: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.index != parser.chars.len() {
+            return Err(parser.error("end of input"));
+        }
+        return Ok(value);
+    }
+}
+
+impl From<bool> for Json {
+    fn from(bool: bool) -> Self {
+        return Json::Bool(bool);
+    }
+}
+
+impl From<i64> for Json {
+    fn from(number: i64) -> Self {
+        return Json::Number(number as f64);
+    }
+}
+
+impl From<usize> for Json {
+    fn from(number: usize) -> Self {
+        return Json::Number(number as f64);
+    }
+}
+
+impl From<&str> for Json {
+    fn from(string: &str) -> Self {
+        return Json::String(string.to_string());
+    }
+}
+
+impl From<String> for Json {
+    fn from(string: String) -> Self {
+        return Json::String(string);
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(elements: Vec<Json>) -> Self {
+        return Json::Array(elements);
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Compact JSON, without any whitespace.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(bool) => write!(f, "{bool}"),
+            Json::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => write!(f, "{}", *number as i64),
+            Json::Number(number) if number.is_finite() => write!(f, "{number}"),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(string) => write_string(f, string),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+
+// -------------- PARSER --------------
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl Parser {
+    fn error(&self, expected: &str) -> String {
+        return match self.chars.get(self.index) {
+            Some(found) => format!("expected {expected} at offset {}, found {found:?}", self.index),
+            None => format!("expected {expected} at offset {}, found the end of input", self.index),
+        };
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.index).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.index += 1;
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.get(self.index) == Some(&expected) {
+            self.index += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        let end = self.index + word.chars().count();
+        if end <= self.chars.len() && self.chars[self.index..end].iter().copied().eq(word.chars()) {
+            self.index = end;
+            return Ok(value);
+        }
+        return Err(self.error(word));
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.index) {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.index += 1;
+                let mut elements = Vec::new();
+                if self.eat(']') {
+                    return Ok(Json::Array(elements));
+                }
+                loop {
+                    elements.push(self.value()?);
+                    if self.eat(']') {
+                        return Ok(Json::Array(elements));
+                    }
+                    if !self.eat(',') {
+                        return Err(self.error("',' or ']'"));
+                    }
+                }
+            },
+            Some('{') => {
+                self.index += 1;
+                let mut fields = Vec::new();
+                if self.eat('}') {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.chars.get(self.index) != Some(&'"') {
+                        return Err(self.error("a string"));
+                    }
+                    let key = self.string()?;
+                    if !self.eat(':') {
+                        return Err(self.error("':'"));
+                    }
+                    fields.push((key, self.value()?));
+                    if self.eat('}') {
+                        return Ok(Json::Object(fields));
+                    }
+                    if !self.eat(',') {
+                        return Err(self.error("',' or '}'"));
+                    }
+                }
+            },
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.index;
+        while self.chars.get(self.index).is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+            self.index += 1;
+        }
+        let text: String = self.chars[start..self.index].iter().collect();
+        return text.parse().map(Json::Number).map_err(|_| {
+            self.index = start;
+            self.error("a number")
+        });
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let digits: String = self.chars.iter().skip(self.index).take(4).collect();
+        let code = u32::from_str_radix(&digits, 16).ok().filter(|_| digits.len() == 4);
+        let Some(code) = code else { return Err(self.error("four hexadecimal digits")) };
+        self.index += 4;
+        return Ok(code);
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        // the opening quote
+        self.index += 1;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.chars.get(self.index).copied() else { return Err(self.error("'\"'")) };
+            self.index += 1;
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let Some(escaped) = self.chars.get(self.index).copied() else { return Err(self.error("an escape")) };
+                    self.index += 1;
+                    match escaped {
+                        '"' | '\\' | '/' => string.push(escaped),
+                        'n' => string.push('\n'),
+                        'r' => string.push('\r'),
+                        't' => string.push('\t'),
+                        'b' => string.push('\u{8}'),
+                        'f' => string.push('\u{c}'),
+                        'u' => {
+                            let mut code = self.hex_escape()?;
+                            // characters outside the basic plane are written
+                            // as a pair of surrogates
+                            if (0xd800..0xdc00).contains(&code) && self.chars.get(self.index..self.index + 2) == Some(&['\\', 'u']) {
+                                self.index += 2;
+                                let low = self.hex_escape()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        },
+                        _ => {
+                            self.index -= 1;
+                            return Err(self.error("an escape"));
+                        },
+                    }
+                },
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+
+// -------------- FRAMING --------------
+/// Reads a message framed by a `Content-Length` header, as the Debug Adapter
+/// and Language Server protocols send them. Returns `None` at the end of the
+/// input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    return Json::parse(&body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    return output.flush();
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_and_printing() {
+        let text = r#"{"seq":1,"arguments":{"lines":[1,-2,3.5],"ok":true,"none":null},"name":"a \"b\"\né😀"}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(Some(1), json.get("seq").as_i64());
+        assert_eq!(3, json.get("arguments").get("lines").as_array().len());
+        assert_eq!(Json::Number(3.5), json.get("arguments").get("lines").as_array()[2]);
+        assert_eq!(Some(true), json.get("arguments").get("ok").as_bool());
+        assert!(json.get("arguments").get("none").is_null());
+        assert!(json.get("missing").get("deeper").is_null());
+        assert_eq!(Some("a \"b\"\né😀"), json.get("name").as_str());
+        assert_eq!(r#"{"seq":1,"arguments":{"lines":[1,-2,3.5],"ok":true,"none":null},"name":"a \"b\"\né😀"}"#, json.to_string());
+        assert_eq!(Json::parse(&json.to_string()), Ok(json));
+        assert_eq!(Ok(Json::Array(Vec::new())), Json::parse(" [ ] "));
+        assert_eq!(Json::parse(r#"{"a":1,"b":[true]}"#), Json::parse(r#"{"b":[true],"a":1}"#));
+        assert_ne!(Json::parse(r#"{"a":1,"b":2}"#), Json::parse(r#"{"a":1,"c":2}"#));
+    }
+
+    #[test]
+    fn malformed_json() {
+        assert_eq!(Err("expected ',' or ']' at offset 3, found '}'".to_string()), Json::parse("[1 }"));
+        assert_eq!(Err("expected a value at offset 0, found the end of input".to_string()), Json::parse(""));
+        assert!(Json::parse(r#"{"a" 1}"#).is_err());
+        assert!(Json::parse(r#""\x""#).is_err());
+        assert!(Json::parse("nul").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn framed_messages() {
+        let mut output = Vec::new();
+        write_message(&mut output, &Json::object([("command", "next".into())])).unwrap();
+        write_message(&mut output, &Json::object([("seq", 2_i64.into())])).unwrap();
+        assert!(output.starts_with(b"Content-Length: 18\r\n\r\n{\"command\":\"next\"}"));
+
+        let mut input = &output[..];
+        assert_eq!(Some("next"), read_message(&mut input).unwrap().unwrap().get("command").as_str());
+        assert_eq!(Some(2), read_message(&mut input).unwrap().unwrap().get("seq").as_i64());
+        assert_eq!(None, read_message(&mut input).unwrap());
+    }
+}
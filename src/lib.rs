@@ -0,0 +1,41 @@
+//! A Lisp interpreter. `Interpreter` is the way in for programs embedding it;
+//! the modules are public for those that need to work with the stages of
+//! evaluation directly.
+
+pub mod parser;
+pub mod analyze;
+pub mod resolve;
+pub mod machine;
+pub mod compiler;
+pub mod convert;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod gc;
+pub mod limits;
+pub mod interrupt;
+pub mod debugger;
+pub mod dap;
+pub mod lsp;
+pub mod lint;
+pub mod types;
+pub mod prelude;
+pub mod capability;
+pub mod port;
+pub mod evaluate;
+pub mod interpreter;
+pub mod json;
+pub mod library;
+pub mod testing;
+pub mod tokenizer;
+pub mod lisp_expression;
+pub mod lisp_error;
+pub mod functions;
+pub mod built_in_functions;
+pub mod benchmark;
+
+pub use capability::{Capabilities, Capability};
+pub use evaluate::{Environment, LispList, LispOutput};
+pub use functions::Arity;
+pub use interpreter::Interpreter;
+pub use limits::EvalLimits;
+pub use lisp_error::{LispError, LispErrorKind, LispResult};
@@ -0,0 +1,614 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::analyze::{analyze_with_spans, Node};
+use crate::capability::{Capabilities, Capability};
+use crate::compiler::compile;
+use crate::evaluate::{evaluate_compiled_with_spans, evaluate_with_spans, Environment, LispOutput};
+use crate::lisp_error::{LispError, LispErrorKind, LispResult};
+use crate::lisp_expression::{LispExpression, SpanTree};
+use crate::machine::{execute, execute_compiled};
+use crate::port::{self, FileAccess};
+use crate::parser::parse_all_with_spans;
+use crate::resolve::resolve;
+use crate::tokenizer::try_tokenize_with_spans;
+
+
+type Env = Rc<RefCell<Environment>>;
+
+/// Libraries are looked for in files named after them with this extension,
+/// so `(utils strings)` is `utils/strings.sld` in one of the directories of
+/// the search path.
+const LIBRARY_EXTENSION: &str = "sld";
+
+thread_local! {
+    static CURRENT: RefCell<Rc<RefCell<Libraries>>> = RefCell::new(Rc::new(RefCell::new(Libraries::default())));
+}
+
+
+// -------------- DEFINITIONS --------------
+/// The name of a library, such as `(utils strings)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LibraryName(pub Vec<String>);
+
+impl fmt::Display for LibraryName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({})", self.0.join(" "))
+    }
+}
+
+/// The names an `import` takes from a library.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportSet {
+    Library(LibraryName),
+    Only(Box<ImportSet>, Vec<String>),
+    Except(Box<ImportSet>, Vec<String>),
+    Prefix(Box<ImportSet>, String),
+    /// Pairs of the imported name and the name to bind it to.
+    Rename(Box<ImportSet>, Vec<(String, String)>),
+}
+
+/// A `define-library` form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LibraryDefinition {
+    pub name: LibraryName,
+    /// Pairs of the name inside the library and the name it is exported as.
+    pub exports: Vec<(String, String)>,
+    pub imports: Vec<ImportSet>,
+    pub body: Vec<Rc<Node>>,
+}
+
+fn symbol<'a>(expr: &'a LispExpression, message: &str) -> LispResult<&'a String> {
+    match expr {
+        LispExpression::Symbol(symbol) => Ok(symbol),
+        _ => Err(LispError::syntax(message)),
+    }
+}
+
+fn is_symbol(expr: &LispExpression, name: &str) -> bool {
+    return matches!(expr, LispExpression::Symbol(symbol) if symbol == name);
+}
+
+fn symbols(exprs: &[LispExpression], form: &str) -> LispResult<Vec<String>> {
+    let message = format!("expecting only identifiers in {form}");
+    return exprs.iter().map(|expr| symbol(expr, &message).cloned()).collect();
+}
+
+/// Reads a name such as `(utils strings)`, whose parts are identifiers or
+/// non-negative integers.
+pub fn parse_library_name(expr: &LispExpression) -> LispResult<LibraryName> {
+    let parts = match expr {
+        LispExpression::List(parts) if !parts.is_empty() => parts,
+        _ => return Err(LispError::syntax("expecting a library name to be a non-empty list")),
+    };
+    return parts.iter().map(|part| match part {
+        LispExpression::Symbol(symbol) => Ok(symbol.clone()),
+        LispExpression::Integer(num) if *num >= 0 => Ok(num.to_string()),
+        _ => Err(LispError::syntax("expecting library name to be made of identifiers and integers")),
+    }).collect::<LispResult<_>>().map(LibraryName);
+}
+
+/// Reads an import set such as `(prefix (utils strings) s:)`.
+pub fn parse_import_set(expr: &LispExpression) -> LispResult<ImportSet> {
+    let parts = match expr {
+        LispExpression::List(parts) => parts,
+        _ => return Err(LispError::syntax("expecting an import set to be a list")),
+    };
+    let form = match parts.first() {
+        Some(LispExpression::Symbol(form)) if parts.len() >= 2 => &form[..],
+        _ => "",
+    };
+    let inner = || parse_import_set(&parts[1]).map(Box::new);
+    let set = match form {
+        "only" => ImportSet::Only(inner()?, symbols(&parts[2..], "only")?),
+        "except" => ImportSet::Except(inner()?, symbols(&parts[2..], "except")?),
+        "prefix" if parts.len() == 3 => {
+            ImportSet::Prefix(inner()?, symbol(&parts[2], "expecting prefix to be an identifier")?.clone())
+        },
+        "rename" => {
+            let renames = parts[2..].iter().map(|rename| match rename {
+                LispExpression::List(pair) if pair.len() == 2 => Ok((
+                    symbol(&pair[0], "expecting only identifiers in rename")?.clone(),
+                    symbol(&pair[1], "expecting only identifiers in rename")?.clone(),
+                )),
+                _ => Err(LispError::syntax("each rename should be a list of two identifiers")),
+            }).collect::<LispResult<_>>()?;
+            ImportSet::Rename(inner()?, renames)
+        },
+        _ => ImportSet::Library(parse_library_name(expr)?),
+    };
+    return Ok(set);
+}
+
+/// Reads `(define-library name declaration ...)`, whose declarations are
+/// `export`, `import` and `begin` forms.
+pub fn parse_library_definition(
+    expressions: &[LispExpression],
+    spans: Option<&SpanTree>,
+) -> LispResult<LibraryDefinition> {
+    let name = match expressions.get(1) {
+        Some(name) => parse_library_name(name)?,
+        None => return Err(LispError::syntax("expecting define-library to be given a library name")),
+    };
+    let mut definition = LibraryDefinition { name, exports: Vec::new(), imports: Vec::new(), body: Vec::new() };
+
+    for (index, declaration) in expressions.iter().enumerate().skip(2) {
+        let parts = match declaration {
+            LispExpression::List(parts) if !parts.is_empty() => parts,
+            _ => return Err(LispError::syntax("expecting library declarations to be non-empty lists")),
+        };
+        let declaration_spans = spans.and_then(|spans| spans.child(index));
+        match &parts[0] {
+            LispExpression::Symbol(form) if form == "export" => {
+                for spec in &parts[1..] {
+                    let export = match spec {
+                        LispExpression::Symbol(name) => (name.clone(), name.clone()),
+                        LispExpression::List(rename) if rename.len() == 3 && is_symbol(&rename[0], "rename") => (
+                            symbol(&rename[1], "expecting only identifiers in rename")?.clone(),
+                            symbol(&rename[2], "expecting only identifiers in rename")?.clone(),
+                        ),
+                        _ => return Err(LispError::syntax("expecting export specs to be identifiers or (rename internal external)")),
+                    };
+                    definition.exports.push(export);
+                }
+            },
+            LispExpression::Symbol(form) if form == "import" => {
+                for set in &parts[1..] {
+                    definition.imports.push(parse_import_set(set)?);
+                }
+            },
+            LispExpression::Symbol(form) if form == "begin" => {
+                for (body_index, expr) in parts.iter().enumerate().skip(1) {
+                    let expr_spans = declaration_spans.and_then(|spans| spans.child(body_index));
+                    definition.body.push(analyze_with_spans(expr, expr_spans)?);
+                }
+            },
+            _ => return Err(LispError::syntax("expecting library declarations to be export, import or begin")),
+        }
+    }
+    return Ok(definition);
+}
+
+
+// -------------- LIBRARIES --------------
+/// A library that has been defined, with the values of its exports as they
+/// were at the end of its body.
+#[derive(Debug)]
+pub struct Library {
+    pub name: LibraryName,
+    pub exports: Vec<(String, LispOutput)>,
+}
+
+/// The libraries known to the evaluations on a thread, and where to look for
+/// those that are not.
+#[derive(Debug)]
+pub struct Libraries {
+    search_path: Vec<PathBuf>,
+    loaded: HashMap<LibraryName, Rc<Library>>,
+    /// The libraries whose files are being loaded, outermost first.
+    loading: Vec<LibraryName>,
+    /// What the built-ins seen by the bodies of libraries may do.
+    capabilities: Capabilities,
+}
+
+impl Default for Libraries {
+    /// Libraries searched for in the current directory.
+    fn default() -> Self {
+        return Libraries {
+            search_path: vec![PathBuf::from(".")],
+            loaded: HashMap::new(),
+            loading: Vec::new(),
+            capabilities: Capabilities::all(),
+        };
+    }
+}
+
+impl Libraries {
+    /// Makes libraries also be looked for in `directory`, after the
+    /// directories already on the search path.
+    pub fn add_search_path(&mut self, directory: impl Into<PathBuf>) {
+        self.search_path.push(directory.into());
+    }
+
+    pub fn search_path(&self) -> &[PathBuf] {
+        return &self.search_path;
+    }
+
+    /// Gives the bodies of libraries defined from now on the built-ins of
+    /// `capabilities` only. They have all of them by default.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        return self.capabilities;
+    }
+
+    pub fn get(&self, name: &LibraryName) -> Option<Rc<Library>> {
+        return self.loaded.get(name).cloned();
+    }
+
+    /// The file of the library `name` in the first directory of the search
+    /// path that has one.
+    fn find_file(&self, name: &LibraryName) -> Option<PathBuf> {
+        return self.search_path.iter()
+            .map(|directory| name.0.iter().fold(directory.clone(), |path, part| path.join(part)))
+            .map(|path| path.with_extension(LIBRARY_EXTENSION))
+            .find(|path| path.is_file());
+    }
+}
+
+/// Runs `evaluation` with `libraries` as the libraries that `import`,
+/// `define-library` and `load` work with. Evaluations outside of this share a
+/// set of libraries per thread, searched for in the current directory.
+pub fn with_libraries<T>(libraries: &Rc<RefCell<Libraries>>, evaluation: impl FnOnce() -> T) -> T {
+    let outer = CURRENT.with(|current| current.replace(libraries.clone()));
+    let result = evaluation();
+    CURRENT.with(|current| *current.borrow_mut() = outer);
+    return result;
+}
+
+fn current() -> Rc<RefCell<Libraries>> {
+    return CURRENT.with(|current| current.borrow().clone());
+}
+
+fn library_error(message: impl Into<String>) -> LispError {
+    return LispError::new(LispErrorKind::Library, message);
+}
+
+/// Reads the file at `path`, as an `Io` error if it can not be read.
+pub fn read_source(path: &Path) -> LispResult<String> {
+    return std::fs::read_to_string(path).map_err(|err| {
+        LispError::new(LispErrorKind::Io, format!("can not read {}: {err}", path.display()))
+    });
+}
+
+/// The expressions in the file at `path`, with their spans.
+fn read_expressions(path: &Path) -> LispResult<Vec<(LispExpression, SpanTree)>> {
+    let source = read_source(path)?;
+    let (tokens, spans) = try_tokenize_with_spans(&source)?;
+    return parse_all_with_spans(&tokens, &spans);
+}
+
+/// Evaluates the expressions in the file at `path` in `env` in turn,
+/// returning the value of the last one.
+pub fn load(path: &Path, env: &Env, compiled: bool) -> LispResult {
+    return evaluate_all(read_expressions(path)?, env, compiled);
+}
+
+fn evaluate_all(expressions: Vec<(LispExpression, SpanTree)>, env: &Env, compiled: bool) -> LispResult {
+    let mut env = env.clone();
+    let mut value = LispOutput::Void;
+    for (tree, span_tree) in expressions {
+        value = match compiled {
+            true => evaluate_compiled_with_spans(&tree, &span_tree, &mut env)?,
+            false => evaluate_with_spans(&tree, &span_tree, &mut env)?,
+        };
+    }
+    return Ok(value);
+}
+
+/// Fails unless every part of `name` can be a file or directory name on its
+/// own, so that its file is inside the directory it is looked for in.
+fn check_file_name(name: &LibraryName) -> LispResult<()> {
+    let unusable = |part: &String| part == "." || part == ".." || part.contains('/') || part.contains(std::path::MAIN_SEPARATOR);
+    match name.0.iter().find(|part| unusable(part)) {
+        Some(part) => Err(library_error(format!("library {name} can not be loaded from a file, {part} is not a file name"))),
+        None => Ok(()),
+    }
+}
+
+/// The library `name`, loading it from the search path the first time it is
+/// asked for, which needs the `IoFilesystem` capability and access to the
+/// file.
+fn find_library(name: &LibraryName, compiled: bool) -> LispResult<Rc<Library>> {
+    let libraries = current();
+    let path = {
+        let mut libraries = libraries.borrow_mut();
+        if let Some(library) = libraries.get(name) {
+            return Ok(library);
+        }
+        if let Some(start) = libraries.loading.iter().position(|loading| loading == name) {
+            let cycle: Vec<String> = libraries.loading[start..].iter().chain([name]).map(|name| name.to_string()).collect();
+            return Err(library_error(format!("cyclic import: {}", cycle.join(" imports "))));
+        }
+        if !libraries.capabilities.contains(Capability::IoFilesystem) {
+            return Err(LispError::new(
+                LispErrorKind::CapabilityDenied,
+                format!("importing {name} from a file needs the {} capability", Capability::IoFilesystem),
+            ));
+        }
+        check_file_name(name)?;
+        let Some(path) = libraries.find_file(name) else {
+            return Err(library_error(format!("library {name} not found in the search path")));
+        };
+        port::check_file_access(&path, FileAccess::ReadOnly)?;
+        libraries.loading.push(name.clone());
+        path
+    };
+
+    let capabilities = libraries.borrow().capabilities;
+    let env = Rc::new(RefCell::new(Environment::global_env_with(capabilities)));
+    let loaded = read_expressions(&path)
+        .map_err(|err| LispError::new(err.kind, format!("can not read library {name}: {}", err.message)))
+        .and_then(|expressions| evaluate_all(expressions, &env, compiled));
+    libraries.borrow_mut().loading.pop();
+    loaded?;
+
+    return libraries.borrow().get(name)
+        .ok_or_else(|| library_error(format!("{} does not define library {name}", path.display())));
+}
+
+/// Fails unless all of `names` are among `bindings`, for import sets naming
+/// what they select or rename.
+fn check_imported<'a>(
+    set: &ImportSet,
+    bindings: &[(String, LispOutput)],
+    mut names: impl Iterator<Item = &'a String>,
+) -> LispResult<()> {
+    match names.find(|name| bindings.iter().all(|(imported, _)| imported != *name)) {
+        Some(name) => Err(library_error(format!("{name} is not imported by {}", set.library()))),
+        None => Ok(()),
+    }
+}
+
+/// The names `set` imports, and their values.
+fn import_bindings(set: &ImportSet, compiled: bool) -> LispResult<Vec<(String, LispOutput)>> {
+    let bindings = match set {
+        ImportSet::Library(name) => find_library(name, compiled)?.exports.clone(),
+        ImportSet::Only(inner, names) => {
+            let bindings = import_bindings(inner, compiled)?;
+            check_imported(set, &bindings, names.iter())?;
+            bindings.into_iter().filter(|(name, _)| names.contains(name)).collect()
+        },
+        ImportSet::Except(inner, names) => {
+            let bindings = import_bindings(inner, compiled)?;
+            check_imported(set, &bindings, names.iter())?;
+            bindings.into_iter().filter(|(name, _)| !names.contains(name)).collect()
+        },
+        ImportSet::Prefix(inner, prefix) => {
+            let bindings = import_bindings(inner, compiled)?;
+            bindings.into_iter().map(|(name, value)| (format!("{prefix}{name}"), value)).collect()
+        },
+        ImportSet::Rename(inner, renames) => {
+            let bindings = import_bindings(inner, compiled)?;
+            check_imported(set, &bindings, renames.iter().map(|(from, _)| from))?;
+            bindings.into_iter().map(|(name, value)| {
+                match renames.iter().find(|(from, _)| *from == name) {
+                    Some((_, to)) => (to.clone(), value),
+                    None => (name, value),
+                }
+            }).collect()
+        },
+    };
+    return Ok(bindings);
+}
+
+impl ImportSet {
+    /// The library the names come from.
+    pub fn library(&self) -> &LibraryName {
+        match self {
+            ImportSet::Library(name) => name,
+            ImportSet::Only(inner, _) | ImportSet::Except(inner, _)
+                | ImportSet::Prefix(inner, _) | ImportSet::Rename(inner, _) => inner.library(),
+        }
+    }
+}
+
+/// Binds the names imported by `sets` in the top-level frame of `env`.
+pub fn import(sets: &[ImportSet], env: &Env, compiled: bool) -> LispResult<()> {
+    let toplevel = Environment::toplevel(env);
+    for set in sets {
+        for (name, value) in import_bindings(set, compiled)? {
+            toplevel.borrow_mut().set(&name, &value);
+        }
+    }
+    return Ok(());
+}
+
+/// Evaluates the body of a library in an environment of its own, and makes
+/// its exports available to `import`.
+pub fn define_library(definition: &LibraryDefinition, compiled: bool) -> LispResult<()> {
+    let env = Rc::new(RefCell::new(Environment::global_env_with(current().borrow().capabilities)));
+    import(&definition.imports, &env, compiled)?;
+    for node in &definition.body {
+        match compiled {
+            true => execute_compiled(compile(node), env.clone())?,
+            false => execute(resolve(node), env.clone())?,
+        };
+    }
+
+    let exports = definition.exports.iter().map(|(internal, external)| {
+        match env.borrow().get(internal) {
+            Ok(value) => Ok((external.clone(), value)),
+            Err(_) => Err(library_error(format!("library {} exports {internal} but does not define it", definition.name))),
+        }
+    }).collect::<LispResult<_>>()?;
+
+    let library = Library { name: definition.name.clone(), exports };
+    current().borrow_mut().loaded.insert(definition.name.clone(), Rc::new(library));
+    return Ok(());
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::evaluate::{evaluate, evaluate_compiled, Engine};
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    const ENGINES: [(Engine, &str); 2] = [(evaluate, "walked"), (evaluate_compiled, "compiled")];
+
+    /// A directory of its own for each test and engine, holding `files`.
+    fn library_directory(test: &str, engine: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("lisp-library-{}-{test}-{engine}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for (path, source) in files {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        return directory;
+    }
+
+    /// Runs `check` for each engine with fresh libraries searched for in a
+    /// directory holding `files`.
+    fn with_files(test: &str, files: &[(&str, &str)], check: impl Fn(&dyn Fn(&str) -> LispResult, &Path)) {
+        for (engine, engine_name) in ENGINES {
+            let directory = library_directory(test, engine_name, files);
+            let mut libraries = Libraries::default();
+            libraries.add_search_path(&directory);
+            let libraries = Rc::new(RefCell::new(libraries));
+
+            let env = RefCell::new(Rc::new(RefCell::new(Environment::global_env())));
+            let eval = |source: &str| with_libraries(&libraries, || engine(&parse(&tokenize(source)), &mut env.borrow_mut()));
+            check(&eval, &directory);
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    const STRINGS: &str = "
+        (define-library (utils strings)
+          (export greet (rename shout yell) calls)
+          (import (utils counter))
+          (begin
+            (define (greet name) (begin (count!) (list \"hello\" name)))
+            (define (shout name) (list \"HELLO\" name))
+            (define (calls) (current-count))))";
+
+    const COUNTER: &str = "
+        (define-library (utils counter)
+          (export count! current-count)
+          (begin
+            (define count 0)
+            (define (count!) (set! count (+ count 1)))
+            (define (current-count) count)))";
+
+    #[test]
+    fn importing_libraries_from_the_search_path() {
+        let files = [("utils/strings.sld", STRINGS), ("utils/counter.sld", COUNTER)];
+        with_files("search-path", &files, |eval, _| {
+            assert_eq!(Ok(LispOutput::Void), eval("(import (utils strings))"));
+            assert_eq!(eval("(list \"hello\" \"bob\")"), eval("(greet \"bob\")"));
+            assert_eq!(eval("(list \"HELLO\" \"bob\")"), eval("(yell \"bob\")"));
+            assert_eq!(Ok(LispOutput::Integer(1)), eval("(calls)"));
+            // the library's own definitions and imports stay inside it
+            assert_eq!(LispErrorKind::UnboundVariable, eval("shout").unwrap_err().kind);
+            assert_eq!(LispErrorKind::UnboundVariable, eval("count!").unwrap_err().kind);
+        });
+    }
+
+    #[test]
+    fn import_sets_select_and_rename() {
+        let files = [("utils/strings.sld", STRINGS), ("utils/counter.sld", COUNTER)];
+        with_files("import-sets", &files, |eval, _| {
+            eval("(import (prefix (utils strings) s:))").unwrap();
+            assert_eq!(eval("(list \"hello\" 1)"), eval("(s:greet 1)"));
+            assert_eq!(LispErrorKind::UnboundVariable, eval("greet").unwrap_err().kind);
+
+            eval("(import (rename (only (utils strings) greet yell) (yell hey)))").unwrap();
+            assert_eq!(eval("(list \"HELLO\" 2)"), eval("(hey 2)"));
+            assert_eq!(LispErrorKind::UnboundVariable, eval("calls").unwrap_err().kind);
+
+            eval("(import (except (utils strings) greet yell))").unwrap();
+            assert_eq!(Ok(LispOutput::Integer(1)), eval("(calls)"));
+
+            let err = eval("(import (only (utils strings) shout))").unwrap_err();
+            assert_eq!("library error: shout is not imported by (utils strings)", err.to_string());
+        });
+    }
+
+    #[test]
+    fn libraries_are_loaded_once() {
+        let files = [("utils/strings.sld", STRINGS), ("utils/counter.sld", COUNTER)];
+        with_files("cache", &files, |eval, directory| {
+            eval("(import (utils strings))").unwrap();
+            eval("(greet 1)").unwrap();
+            fs::remove_file(directory.join("utils/strings.sld")).unwrap();
+
+            // both imports share the state of the library loaded the first time
+            eval("(import (prefix (utils strings) again:))").unwrap();
+            eval("(import (utils counter))").unwrap();
+            assert_eq!(Ok(LispOutput::Integer(1)), eval("(again:calls)"));
+            eval("(count!)").unwrap();
+            assert_eq!(Ok(LispOutput::Integer(2)), eval("(calls)"));
+        });
+    }
+
+    #[test]
+    fn cyclic_imports_are_reported() {
+        let files = [
+            ("a.sld", "(define-library (a) (export x) (import (b)) (begin (define x 1)))"),
+            ("b.sld", "(define-library (b) (export y) (import (c)) (begin (define y 2)))"),
+            ("c.sld", "(define-library (c) (export z) (import (a)) (begin (define z 3)))"),
+        ];
+        with_files("cycle", &files, |eval, _| {
+            let err = eval("(import (a))").unwrap_err();
+            assert_eq!(LispErrorKind::Library, err.kind);
+            assert_eq!("cyclic import: (a) imports (b) imports (c) imports (a)", err.message);
+            // nothing is left half-loaded
+            let err = eval("(import (b))").unwrap_err();
+            assert_eq!("cyclic import: (b) imports (c) imports (a) imports (b)", err.message);
+        });
+    }
+
+    #[test]
+    fn missing_and_malformed_libraries() {
+        let files = [
+            ("empty.sld", "(define unrelated 1)"),
+            ("broken.sld", "(define-library (broken) (export missing) (begin (define present 1)))"),
+            ("unclosed.sld", "(define-library (unclosed) (export s) (begin (define s \"never closed)))"),
+        ];
+        with_files("missing", &files, |eval, directory| {
+            let err = eval("(import (nowhere))").unwrap_err();
+            assert_eq!("library error: library (nowhere) not found in the search path", err.to_string());
+            let err = eval("(import (empty))").unwrap_err();
+            assert_eq!(format!("library error: {} does not define library (empty)", directory.join("empty.sld").display()), err.to_string());
+            let err = eval("(import (broken))").unwrap_err();
+            assert_eq!("library error: library (broken) exports missing but does not define it", err.to_string());
+            let err = eval("(import (unclosed))").unwrap_err();
+            assert_eq!(LispErrorKind::Syntax, err.kind);
+            assert!(err.message.starts_with("can not read library (unclosed): "), "{}", err.message);
+            assert_eq!(LispErrorKind::Syntax, eval("(import (prefix (broken)))").unwrap_err().kind);
+            assert_eq!(LispErrorKind::Syntax, eval("(define-library (x) (provide y))").unwrap_err().kind);
+        });
+    }
+
+    #[test]
+    fn libraries_defined_inline() {
+        with_files("inline", &[], |eval, _| {
+            eval("(define-library (math) (export square) (begin (define (square x) (* x x))))").unwrap();
+            eval("(import (rename (math) (square sq)))").unwrap();
+            assert_eq!(Ok(LispOutput::Integer(49)), eval("(sq 7)"));
+            // imports inside procedures still bind at the top level
+            eval("(define (import-math) (import (math)))").unwrap();
+            eval("(import-math)").unwrap();
+            assert_eq!(Ok(LispOutput::Integer(4)), eval("(square 2)"));
+        });
+    }
+
+    #[test]
+    fn loading_files() {
+        let files = [("helpers.scm", "(define (double x) (* 2 x))\n(define loaded #t)\n(double 21)")];
+        with_files("load", &files, |eval, directory| {
+            let path = directory.join("helpers.scm").display().to_string();
+            assert_eq!(Ok(LispOutput::Integer(42)), eval(&format!("(load {path:?})")));
+            assert_eq!(Ok(LispOutput::Integer(10)), eval("(double 5)"));
+
+            eval("(define env (make-environment))").unwrap();
+            eval(&format!("(load {path:?} env)")).unwrap();
+            assert_eq!(Ok(LispOutput::Bool(true)), eval("(environment-bound? env 'double)"));
+
+            let err = eval("(load \"no-such-file.scm\")").unwrap_err();
+            assert_eq!(LispErrorKind::Io, err.kind);
+        });
+    }
+}
@@ -0,0 +1,150 @@
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use crate::gc;
+use crate::lisp_error::{LispError, LispErrorKind, LispResult};
+
+
+/// The deadline and the size of the heap are checked once every this many
+/// steps, since looking at the clock on every step would slow evaluation down.
+const CHECK_INTERVAL: u64 = 256;
+
+thread_local! {
+    static BUDGET: RefCell<Option<Budget>> = const { RefCell::new(None) };
+    // calls in progress on this thread, counted whether or not there are limits
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Bounds on the resources an evaluation may use, for running code that is
+/// not trusted. Limits left as `None` are not enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EvalLimits {
+    /// The most steps the evaluator may take.
+    pub fuel: Option<u64>,
+    /// The most procedure calls that may be in progress at once, counting
+    /// tail calls once and calls made by built-ins such as `map`.
+    pub max_depth: Option<usize>,
+    /// The most environment frames, closure cells and list cells that may be
+    /// alive at once, counting those made before the evaluation started.
+    pub max_cells: Option<usize>,
+    /// When the evaluation has to be finished by.
+    pub deadline: Option<Instant>,
+}
+
+impl EvalLimits {
+    pub fn with_fuel(self, fuel: u64) -> Self {
+        return EvalLimits { fuel: Some(fuel), ..self };
+    }
+
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        return EvalLimits { max_depth: Some(max_depth), ..self };
+    }
+
+    pub fn with_max_cells(self, max_cells: usize) -> Self {
+        return EvalLimits { max_cells: Some(max_cells), ..self };
+    }
+
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        return EvalLimits { deadline: Some(deadline), ..self };
+    }
+
+    /// A deadline `timeout` from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        return self.with_deadline(Instant::now() + timeout);
+    }
+}
+
+/// What is left of the limits of the evaluation running on this thread.
+struct Budget {
+    limits: EvalLimits,
+    steps: u64,
+    depth: usize,
+}
+
+/// Runs `evaluation` under `limits`. An evaluation that exceeds one of them
+/// fails with an error of kind `OutOfFuel`, `DepthExceeded`, `OutOfMemory` or
+/// `Timeout`; these cannot be caught by Lisp code, so they always reach the
+/// caller, which can go on evaluating in the same environment.
+pub fn with_limits<T>(limits: &EvalLimits, evaluation: impl FnOnce() -> LispResult<T>) -> LispResult<T> {
+    let budget = Budget { limits: *limits, steps: 0, depth: 0 };
+    let outer = BUDGET.with(|current| current.borrow_mut().replace(budget));
+    let result = evaluation();
+    BUDGET.with(|current| *current.borrow_mut() = outer);
+    return result;
+}
+
+/// Counts a step of the evaluator.
+pub fn step() -> LispResult<()> {
+    let cells = BUDGET.with(|current| {
+        let mut current = current.borrow_mut();
+        let Some(budget) = current.as_mut() else { return Ok(None) };
+        budget.steps += 1;
+        if let Some(fuel) = budget.limits.fuel.filter(|fuel| budget.steps > *fuel) {
+            return Err(LispError::new(LispErrorKind::OutOfFuel, format!("evaluation took more than {fuel} steps")));
+        }
+        if budget.steps % CHECK_INTERVAL != 0 {
+            return Ok(None);
+        }
+        if budget.limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(LispError::new(LispErrorKind::Timeout, "evaluation ran past its deadline"));
+        }
+        return Ok(budget.limits.max_cells);
+    })?;
+
+    // the heap may still hold garbage, so it is only over the limit if a
+    // collection does not bring it back under
+    if let Some(max_cells) = cells {
+        if gc::size() > max_cells {
+            gc::collect();
+        }
+        if gc::size() > max_cells {
+            return Err(LispError::new(
+                LispErrorKind::OutOfMemory,
+                format!("evaluation used more than {max_cells} cells"),
+            ));
+        }
+    }
+    return Ok(());
+}
+
+/// Counts a call that does not replace the one it was made from.
+pub fn enter_call() -> LispResult<()> {
+    BUDGET.with(|current| {
+        let mut current = current.borrow_mut();
+        let Some(budget) = current.as_mut() else { return Ok(()) };
+        if let Some(max_depth) = budget.limits.max_depth.filter(|max_depth| budget.depth >= *max_depth) {
+            return Err(LispError::new(LispErrorKind::DepthExceeded, format!("more than {max_depth} calls in progress")));
+        }
+        budget.depth += 1;
+        return Ok(());
+    })?;
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    return Ok(());
+}
+
+/// Counts `calls` calls as started, without checking the depth; used when a
+/// continuation puts back calls that were in progress before.
+pub fn enter_calls(calls: usize) {
+    BUDGET.with(|current| {
+        if let Some(budget) = current.borrow_mut().as_mut() {
+            budget.depth += calls;
+        }
+    });
+    DEPTH.with(|depth| depth.set(depth.get() + calls));
+}
+
+/// Counts `calls` calls as finished.
+pub fn leave_calls(calls: usize) {
+    BUDGET.with(|current| {
+        if let Some(budget) = current.borrow_mut().as_mut() {
+            budget.depth = budget.depth.saturating_sub(calls);
+        }
+    });
+    DEPTH.with(|depth| depth.set(depth.get().saturating_sub(calls)));
+}
+
+/// The number of calls in progress on this thread, counted like `max_depth`
+/// but whether or not the evaluation has limits.
+pub fn depth() -> usize {
+    return DEPTH.with(Cell::get);
+}
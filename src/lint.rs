@@ -0,0 +1,535 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::analyze::{analyze_with_spans, check_special_form};
+use crate::evaluate::{Environment, LispOutput};
+use crate::functions::{LispFunction, Parameters, KEYWORD_PREFIX};
+use crate::lisp_expression::{LispExpression, Span, SpanTree};
+use crate::lsp::document::{BindingKind, Document, SPECIAL_FORMS};
+use crate::parser::parse_all_with_spans;
+use crate::tokenizer::try_tokenize_with_spans;
+use crate::types;
+
+
+/// What a warning is about. Suppression comments name warnings by the
+/// `name` of their code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    /// The source does not parse, or a form is malformed.
+    Syntax,
+    UnboundVariable,
+    /// A variable bound by a `let` form and never used.
+    UnusedBinding,
+    /// A call to a known procedure with a number of arguments it does not take.
+    Arity,
+    /// A special form without the number of parts it must have.
+    SpecialFormArity,
+    ShadowedBuiltIn,
+    SetUndefined,
+    /// An `if` whose condition is a constant, so that one branch is never taken.
+    ConstantCondition,
+}
+
+impl Code {
+    pub const ALL: [Code; 8] = [
+        Code::Syntax,
+        Code::UnboundVariable,
+        Code::UnusedBinding,
+        Code::Arity,
+        Code::SpecialFormArity,
+        Code::ShadowedBuiltIn,
+        Code::SetUndefined,
+        Code::ConstantCondition,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Code::Syntax => "syntax",
+            Code::UnboundVariable => "unbound-variable",
+            Code::UnusedBinding => "unused-binding",
+            Code::Arity => "arity",
+            Code::SpecialFormArity => "special-form-arity",
+            Code::ShadowedBuiltIn => "shadowed-built-in",
+            Code::SetUndefined => "set-undefined",
+            Code::ConstantCondition => "constant-condition",
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        return Code::ALL.into_iter().find(|code| code.name() == name);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub code: Code,
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}:{}: warning[{}]: {}", self.span.line, self.span.column, self.code.name(), self.message);
+    }
+}
+
+
+// -------------- LINTER --------------
+/// Finds likely mistakes in source files without evaluating them.
+///
+/// A warning is turned off by a comment naming its code, `; lint:
+/// allow(unused-binding)`, or several codes separated by commas. At the end
+/// of a line the comment turns off the warnings starting on that line, on a
+/// line of its own those starting on the next one. Variables whose names
+/// start with `_` are never reported as unused.
+pub struct Linter {
+    /// The frame of the built-ins, which are bound without being defined.
+    built_ins: Rc<RefCell<Environment>>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        return Linter::new();
+    }
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        return Linter { built_ins: Environment::built_ins_env() };
+    }
+
+    /// The warnings about `source`, in the order they appear in it.
+    pub fn lint(&self, source: &str) -> Vec<Warning> {
+        let built_ins = self.built_ins.borrow();
+        let document = Document::analyze(source, &|name| built_ins.is_bound(name));
+        let mut warnings = if document.parsed {
+            let forms = try_tokenize_with_spans(source)
+                .and_then(|(tokens, spans)| parse_all_with_spans(&tokens, &spans))
+                .expect("the document parsed");
+            Pass::new(&document, &built_ins, &forms).run()
+        } else {
+            document.diagnostics.iter()
+                .map(|diagnostic| Warning { code: Code::Syntax, span: diagnostic.span, message: diagnostic.message.clone() })
+                .collect()
+        };
+
+        let allowed = allowed(source);
+        warnings.retain(|warning| {
+            return !allowed.get(&warning.span.line).is_some_and(|codes| codes.contains(&warning.code.name()));
+        });
+        warnings.sort_by_key(|warning| (warning.span.line, warning.span.column));
+        return warnings;
+    }
+}
+
+/// The `.lisp` files in `directory` and the directories below it.
+pub fn discover(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(discover(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "lisp") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    return Ok(files);
+}
+
+/// The codes allowed by suppression comments, by the line they apply to.
+fn allowed(source: &str) -> HashMap<usize, Vec<&str>> {
+    let mut allowed: HashMap<usize, Vec<&str>> = HashMap::new();
+    let mut in_string = false;
+    for (index, text) in source.lines().enumerate() {
+        let mut escaped = false;
+        let comment = text.char_indices().find(|(_, next)| {
+            match (in_string, escaped, next) {
+                (true, true, _) => escaped = false,
+                (true, false, '\\') => escaped = true,
+                (_, false, '"') => in_string = !in_string,
+                (false, _, ';') => return true,
+                _ => {},
+            }
+            return false;
+        });
+        let Some((offset, _)) = comment else { continue };
+        let comment = text[offset..].trim_start_matches(';').trim();
+        let Some(codes) = comment.strip_prefix("lint:").and_then(|rest| rest.trim().strip_prefix("allow(")) else { continue };
+        let Some((codes, _)) = codes.split_once(')') else { continue };
+        let line = if text[..offset].trim().is_empty() { index + 2 } else { index + 1 };
+        allowed.entry(line).or_default().extend(codes.split(',').map(str::trim));
+    }
+    return allowed;
+}
+
+
+// -------------- CHECKS --------------
+fn start(span: Span) -> (usize, usize) {
+    return (span.line, span.column);
+}
+
+/// The value of `expression` if it is a constant, for the literals and the
+/// quoted data of the source.
+fn constant(expression: &LispExpression) -> Option<LispOutput> {
+    return match expression {
+        LispExpression::Integer(_) | LispExpression::String(_) => Some(LispOutput::from_datum(expression)),
+        LispExpression::Symbol(keyword) if keyword.starts_with(KEYWORD_PREFIX) => Some(LispOutput::from_datum(expression)),
+        LispExpression::List(quoted) if quoted.len() == 2 && quoted[0] == LispExpression::Symbol("quote".to_string()) => {
+            Some(LispOutput::from_datum(&quoted[1]))
+        },
+        _ => None,
+    };
+}
+
+/// The parameter list of the procedure defined by `(define (name parameter
+/// ...) body)` or `(define name (lambda parameters body))`, with any type
+/// annotations taken out.
+fn defined_parameters(define: &[LispExpression]) -> Option<LispExpression> {
+    let parameters = match define.get(1)? {
+        LispExpression::List(signature) => Some(LispExpression::List(signature.get(1..)?.to_vec())),
+        LispExpression::Symbol(_) => match define.get(2)? {
+            LispExpression::List(lambda) if lambda.first() == Some(&LispExpression::Symbol("lambda".to_string())) => {
+                lambda.get(1).cloned()
+            },
+            _ => None,
+        },
+        _ => None,
+    }?;
+    return types::split_parameters(&parameters).ok().map(|(plain, _)| plain);
+}
+
+/// One run of the checks over an analyzed document.
+struct Pass<'a> {
+    document: &'a Document,
+    built_ins: &'a Environment,
+    forms: &'a [(LispExpression, SpanTree)],
+    /// Every list of the source, by where it starts.
+    lists: HashMap<(usize, usize), (&'a [LispExpression], &'a SpanTree)>,
+    /// The index of the reference at each place a variable is named.
+    references: HashMap<(usize, usize), usize>,
+    /// Where the variables given values by `set!` are named.
+    set_targets: HashSet<(usize, usize)>,
+    /// Where `define` and `set!` name the variables they give values to.
+    assigned: HashSet<(usize, usize)>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Pass<'a> {
+    fn new(document: &'a Document, built_ins: &'a Environment, forms: &'a [(LispExpression, SpanTree)]) -> Self {
+        let mut pass = Pass {
+            document,
+            built_ins,
+            forms,
+            lists: HashMap::new(),
+            references: document.references.iter().enumerate().map(|(index, reference)| (start(reference.span), index)).collect(),
+            set_targets: HashSet::new(),
+            assigned: HashSet::new(),
+            warnings: Vec::new(),
+        };
+        for (form, spans) in forms {
+            pass.collect_lists(form, spans);
+        }
+        for span in &document.code {
+            let (items, spans) = pass.lists[&start(*span)];
+            match (items.first(), items.get(1)) {
+                (Some(LispExpression::Symbol(form)), Some(LispExpression::Symbol(_))) if form == "set!" => {
+                    pass.set_targets.insert(start(spans.children[1].span));
+                    pass.assigned.insert(start(spans.children[1].span));
+                },
+                (Some(LispExpression::Symbol(form)), Some(LispExpression::Symbol(_))) if form == "define" => {
+                    pass.assigned.insert(start(spans.children[1].span));
+                },
+                (Some(LispExpression::Symbol(form)), Some(LispExpression::List(signature))) if form == "define" && !signature.is_empty() => {
+                    pass.assigned.insert(start(spans.children[1].children[0].span));
+                },
+                _ => {},
+            }
+        }
+        return pass;
+    }
+
+    fn collect_lists(&mut self, expression: &'a LispExpression, spans: &'a SpanTree) {
+        let LispExpression::List(items) = expression else { return };
+        self.lists.insert(start(spans.span), (items, spans));
+        for (item, item_spans) in items.iter().zip(&spans.children) {
+            self.collect_lists(item, item_spans);
+        }
+    }
+
+    fn warn(&mut self, code: Code, span: Span, message: String) {
+        self.warnings.push(Warning { code, span, message });
+    }
+
+    fn run(mut self) -> Vec<Warning> {
+        for span in &self.document.code {
+            let (items, spans) = self.lists[&start(*span)];
+            match items.first() {
+                Some(LispExpression::Symbol(form)) if SPECIAL_FORMS.contains(&form.as_str()) => {
+                    self.check_special_form(form, items, spans);
+                },
+                Some(LispExpression::Symbol(name)) if !name.starts_with(KEYWORD_PREFIX) => self.check_call(name, items, spans),
+                _ => {},
+            }
+        }
+        self.check_bindings();
+        self.check_references();
+        self.check_malformed_forms();
+        return self.warnings;
+    }
+
+    fn is_built_in(&self, name: &str) -> bool {
+        return self.built_ins.is_bound(name);
+    }
+
+    /// Whether the variable at `span` is bound neither by the document nor
+    /// by the built-ins, as far as can be told.
+    fn is_undefined(&self, span: Span, name: &str) -> bool {
+        let Some(&reference) = self.references.get(&start(span)) else { return false };
+        return self.document.references[reference].binding.is_none() && !self.is_built_in(name) && !self.document.imports;
+    }
+
+    fn check_special_form(&mut self, form: &str, items: &[LispExpression], spans: &SpanTree) {
+        if let Err(err) = check_special_form(items) {
+            self.warn(Code::SpecialFormArity, spans.span, err.message);
+            return;
+        }
+        match (form, items.get(1)) {
+            ("if", Some(condition)) => {
+                let value = match condition {
+                    // unless the document binds them
+                    LispExpression::Symbol(name) if matches!(name.as_str(), "#t" | "#f" | "nil") => {
+                        let reference = self.references.get(&start(spans.children[1].span));
+                        match reference.is_some_and(|&index| self.document.references[index].binding.is_none()) {
+                            true => self.built_ins.get(name).ok(),
+                            false => None,
+                        }
+                    },
+                    _ => constant(condition),
+                };
+                let Some(value) = value else { return };
+                // anything but #t takes the alternative
+                let (never, condition) = match value == LispOutput::Bool(true) {
+                    true => (3, "always #t"),
+                    false => (2, "never #t"),
+                };
+                let message = format!("the condition is {condition}, so this branch is never evaluated");
+                self.warn(Code::ConstantCondition, spans.children[never].span, message);
+            },
+            ("set!", Some(LispExpression::Symbol(name))) if self.is_undefined(spans.children[1].span, name) => {
+                self.warn(Code::SetUndefined, spans.children[1].span, format!("set! of undefined variable {name}"));
+            },
+            _ => {},
+        }
+    }
+
+    /// Checks the number of arguments of a call to a procedure the document
+    /// defines once and never sets, or to a built-in.
+    fn check_call(&mut self, name: &str, items: &[LispExpression], spans: &SpanTree) {
+        let Some(&reference) = self.references.get(&start(spans.children[0].span)) else { return };
+        let (arity, keywords) = match self.document.references[reference].binding {
+            Some(index) => {
+                let binding = &self.document.bindings[index];
+                let reassigned = self.document.references_to(index)
+                    .any(|reference| reference.span != binding.span && self.assigned.contains(&start(reference.span)));
+                if binding.kind != BindingKind::Procedure || reassigned {
+                    return;
+                }
+                let Some(&(define, _)) = self.lists.get(&start(binding.form)) else { return };
+                let Some(Ok(parameters)) = defined_parameters(define).map(|parameters| Parameters::parse(&parameters)) else { return };
+                (parameters.arity(), parameters.takes_keywords())
+            },
+            None => match self.built_ins.get(name) {
+                Ok(LispOutput::Lambda(function)) if !matches!(function, LispFunction::Continuation(_)) => (function.arity(), false),
+                _ => return,
+            },
+        };
+        let count = match keywords {
+            true => count_positional(&items[1..]),
+            false => items.len() - 1,
+        };
+        if !arity.accepts(count) {
+            self.warn(Code::Arity, spans.span, format!("{name} expects {arity}, got {count}"));
+        }
+    }
+
+    fn check_bindings(&mut self) {
+        for (index, binding) in self.document.bindings.iter().enumerate() {
+            if self.is_built_in(&binding.name) {
+                let message = format!("{} shadows the built-in of the same name", binding.name);
+                self.warn(Code::ShadowedBuiltIn, binding.span, message);
+            }
+            // the only reference to an unused variable is where it is bound
+            let unused = self.document.references_to(index).nth(1).is_none();
+            if binding.kind == BindingKind::Local && unused && !binding.name.starts_with('_') {
+                self.warn(Code::UnusedBinding, binding.span, format!("{} is bound but never used", binding.name));
+            }
+        }
+    }
+
+    fn check_references(&mut self) {
+        for reference in &self.document.references {
+            let set = self.set_targets.contains(&start(reference.span));
+            let special = SPECIAL_FORMS.contains(&reference.name.as_str());
+            if !set && !special && self.is_undefined(reference.span, &reference.name) {
+                self.warn(Code::UnboundVariable, reference.span, format!("unbound variable {}", reference.name));
+            }
+        }
+    }
+
+    /// Reports the first thing analysis rejects in each top-level form,
+    /// unless it is a special form of the wrong length, reported already.
+    fn check_malformed_forms(&mut self) {
+        for (form, spans) in self.forms {
+            let within = |warning: &Warning| start(spans.span) <= start(warning.span) && start(warning.span) < (spans.span.end_line, spans.span.end_column);
+            if self.warnings.iter().any(|warning| warning.code == Code::SpecialFormArity && within(warning)) {
+                continue;
+            }
+            if let Err(err) = analyze_with_spans(form, Some(spans)) {
+                let span = err.span().unwrap_or(spans.span);
+                self.warn(Code::Syntax, span, err.message);
+            }
+        }
+    }
+}
+
+/// The number of arguments that are not `#:name value` pairs.
+fn count_positional(arguments: &[LispExpression]) -> usize {
+    let mut count = 0;
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        match argument {
+            LispExpression::Symbol(keyword) if keyword.starts_with(KEYWORD_PREFIX) => {
+                arguments.next();
+            },
+            _ => count += 1,
+        }
+    }
+    return count;
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each warning as `code@line:column`.
+    fn codes(source: &str) -> Vec<String> {
+        return Linter::new().lint(source).iter()
+            .map(|warning| format!("{}@{}:{}", warning.code.name(), warning.span.line, warning.span.column))
+            .collect();
+    }
+
+    #[test]
+    fn variables() {
+        let source = "\
+(define (f x)
+  (let ((unused 1) (_ignored 2) (used 3))
+    (+ x used y)))
+(set! z 1)
+(set! f 2)
+(define (g list) (car list))
+(guard (e (else 0)) (car nil))";
+        let expected = vec![
+            "unused-binding@2:10",
+            "unbound-variable@3:15",
+            "set-undefined@4:7",
+            "shadowed-built-in@6:12",
+        ];
+        assert_eq!(expected, codes(source));
+
+        // nothing is unbound once a library is imported
+        assert!(codes("(import (numbers))\n(double 2)\n(set! twice 2)").is_empty());
+    }
+
+    #[test]
+    fn arguments() {
+        let source = "\
+(define (f x #!optional y) x)
+(define g (lambda (a . rest) a))
+(define (h a #!key scale) a)
+(f 1) (f 1 2) (f) (f 1 2 3)
+(g) (g 1 2 3)
+(h 1 #:scale 2) (h #:scale 2)
+(car '(1) 2)
+(let loop ((n 1)) (if (< n 3) (loop (+ n 1) 0) n))
+(define (k) 1)
+(define k (lambda (x) x))
+(k 1)";
+        let expected = vec!["arity@4:15", "arity@4:19", "arity@5:1", "arity@6:17", "arity@7:1"];
+        assert_eq!(expected, codes(source));
+
+        let warnings = Linter::new().lint(source);
+        assert_eq!("f expects between 1 and 2 arguments, got 0", warnings[0].message);
+        assert_eq!("car expects 1 argument, got 2", warnings[4].message);
+    }
+
+    #[test]
+    fn special_forms() {
+        let source = "\
+(define (f x)
+  (if x 1))
+(lambda (x))
+(let ((if 1)) (quote))
+(set! x)
+(define (g #!rest) 1)";
+        let warnings = Linter::new().lint(source);
+        let found: Vec<(Code, usize, usize)> = warnings.iter().map(|warning| (warning.code, warning.span.line, warning.span.column)).collect();
+        let expected = vec![
+            (Code::SpecialFormArity, 2, 3),
+            (Code::SpecialFormArity, 3, 1),
+            (Code::UnusedBinding, 4, 8),
+            (Code::SpecialFormArity, 4, 15),
+            (Code::SpecialFormArity, 5, 1),
+            (Code::Syntax, 6, 1),
+        ];
+        assert_eq!(expected, found);
+        assert_eq!("special form if was not supplied with correct number of arguments: got 2, expected 3", warnings[0].message);
+        assert_eq!("missing rest parameter name", warnings[5].message);
+
+        assert_eq!(vec!["syntax@1:4"], codes("(f \"open"));
+    }
+
+    #[test]
+    fn constant_conditions() {
+        let source = "\
+(if #t 1 2)
+(if 0 1 2)
+(if '(1) \"a\" \"b\")
+(if (car '(#t)) 1 2)
+(and) (the-environment)
+(let ((#t 0)) (if #t 1 2))";
+        let expected = vec![
+            "constant-condition@1:10",
+            "constant-condition@2:7",
+            "constant-condition@3:10",
+            "shadowed-built-in@6:8",
+        ];
+        assert_eq!(expected, codes(source));
+        let warnings = Linter::new().lint(source);
+        assert_eq!("the condition is always #t, so this branch is never evaluated", warnings[0].message);
+        assert_eq!("the condition is never #t, so this branch is never evaluated", warnings[1].message);
+    }
+
+    #[test]
+    fn suppression_comments() {
+        let source = "\
+(car 1 2) ; lint: allow(arity)
+; lint: allow(unbound-variable, arity)
+(car x 2)
+(car y)
+(car z) ; lint: allow(arity)
+(display \"; lint: allow(unbound-variable)\" w)";
+        let expected = vec!["unbound-variable@4:6", "unbound-variable@5:6", "unbound-variable@6:44"];
+        assert_eq!(expected, codes(source));
+        assert_eq!(Some(Code::UnusedBinding), Code::from_name("unused-binding"));
+        assert!(Code::ALL.iter().all(|code| Code::from_name(code.name()) == Some(*code)));
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::evaluate::LispOutput;
+use crate::lisp_expression::Span;
+use crate::machine::Jump;
+
+
+pub type LispResult<T = LispOutput> = Result<T, LispError>;
+
+/// Reports show this many of the innermost calls of a backtrace and this many
+/// of the outermost, leaving out the ones in between.
+const REPORTED_FRAMES: usize = 10;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LispErrorKind {
+    Syntax,
+    UnboundVariable,
+    Type,
+    Arity,
+    IndexOutOfBounds,
+    DivisionByZero,
+    Continuation,
+    /// Signalled by the `error` procedure.
+    User,
+    /// A raised object that is not an error object went unhandled.
+    Raise,
+    /// The evaluation ran out of one of its `EvalLimits`.
+    OutOfFuel,
+    DepthExceeded,
+    OutOfMemory,
+    Timeout,
+    /// The evaluation was interrupted, usually with Ctrl-C.
+    Interrupted,
+    /// A file could not be read.
+    Io,
+    /// A library could not be found or imported.
+    Library,
+    /// A built-in was called without the capability it belongs to.
+    CapabilityDenied,
+    /// An assertion of a test failed.
+    Assertion,
+}
+
+impl LispErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LispErrorKind::Syntax => "syntax",
+            LispErrorKind::UnboundVariable => "unbound-variable",
+            LispErrorKind::Type => "type",
+            LispErrorKind::Arity => "arity",
+            LispErrorKind::IndexOutOfBounds => "index-out-of-bounds",
+            LispErrorKind::DivisionByZero => "division-by-zero",
+            LispErrorKind::Continuation => "continuation",
+            LispErrorKind::User => "user",
+            LispErrorKind::Raise => "raise",
+            LispErrorKind::OutOfFuel => "out-of-fuel",
+            LispErrorKind::DepthExceeded => "depth-exceeded",
+            LispErrorKind::OutOfMemory => "out-of-memory",
+            LispErrorKind::Timeout => "timeout",
+            LispErrorKind::Interrupted => "interrupted",
+            LispErrorKind::Io => "io",
+            LispErrorKind::Library => "library",
+            LispErrorKind::CapabilityDenied => "capability-denied",
+            LispErrorKind::Assertion => "assertion",
+        }
+    }
+
+    /// Whether the error stops the evaluation altogether, for exceeding a
+    /// limit or being interrupted, in which case exception handlers do not
+    /// get to see it.
+    pub fn stops_evaluation(&self) -> bool {
+        return matches!(
+            self,
+            LispErrorKind::OutOfFuel
+                | LispErrorKind::DepthExceeded
+                | LispErrorKind::OutOfMemory
+                | LispErrorKind::Timeout
+                | LispErrorKind::Interrupted
+        );
+    }
+}
+
+
+/// An error as seen by Lisp code: what `error` raises and what exception
+/// handlers receive for errors signalled by the interpreter itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorObject {
+    pub kind: LispErrorKind,
+    pub message: String,
+    pub irritants: Vec<LispOutput>,
+    pub backtrace: Vec<TraceFrame>,
+}
+
+
+/// A procedure call that was in progress when an error was raised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// The name given by `define` or a named `let`, if any.
+    pub name: Option<String>,
+    /// Where the call was written, when the code came with spans.
+    pub span: Option<Span>,
+    pub arguments: Vec<LispOutput>,
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}", self.name.as_deref().unwrap_or("<anonymous>"))?;
+        for argument in &self.arguments {
+            write!(f, " {argument}")?;
+        }
+        write!(f, ")")?;
+        match &self.span {
+            Some(span) => write!(f, " at {span}"),
+            None => Ok(()),
+        }
+    }
+}
+
+
+/// What some errors carry besides their message. No error needs more than
+/// one of these.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Attachment {
+    /// This "error" is really a continuation unwinding the Rust stack on its
+    /// way to the machine that resumes it.
+    Jump(Jump),
+    /// The object passed to `raise`, or the error object built by `error`.
+    Raised(LispOutput),
+    /// Where a syntax error is in the source, when it was read with spans.
+    Span(Span),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LispError {
+    pub kind: LispErrorKind,
+    pub message: String,
+    /// Boxed to keep results small, since deep recursion in Rust holds on
+    /// to a good many of them.
+    pub(crate) attachment: Option<Box<Attachment>>,
+    /// The calls in progress when the error was raised, innermost first.
+    pub backtrace: Vec<TraceFrame>,
+}
+
+impl LispError {
+    pub fn new(kind: LispErrorKind, message: impl Into<String>) -> Self {
+        return LispError {
+            kind,
+            message: message.into(),
+            attachment: None,
+            backtrace: Vec::new(),
+        };
+    }
+
+    pub(crate) fn attach(mut self, attachment: Attachment) -> Self {
+        self.attachment = Some(Box::new(attachment));
+        return self;
+    }
+
+    /// Records where a syntax error is, if known.
+    pub fn with_span(self, span: Option<Span>) -> Self {
+        return match span {
+            Some(span) => self.attach(Attachment::Span(span)),
+            None => self,
+        };
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        return match self.attachment.as_deref() {
+            Some(Attachment::Span(span)) => Some(*span),
+            _ => None,
+        };
+    }
+
+    fn raised(&self) -> Option<&LispOutput> {
+        return match self.attachment.as_deref() {
+            Some(Attachment::Raised(raised)) => Some(raised),
+            _ => None,
+        };
+    }
+
+    /// The jump this error stands for, if it is a continuation unwinding.
+    pub(crate) fn as_jump(&self) -> Option<&Jump> {
+        return match self.attachment.as_deref() {
+            Some(Attachment::Jump(jump)) => Some(jump),
+            _ => None,
+        };
+    }
+
+    pub(crate) fn into_jump(self) -> Option<Jump> {
+        return match self.attachment.map(|attachment| *attachment) {
+            Some(Attachment::Jump(jump)) => Some(jump),
+            _ => None,
+        };
+    }
+
+    /// The error reported when a raised object is not handled.
+    pub fn raise(raised: LispOutput) -> Self {
+        let err = match &raised {
+            LispOutput::ErrorObject(error) => {
+                let mut message = error.message.clone();
+                for irritant in &error.irritants {
+                    message.push_str(&format!(" {irritant}"));
+                }
+                Self::new(error.kind, message)
+            },
+            _ => Self::new(LispErrorKind::Raise, format!("non-condition object raised: {raised}")),
+        };
+        return err.attach(Attachment::Raised(raised));
+    }
+
+    /// What an exception handler receives for this error. Error objects are
+    /// given the backtrace of the first place they were raised from.
+    pub fn condition(&self) -> LispOutput {
+        return match self.raised() {
+            Some(LispOutput::ErrorObject(error)) if error.backtrace.is_empty() => {
+                LispOutput::ErrorObject(Rc::new(ErrorObject {
+                    backtrace: self.backtrace.clone(),
+                    ..(**error).clone()
+                }))
+            },
+            Some(raised) => raised.clone(),
+            None => LispOutput::ErrorObject(Rc::new(ErrorObject {
+                kind: self.kind,
+                message: self.message.clone(),
+                irritants: Vec::new(),
+                backtrace: self.backtrace.clone(),
+            })),
+        };
+    }
+
+    /// The error followed by its backtrace, one call per line, with the
+    /// middle of a long backtrace collapsed into a count of the calls left out.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let omitted = self.backtrace.len().saturating_sub(2 * REPORTED_FRAMES);
+        for (index, frame) in self.backtrace.iter().enumerate() {
+            if omitted > 0 && index == REPORTED_FRAMES {
+                report.push_str(&format!("\n    … {omitted} more calls"));
+            }
+            if omitted > 0 && (REPORTED_FRAMES..REPORTED_FRAMES + omitted).contains(&index) {
+                continue;
+            }
+            report.push_str(&format!("\n    in {frame}"));
+        }
+        return report;
+    }
+
+    pub(crate) fn jump(jump: Jump) -> Self {
+        return Self::new(LispErrorKind::Continuation, "continuation invoked outside of its evaluation")
+            .attach(Attachment::Jump(jump));
+    }
+
+    pub fn syntax(message: impl Into<String>) -> Self {
+        return Self::new(LispErrorKind::Syntax, message);
+    }
+
+    pub fn unbound_variable(var: &str) -> Self {
+        return Self::new(LispErrorKind::UnboundVariable, format!("variable not found in any environment: {var}"));
+    }
+
+    pub fn type_mismatch(message: impl Into<String>) -> Self {
+        return Self::new(LispErrorKind::Type, message);
+    }
+
+    pub fn arity(message: impl Into<String>) -> Self {
+        return Self::new(LispErrorKind::Arity, message);
+    }
+}
+
+impl fmt::Display for LispError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind.name(), self.message)
+    }
+}
+
+impl std::error::Error for LispError {}
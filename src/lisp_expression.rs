@@ -2,5 +2,36 @@
 pub enum LispExpression {
     Integer(i64),
     Symbol(String),
+    String(String),
     List(Vec<LispExpression>),
-}
\ No newline at end of file
+}
+
+/// Where an expression sits in the source, as 1-based lines and columns. The
+/// end is just past the last character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// The spans of an expression, shaped like the expression itself: a list has
+/// one child per element.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    pub fn child(&self, index: usize) -> Option<&SpanTree> {
+        return self.children.get(index);
+    }
+}
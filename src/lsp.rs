@@ -0,0 +1,362 @@
+pub mod document;
+pub mod format;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use crate::evaluate::{Environment, LispOutput};
+use crate::functions::LispFunction;
+use crate::json::{read_message, write_message, Json};
+use crate::lisp_expression::Span;
+use crate::prelude;
+use self::document::{Binding, BindingKind, Document, Severity, SPECIAL_FORMS};
+
+
+/// Error codes of JSON-RPC and of the protocol.
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// Kinds of completion items and of document symbols.
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+
+/// An error to answer a request with.
+struct Failure {
+    code: i64,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        return Failure { code, message: message.into() };
+    }
+}
+
+type Answer = Result<Json, Failure>;
+
+/// A Language Server Protocol server for Lisp source files. Documents are
+/// analyzed whenever they change, without being evaluated.
+pub struct LspServer {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    documents: HashMap<String, Document>,
+    /// The frame of the built-ins, for what is known about them.
+    built_ins: Rc<RefCell<Environment>>,
+    /// The prelude analyzed as a document, for the signatures and comments
+    /// of the procedures it defines.
+    prelude: Document,
+    shut_down: bool,
+}
+
+/// Serves the Language Server Protocol on `input` and `output` until the
+/// client says to exit or the input ends.
+pub fn serve(input: impl BufRead + 'static, output: impl Write + 'static) -> io::Result<()> {
+    let mut server = LspServer::new(input, output);
+    while let Some(message) = read_message(&mut server.input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    return Ok(());
+}
+
+/// A position of the protocol, 0-based, as the 1-based line and column of
+/// spans. Columns count characters, which is what the protocol counts
+/// outside of the astral planes.
+fn position(position: &Json) -> Option<(usize, usize)> {
+    let line = usize::try_from(position.get("line").as_i64()?).ok()?;
+    let character = usize::try_from(position.get("character").as_i64()?).ok()?;
+    return Some((line + 1, character + 1));
+}
+
+fn range(span: Span) -> Json {
+    let position = |line: usize, column: usize| Json::object([
+        ("line", line.saturating_sub(1).into()),
+        ("character", column.saturating_sub(1).into()),
+    ]);
+    return Json::object([
+        ("start", position(span.line, span.column)),
+        ("end", position(span.end_line, span.end_column)),
+    ]);
+}
+
+/// Whether `name` can be written as a symbol, and so be a variable.
+fn is_symbol(name: &str) -> bool {
+    return !name.is_empty()
+        && name.parse::<i64>().is_err()
+        && !name.chars().any(|next| next.is_whitespace() || matches!(next, '(' | ')' | '[' | ']' | '"' | ';' | '\''));
+}
+
+fn markdown(code: &str, text: Option<&str>) -> Json {
+    let mut value = format!("```lisp\n{code}\n```");
+    if let Some(text) = text {
+        value.push_str(&format!("\n\n{text}"));
+    }
+    return Json::object([("kind", "markdown".into()), ("value", value.into())]);
+}
+
+impl LspServer {
+    pub fn new(input: impl BufRead + 'static, output: impl Write + 'static) -> Self {
+        let built_ins = Environment::built_ins_env();
+        let prelude = {
+            let built_ins = built_ins.borrow();
+            Document::analyze(prelude::SOURCE, &|name| built_ins.is_bound(name))
+        };
+        return LspServer {
+            input: Box::new(input),
+            output: Box::new(output),
+            documents: HashMap::new(),
+            built_ins,
+            prelude,
+            shut_down: false,
+        };
+    }
+
+    fn send<'a>(&mut self, fields: impl IntoIterator<Item = (&'a str, Json)>) -> io::Result<()> {
+        let message = Json::object([("jsonrpc", "2.0".into())].into_iter().chain(fields));
+        return write_message(&mut self.output, &message);
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        return self.send([("method", method.into()), ("params", params)]);
+    }
+
+    /// Handles one message, returning whether to go on to the next.
+    fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let method = message.get("method").as_str().unwrap_or_default();
+        let params = message.get("params");
+        let id = message.get("id");
+        if id.is_null() {
+            match method {
+                "exit" => return Ok(false),
+                "textDocument/didOpen" => {
+                    let document = params.get("textDocument");
+                    self.update(document.get("uri").as_str().unwrap_or_default(), document.get("text").as_str().unwrap_or_default())?;
+                },
+                "textDocument/didChange" => {
+                    // the whole text, as asked for by the sync kind
+                    if let Some(change) = params.get("contentChanges").as_array().last() {
+                        let uri = params.get("textDocument").get("uri").as_str().unwrap_or_default();
+                        self.update(uri, change.get("text").as_str().unwrap_or_default())?;
+                    }
+                },
+                "textDocument/didClose" => {
+                    let uri = params.get("textDocument").get("uri").as_str().unwrap_or_default().to_string();
+                    self.documents.remove(&uri);
+                    self.notify("textDocument/publishDiagnostics", Json::object([
+                        ("uri", uri.into()),
+                        ("diagnostics", Json::Array(Vec::new())),
+                    ]))?;
+                },
+                // anything else needs no answer
+                _ => {},
+            }
+            return Ok(true);
+        }
+
+        let answer = match method {
+            "initialize" => Ok(Self::capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            },
+            _ if self.shut_down => Err(Failure::new(REQUEST_FAILED, "the server is shutting down")),
+            "textDocument/hover" => self.at_position(params, Self::hover),
+            "textDocument/definition" => self.at_position(params, Self::definition),
+            "textDocument/completion" => self.at_position(params, Self::completion),
+            "textDocument/rename" => self.at_position(params, Self::rename),
+            "textDocument/documentSymbol" => self.in_document(params, |_, _, document| Ok(Self::symbols(document))),
+            "textDocument/formatting" => self.in_document(params, |_, _, document| Ok(Self::formatting(document))),
+            _ => Err(Failure::new(METHOD_NOT_FOUND, format!("unsupported method {method}"))),
+        };
+        let outcome = match answer {
+            Ok(result) => ("result", result),
+            Err(failure) => ("error", Json::object([("code", failure.code.into()), ("message", failure.message.into())])),
+        };
+        self.send([("id", id.clone()), outcome])?;
+        return Ok(true);
+    }
+
+    fn capabilities() -> Json {
+        return Json::object([
+            ("capabilities", Json::object([
+                // the whole document is sent on every change
+                ("textDocumentSync", 1_i64.into()),
+                ("hoverProvider", true.into()),
+                ("definitionProvider", true.into()),
+                ("completionProvider", Json::object([])),
+                ("documentSymbolProvider", true.into()),
+                ("renameProvider", true.into()),
+                ("documentFormattingProvider", true.into()),
+            ])),
+            ("serverInfo", Json::object([("name", "lisp".into()), ("version", env!("CARGO_PKG_VERSION").into())])),
+        ]);
+    }
+
+    fn update(&mut self, uri: &str, text: &str) -> io::Result<()> {
+        let document = {
+            let built_ins = self.built_ins.borrow();
+            Document::analyze(text, &|name| built_ins.is_bound(name))
+        };
+        let diagnostics: Vec<Json> = document.diagnostics.iter().map(|diagnostic| Json::object([
+            ("range", range(diagnostic.span)),
+            ("severity", match diagnostic.severity {
+                Severity::Error => 1_i64,
+                Severity::Warning => 2_i64,
+            }.into()),
+            ("source", "lisp".into()),
+            ("message", diagnostic.message.clone().into()),
+        ])).collect();
+        self.documents.insert(uri.to_string(), document);
+        return self.notify("textDocument/publishDiagnostics", Json::object([
+            ("uri", uri.into()),
+            ("diagnostics", diagnostics.into()),
+        ]));
+    }
+
+    fn in_document(&self, params: &Json, answer: impl FnOnce(&Self, &str, &Document) -> Answer) -> Answer {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or_default();
+        return match self.documents.get(uri) {
+            Some(document) => answer(self, uri, document),
+            None => Err(Failure::new(INVALID_PARAMS, format!("{uri} is not open"))),
+        };
+    }
+
+    fn at_position(&self, params: &Json, answer: impl FnOnce(&Self, &str, &Document, (usize, usize), &Json) -> Answer) -> Answer {
+        let Some(at) = position(params.get("position")) else {
+            return Err(Failure::new(INVALID_PARAMS, "expecting a position"));
+        };
+        return self.in_document(params, |server, uri, document| answer(server, uri, document, at, params));
+    }
+
+    fn hover(&self, _uri: &str, document: &Document, (line, column): (usize, usize), _params: &Json) -> Answer {
+        let Some(reference) = document.reference_at(line, column) else { return Ok(Json::Null) };
+        let contents = match reference.binding {
+            Some(index) => Self::describe(&document.bindings[index]),
+            None => match self.describe_built_in(&reference.name) {
+                Some(contents) => contents,
+                None => return Ok(Json::Null),
+            },
+        };
+        return Ok(Json::object([("contents", contents), ("range", range(reference.span))]));
+    }
+
+    fn describe(binding: &Binding) -> Json {
+        return match (&binding.signature, binding.kind) {
+            (Some(signature), _) => markdown(signature, binding.doc.as_deref()),
+            (None, BindingKind::Parameter) => markdown(&binding.name, Some("parameter")),
+            (None, BindingKind::Local) => markdown(&binding.name, Some("local variable")),
+            (None, BindingKind::Condition) => markdown(&binding.name, Some("raised condition")),
+            (None, _) => markdown(&binding.name, binding.doc.as_deref()),
+        };
+    }
+
+    fn describe_built_in(&self, name: &str) -> Option<Json> {
+        if let Some(binding) = self.prelude.bindings.iter().find(|binding| binding.global && binding.name == name) {
+            return Some(Self::describe(binding));
+        }
+        let value = self.built_ins.borrow().get(name).ok()?;
+        return Some(match value {
+            LispOutput::Lambda(LispFunction::BuiltInFunction(built_in)) => {
+                markdown(&format!("({name} ...)"), Some(&format!("built-in procedure taking {}", built_in.arity())))
+            },
+            value => markdown(name, Some(&format!("built-in, bound to `{value}`"))),
+        });
+    }
+
+    fn definition(&self, uri: &str, document: &Document, (line, column): (usize, usize), _params: &Json) -> Answer {
+        let binding = document.reference_at(line, column).and_then(|reference| reference.binding);
+        return Ok(match binding {
+            Some(index) => Json::object([("uri", uri.into()), ("range", range(document.bindings[index].span))]),
+            None => Json::Null,
+        });
+    }
+
+    /// The special forms, variables and built-ins starting with the word
+    /// before the position.
+    fn completion(&self, _uri: &str, document: &Document, (line, column): (usize, usize), _params: &Json) -> Answer {
+        let text = document.text(Span { line, column: 1, end_line: line, end_column: column });
+        let prefix: String = text.chars().rev()
+            .take_while(|next| !next.is_whitespace() && !matches!(next, '(' | ')' | '[' | ']' | '"' | '\''))
+            .collect::<Vec<char>>()
+            .into_iter()
+            .rev()
+            .collect();
+
+        let mut candidates: BTreeMap<String, (i64, Option<String>)> = BTreeMap::new();
+        for (name, value) in self.built_ins.borrow().local_bindings() {
+            let kind = if matches!(value, LispOutput::Lambda(_)) { COMPLETION_FUNCTION } else { COMPLETION_VARIABLE };
+            candidates.insert(name, (kind, None));
+        }
+        for form in SPECIAL_FORMS {
+            candidates.insert(form.to_string(), (COMPLETION_KEYWORD, Some("special form".to_string())));
+        }
+        for binding in document.visible_at(line, column) {
+            let kind = if binding.kind == BindingKind::Procedure { COMPLETION_FUNCTION } else { COMPLETION_VARIABLE };
+            candidates.insert(binding.name.clone(), (kind, binding.signature.clone()));
+        }
+
+        let items: Vec<Json> = candidates.into_iter()
+            .filter(|(name, _)| name.starts_with(&prefix))
+            .map(|(name, (kind, detail))| {
+                let mut item = Json::object([("label", name.into()), ("kind", kind.into())]);
+                if let Some(detail) = detail {
+                    item.set("detail", detail.into());
+                }
+                return item;
+            })
+            .collect();
+        return Ok(items.into());
+    }
+
+    fn rename(&self, uri: &str, document: &Document, (line, column): (usize, usize), params: &Json) -> Answer {
+        let new_name = params.get("newName").as_str().unwrap_or_default();
+        if !is_symbol(new_name) || SPECIAL_FORMS.contains(&new_name) {
+            return Err(Failure::new(INVALID_PARAMS, format!("{new_name} can not be the name of a variable")));
+        }
+        let Some(reference) = document.reference_at(line, column) else {
+            return Err(Failure::new(REQUEST_FAILED, "there is no variable to rename here"));
+        };
+        let Some(binding) = reference.binding else {
+            return Err(Failure::new(REQUEST_FAILED, format!("{} is not defined in this document", reference.name)));
+        };
+        let edits: Vec<Json> = document.references_to(binding)
+            .map(|reference| Json::object([("range", range(reference.span)), ("newText", new_name.into())]))
+            .collect();
+        return Ok(Json::object([("changes", Json::object([(uri, edits.into())]))]));
+    }
+
+    fn symbols(document: &Document) -> Json {
+        let symbols: Vec<Json> = document.bindings.iter().filter(|binding| binding.global).map(|binding| {
+            let kind = if binding.kind == BindingKind::Procedure { SYMBOL_FUNCTION } else { SYMBOL_VARIABLE };
+            let mut symbol = Json::object([
+                ("name", binding.name.clone().into()),
+                ("kind", kind.into()),
+                ("range", range(binding.form)),
+                ("selectionRange", range(binding.span)),
+            ]);
+            if let Some(signature) = &binding.signature {
+                symbol.set("detail", signature.clone().into());
+            }
+            return symbol;
+        }).collect();
+        return symbols.into();
+    }
+
+    /// An edit replacing the whole document, if formatting changes it.
+    /// Documents that do not parse are left alone.
+    fn formatting(document: &Document) -> Json {
+        let whole = Span { line: 1, column: 1, end_line: document.line_count() + 1, end_column: 1 };
+        let formatted = format::format(document.source());
+        if !document.parsed || formatted == document.source() {
+            return Json::Array(Vec::new());
+        }
+        return vec![Json::object([("range", range(whole)), ("newText", formatted.into())])].into();
+    }
+}
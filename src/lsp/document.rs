@@ -0,0 +1,630 @@
+use std::collections::HashSet;
+
+use crate::analyze::analyze_with_spans;
+use crate::functions::{Parameters, KEYWORD_PREFIX};
+use crate::lisp_expression::{LispExpression, Span, SpanTree};
+use crate::parser::parse_all_with_spans;
+use crate::tokenizer::try_tokenize_with_spans;
+use crate::types;
+
+
+/// The special forms, which are never looked up as variables.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "define", "lambda", "if", "and", "or", "del", "let", "let*", "letrec", "letrec*", "quote",
+    "the-environment", "guard", "import", "define-library", "define-test", "test-group", "assert-error", "set!", ":",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Procedure,
+    Variable,
+    Parameter,
+    /// Bound by one of the `let` forms.
+    Local,
+    /// The variable of a `guard`, bound to what was raised.
+    Condition,
+}
+
+/// A variable the document binds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub kind: BindingKind,
+    /// Where the name is written in its first definition.
+    pub span: Span,
+    /// The whole of the form binding it.
+    pub form: Span,
+    /// The form the binding is seen in, the whole document for globals.
+    pub scope: Span,
+    pub global: bool,
+    /// How a procedure is called, `(name parameter ...)` as written.
+    pub signature: Option<String>,
+    /// The comment just above a global definition.
+    pub doc: Option<String>,
+}
+
+/// A place where a variable is named, including where it is bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+    /// The index of the binding among `Document::bindings`, or `None` for
+    /// built-ins and unbound variables.
+    pub binding: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// A source file as the language server sees it: the variables it binds,
+/// where each is referred to and what is wrong with it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    source: String,
+    lines: Vec<Vec<char>>,
+    pub bindings: Vec<Binding>,
+    pub references: Vec<Reference>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The lists evaluated as code, calls and special forms, leaving out
+    /// quoted data and lists of parameters or bindings.
+    pub code: Vec<Span>,
+    /// Whether the source could be parsed at all.
+    pub parsed: bool,
+    /// Whether it imports libraries, whose names are then bound without the
+    /// document knowing them.
+    pub imports: bool,
+}
+
+impl Document {
+    /// Analyzes `source`, where the names `is_built_in` accepts are bound
+    /// without being defined.
+    pub fn analyze(source: &str, is_built_in: &dyn Fn(&str) -> bool) -> Self {
+        let mut document = Document {
+            source: source.to_string(),
+            lines: source.lines().map(|line| line.chars().collect()).collect(),
+            ..Document::default()
+        };
+        let parsed = try_tokenize_with_spans(source)
+            .and_then(|(tokens, spans)| parse_all_with_spans(&tokens, &spans));
+        let forms = match parsed {
+            Ok(forms) => forms,
+            Err(err) => {
+                let span = err.span().unwrap_or(Span { line: 1, column: 1, end_line: 1, end_column: 1 });
+                document.diagnostics.push(Diagnostic { span, severity: Severity::Error, message: err.message });
+                return document;
+            },
+        };
+        document.parsed = true;
+        for (form, spans) in &forms {
+            if let Err(err) = analyze_with_spans(form, Some(spans)) {
+                let span = err.span().unwrap_or(spans.span);
+                document.diagnostics.push(Diagnostic { span, severity: Severity::Error, message: err.message });
+            }
+        }
+
+        let whole = Span { line: 1, column: 1, end_line: document.lines.len() + 1, end_column: 1 };
+        let mut analyzer = Analyzer { document, scopes: vec![Vec::new()], declared: HashSet::new() };
+        let (expressions, span_trees): (Vec<_>, Vec<_>) = forms.into_iter().unzip();
+        analyzer.walk_body(&expressions, &span_trees, whole);
+
+        let mut document = analyzer.document;
+        if !document.imports {
+            // what an import binds is not known without loading the library
+            let unbound: Vec<Diagnostic> = document.references.iter()
+                .filter(|reference| reference.binding.is_none() && !is_built_in(&reference.name))
+                .filter(|reference| !SPECIAL_FORMS.contains(&reference.name.as_str()))
+                .map(|reference| Diagnostic {
+                    span: reference.span,
+                    severity: Severity::Warning,
+                    message: format!("unbound variable {}", reference.name),
+                })
+                .collect();
+            document.diagnostics.extend(unbound);
+        }
+        return document;
+    }
+
+    /// The text of the source within `span`.
+    pub fn text(&self, span: Span) -> String {
+        let mut text = String::new();
+        for line in span.line..=span.end_line {
+            let Some(chars) = self.lines.get(line - 1) else { break };
+            let start = if line == span.line { span.column - 1 } else { 0 };
+            let end = if line == span.end_line { span.end_column - 1 } else { chars.len() };
+            text.extend(chars.iter().take(end).skip(start));
+            if line != span.end_line {
+                text.push('\n');
+            }
+        }
+        return text;
+    }
+
+    pub fn source(&self) -> &str {
+        return &self.source;
+    }
+
+    pub fn line_count(&self) -> usize {
+        return self.lines.len();
+    }
+
+    /// The reference at the 1-based `line` and `column`, including just
+    /// after its end.
+    pub fn reference_at(&self, line: usize, column: usize) -> Option<&Reference> {
+        return self.references.iter().find(|reference| {
+            let span = reference.span;
+            return span.line == line && span.column <= column && column <= span.end_column;
+        });
+    }
+
+    pub fn references_to(&self, binding: usize) -> impl Iterator<Item = &Reference> {
+        return self.references.iter().filter(move |reference| reference.binding == Some(binding));
+    }
+
+    /// The bindings seen at the 1-based `line` and `column`.
+    pub fn visible_at(&self, line: usize, column: usize) -> impl Iterator<Item = &Binding> {
+        return self.bindings.iter().filter(move |binding| binding.global || contains(binding.scope, line, column));
+    }
+
+    /// The comment lines right above `line`, without their semicolons.
+    fn comment_above(&self, line: usize) -> Option<String> {
+        let mut comment = Vec::new();
+        for above in (0..line - 1).rev() {
+            let text: String = self.lines[above].iter().collect();
+            let Some(content) = text.trim().strip_prefix(';') else { break };
+            let content = content.trim_start_matches(';');
+            comment.push(content.strip_prefix(' ').unwrap_or(content).to_string());
+        }
+        comment.reverse();
+        return (!comment.is_empty()).then(|| comment.join("\n"));
+    }
+}
+
+fn contains(span: Span, line: usize, column: usize) -> bool {
+    return (span.line, span.column) <= (line, column) && (line, column) < (span.end_line, span.end_column);
+}
+
+fn head(items: &[LispExpression]) -> Option<&str> {
+    return match items.first() {
+        Some(LispExpression::Symbol(symbol)) => Some(symbol),
+        _ => None,
+    };
+}
+
+fn is_lambda(expression: Option<&LispExpression>) -> bool {
+    return matches!(expression, Some(LispExpression::List(items)) if head(items) == Some("lambda"));
+}
+
+/// The `(name init)` pairs of a binding list, leaving out malformed ones.
+fn binding_pairs<'a>(bindings: &'a LispExpression, spans: &'a SpanTree) -> Vec<(&'a str, Span, &'a LispExpression, &'a SpanTree)> {
+    let LispExpression::List(bindings) = bindings else { return Vec::new() };
+    return bindings.iter().zip(&spans.children).filter_map(|(binding, spans)| match binding {
+        LispExpression::List(pair) if pair.len() == 2 => match &pair[0] {
+            LispExpression::Symbol(name) => Some((name.as_str(), spans.children[0].span, &pair[1], &spans.children[1])),
+            _ => None,
+        },
+        _ => None,
+    }).collect();
+}
+
+/// Walks the forms of a document, resolving every variable named to the
+/// binding it refers to.
+struct Analyzer {
+    document: Document,
+    /// The bindings of each enclosing form, innermost last.
+    scopes: Vec<Vec<(String, usize)>>,
+    /// The spans of the names defined already, which `walk` comes across again.
+    declared: HashSet<(usize, usize)>,
+}
+
+impl Analyzer {
+    fn lookup(&self, name: &str) -> Option<usize> {
+        return self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(bound, _)| bound == name)
+            .map(|(_, binding)| *binding);
+    }
+
+    fn refer(&mut self, name: &str, span: Span) {
+        let binding = self.lookup(name);
+        self.document.references.push(Reference { name: name.to_string(), span, binding });
+    }
+
+    fn bind(&mut self, binding: Binding) {
+        let index = self.document.bindings.len();
+        self.document.references.push(Reference { name: binding.name.clone(), span: binding.span, binding: Some(index) });
+        self.scopes.last_mut().unwrap().push((binding.name.clone(), index));
+        self.document.bindings.push(binding);
+    }
+
+    fn bind_local(&mut self, name: &str, span: Span, kind: BindingKind, scope: Span) {
+        self.bind(Binding {
+            name: name.to_string(),
+            kind,
+            span,
+            form: span,
+            scope,
+            global: false,
+            signature: None,
+            doc: None,
+        });
+    }
+
+    /// Binds the name of `(define ...)` in the innermost scope, unless it is
+    /// a redefinition there.
+    fn declare(&mut self, items: &[LispExpression], spans: &SpanTree, scope: Span) {
+        let (name, span, kind, signature) = match items.get(1) {
+            Some(LispExpression::Symbol(name)) => {
+                let signature = match items.get(2) {
+                    Some(LispExpression::List(lambda)) if is_lambda(items.get(2)) => {
+                        let parameters = match lambda.get(1) {
+                            Some(LispExpression::List(parameters)) if !parameters.is_empty() => {
+                                let text = self.document.text(spans.children[2].children[1].span);
+                                format!(" {}", &text[1..text.len() - 1])
+                            },
+                            Some(LispExpression::Symbol(rest)) => format!(" . {rest}"),
+                            _ => String::new(),
+                        };
+                        Some(format!("({name}{parameters})"))
+                    },
+                    _ => None,
+                };
+                let kind = if signature.is_some() { BindingKind::Procedure } else { BindingKind::Variable };
+                (name, spans.children[1].span, kind, signature)
+            },
+            Some(LispExpression::List(signature)) => match signature.first() {
+                Some(LispExpression::Symbol(name)) => {
+                    let text = self.document.text(spans.children[1].span);
+                    (name, spans.children[1].children[0].span, BindingKind::Procedure, Some(text))
+                },
+                _ => return,
+            },
+            _ => return,
+        };
+        if !self.declared.insert((span.line, span.column)) {
+            return;
+        }
+        let redefined = self.scopes.last().unwrap().iter().rev().find(|(bound, _)| bound == name).map(|(_, index)| *index);
+        if let Some(index) = redefined {
+            self.document.references.push(Reference { name: name.clone(), span, binding: Some(index) });
+            return;
+        }
+        let global = self.scopes.len() == 1;
+        let doc = if global { self.document.comment_above(spans.span.line) } else { None };
+        self.bind(Binding { name: name.clone(), kind, span, form: spans.span, scope, global, signature, doc });
+    }
+
+    /// Binds what `expression` defines in the frame it is evaluated in,
+    /// without going into the forms that make frames of their own.
+    fn collect_definitions(&mut self, expression: &LispExpression, spans: &SpanTree, scope: Span) {
+        let LispExpression::List(items) = expression else { return };
+        match head(items) {
+            Some("define") => {
+                self.declare(items, spans, scope);
+                if let (Some(LispExpression::Symbol(_)), Some(value)) = (items.get(1), items.get(2)) {
+                    self.collect_definitions(value, &spans.children[2], scope);
+                }
+            },
+            Some("let" | "let*") => {
+                let bindings = if matches!(items.get(1), Some(LispExpression::Symbol(_))) { 2 } else { 1 };
+                if let Some(bindings_expression) = items.get(bindings) {
+                    for (_, _, init, init_spans) in binding_pairs(bindings_expression, &spans.children[bindings]) {
+                        self.collect_definitions(init, init_spans, scope);
+                    }
+                }
+            },
+            Some("guard") => {
+                if let Some(body) = items.get(2) {
+                    self.collect_definitions(body, &spans.children[2], scope);
+                }
+            },
+            Some(form) if SPECIAL_FORMS.contains(&form) && !matches!(form, "if" | "and" | "or" | "set!") => {},
+            _ => {
+                for (item, item_spans) in items.iter().zip(&spans.children) {
+                    self.collect_definitions(item, item_spans, scope);
+                }
+            },
+        }
+    }
+
+    /// Walks the expressions of a body, whose definitions are seen all
+    /// through it.
+    fn walk_body(&mut self, body: &[LispExpression], spans: &[SpanTree], scope: Span) {
+        for (expression, expression_spans) in body.iter().zip(spans) {
+            self.collect_definitions(expression, expression_spans, scope);
+        }
+        for (expression, expression_spans) in body.iter().zip(spans) {
+            self.walk(expression, expression_spans);
+        }
+    }
+
+    fn walk(&mut self, expression: &LispExpression, spans: &SpanTree) {
+        match expression {
+            LispExpression::Symbol(name) if !name.starts_with(KEYWORD_PREFIX) => self.refer(name, spans.span),
+            LispExpression::List(items) => self.walk_list(items, spans),
+            _ => {},
+        }
+    }
+
+    fn walk_all(&mut self, items: &[LispExpression], spans: &[SpanTree]) {
+        for (item, item_spans) in items.iter().zip(spans) {
+            self.walk(item, item_spans);
+        }
+    }
+
+    /// Walks a procedure made of `parameters` and `body`, in a scope of its
+    /// own.
+    fn walk_procedure(&mut self, parameters: &[LispExpression], parameter_spans: &[SpanTree], body: &[LispExpression], body_spans: &[SpanTree], scope: Span) {
+        self.scopes.push(Vec::new());
+        for (parameter, spans) in parameters.iter().zip(parameter_spans) {
+            match parameter {
+                LispExpression::Symbol(marker) if Parameters::is_marker(marker) => {},
+                LispExpression::Symbol(name) => self.bind_local(name, spans.span, BindingKind::Parameter, scope),
+                // [name : Type] or [name : Type default], where the type refers to nothing
+                annotated @ LispExpression::List(parts) if types::annotated_parameter(annotated).is_some() => {
+                    if let Some(default) = parts.get(3) {
+                        self.walk(default, &spans.children[3]);
+                    }
+                    if let LispExpression::Symbol(name) = &parts[0] {
+                        self.bind_local(name, spans.children[0].span, BindingKind::Parameter, scope);
+                    }
+                },
+                // (name default), where the default sees the parameters before it
+                LispExpression::List(pair) if pair.len() == 2 => {
+                    self.walk(&pair[1], &spans.children[1]);
+                    if let LispExpression::Symbol(name) = &pair[0] {
+                        self.bind_local(name, spans.children[0].span, BindingKind::Parameter, scope);
+                    }
+                },
+                _ => {},
+            }
+        }
+        self.walk_body(body, body_spans, scope);
+        self.scopes.pop();
+    }
+
+    fn walk_list(&mut self, items: &[LispExpression], spans: &SpanTree) {
+        let children = &spans.children[..];
+        let form = spans.span;
+        self.document.code.push(form);
+        match head(items) {
+            Some("quote" | "the-environment" | "define-library") => {},
+            Some("import") => self.document.imports = true,
+            // (: name Type) refers to the name; the type is not code
+            Some(":") => {
+                if let Some(declared @ LispExpression::Symbol(_)) = items.get(1) {
+                    self.walk(declared, &children[1]);
+                }
+            },
+            Some("define") => match items.get(1) {
+                Some(LispExpression::Symbol(_)) => {
+                    self.declare(items, spans, form);
+                    self.walk_all(&items[2..], &children[2..]);
+                },
+                Some(LispExpression::List(signature)) if !signature.is_empty() => {
+                    self.declare(items, spans, form);
+                    let body = types::body_index(items).min(items.len());
+                    self.walk_procedure(&signature[1..], &children[1].children[1..], &items[body..], &children[body..], form);
+                },
+                _ => self.walk_all(&items[1..], &children[1..]),
+            },
+            Some("lambda") => match items.get(1) {
+                Some(LispExpression::List(parameters)) => {
+                    let body = types::body_index(items).min(items.len());
+                    self.walk_procedure(parameters, &children[1].children, &items[body..], &children[body..], form);
+                },
+                Some(rest @ LispExpression::Symbol(_)) => {
+                    self.walk_procedure(std::slice::from_ref(rest), &children[1..2], &items[2..], &children[2..], form);
+                },
+                _ => self.walk_all(&items[1..], &children[1..]),
+            },
+            Some("let") if matches!(items.get(1), Some(LispExpression::Symbol(_))) => {
+                let LispExpression::Symbol(name) = &items[1] else { return };
+                let pairs = items.get(2).map(|bindings| binding_pairs(bindings, &children[2])).unwrap_or_default();
+                for (_, _, init, init_spans) in &pairs {
+                    self.walk(init, init_spans);
+                }
+                self.scopes.push(Vec::new());
+                self.bind_local(name, children[1].span, BindingKind::Procedure, form);
+                for (var, var_span, _, _) in &pairs {
+                    self.bind_local(var, *var_span, BindingKind::Parameter, form);
+                }
+                self.walk_body(items.get(3..).unwrap_or_default(), children.get(3..).unwrap_or_default(), form);
+                self.scopes.pop();
+            },
+            Some(kind @ ("let" | "let*" | "letrec" | "letrec*")) => {
+                let pairs = items.get(1).map(|bindings| binding_pairs(bindings, &children[1])).unwrap_or_default();
+                if kind == "let" {
+                    for (_, _, init, init_spans) in &pairs {
+                        self.walk(init, init_spans);
+                    }
+                }
+                self.scopes.push(Vec::new());
+                match kind {
+                    "let" => pairs.iter().for_each(|(var, span, _, _)| self.bind_local(var, *span, BindingKind::Local, form)),
+                    // each init sees the variables before it
+                    "let*" => for (var, span, init, init_spans) in &pairs {
+                        self.walk(init, init_spans);
+                        self.bind_local(var, *span, BindingKind::Local, form);
+                    },
+                    _ => {
+                        pairs.iter().for_each(|(var, span, _, _)| self.bind_local(var, *span, BindingKind::Local, form));
+                        for (_, _, init, init_spans) in &pairs {
+                            self.walk(init, init_spans);
+                        }
+                    },
+                }
+                self.walk_body(items.get(2..).unwrap_or_default(), children.get(2..).unwrap_or_default(), form);
+                self.scopes.pop();
+            },
+            Some("guard") => {
+                // the body is evaluated where the guard is
+                self.walk_all(items.get(2..).unwrap_or_default(), children.get(2..).unwrap_or_default());
+                let Some(LispExpression::List(spec)) = items.get(1) else { return };
+                let spec_spans = &children[1].children;
+                self.scopes.push(Vec::new());
+                if let Some(LispExpression::Symbol(var)) = spec.first() {
+                    self.bind_local(var, spec_spans[0].span, BindingKind::Condition, form);
+                }
+                for (clause, clause_spans) in spec.iter().zip(spec_spans).skip(1) {
+                    let LispExpression::List(parts) = clause else { continue };
+                    for (index, (part, part_spans)) in parts.iter().zip(&clause_spans.children).enumerate() {
+                        if !(index == 0 && *part == LispExpression::Symbol("else".to_string())) {
+                            self.walk(part, part_spans);
+                        }
+                    }
+                }
+                self.scopes.pop();
+            },
+            Some("define-test" | "test-group") => self.walk_all(items.get(2..).unwrap_or_default(), children.get(2..).unwrap_or_default()),
+            Some("assert-error") => self.walk_all(items.get(1..2).unwrap_or_default(), children.get(1..2).unwrap_or_default()),
+            Some(form) if SPECIAL_FORMS.contains(&form) => self.walk_all(&items[1..], &children[1..]),
+            _ => self.walk_all(items, children),
+        }
+    }
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(source: &str) -> Document {
+        return Document::analyze(source, &|name| matches!(name, "+" | "*" | "car" | "list" | "display"));
+    }
+
+    /// Each reference as `name@line:column`, followed by where its binding
+    /// is or by `-` when it has none.
+    fn resolved(document: &Document) -> Vec<String> {
+        return document.references.iter().map(|reference| {
+            let target = match reference.binding {
+                Some(index) => {
+                    let span = document.bindings[index].span;
+                    format!("{}:{}", span.line, span.column)
+                },
+                None => "-".to_string(),
+            };
+            return format!("{}@{}:{} {target}", reference.name, reference.span.line, reference.span.column);
+        }).collect();
+    }
+
+    fn messages(document: &Document) -> Vec<String> {
+        return document.diagnostics.iter()
+            .map(|diagnostic| format!("{}:{} {}", diagnostic.span.line, diagnostic.span.column, diagnostic.message))
+            .collect();
+    }
+
+    #[test]
+    fn resolving_variables() {
+        let document = analyze("\
+(define (square x) (* x x))
+(define (sum-squares a #!optional (b a))
+  (let ((sa (square a)) (sb (square b)))
+    (+ sa sb)))
+(define total (let loop ((n 3)) (if n (loop n) total)))");
+        let expected = vec![
+            "square@1:10 1:10", "sum-squares@2:10 2:10", "total@5:9 5:9",
+            "x@1:17 1:17", "*@1:21 -", "x@1:23 1:17", "x@1:25 1:17",
+            "a@2:22 2:22", "a@2:38 2:22", "b@2:36 2:36",
+            "square@3:14 1:10", "a@3:21 2:22", "square@3:30 1:10", "b@3:37 2:36", "sa@3:10 3:10", "sb@3:26 3:26",
+            "+@4:6 -", "sa@4:8 3:10", "sb@4:11 3:26",
+            "loop@5:20 5:20", "n@5:27 5:27", "n@5:37 5:27", "loop@5:40 5:20", "n@5:45 5:27", "total@5:48 5:9",
+        ];
+        assert_eq!(expected, resolved(&document));
+        assert!(document.diagnostics.is_empty(), "{:?}", messages(&document));
+
+        let square = &document.bindings[0];
+        assert_eq!((BindingKind::Procedure, Some("(square x)")), (square.kind, square.signature.as_deref()));
+        assert_eq!(Some("(sum-squares a #!optional (b a))"), document.bindings[1].signature.as_deref());
+        assert_eq!((BindingKind::Variable, None), (document.bindings[2].kind, document.bindings[2].signature.as_deref()));
+    }
+
+    #[test]
+    fn scopes_and_redefinitions() {
+        let document = analyze("\
+(define f (lambda args (car args)))
+(define (g) (begin (define inner 1) (list inner f)))
+(define f 2)
+(let* ((x 1) (x (+ x 1))) x)
+(guard (e (else e)) (car e))");
+        let references = resolved(&document);
+        // a redefinition refers to the first definition
+        assert!(references.contains(&"f@3:9 1:9".to_string()), "{references:?}");
+        assert!(references.contains(&"inner@2:43 2:28".to_string()), "{references:?}");
+        assert!(references.contains(&"f@2:49 1:9".to_string()), "{references:?}");
+        // the second init of let* sees the first x, the body the second
+        assert!(references.contains(&"x@4:20 4:9".to_string()), "{references:?}");
+        assert!(references.contains(&"x@4:27 4:15".to_string()), "{references:?}");
+        // the guard variable is only seen by the clauses
+        assert!(references.contains(&"e@5:26 -".to_string()), "{references:?}");
+        assert!(references.contains(&"e@5:17 5:9".to_string()), "{references:?}");
+        assert_eq!(Some("(f . args)"), document.bindings[0].signature.as_deref());
+        let inner = document.bindings.iter().find(|binding| binding.name == "inner").unwrap();
+        assert!(!inner.global);
+        let e = document.bindings.iter().find(|binding| binding.name == "e").unwrap();
+        assert_eq!(BindingKind::Condition, e.kind);
+        // the binding lists of let* and the clauses of guard are not code
+        assert_eq!(12, document.code.len());
+    }
+
+    #[test]
+    fn diagnostics() {
+        let document = analyze("; sums\n(define (f x) (+ x y))\n(if 1 2)\n(display z)\n(quote w)");
+        let expected = vec![
+            "3:1 special form if was not supplied with correct number of arguments: got 2, expected 3",
+            "2:20 unbound variable y",
+            "4:10 unbound variable z",
+        ];
+        assert_eq!(expected, messages(&document));
+        assert_eq!(Severity::Warning, document.diagnostics[1].severity);
+        assert_eq!(Some("sums".to_string()), document.bindings[0].doc);
+
+        let document = analyze("(define x 1)\n(f \"open");
+        assert_eq!(vec!["2:4 unterminated string literal"], messages(&document));
+        assert!(!document.parsed);
+        assert_eq!(vec!["2:1 missing right parenthesis while trying to parse expression"], messages(&analyze("(f)\n(g (h)")));
+
+        // nothing is unbound once a library is imported
+        let document = analyze("(import (numbers))\n(double 2)");
+        assert!(document.imports);
+        assert!(document.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn positions() {
+        let document = analyze("(define (f x)\n  (car x))");
+        assert_eq!(Some("x"), document.reference_at(2, 8).map(|reference| reference.name.as_str()));
+        assert_eq!(Some("x"), document.reference_at(2, 9).map(|reference| reference.name.as_str()));
+        assert_eq!(None, document.reference_at(2, 2));
+        assert_eq!(2, document.references_to(1).count());
+        assert_eq!("(f x)\n  (car x)", document.text(Span { line: 1, column: 9, end_line: 2, end_column: 10 }));
+        let visible: Vec<&str> = document.visible_at(2, 5).map(|binding| binding.name.as_str()).collect();
+        assert_eq!(vec!["f", "x"], visible);
+        assert_eq!(vec!["f"], document.visible_at(3, 1).map(|binding| binding.name.as_str()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn type_annotations() {
+        let document = analyze("\
+(: scale (-> Int Int))
+(define (scale [x : Int] #!optional [by : Int x]) : Int (* x by))
+(define twice (lambda ([n : Int]) : Int (scale n 2)))");
+        // the types refer to nothing, and the declaration to what it declares
+        assert_eq!(Vec::<String>::new(), messages(&document));
+        let references = resolved(&document);
+        for expected in ["scale@1:4 2:10", "x@2:47 2:17", "x@2:60 2:17", "by@2:62 2:38", "n@3:48 3:25"] {
+            assert!(references.contains(&expected.to_string()), "{expected} in {references:?}");
+        }
+    }
+}
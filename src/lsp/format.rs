@@ -0,0 +1,186 @@
+/// Forms whose last parts are a body, which is indented by two whatever
+/// comes first.
+const BODY_FORMS: &[&str] = &[
+    "define", "lambda", "let", "let*", "letrec", "letrec*", "guard", "define-library", "define-test", "test-group",
+];
+
+const BODY_INDENT: usize = 2;
+
+/// A list left open at the end of a line, with columns counted from 0.
+struct Open {
+    column: usize,
+    line: usize,
+    /// How many of its elements have started so far.
+    elements: usize,
+    head: Option<String>,
+    /// Where its second element starts, if that is on its first line.
+    first_argument: Option<usize>,
+}
+
+impl Open {
+    /// The indentation of the lines inside the list.
+    fn indent(&self) -> usize {
+        if self.head.as_deref().is_some_and(|head| BODY_FORMS.contains(&head)) {
+            return self.column + BODY_INDENT;
+        }
+        // arguments line up with the first one, anything else with the head
+        return self.first_argument.unwrap_or(self.column + 1);
+    }
+}
+
+fn start_element(open: &mut [Open], line: usize, column: usize, word: Option<&str>) {
+    let Some(list) = open.last_mut() else { return };
+    if list.elements == 0 {
+        list.head = word.map(str::to_string);
+    } else if list.elements == 1 && list.line == line {
+        list.first_argument = Some(column);
+    }
+    list.elements += 1;
+}
+
+fn ends_word(next: char) -> bool {
+    return next.is_whitespace() || matches!(next, '(' | ')' | '[' | ']' | '"' | ';' | '\'');
+}
+
+/// Indents every line of `source` by the lists it is in and removes
+/// trailing whitespace and blank lines at the end. Where lines break is left
+/// as it is, and so are lines that start inside a string.
+pub fn format(source: &str) -> String {
+    let mut open: Vec<Open> = Vec::new();
+    let mut in_string = false;
+    let mut lines = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let mut formatted = match in_string {
+            true => line.to_string(),
+            false => match line.trim() {
+                "" => String::new(),
+                content => format!("{}{content}", " ".repeat(open.last().map_or(0, Open::indent))),
+            },
+        };
+
+        let chars: Vec<char> = formatted.chars().collect();
+        let mut escaped = false;
+        let mut quoted = false;
+        let mut index = 0;
+        while index < chars.len() {
+            let next = chars[index];
+            index += 1;
+            if in_string {
+                match next {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {},
+                }
+                continue;
+            }
+            // a quoted datum starts at its quote
+            let starts = !std::mem::take(&mut quoted);
+            match next {
+                ';' => break,
+                _ if next.is_whitespace() => quoted = !starts,
+                '\'' => {
+                    if starts {
+                        start_element(&mut open, number, index - 1, None);
+                    }
+                    quoted = true;
+                },
+                '(' | '[' => {
+                    if starts {
+                        start_element(&mut open, number, index - 1, None);
+                    }
+                    open.push(Open { column: index - 1, line: number, elements: 0, head: None, first_argument: None });
+                },
+                ')' | ']' => {
+                    open.pop();
+                },
+                '"' => {
+                    if starts {
+                        start_element(&mut open, number, index - 1, None);
+                    }
+                    in_string = true;
+                },
+                _ => {
+                    let start = index - 1;
+                    while index < chars.len() && !ends_word(chars[index]) {
+                        index += 1;
+                    }
+                    let word: String = chars[start..index].iter().collect();
+                    if starts {
+                        start_element(&mut open, number, start, Some(&word));
+                    }
+                },
+            }
+        }
+        if !in_string {
+            formatted.truncate(formatted.trim_end().len());
+        }
+        lines.push(formatted);
+    }
+
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    return lines.join("\n") + "\n";
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indenting() {
+        let source = "\
+   (define (fact n)
+(if (< n 2)
+ 1
+        (* n (fact (- n 1)))))
+
+  ; comment (with a parenthesis
+(let ((a 1)
+(b '(1
+2)))
+      (list a
+b \"c (
+  d\"
+    e))
+
+
+";
+        let expected = "\
+(define (fact n)
+  (if (< n 2)
+      1
+      (* n (fact (- n 1)))))
+
+; comment (with a parenthesis
+(let ((a 1)
+      (b '(1
+           2)))
+  (list a
+        b \"c (
+  d\"
+        e))
+";
+        assert_eq!(expected, format(source));
+        assert_eq!(expected, format(expected));
+    }
+
+    #[test]
+    fn lists_without_arguments_on_their_first_line() {
+        assert_eq!("(f\n g\n h)\n", format("(f\ng\n   h)"));
+        assert_eq!("((lambda (x)\n   x)\n 1)\n", format("((lambda (x)\nx)\n1)"));
+        assert_eq!("", format("\n\n"));
+    }
+
+    #[test]
+    fn brackets_are_lists() {
+        assert_eq!("(define (f [x : Int]\n           [y : Int])\n  x)\n", format("(define (f [x : Int]\n[y : Int])\nx)"));
+    }
+}
@@ -0,0 +1,868 @@
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::analyze::{analyze, Lambda, Node, Slots};
+use crate::compiler::{compile, Proto};
+use crate::debugger;
+use crate::evaluate::{Environment, EnvironmentRef, LispOutput};
+use crate::gc;
+use crate::interrupt;
+use crate::library;
+use crate::limits;
+use crate::port::{self, FileAccess};
+use crate::functions::{Function, Intrinsic, LispFunction, LispFunctionCall};
+use crate::lisp_error::{LispError, LispErrorKind, LispResult, TraceFrame};
+use crate::lisp_expression::Span;
+use crate::resolve::resolve;
+
+mod vm;
+
+use vm::CodeFrame;
+
+
+type Env = Rc<RefCell<Environment>>;
+
+thread_local! {
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+    // machines currently running on this thread, outermost first
+    static ACTIVE_MACHINES: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    // where on the Rust stack the outermost machine started
+    static STACK_BASE: Cell<usize> = const { Cell::new(0) };
+}
+
+/// How much of the Rust stack the machines started by built-ins, such as the
+/// callbacks of `map`, may use between them. Each runs on the stack of the
+/// machine that called the built-in, so recursing through them could overflow
+/// the stack whatever the limits; past this they fail with `DepthExceeded`.
+const NESTED_STACK_BUDGET: usize = 1 << 20;
+
+fn next_id() -> u64 {
+    return NEXT_ID.with(|next| {
+        let id = next.get();
+        next.set(id + 1);
+        id
+    });
+}
+
+
+// -------------- CONTINUATIONS --------------
+/// The rest of a computation, captured by `call/cc` as a copy of the frame
+/// stack of the machine that was running at the time.
+#[derive(Clone)]
+pub struct Continuation {
+    machine_id: u64,
+    /// Whether the machine was the outermost one running. The frames of a
+    /// nested machine are only the rest of a call made by a built-in, so they
+    /// can not be resumed once that call has returned.
+    outermost: bool,
+    frames: Rc<Vec<Frame>>,
+    /// Present for escape continuations; cleared once the extent of the
+    /// `call-with-escape-continuation` that created them has ended.
+    escape_valid: Option<Rc<Cell<bool>>>,
+}
+
+impl std::fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Continuation")
+    }
+}
+
+impl PartialEq for Continuation {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.frames, &other.frames)
+    }
+}
+
+/// A continuation invoked from inside a nested machine, for instance from a
+/// function called by the `map` built-in. It travels through the Rust stack as
+/// an error until it reaches the machine named by `target`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jump {
+    target: u64,
+    continuation: Continuation,
+    value: LispOutput,
+}
+
+
+// -------------- FRAMES --------------
+/// What to do with the value of the expression currently being evaluated.
+#[derive(Clone)]
+enum Frame {
+    /// Evaluating the operands of an `Application`, `Let` or `NamedLet`.
+    Operands { node: Rc<Node>, values: Vec<LispOutput>, env: Env },
+    If { node: Rc<Node>, env: Env },
+    Define { node: Rc<Node>, env: Env },
+    SetBang { node: Rc<Node>, env: Env },
+    And { node: Rc<Node>, index: usize, env: Env },
+    Or { node: Rc<Node>, index: usize, env: Env },
+    LetStar { node: Rc<Node>, index: usize, env: Env },
+    Letrec { node: Rc<Node>, values: Vec<LispOutput>, env: Env },
+    /// `dynamic-wind` waiting for its `before` thunk.
+    WindBefore { before: LispFunction, thunk: LispFunction, after: LispFunction },
+    /// The dynamic extent of a `dynamic-wind` thunk.
+    Wind { id: u64, before: LispFunction, after: LispFunction },
+    /// `dynamic-wind` waiting for its `after` thunk before returning `value`.
+    WindAfter { value: LispOutput },
+    /// The dynamic extent of a `call-with-escape-continuation`.
+    EscapeExtent { valid: Rc<Cell<bool>> },
+    /// A handler installed by `with-exception-handler` around its thunk.
+    Handler { handler: LispFunction },
+    /// The handler at `handler_index` is handling `condition`; while it runs
+    /// only the handlers below it are in effect.
+    Handling { handler_index: usize, condition: LispOutput, continuable: bool },
+    /// The body of a `guard`, which catches whatever is raised inside it.
+    Guard { node: Rc<Node>, env: Env },
+    /// Testing the clause at `index` of a `guard` that caught `condition`.
+    GuardClause { node: Rc<Node>, index: usize, env: Env, condition: LispOutput },
+    /// Marks the body of a procedure call, for backtraces. A call in tail
+    /// position replaces the marker of its caller.
+    Call { lambda: Rc<Lambda>, span: Option<Span>, arguments: Vec<LispOutput> },
+    /// Compiled code waiting for the value of a call.
+    Code(CodeFrame),
+    /// The body of a compiled `guard`; `frame` is where its handler code
+    /// resumes.
+    CodeGuard { frame: CodeFrame },
+}
+
+enum Control {
+    Eval(Rc<Node>, Env),
+    Return(LispOutput),
+    /// A function, its arguments, the environment of the caller and where
+    /// the call was written.
+    Apply(LispFunction, Vec<LispOutput>, Option<Env>, Option<Span>),
+    /// Compiled code, run by the virtual machine in `vm`.
+    Run(CodeFrame),
+}
+
+enum Step {
+    Continue(Control),
+    Done(LispOutput),
+}
+
+fn return_value(value: LispOutput) -> LispResult<Step> {
+    return Ok(Step::Continue(Control::Return(value)));
+}
+
+fn operands(node: &Node) -> &[Rc<Node>] {
+    match node {
+        Node::Application(operands, _) => operands,
+        Node::Let { inits, .. } | Node::NamedLet { inits, .. } => inits,
+        _ => unreachable!("node has no operands"),
+    }
+}
+
+fn child_environment(slots: &Slots, values: impl IntoIterator<Item = LispOutput>, parent: &Env) -> Env {
+    return gc::allocate(Environment::frame(slots.clone(), values, parent));
+}
+
+
+// -------------- MACHINE --------------
+/// Evaluates analyzed code with an explicit stack of frames instead of Rust
+/// recursion, which is what lets `call/cc` capture the rest of a computation.
+struct Machine {
+    id: u64,
+    stack: Vec<Frame>,
+    /// Whether `eval` compiles the code it is given.
+    compiled: bool,
+    /// The number of `Call` frames on the stack.
+    calls: usize,
+}
+
+impl Drop for Machine {
+    fn drop(&mut self) {
+        limits::leave_calls(self.calls);
+    }
+}
+
+/// Marks a machine as running for as long as it is alive.
+struct ActiveMachine;
+
+impl ActiveMachine {
+    fn enter(id: u64) -> LispResult<Self> {
+        let marker = 0u8;
+        let position = std::ptr::addr_of!(marker) as usize;
+        return ACTIVE_MACHINES.with(|active| {
+            let mut active = active.borrow_mut();
+            match active.is_empty() {
+                true => STACK_BASE.with(|base| base.set(position)),
+                false => {
+                    let used = STACK_BASE.with(|base| base.get().abs_diff(position));
+                    if used > NESTED_STACK_BUDGET {
+                        return Err(LispError::new(
+                            LispErrorKind::DepthExceeded,
+                            format!("more than {} procedures called from built-ins in progress", active.len()),
+                        ));
+                    }
+                }
+            }
+            active.push(id);
+            Ok(ActiveMachine)
+        });
+    }
+}
+
+impl Drop for ActiveMachine {
+    fn drop(&mut self) {
+        ACTIVE_MACHINES.with(|active| active.borrow_mut().pop());
+    }
+}
+
+pub fn execute(node: Rc<Node>, env: Env) -> LispResult {
+    return Machine::new(false).run(Control::Eval(node, env));
+}
+
+/// Runs code produced by the `compiler` on the virtual machine.
+pub fn execute_compiled(proto: Rc<Proto>, env: Env) -> LispResult {
+    return Machine::new(true).run(Control::Run(CodeFrame::new(proto, env)));
+}
+
+pub fn call_function(function: LispFunction, args: Vec<LispOutput>) -> LispResult {
+    let compiled = matches!(function, LispFunction::Closure(_));
+    return Machine::new(compiled).run(Control::Apply(function, args, None, None));
+}
+
+impl Machine {
+    fn new(compiled: bool) -> Self {
+        return Machine {
+            id: next_id(),
+            stack: Vec::new(),
+            compiled,
+            calls: 0,
+        };
+    }
+
+    fn run(&mut self, mut control: Control) -> LispResult {
+        let _active = ActiveMachine::enter(self.id)?;
+        loop {
+            let step = match limits::step().and_then(|()| interrupt::check()) {
+                Err(err) => Err(err),
+                Ok(()) => match control {
+                    Control::Eval(node, env) => self.eval(node, env),
+                    Control::Return(value) => match self.stack.pop() {
+                        Some(frame) => self.resume(frame, value),
+                        None => Ok(Step::Done(value)),
+                    },
+                    Control::Apply(function, args, env, span) => self.apply(function, args, env, span),
+                    Control::Run(frame) => self.run_code(frame),
+                },
+            };
+
+            control = match step {
+                Ok(Step::Continue(next)) => next,
+                Ok(Step::Done(value)) => return Ok(value),
+                Err(mut err) => {
+                    if err.as_jump().is_none() {
+                        err.backtrace.extend(self.backtrace());
+                    }
+                    match self.recover(err)? {
+                        Step::Continue(next) => next,
+                        Step::Done(value) => return Ok(value),
+                    }
+                },
+            };
+        }
+    }
+
+    fn eval(&mut self, node: Rc<Node>, env: Env) -> LispResult<Step> {
+        let next = match &*node {
+            Node::Constant(value) => return return_value(value.clone()),
+            Node::Variable(var, reference) => return return_value(env.borrow().lookup(var, *reference)?),
+            Node::Lambda(lambda) => {
+                let function = Function::new(lambda.clone(), env);
+                return return_value(LispOutput::Lambda(LispFunction::Function(function)));
+            },
+            Node::Del(var) => return return_value(env.borrow_mut().del(var)?),
+            Node::TheEnvironment => return return_value(LispOutput::Environment(EnvironmentRef(env))),
+            Node::Import(sets) => {
+                library::import(sets, &env, self.compiled)?;
+                return return_value(LispOutput::Void);
+            },
+            Node::DefineLibrary(definition) => {
+                library::define_library(definition, self.compiled)?;
+                return return_value(LispOutput::Void);
+            },
+            Node::Define(_, value) => {
+                let value = value.clone();
+                self.stack.push(Frame::Define { node, env: env.clone() });
+                Control::Eval(value, env)
+            },
+            Node::SetBang(_, _, value) => {
+                let value = value.clone();
+                self.stack.push(Frame::SetBang { node, env: env.clone() });
+                Control::Eval(value, env)
+            },
+            Node::If(condition, _, _) => {
+                let condition = condition.clone();
+                self.stack.push(Frame::If { node, env: env.clone() });
+                Control::Eval(condition, env)
+            },
+            Node::And(clauses) | Node::Or(clauses) if clauses.is_empty() => {
+                return return_value(LispOutput::Bool(matches!(*node, Node::And(_))));
+            },
+            Node::And(clauses) => {
+                let first = clauses[0].clone();
+                self.stack.push(Frame::And { node, index: 0, env: env.clone() });
+                Control::Eval(first, env)
+            },
+            Node::Or(clauses) => {
+                let first = clauses[0].clone();
+                self.stack.push(Frame::Or { node, index: 0, env: env.clone() });
+                Control::Eval(first, env)
+            },
+            Node::Application(..) | Node::Let { .. } | Node::NamedLet { .. } => {
+                return self.next_operand(node, Vec::new(), env);
+            },
+            Node::LetStar { bindings, body, .. } => match bindings.first() {
+                None => Control::Eval(body.clone(), env),
+                Some((_, init)) => {
+                    let init = init.clone();
+                    self.stack.push(Frame::LetStar { node, index: 0, env: env.clone() });
+                    Control::Eval(init, env)
+                },
+            },
+            Node::Guard { body, .. } => {
+                let body = body.clone();
+                self.stack.push(Frame::Guard { node, env: env.clone() });
+                Control::Eval(body, env)
+            },
+            Node::Letrec { bindings, body, slots, .. } => {
+                let placeholders = bindings.iter().map(|_| LispOutput::Void);
+                let new_env = child_environment(slots, placeholders, &env);
+                match bindings.first() {
+                    None => Control::Eval(body.clone(), new_env),
+                    Some((_, init)) => {
+                        let init = init.clone();
+                        self.stack.push(Frame::Letrec { node, values: Vec::new(), env: new_env.clone() });
+                        Control::Eval(init, new_env)
+                    },
+                }
+            },
+        };
+        return Ok(Step::Continue(next));
+    }
+
+    fn next_operand(&mut self, node: Rc<Node>, values: Vec<LispOutput>, env: Env) -> LispResult<Step> {
+        let next = match operands(&node).get(values.len()) {
+            Some(next) => next.clone(),
+            None => return self.finish_operands(node, values, env),
+        };
+        self.stack.push(Frame::Operands { node, values, env: env.clone() });
+        return Ok(Step::Continue(Control::Eval(next, env)));
+    }
+
+    fn finish_operands(&mut self, node: Rc<Node>, mut values: Vec<LispOutput>, env: Env) -> LispResult<Step> {
+        let next = match &*node {
+            Node::Application(_, span) => {
+                let function = match values.remove(0) {
+                    LispOutput::Lambda(function) => function,
+                    _ => return Err(LispError::type_mismatch("expected function for first expression of list")),
+                };
+                Control::Apply(function, values, Some(env), *span)
+            },
+            Node::Let { body, slots, .. } => {
+                Control::Eval(body.clone(), child_environment(slots, values, &env))
+            },
+            Node::NamedLet { lambda, slots, .. } => {
+                // the loop procedure lives in its own frame so that it is
+                // visible from its body but not from the initializers
+                let loop_env = child_environment(slots, [], &env);
+                let function = LispFunction::Function(Function::new(lambda.clone(), loop_env.clone()));
+                loop_env.borrow_mut().set_slot(0, LispOutput::Lambda(function.clone()));
+                Control::Apply(function, values, Some(env), None)
+            },
+            _ => unreachable!("node has no operands"),
+        };
+        return Ok(Step::Continue(next));
+    }
+
+    fn resume(&mut self, frame: Frame, value: LispOutput) -> LispResult<Step> {
+        let next = match frame {
+            Frame::Operands { node, mut values, env } => {
+                values.push(value);
+                return self.next_operand(node, values, env);
+            },
+            Frame::If { node, env } => match &*node {
+                Node::If(_, consequent, alternative) => {
+                    let branch = if value == LispOutput::Bool(true) { consequent } else { alternative };
+                    Control::Eval(branch.clone(), env)
+                },
+                _ => unreachable!(),
+            },
+            Frame::Define { node, env } => match &*node {
+                Node::Define(var, _) => {
+                    env.borrow_mut().set(var, &value);
+                    Control::Return(value)
+                },
+                _ => unreachable!(),
+            },
+            Frame::SetBang { node, env } => match &*node {
+                Node::SetBang(var, reference, _) => Control::Return(env.borrow_mut().assign(var, *reference, value)?),
+                _ => unreachable!(),
+            },
+            Frame::And { node, index, env } => match &*node {
+                Node::And(clauses) => {
+                    if value == LispOutput::Bool(false) {
+                        return return_value(value);
+                    }
+                    match clauses.get(index + 1) {
+                        None => Control::Return(LispOutput::Bool(true)),
+                        Some(next) => {
+                            let next = next.clone();
+                            self.stack.push(Frame::And { node, index: index + 1, env: env.clone() });
+                            Control::Eval(next, env)
+                        },
+                    }
+                },
+                _ => unreachable!(),
+            },
+            Frame::Or { node, index, env } => match &*node {
+                Node::Or(clauses) => {
+                    if value == LispOutput::Bool(true) {
+                        return return_value(value);
+                    }
+                    match clauses.get(index + 1) {
+                        None => Control::Return(LispOutput::Bool(false)),
+                        Some(next) => {
+                            let next = next.clone();
+                            self.stack.push(Frame::Or { node, index: index + 1, env: env.clone() });
+                            Control::Eval(next, env)
+                        },
+                    }
+                },
+                _ => unreachable!(),
+            },
+            Frame::LetStar { node, index, env } => match &*node {
+                // every binding gets its own frame, so later initializers see
+                // (and may shadow) the earlier ones
+                Node::LetStar { bindings, body, slots } => {
+                    let new_env = child_environment(&slots[index], [value], &env);
+                    match bindings.get(index + 1) {
+                        None => Control::Eval(body.clone(), new_env),
+                        Some((_, init)) => {
+                            let init = init.clone();
+                            self.stack.push(Frame::LetStar { node, index: index + 1, env: new_env.clone() });
+                            Control::Eval(init, new_env)
+                        },
+                    }
+                },
+                _ => unreachable!(),
+            },
+            Frame::Letrec { node, mut values, env } => match &*node {
+                Node::Letrec { bindings, body, sequential, .. } => {
+                    if *sequential {
+                        env.borrow_mut().set_slot(values.len(), value.clone());
+                    }
+                    values.push(value);
+
+                    match bindings.get(values.len()) {
+                        Some((_, init)) => {
+                            let init = init.clone();
+                            self.stack.push(Frame::Letrec { node, values, env: env.clone() });
+                            Control::Eval(init, env)
+                        },
+                        None => {
+                            for (index, value) in values.into_iter().enumerate() {
+                                env.borrow_mut().set_slot(index, value);
+                            }
+                            Control::Eval(body.clone(), env)
+                        },
+                    }
+                },
+                _ => unreachable!(),
+            },
+            Frame::WindBefore { before, thunk, after } => {
+                self.stack.push(Frame::Wind { id: next_id(), before, after });
+                Control::Apply(thunk, Vec::new(), None, None)
+            },
+            Frame::Wind { after, .. } => {
+                self.stack.push(Frame::WindAfter { value });
+                Control::Apply(after, Vec::new(), None, None)
+            },
+            Frame::WindAfter { value } => Control::Return(value),
+            Frame::EscapeExtent { valid } => {
+                valid.set(false);
+                Control::Return(value)
+            },
+            Frame::Code(frame) => frame.resume(value),
+            Frame::Call { .. } => {
+                self.leave_calls(1);
+                Control::Return(value)
+            },
+            Frame::Handler { .. } | Frame::Guard { .. } | Frame::CodeGuard { .. } => Control::Return(value),
+            Frame::Handling { continuable: true, .. } => Control::Return(value),
+            Frame::Handling { handler_index, condition, continuable: false } => {
+                // the secondary error is raised where the handler ran, so
+                // that the handler does not see it again
+                let message = format!("exception handler returned from non-continuable raise of {condition}");
+                self.stack.push(Frame::Handling { handler_index, condition, continuable: false });
+                return Err(LispError::new(LispErrorKind::Raise, message));
+            },
+            Frame::GuardClause { node, index, env, condition } => match &*node {
+                Node::Guard { clauses, .. } => {
+                    if value == LispOutput::Bool(true) {
+                        Control::Eval(clauses[index].1.clone(), env)
+                    } else {
+                        return self.next_guard_clause(node, index + 1, env, condition);
+                    }
+                },
+                _ => unreachable!(),
+            },
+        };
+        return Ok(Step::Continue(next));
+    }
+
+    fn apply(
+        &mut self,
+        function: LispFunction,
+        mut args: Vec<LispOutput>,
+        env: Option<Env>,
+        span: Option<Span>,
+    ) -> LispResult<Step> {
+        if let Some(env) = &env {
+            debugger::before_call(&function, &args, env, span, self.compiled, || self.backtrace())?;
+        }
+        let intrinsic = match function {
+            LispFunction::Function(function) => {
+                self.enter_call(Frame::Call { lambda: function.lambda().clone(), span, arguments: args.clone() })?;
+                let new_env = function.bind_arguments(args)?;
+                return Ok(Step::Continue(Control::Eval(function.body(), new_env)));
+            },
+            LispFunction::Closure(closure) => {
+                self.enter_call(Frame::Call { lambda: closure.lambda().clone(), span, arguments: args.clone() })?;
+                return Ok(Step::Continue(Control::Run(CodeFrame::call(&closure, args)?)));
+            },
+            LispFunction::BuiltInFunction(built_in) => return return_value(built_in.call(args)?),
+            LispFunction::Continuation(continuation) => {
+                LispFunction::Continuation(continuation.clone()).arity().check(args.len())?;
+                let value = args.pop().unwrap_or(LispOutput::Void);
+                return self.throw(continuation, value);
+            },
+            LispFunction::Intrinsic(intrinsic) => intrinsic,
+        };
+
+        intrinsic.arity().check(args.len())?;
+        let caller_env = || match &env {
+            Some(env) => Ok(Environment::toplevel(env)),
+            None => Err(LispError::type_mismatch(format!("{} needs an explicit environment here", intrinsic.name()))),
+        };
+        let expect_function = |arg: LispOutput, position: &str| match arg {
+            LispOutput::Lambda(function) => Ok(function),
+            _ => Err(LispError::type_mismatch(format!("expecting {position} argument to {} to be lisp function!", intrinsic.name()))),
+        };
+
+        let next = match intrinsic {
+            Intrinsic::Apply => {
+                // (apply f a b '(c d)) calls (f a b c d)
+                let spread = match args.pop() {
+                    Some(LispOutput::List(list)) => list.to_vec(),
+                    _ => return Err(LispError::type_mismatch("expecting last argument to apply to be lisp list!")),
+                };
+                let mut rest = args.into_iter();
+                let function = expect_function(rest.next().unwrap(), "first")?;
+                Control::Apply(function, rest.chain(spread).collect(), env, span)
+            },
+            Intrinsic::Eval => {
+                let target_env = match args.get(1) {
+                    Some(LispOutput::Environment(target_env)) => target_env.0.clone(),
+                    Some(_) => return Err(LispError::type_mismatch("expecting second argument to eval to be an environment!")),
+                    None => caller_env()?,
+                };
+                let node = analyze(&args[0].to_expression()?)?;
+                match self.compiled {
+                    true => Control::Run(CodeFrame::new(compile(&node), target_env)),
+                    false => Control::Eval(resolve(&node), target_env),
+                }
+            },
+            Intrinsic::Load => {
+                let target_env = match args.get(1) {
+                    Some(LispOutput::Environment(target_env)) => target_env.0.clone(),
+                    Some(_) => return Err(LispError::type_mismatch("expecting second argument to load to be an environment!")),
+                    None => caller_env()?,
+                };
+                let path = match &args[0] {
+                    LispOutput::String(path) => path,
+                    _ => return Err(LispError::type_mismatch("expecting first argument to load to be a file name!")),
+                };
+                port::check_file_access(Path::new(path), FileAccess::ReadOnly)?;
+                Control::Return(library::load(Path::new(path), &target_env, self.compiled)?)
+            },
+            Intrinsic::InteractionEnvironment => {
+                Control::Return(LispOutput::Environment(EnvironmentRef(caller_env()?)))
+            },
+            Intrinsic::CallWithCurrentContinuation => {
+                let receiver = expect_function(args.remove(0), "first")?;
+                let continuation = self.capture(None);
+                Control::Apply(receiver, vec![continuation], env, None)
+            },
+            Intrinsic::CallWithEscapeContinuation => {
+                let receiver = expect_function(args.remove(0), "first")?;
+                let valid = Rc::new(Cell::new(true));
+                self.stack.push(Frame::EscapeExtent { valid: valid.clone() });
+                let continuation = self.capture(Some(valid));
+                Control::Apply(receiver, vec![continuation], env, None)
+            },
+            Intrinsic::DynamicWind => {
+                let mut args = args.into_iter();
+                let before = expect_function(args.next().unwrap(), "first")?;
+                let thunk = expect_function(args.next().unwrap(), "second")?;
+                let after = expect_function(args.next().unwrap(), "third")?;
+                self.stack.push(Frame::WindBefore { before: before.clone(), thunk, after });
+                Control::Apply(before, Vec::new(), None, None)
+            },
+            Intrinsic::Raise => return Err(LispError::raise(args.remove(0))),
+            Intrinsic::RaiseContinuable => {
+                let mut err = LispError::raise(args.remove(0));
+                err.backtrace = self.backtrace();
+                return self.signal(err.condition(), true, err);
+            },
+            Intrinsic::WithExceptionHandler => {
+                let mut args = args.into_iter();
+                let handler = expect_function(args.next().unwrap(), "first")?;
+                let thunk = expect_function(args.next().unwrap(), "second")?;
+                self.stack.push(Frame::Handler { handler });
+                Control::Apply(thunk, Vec::new(), None, None)
+            },
+            // the pause, if any, has already happened
+            Intrinsic::Break => Control::Return(LispOutput::Void),
+        };
+        return Ok(Step::Continue(next));
+    }
+
+    /// Pushes the marker of a call, in place of the marker of its caller when
+    /// the call is in tail position.
+    fn enter_call(&mut self, frame: Frame) -> LispResult<()> {
+        match self.stack.last() {
+            Some(Frame::Call { .. }) => {
+                self.stack.pop();
+            },
+            _ => {
+                limits::enter_call()?;
+                self.calls += 1;
+            },
+        }
+        self.stack.push(frame);
+        return Ok(());
+    }
+
+    fn leave_calls(&mut self, calls: usize) {
+        self.calls -= calls;
+        limits::leave_calls(calls);
+    }
+
+    fn capture(&self, escape_valid: Option<Rc<Cell<bool>>>) -> LispOutput {
+        return LispOutput::Lambda(LispFunction::Continuation(Continuation {
+            machine_id: self.id,
+            outermost: ACTIVE_MACHINES.with(|active| active.borrow().first() == Some(&self.id)),
+            frames: Rc::new(self.stack.clone()),
+            escape_valid,
+        }));
+    }
+
+    /// Passes `value` to `continuation`, either here or, when the continuation
+    /// belongs to a machine further out, by unwinding towards that machine.
+    fn throw(&mut self, continuation: Continuation, value: LispOutput) -> LispResult<Step> {
+        if let Some(valid) = &continuation.escape_valid {
+            if !valid.get() {
+                return Err(LispError::new(
+                    LispErrorKind::Continuation,
+                    "escape continuation invoked outside of its extent",
+                ));
+            }
+        }
+
+        // continuations of outermost machines that have already finished are
+        // resumed by the outermost machine still running
+        let target = ACTIVE_MACHINES.with(|active| {
+            let active = active.borrow();
+            match active.contains(&continuation.machine_id) {
+                true => Some(continuation.machine_id),
+                false => continuation.outermost.then_some(active[0]),
+            }
+        });
+        let Some(target) = target else {
+            return Err(LispError::new(
+                LispErrorKind::Continuation,
+                "continuation invoked after the built-in call it was captured in returned",
+            ));
+        };
+
+        if target == self.id {
+            return self.reinstate(continuation, value);
+        }
+        return Err(LispError::jump(Jump { target, continuation, value }));
+    }
+
+    /// Replaces the stack with the one captured by `continuation`, leaving and
+    /// entering `dynamic-wind` extents along the way.
+    fn reinstate(&mut self, continuation: Continuation, value: LispOutput) -> LispResult<Step> {
+        let target_frames = continuation.frames;
+        let target_winds: Vec<(u64, &LispFunction)> = target_frames.iter()
+            .filter_map(|frame| match frame {
+                Frame::Wind { id, before, .. } => Some((*id, before)),
+                _ => None,
+            })
+            .collect();
+        let current_winds: Vec<(u64, LispFunction)> = self.stack.iter()
+            .filter_map(|frame| match frame {
+                Frame::Wind { id, after, .. } => Some((*id, after.clone())),
+                _ => None,
+            })
+            .collect();
+        let common = target_winds.iter().zip(&current_winds)
+            .take_while(|((target_id, _), (current_id, _))| target_id == current_id)
+            .count();
+
+        for frame in &self.stack {
+            if let Frame::EscapeExtent { valid } = frame {
+                let kept = target_frames.iter().any(|target| match target {
+                    Frame::EscapeExtent { valid: target_valid } => Rc::ptr_eq(valid, target_valid),
+                    _ => false,
+                });
+                if !kept {
+                    valid.set(false);
+                }
+            }
+        }
+
+        for (_, after) in current_winds[common..].iter().rev() {
+            call_function(after.clone(), Vec::new())?;
+        }
+        for (_, before) in &target_winds[common..] {
+            call_function((*before).clone(), Vec::new())?;
+        }
+
+        self.stack = (*target_frames).clone();
+        let calls = self.stack.iter().filter(|frame| matches!(frame, Frame::Call { .. })).count();
+        if calls >= self.calls {
+            limits::enter_calls(calls - self.calls);
+        } else {
+            limits::leave_calls(self.calls - calls);
+        }
+        self.calls = calls;
+        return return_value(value);
+    }
+
+    /// Handles an error raised while running: a jump aimed at this machine
+    /// resumes its continuation, other jumps unwind the whole stack and
+    /// anything else is passed to the innermost exception handler.
+    fn recover(&mut self, err: LispError) -> LispResult<Step> {
+        if let Some(jump) = err.as_jump() {
+            if jump.target == self.id {
+                let jump = err.into_jump().unwrap();
+                return self.reinstate(jump.continuation, jump.value);
+            }
+            self.unwind(0)?;
+            return Err(err);
+        }
+        if err.kind.stops_evaluation() {
+            self.unwind(0)?;
+            return Err(err);
+        }
+
+        // errors raised while looking for a handler, such as those of an
+        // `after` thunk, go to the handlers further out in turn
+        return match self.signal(err.condition(), false, err) {
+            Err(err) if !self.stack.is_empty() => self.recover(err),
+            result => result,
+        };
+    }
+
+    fn backtrace(&self) -> Vec<TraceFrame> {
+        return self.stack.iter().rev()
+            .filter_map(|frame| match frame {
+                Frame::Call { lambda, span, arguments } => Some(TraceFrame {
+                    name: lambda.name.clone(),
+                    span: *span,
+                    arguments: arguments.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+    }
+
+    /// The index of the innermost handler or `guard` in effect.
+    fn find_handler(&self) -> Option<usize> {
+        let mut index = self.stack.len();
+        while index > 0 {
+            index -= 1;
+            match &self.stack[index] {
+                Frame::Handler { .. } | Frame::Guard { .. } | Frame::CodeGuard { .. } => return Some(index),
+                Frame::Handling { handler_index, .. } => index = *handler_index,
+                _ => {},
+            }
+        }
+        return None;
+    }
+
+    /// Passes `condition` to the innermost handler, or fails with `err` when
+    /// there is none. Handlers only see what is raised on this machine's
+    /// stack; errors from nested machines reach them once the nested machine
+    /// has given up, and so are never continuable.
+    fn signal(&mut self, condition: LispOutput, continuable: bool, err: LispError) -> LispResult<Step> {
+        let handler_index = match self.find_handler() {
+            Some(handler_index) => handler_index,
+            None => {
+                self.unwind(0)?;
+                return Err(err);
+            },
+        };
+
+        match self.stack[handler_index].clone() {
+            Frame::Handler { handler } => {
+                self.stack.push(Frame::Handling { handler_index, condition: condition.clone(), continuable });
+                return Ok(Step::Continue(Control::Apply(handler, vec![condition], None, None)));
+            },
+            Frame::Guard { node, env } => {
+                self.unwind(handler_index)?;
+                let guard_env = match &*node {
+                    Node::Guard { slots, .. } => child_environment(slots, [condition.clone()], &env),
+                    _ => unreachable!(),
+                };
+                return self.next_guard_clause(node, 0, guard_env, condition);
+            },
+            Frame::CodeGuard { frame } => {
+                self.unwind(handler_index)?;
+                return Ok(Step::Continue(frame.catch(condition)));
+            },
+            _ => unreachable!("not a handler frame"),
+        }
+    }
+
+    fn next_guard_clause(&mut self, node: Rc<Node>, index: usize, env: Env, condition: LispOutput) -> LispResult<Step> {
+        let clauses = match &*node {
+            Node::Guard { clauses, .. } => clauses,
+            _ => unreachable!(),
+        };
+        let next = match clauses.get(index) {
+            // nothing matched, so the condition goes on to the next handler out
+            None => return self.signal(condition.clone(), false, LispError::raise(condition)),
+            Some((None, expr)) => Control::Eval(expr.clone(), env),
+            Some((Some(test), _)) => {
+                let test = test.clone();
+                self.stack.push(Frame::GuardClause { node, index, env: env.clone(), condition });
+                Control::Eval(test, env)
+            },
+        };
+        return Ok(Step::Continue(next));
+    }
+
+    /// Pops frames down to `depth`, leaving the `dynamic-wind` and escape
+    /// extents on the way. Fails with the last error of an `after` thunk.
+    fn unwind(&mut self, depth: usize) -> LispResult<()> {
+        let mut result = Ok(());
+        while self.stack.len() > depth {
+            match self.stack.pop().unwrap() {
+                Frame::Wind { after, .. } => {
+                    if let Err(after_err) = call_function(after, Vec::new()) {
+                        result = Err(after_err);
+                    }
+                },
+                Frame::EscapeExtent { valid } => valid.set(false),
+                Frame::Call { .. } => self.leave_calls(1),
+                _ => {},
+            }
+        }
+        return result;
+    }
+}
@@ -0,0 +1,260 @@
+use std::rc::Rc;
+
+use crate::compiler::{Capture, Op, Proto, Upvalue};
+use crate::debugger;
+use crate::evaluate::LispOutput;
+use crate::gc;
+use crate::functions::{Closure, LispFunction, LispFunctionCall};
+use crate::lisp_error::{LispError, LispResult};
+
+use super::{return_value, Control, Env, Frame, Machine, Step};
+
+
+// -------------- CODE FRAMES --------------
+#[derive(Clone)]
+enum Slot {
+    Unassigned,
+    Value(LispOutput),
+    Cell(Upvalue),
+}
+
+/// A running call of compiled code. While the code runs the frame is held by
+/// the machine's `Control`; during a call it waits on the stack like any other
+/// frame, which is what lets continuations copy it.
+#[derive(Clone)]
+pub(super) struct CodeFrame {
+    proto: Rc<Proto>,
+    upvalues: Rc<[Upvalue]>,
+    env: Env,
+    pc: usize,
+    slots: Vec<Slot>,
+    stack: Vec<LispOutput>,
+}
+
+impl CodeFrame {
+    /// A frame for top-level code, or code given to `eval`.
+    pub(super) fn new(proto: Rc<Proto>, env: Env) -> Self {
+        let slots = vec![Slot::Unassigned; proto.slot_names.len()];
+        return CodeFrame {
+            proto,
+            upvalues: Rc::new([]),
+            env,
+            pc: 0,
+            slots,
+            stack: Vec::new(),
+        };
+    }
+
+    /// A frame for a call of `closure`, with the arguments in the parameter
+    /// slots. Missing arguments with defaults are left for the code to fill.
+    pub(super) fn call(closure: &Closure, args: Vec<LispOutput>) -> LispResult<Self> {
+        let proto = closure.proto().clone();
+        let values = closure.lambda().parameters.match_arguments(args)?;
+
+        let mut slots = Vec::with_capacity(proto.slot_names.len());
+        for (value, boxed) in values.into_iter().zip(&proto.boxed_parameters) {
+            slots.push(match (value, boxed) {
+                (value, true) => Slot::Cell(gc::allocate_cell(value)),
+                (Some(value), false) => Slot::Value(value),
+                (None, false) => Slot::Unassigned,
+            });
+        }
+        slots.resize(proto.slot_names.len(), Slot::Unassigned);
+
+        return Ok(CodeFrame {
+            proto,
+            upvalues: closure.upvalues().clone(),
+            env: closure.env().clone(),
+            pc: 0,
+            slots,
+            stack: Vec::new(),
+        });
+    }
+
+    /// Resumes the frame with the value of the call it was waiting on.
+    pub(super) fn resume(mut self, value: LispOutput) -> Control {
+        self.stack.push(value);
+        return Control::Run(self);
+    }
+
+    /// Resumes the frame at the handler of a `guard` that caught `condition`.
+    pub(super) fn catch(mut self, condition: LispOutput) -> Control {
+        self.stack.push(condition);
+        return Control::Run(self);
+    }
+
+    fn load(&self, slot: usize) -> LispResult {
+        let value = match &self.slots[slot] {
+            Slot::Value(value) => Some(value.clone()),
+            Slot::Cell(cell) => cell.borrow().clone(),
+            Slot::Unassigned => None,
+        };
+        return value.ok_or_else(|| LispError::unbound_variable(&self.proto.slot_names[slot]));
+    }
+
+    fn is_bound(&self, slot: usize) -> bool {
+        match &self.slots[slot] {
+            Slot::Value(_) => true,
+            Slot::Cell(cell) => cell.borrow().is_some(),
+            Slot::Unassigned => false,
+        }
+    }
+
+    fn store(&mut self, slot: usize, value: LispOutput) {
+        match &self.slots[slot] {
+            Slot::Cell(cell) => *cell.borrow_mut() = Some(value),
+            _ => self.slots[slot] = Slot::Value(value),
+        }
+    }
+
+    /// The cell of a slot, moving its value into one if it has none yet.
+    fn cell(&mut self, slot: usize) -> Upvalue {
+        let cell = match &self.slots[slot] {
+            Slot::Cell(cell) => return cell.clone(),
+            Slot::Value(value) => gc::allocate_cell(Some(value.clone())),
+            Slot::Unassigned => gc::allocate_cell(None),
+        };
+        self.slots[slot] = Slot::Cell(cell.clone());
+        return cell;
+    }
+
+    fn pop(&mut self) -> LispOutput {
+        return self.stack.pop().expect("operand stack underflow");
+    }
+}
+
+
+// -------------- VIRTUAL MACHINE --------------
+impl Machine {
+    /// Runs compiled code until it returns or calls a procedure that is not
+    /// built in.
+    pub(super) fn run_code(&mut self, mut frame: CodeFrame) -> LispResult<Step> {
+        let proto = frame.proto.clone();
+        loop {
+            let op = proto.code[frame.pc];
+            frame.pc += 1;
+            match op {
+                Op::Constant(index) => frame.stack.push(proto.constants[index].clone()),
+                Op::Pop => {
+                    frame.pop();
+                },
+                Op::LoadName(index) => {
+                    let value = frame.env.borrow().get(&proto.names[index])?;
+                    frame.stack.push(value);
+                },
+                Op::DefineName(index) => {
+                    let value = frame.stack.last().unwrap();
+                    frame.env.borrow_mut().set(&proto.names[index], value);
+                },
+                Op::SetName(index) => {
+                    let value = frame.pop();
+                    let value = frame.env.borrow_mut().set_bang(&proto.names[index], value)?;
+                    frame.stack.push(value);
+                },
+                Op::DelName(index) => {
+                    let value = frame.env.borrow_mut().del(&proto.names[index])?;
+                    frame.stack.push(value);
+                },
+                Op::LoadLocal(slot) => {
+                    let value = frame.load(slot)?;
+                    frame.stack.push(value);
+                },
+                Op::StoreLocal(slot) => {
+                    let value = frame.stack.last().unwrap().clone();
+                    frame.store(slot, value);
+                },
+                Op::BindLocal(slot) => frame.slots[slot] = Slot::Value(frame.pop()),
+                Op::BindCell(slot) => frame.slots[slot] = Slot::Cell(gc::allocate_cell(Some(frame.pop()))),
+                Op::DeclareCell(slot) => frame.slots[slot] = Slot::Cell(gc::allocate_cell(None)),
+                Op::LoadUpvalue(index) => {
+                    let value = frame.upvalues[index].borrow().clone()
+                        .ok_or_else(|| LispError::unbound_variable(&proto.upvalue_names[index]))?;
+                    frame.stack.push(value);
+                },
+                Op::StoreUpvalue(index) => {
+                    let value = frame.stack.last().unwrap().clone();
+                    *frame.upvalues[index].borrow_mut() = Some(value);
+                },
+                Op::JumpIfLocalBound(slot, target) => {
+                    if frame.is_bound(slot) {
+                        frame.pc = target;
+                    }
+                },
+                Op::JumpIfUpvalueBound(index, target) => {
+                    if frame.upvalues[index].borrow().is_some() {
+                        frame.pc = target;
+                    }
+                },
+                Op::MakeClosure(index) => {
+                    let child = proto.protos[index].clone();
+                    let upvalues = child.captures.iter()
+                        .map(|capture| match *capture {
+                            Capture::Local(slot) => frame.cell(slot),
+                            Capture::Upvalue(index) => frame.upvalues[index].clone(),
+                        })
+                        .collect();
+                    let closure = Closure::new(child, upvalues, frame.env.clone());
+                    frame.stack.push(LispOutput::Lambda(LispFunction::Closure(closure)));
+                },
+                Op::Jump(target) => frame.pc = target,
+                Op::JumpIfNotTrue(target) => {
+                    if frame.pop() != LispOutput::Bool(true) {
+                        frame.pc = target;
+                    }
+                },
+                Op::JumpIfFalse(target) => {
+                    if frame.pop() == LispOutput::Bool(false) {
+                        frame.pc = target;
+                    }
+                },
+                Op::JumpIfTrue(target) => {
+                    if frame.pop() == LispOutput::Bool(true) {
+                        frame.pc = target;
+                    }
+                },
+                Op::Call(argc, span) | Op::TailCall(argc, span) => {
+                    let args = frame.stack.split_off(frame.stack.len() - argc);
+                    let function = match frame.pop() {
+                        LispOutput::Lambda(function) => function,
+                        _ => return Err(LispError::type_mismatch("expected function for first expression of list")),
+                    };
+                    let tail = matches!(op, Op::TailCall(..));
+
+                    // built-in procedures cannot capture the frame, so they
+                    // are called right here
+                    if let LispFunction::BuiltInFunction(built_in) = &function {
+                        debugger::before_call(&function, &args, &frame.env, proto.spans[span], true, || self.backtrace())?;
+                        let value = built_in.call(args)?;
+                        if tail {
+                            return return_value(value);
+                        }
+                        frame.stack.push(value);
+                        continue;
+                    }
+
+                    let env = frame.env.clone();
+                    if !tail {
+                        self.stack.push(Frame::Code(frame));
+                    }
+                    return Ok(Step::Continue(Control::Apply(function, args, Some(env), proto.spans[span])));
+                },
+                Op::Return => return return_value(frame.pop()),
+                Op::PushGuard(handler) => {
+                    let mut guard = frame.clone();
+                    guard.pc = handler;
+                    self.stack.push(Frame::CodeGuard { frame: guard });
+                },
+                Op::PopGuard => {
+                    self.stack.pop();
+                },
+                Op::Reraise => {
+                    let condition = frame.pop();
+                    return self.signal(condition.clone(), false, LispError::raise(condition));
+                },
+                Op::Interpret(index) => {
+                    return Ok(Step::Continue(Control::Eval(proto.nodes[index].clone(), frame.env)));
+                },
+            }
+        }
+    }
+}
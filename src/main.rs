@@ -1,75 +1,225 @@
-use crate::parser::parse;
-use crate::tokenizer::tokenize;
-use crate::evaluate::{evaluate, Environment, LispOutput};
+use lisp::{dap, lint, lsp, types};
+use lisp::debugger::repl::DebugRepl;
+use lisp::testing::{self, report::{self, Format}};
+use lisp::{interrupt, Capabilities, Capability, Interpreter, LispOutput};
 
+use std::cell::RefCell;
 use std::io;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::cell::RefCell;
-
-pub mod parser;
-pub mod evaluate;
-pub mod tokenizer;
-pub mod lisp_expression;
-pub mod functions;
-pub mod built_in_functions;
 
 fn main() {
-    // let mut env = Rc::new(RefCell::new(Environment::global_env()));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--bench") {
+        lisp::benchmark::run();
+        return;
+    }
+    if args.first().is_some_and(|arg| arg == "test") {
+        run_tests(&args[1..]);
+        return;
+    }
+    if args.first().is_some_and(|arg| arg == "lint") {
+        run_lint(&args[1..]);
+        return;
+    }
+    if args.first().is_some_and(|arg| arg == "check") {
+        run_check(&args[1..]);
+        return;
+    }
+    if args.first().is_some_and(|arg| arg == "dap") {
+        // the editor talks to the server over standard input and output
+        if let Err(err) = dap::serve(io::stdin().lock(), io::stdout()) {
+            eprintln!("debug adapter failed: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().is_some_and(|arg| arg == "lsp") {
+        if let Err(err) = lsp::serve(io::stdin().lock(), io::stdout()) {
+            eprintln!("language server failed: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    // let add_one = evaluate(&parse(&tokenize("(define add_one (lambda (y) (+ y 1)))")), &mut env);
-    // let add_one_twice = evaluate(&parse(&tokenize("(add_one (add_one 2))")), &mut env);
+    let interpreter = match sandbox_directory(&args) {
+        Some(directory) => sandboxed(directory),
+        None => Interpreter::new(),
+    };
+    let debugger = Rc::new(RefCell::new(DebugRepl::console()));
+    let interpreter = interpreter.compiled(args.iter().any(|arg| arg == "--vm")).with_debugger(debugger.clone());
+    match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => run_file(interpreter, path),
+        None => repl(interpreter, &debugger),
+    }
+}
 
-    // let anonymous_lambda_result = evaluate(&parse(&tokenize("((lambda (y) (+ y 1)) 5)")), &mut env);
-    // let comparisons = evaluate(&parse(&tokenize("(equal? 5 5 5)")), &mut env);
+/// The directory given by `--sandbox=<directory>`, or the current one for a
+/// bare `--sandbox`.
+fn sandbox_directory(args: &[String]) -> Option<PathBuf> {
+    return args.iter().find_map(|arg| match arg.as_str() {
+        "--sandbox" => Some(PathBuf::from(".")),
+        _ => arg.strip_prefix("--sandbox=").map(PathBuf::from),
+    });
+}
 
-    // let if_statement = evaluate(&parse(&tokenize("(if (< 1 2 3) 1 0)")), &mut env);
+/// An interpreter for untrusted code, which can use the console and the files
+/// inside `directory` but not the process, the clock or random numbers.
+fn sandboxed(directory: PathBuf) -> Interpreter {
+    let capabilities = Capabilities::only(&[Capability::Pure, Capability::IoConsole, Capability::IoFilesystem]);
+    return Interpreter::sandboxed(capabilities).with_allowed_directory(directory);
+}
 
-    // let list_statement = evaluate(&parse(&tokenize("(list 1 2 3 4)")), &mut env);
-    // let car_statement = evaluate(&parse(&tokenize("(car (list 1 2 3 4))")), &mut env);
-    // let cdr_statement = evaluate(&parse(&tokenize("(car (cdr (list 1 2 3 4)))")), &mut env);
+/// `lisp test <directory> --format=<text|tap|junit>` runs the tests of the
+/// `*_test.lisp` files in the directory, the current one by default, exiting
+/// with an error status if any of them fail.
+fn run_tests(args: &[String]) {
+    let directory = args.iter().find(|arg| !arg.starts_with("--")).map_or(".", String::as_str);
+    let format = match args.iter().find_map(|arg| arg.strip_prefix("--format=")) {
+        None => Format::Text,
+        Some(name) => Format::from_name(name).unwrap_or_else(|| {
+            eprintln!("unknown report format {name}, expecting text, tap or junit");
+            std::process::exit(2);
+        }),
+    };
+    let reports = match testing::run_directory(Path::new(directory), args.iter().any(|arg| arg == "--vm")) {
+        Ok(reports) => reports,
+        Err(err) => {
+            eprintln!("can not read {directory}: {err}");
+            std::process::exit(2);
+        },
+    };
+    print!("{}", report::render(&reports, format));
+    if reports.iter().any(|report| report.failures() > 0) {
+        std::process::exit(1);
+    }
+}
 
-    // println!("add_one lambda {:?}", add_one);
-    // println!("Should get 2 + 1 + 1 = 4: {:?}", add_one_twice);
-    // println!("Expecting 6: {:?}", anonymous_lambda_result);
-    // println!("Expecting False: {:?}", comparisons);
-    // println!("Expecting 1: {:?}", if_statement);
+/// `lisp lint <path> ...` reports likely mistakes in the files given and in
+/// the `.lisp` files of the directories given, the current one by default,
+/// exiting with an error status if there are any.
+fn run_lint(args: &[String]) {
+    let linter = lint::Linter::new();
+    let mut warnings = 0;
+    for (file, source) in read_sources(args) {
+        for warning in linter.lint(&source) {
+            println!("{}:{warning}", file.display());
+            warnings += 1;
+        }
+    }
+    if warnings > 0 {
+        std::process::exit(1);
+    }
+}
 
-    // println!("Expecting LispList with 1, 2, 3, 4: {:?}", list_statement);
-    // println!("Expecting to car of list, should be 1: {:?}", car_statement);
-    // println!("Expecting second element of list, should be 2: {:?}", cdr_statement);
+/// `lisp check <path> ...` reports the type errors of the files and
+/// directories given as `lisp lint` takes them, exiting with an error status
+/// if there are any.
+fn run_check(args: &[String]) {
+    let mut errors = 0;
+    for (file, source) in read_sources(args) {
+        for error in types::check::check(&source) {
+            println!("{}:{error}", file.display());
+            errors += 1;
+        }
+    }
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
 
-    repl();
+/// The files named by `args`, with the `.lisp` files of the directories
+/// named, the current one if there are none, along with their contents.
+/// Exits if any can not be read.
+fn read_sources(args: &[String]) -> Vec<(PathBuf, String)> {
+    let mut paths: Vec<&str> = args.iter().filter(|arg| !arg.starts_with("--")).map(String::as_str).collect();
+    if paths.is_empty() {
+        paths.push(".");
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            files.push(PathBuf::from(path));
+            continue;
+        }
+        match lint::discover(Path::new(path)) {
+            Ok(found) => files.extend(found),
+            Err(err) => {
+                eprintln!("can not read {path}: {err}");
+                std::process::exit(2);
+            },
+        }
+    }
+    return files.into_iter()
+        .map(|file| match std::fs::read_to_string(&file) {
+            Ok(source) => (file, source),
+            Err(err) => {
+                eprintln!("can not read {}: {err}", file.display());
+                std::process::exit(2);
+            },
+        })
+        .collect();
 }
 
-fn read_string() -> String {
+/// A line of input, or `None` at the end of it.
+fn read_string() -> Option<String> {
     let mut input = String::new();
-    io::stdin()
+    let read = io::stdin()
         .read_line(&mut input)
         .expect("can not read user input");
-    input
+    return (read > 0).then_some(input);
+}
+
+/// Evaluates the file at `path`, exiting with an error status if it fails. Libraries are looked for
+/// next to the file as well as in the current directory.
+fn run_file(mut interpreter: Interpreter, path: &str) {
+    if let Some(directory) = Path::new(path).parent() {
+        interpreter.add_library_path(directory);
+    }
+    interrupt::install_handler();
+    if let Err(err) = interrupt::interruptible(|| interpreter.eval_file(path)) {
+        eprintln!("{}", err.report());
+        std::process::exit(1);
+    }
 }
 
-fn repl() {
-    let mut env = Rc::new(RefCell::new(Environment::global_env()));
+/// Reads and evaluates expressions until `exit` or the end of the input. Ctrl-C stops the expression
+/// being evaluated and keeps everything defined so far. `(break)` and the breakpoints set with
+/// `:break <function>` pause evaluation in a nested debug REPL.
+fn repl(mut interpreter: Interpreter, debugger: &RefCell<DebugRepl>) {
+    interrupt::install_handler();
     loop {
         print!(">>> ");
         let _ = io::stdout().flush();
-        let input = read_string();
+        let Some(input) = read_string() else { break };
 
-        if &input == "exit" {
+        if input.trim() == "exit" {
             break;
         }
+        if input.trim().is_empty() {
+            continue;
+        }
+        if let Some(message) = debugger.borrow_mut().breakpoint_command(input.trim()) {
+            println!("{message}");
+            continue;
+        }
+        debugger.borrow_mut().set_source(&input);
 
-        let output = evaluate(&parse(&tokenize(&input)), &mut env);
-
+        let output = interrupt::interruptible(|| interpreter.eval_str(&input));
 
         match output {
-            LispOutput::Integer(num) => println!("{:?}", num),
-            LispOutput::Bool(bool_val) => println!("{:?}", bool_val),
-            LispOutput::Lambda(func) => println!("{:?}", func),
-            LispOutput::List(list) => println!("{:?}", *list),
-            LispOutput::Void => println!("void"),
+            Err(err) => println!("{}", err.report()),
+            Ok(LispOutput::Integer(num)) => println!("{:?}", num),
+            Ok(LispOutput::Bool(bool_val)) => println!("{:?}", bool_val),
+            Ok(LispOutput::String(string)) => println!("{:?}", string),
+            Ok(LispOutput::Lambda(func)) => println!("{:?}", func),
+            Ok(LispOutput::List(list)) => println!("{:?}", *list),
+            Ok(LispOutput::Void) => println!("void"),
+            Ok(LispOutput::Keyword(keyword)) => println!("#:{}", keyword),
+            Ok(LispOutput::Symbol(symbol)) => println!("{}", symbol),
+            Ok(LispOutput::Environment(env)) => println!("{:?}", env),
+            Ok(output @ (LispOutput::ErrorObject(_) | LispOutput::Port(_) | LispOutput::Eof)) => println!("{}", output),
         };
     }
-}
\ No newline at end of file
+}
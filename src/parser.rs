@@ -1,43 +1,117 @@
-use crate::lisp_expression::LispExpression;
+use crate::lisp_error::{LispError, LispResult};
+use crate::lisp_expression::{LispExpression, Span, SpanTree};
 use crate::tokenizer::LispToken;
 
-pub fn parse(tokens: &Vec<LispToken>) -> LispExpression {
-    fn parse_expression(mut index: usize, tokens: &Vec<LispToken>) -> (usize, LispExpression) {
-        let token = &tokens[index];
-
-        match token {
-            LispToken::Integer(num) => (index + 1, LispExpression::Integer(*num)),
-            LispToken::Symbol(sym) => (index + 1, LispExpression::Symbol(sym.clone())),
-            LispToken::RightParen => panic!("unmatched right parenthesis while trying to parse expression at index: {index}"),
-            LispToken::LeftParen => {
-                let mut expressions = Vec::new();
-                index += 1;
-
-                while index < tokens.len() && tokens[index] != LispToken::RightParen {
-                    let (next_index, expression) = parse_expression(index, tokens);
-                    index = next_index;
-                    expressions.push(expression);
-                }
-
-                if index >= tokens.len() || tokens[index] != LispToken::RightParen {
-                    panic!("missing right parenthesis while trying to parse expression");
-                }
-
-                return (index + 1, LispExpression::List(expressions));
-            }
+pub fn parse(tokens: &[LispToken]) -> LispExpression {
+    return parse_with_spans(tokens, &[]).0;
+}
+
+/// Parses `tokens` along with the spans of every sub-expression. `spans` has
+/// one entry per token; when it is empty every span is left at its default.
+pub fn parse_with_spans(tokens: &[LispToken], spans: &[Span]) -> (LispExpression, SpanTree) {
+    if tokens.is_empty() {
+        panic!("nothing to parse!");
+    }
+    let parsed = parse_expression(0, tokens, spans).and_then(|(final_index, final_expression, final_spans)| {
+        if final_index != tokens.len() {
+            return Err(LispError::syntax("did not parse expression completely"));
         }
+        return Ok((final_expression, final_spans));
+    });
+
+    match parsed {
+        Ok(parsed) => return parsed,
+        Err(err) => panic!("{}", err.message),
     }
+}
 
-    if tokens.len() == 0 {
-        panic!("nothing to parse!");
+/// Parses every expression in `tokens`, in order, with their spans. Unlike
+/// `parse`, malformed input is reported as a syntax error rather than a panic,
+/// and empty input is no expressions at all.
+pub fn parse_all_with_spans(tokens: &[LispToken], spans: &[Span]) -> LispResult<Vec<(LispExpression, SpanTree)>> {
+    let mut expressions = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        let (next_index, expression, expression_spans) = parse_expression(index, tokens, spans)?;
+        index = next_index;
+        expressions.push((expression, expression_spans));
     }
-    let (final_index, final_expression) = parse_expression(0, tokens);
+    return Ok(expressions);
+}
+
+fn span_at(spans: &[Span], index: usize) -> Span {
+    return spans.get(index).copied().unwrap_or_default();
+}
+
+fn parse_expression(mut index: usize, tokens: &[LispToken], spans: &[Span]) -> LispResult<(usize, LispExpression, SpanTree)> {
+    let token = &tokens[index];
+    let leaf = SpanTree { span: span_at(spans, index), children: Vec::new() };
+
+    match token {
+        LispToken::Integer(num) => Ok((index + 1, LispExpression::Integer(*num), leaf)),
+        LispToken::Symbol(sym) => Ok((index + 1, LispExpression::Symbol(sym.clone()), leaf)),
+        LispToken::String(string) => Ok((index + 1, LispExpression::String(string.clone()), leaf)),
+        LispToken::RightParen | LispToken::RightBracket => Err(LispError::syntax(
+            format!("unmatched {} while trying to parse expression at index: {index}", describe(token))
+        ).with_span(spans.get(index).copied())),
+        LispToken::Quote => {
+            if index + 1 >= tokens.len() {
+                return Err(LispError::syntax("missing expression after quote").with_span(spans.get(index).copied()));
+            }
+            // 'x is shorthand for (quote x)
+            let (next_index, quoted, quoted_spans) = parse_expression(index + 1, tokens, spans)?;
+            let span = Span {
+                end_line: quoted_spans.span.end_line,
+                end_column: quoted_spans.span.end_column,
+                ..leaf.span
+            };
+            Ok((next_index, LispExpression::List(vec![
+                LispExpression::Symbol("quote".to_string()),
+                quoted,
+            ]), SpanTree { span, children: vec![leaf, quoted_spans] }))
+        },
+        LispToken::LeftParen | LispToken::LeftBracket => {
+            let open = index;
+            let close = match token {
+                LispToken::LeftParen => LispToken::RightParen,
+                _ => LispToken::RightBracket,
+            };
+            let mut expressions = Vec::new();
+            let mut children = Vec::new();
+            index += 1;
 
-    if final_index != tokens.len() {
-        panic!("did not parse expression completely");
+            while index < tokens.len() && !matches!(tokens[index], LispToken::RightParen | LispToken::RightBracket) {
+                let (next_index, expression, expression_spans) = parse_expression(index, tokens, spans)?;
+                index = next_index;
+                expressions.push(expression);
+                children.push(expression_spans);
+            }
+
+            if index >= tokens.len() {
+                // reported at the parenthesis left open
+                return Err(LispError::syntax(format!("missing {} while trying to parse expression", describe(&close)))
+                    .with_span(spans.get(open).copied()));
+            }
+            if tokens[index] != close {
+                return Err(LispError::syntax(format!("{} does not match the {} it closes", describe(&tokens[index]), describe(token)))
+                    .with_span(spans.get(index).copied()));
+            }
+
+            let close = span_at(spans, index);
+            let span = Span { end_line: close.end_line, end_column: close.end_column, ..leaf.span };
+            return Ok((index + 1, LispExpression::List(expressions), SpanTree { span, children }));
+        }
     }
+}
 
-    return final_expression;
+fn describe(token: &LispToken) -> &'static str {
+    return match token {
+        LispToken::LeftParen => "left parenthesis",
+        LispToken::RightParen => "right parenthesis",
+        LispToken::LeftBracket => "left bracket",
+        LispToken::RightBracket => "right bracket",
+        _ => "token",
+    };
 }
 
 
@@ -46,7 +120,7 @@ pub fn parse(tokens: &Vec<LispToken>) -> LispExpression {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tokenizer::tokenize;
+    use crate::tokenizer::{tokenize, tokenize_with_spans};
 
     #[test]
     #[should_panic]
@@ -119,10 +193,99 @@ mod tests {
         parse(&tokens);
     }
 
+    #[test]
+    fn quote_shorthand() {
+        let quoted = parse(&tokenize("'(1 x)"));
+
+        let expected = LispExpression::List(vec![
+            LispExpression::Symbol("quote".to_string()),
+            LispExpression::List(vec![
+                LispExpression::Integer(1),
+                LispExpression::Symbol("x".to_string()),
+            ]),
+        ]);
+
+        assert_eq!(expected, quoted);
+    }
+
+    #[test]
+    #[should_panic]
+    fn quote_without_expression() {
+        parse(&tokenize("'"));
+    }
+
     #[test]
     #[should_panic]
     fn list_expression_without_parenthesis() {
         let tokens = tokenize("+ 2 3");
         parse(&tokens);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn expression_spans() {
+        let (tokens, spans) = tokenize_with_spans("(f 1\n   '(g))");
+        let (_, span_tree) = parse_with_spans(&tokens, &spans);
+
+        assert_eq!(Span { line: 1, column: 1, end_line: 2, end_column: 9 }, span_tree.span);
+        assert_eq!(3, span_tree.children.len());
+        assert_eq!(Span { line: 1, column: 4, end_line: 1, end_column: 5 }, span_tree.children[1].span);
+
+        let quoted = &span_tree.children[2];
+        assert_eq!(Span { line: 2, column: 4, end_line: 2, end_column: 8 }, quoted.span);
+        assert_eq!(Span { line: 2, column: 5, end_line: 2, end_column: 8 }, quoted.children[1].span);
+    }
+
+    #[test]
+    fn parse_all_expressions() {
+        let (tokens, spans) = tokenize_with_spans("(define x 2)\nx");
+        let expressions = parse_all_with_spans(&tokens, &spans).unwrap();
+        let parsed: Vec<LispExpression> = expressions.into_iter().map(|(expression, _)| expression).collect();
+        assert_eq!(vec![
+            LispExpression::List(vec![
+                LispExpression::Symbol("define".to_string()),
+                LispExpression::Symbol("x".to_string()),
+                LispExpression::Integer(2),
+            ]),
+            LispExpression::Symbol("x".to_string()),
+        ], parsed);
+        assert_eq!(Ok(Vec::new()), parse_all_with_spans(&[], &[]));
+    }
+
+    #[test]
+    fn parse_all_reports_malformed_input() {
+        for source in ["(define x", ")", "'"] {
+            let err = parse_all_with_spans(&tokenize(source), &[]).unwrap_err();
+            assert_eq!(crate::lisp_error::LispErrorKind::Syntax, err.kind);
+            assert_eq!(None, err.span());
+        }
+    }
+
+    #[test]
+    fn syntax_errors_have_spans() {
+        let span_of = |source: &str| {
+            let (tokens, spans) = tokenize_with_spans(source);
+            return parse_all_with_spans(&tokens, &spans).unwrap_err().span();
+        };
+        assert_eq!(Some(Span { line: 2, column: 3, end_line: 2, end_column: 4 }), span_of("(f)\n  (g (h)"));
+        assert_eq!(Some(Span { line: 1, column: 4, end_line: 1, end_column: 5 }), span_of("(f))"));
+        assert_eq!(Some(Span { line: 1, column: 3, end_line: 1, end_column: 4 }), span_of("x '"));
+        assert_eq!(Some(Span { line: 1, column: 6, end_line: 1, end_column: 7 }), span_of("(f [x)]"));
+    }
+
+    #[test]
+    fn brackets() {
+        let (tokens, spans) = tokenize_with_spans("(f [x : Int])");
+        let (parsed, _) = parse_with_spans(&tokens, &spans);
+        let annotated = LispExpression::List(vec![
+            LispExpression::Symbol("x".to_string()),
+            LispExpression::Symbol(":".to_string()),
+            LispExpression::Symbol("Int".to_string()),
+        ]);
+        assert_eq!(LispExpression::List(vec![LispExpression::Symbol("f".to_string()), annotated]), parsed);
+
+        let message = |source: &str| parse_all_with_spans(&tokenize(source), &[]).unwrap_err().message;
+        assert_eq!("right bracket does not match the left parenthesis it closes", message("(f]"));
+        assert_eq!("missing right bracket while trying to parse expression", message("[f"));
+        assert_eq!("unmatched right bracket while trying to parse expression at index: 0", message("]"));
+    }
+}
@@ -0,0 +1,608 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::evaluate::LispOutput;
+use crate::lisp_error::{LispError, LispErrorKind, LispResult};
+use crate::parser::parse_all_with_spans;
+use crate::tokenizer::try_tokenize_with_spans;
+
+
+/// What evaluations may do with files, from opening none of them to reading
+/// and writing any. Each setting allows everything the ones before it do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileAccess {
+    Denied,
+    ReadOnly,
+    #[default]
+    ReadWrite,
+}
+
+
+/// A port used as a first-class value. Two references are equal when they
+/// point at the same port.
+#[derive(Clone)]
+pub struct PortRef(pub Rc<Port>);
+
+impl fmt::Debug for PortRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl PartialEq for PortRef {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl PortRef {
+    fn new(name: impl Into<String>, kind: Kind) -> Self {
+        let input = matches!(kind, Kind::Input(_));
+        return PortRef(Rc::new(Port { name: name.into(), input, kind: RefCell::new(kind), closed: Cell::new(false) }));
+    }
+
+    /// An input port reading `text`.
+    pub fn input_string(text: &str) -> Self {
+        return Self::new("string", Kind::Input(Input::new(text, None)));
+    }
+
+    /// An output port collecting what is written to it, see `Port::written`.
+    pub fn output_string() -> Self {
+        return Self::new("string", Kind::Output(Output::String(String::new())));
+    }
+
+    /// An input port reading the file at `path`, if file access allows it.
+    pub fn open_input_file(path: &Path) -> LispResult<Self> {
+        check_file_access(path, FileAccess::ReadOnly)?;
+        let file = File::open(path).map_err(|err| open_error(path, err))?;
+        let source = Source::Reader(Box::new(BufReader::new(file)));
+        return Ok(Self::new(path.display().to_string(), Kind::Input(Input::new("", Some(source)))));
+    }
+
+    /// An output port writing to the file at `path`, which is created or
+    /// truncated, if file access allows it.
+    pub fn open_output_file(path: &Path) -> LispResult<Self> {
+        check_file_access(path, FileAccess::ReadWrite)?;
+        // a new file is created without following a link put there since the check
+        let file = match path.symlink_metadata() {
+            Ok(_) => File::create(path),
+            Err(_) => OpenOptions::new().write(true).create_new(true).open(path),
+        };
+        let file = file.map_err(|err| open_error(path, err))?;
+        return Ok(Self::new(path.display().to_string(), Kind::Output(Output::File(BufWriter::new(file)))));
+    }
+}
+
+
+/// A source of characters to read or a sink for characters written.
+pub struct Port {
+    name: String,
+    // kept apart from `kind` so that it can be printed while that is borrowed
+    input: bool,
+    kind: RefCell<Kind>,
+    closed: Cell<bool>,
+}
+
+enum Kind {
+    Input(Input),
+    Output(Output),
+}
+
+/// The characters read ahead from where an input port reads.
+struct Input {
+    text: Vec<char>,
+    position: usize,
+    // `None` once there is nothing more than `text`
+    source: Option<Source>,
+}
+
+enum Source {
+    Stdin,
+    Reader(Box<dyn BufRead>),
+}
+
+enum Output {
+    Stdout,
+    String(String),
+    File(BufWriter<File>),
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.input { "input" } else { "output" };
+        write!(f, "#[{direction} port {}]", self.name)
+    }
+}
+
+impl Port {
+    pub fn is_input(&self) -> bool {
+        return self.input;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        return self.closed.get();
+    }
+
+    /// Closes the port, writing out anything still buffered. Closing a port
+    /// again does nothing.
+    pub fn close(&self) -> LispResult<()> {
+        if self.closed.replace(true) {
+            return Ok(());
+        }
+        if let Kind::Output(Output::File(file)) = &mut *self.kind.borrow_mut() {
+            file.flush().map_err(|err| self.write_error(err))?;
+        }
+        return Ok(());
+    }
+
+    /// The text written to a string port so far, or `None` for other ports.
+    pub fn written(&self) -> Option<String> {
+        match &*self.kind.borrow() {
+            Kind::Output(Output::String(text)) => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn write_str(&self, text: &str) -> LispResult<()> {
+        let mut kind = self.open_kind()?;
+        let written = match &mut *kind {
+            Kind::Output(Output::Stdout) => {
+                let mut stdout = io::stdout();
+                stdout.write_all(text.as_bytes()).and_then(|_| stdout.flush())
+            },
+            Kind::Output(Output::String(string)) => {
+                string.push_str(text);
+                Ok(())
+            },
+            Kind::Output(Output::File(file)) => file.write_all(text.as_bytes()),
+            Kind::Input(_) => return Err(LispError::type_mismatch(format!("expecting an output port, got {self}"))),
+        };
+        return written.map_err(|err| self.write_error(err));
+    }
+
+    /// The next line without its line ending, or `None` at the end of the
+    /// input.
+    pub fn read_line(&self) -> LispResult<Option<String>> {
+        return self.with_input(|input| {
+            let mut length = 0;
+            loop {
+                match input.peek(length)? {
+                    Some('\n') => break,
+                    Some(_) => length += 1,
+                    None if length == 0 => return Ok(None),
+                    None => return Ok(Some(input.take(length))),
+                }
+            }
+            let line = input.take(length);
+            input.position += 1;
+            return Ok(Some(line.strip_suffix('\r').map(str::to_string).unwrap_or(line)));
+        });
+    }
+
+    /// The next character, or `None` at the end of the input.
+    pub fn read_char(&self) -> LispResult<Option<char>> {
+        return self.with_input(|input| {
+            let next = input.peek(0)?;
+            if next.is_some() {
+                input.position += 1;
+            }
+            return Ok(next);
+        });
+    }
+
+    /// The character `read_char` would return, without reading it.
+    pub fn peek_char(&self) -> LispResult<Option<char>> {
+        return self.with_input(|input| input.peek(0));
+    }
+
+    /// Parses the next datum, reading as many lines as it takes, or returns
+    /// `None` at the end of the input.
+    pub fn read(&self) -> LispResult<Option<LispOutput>> {
+        return self.with_input(|input| {
+            let Some(start) = skip_atmosphere(input, 0)? else { return Ok(None) };
+            let end = datum_end(input, start)?;
+            input.position += start;
+            let text = input.take(end - start);
+            let (tokens, spans) = try_tokenize_with_spans(&text)?;
+            let expressions = parse_all_with_spans(&tokens, &spans)?;
+            return Ok(expressions.first().map(|(datum, _)| LispOutput::from_datum(datum)));
+        });
+    }
+
+    fn open_kind(&self) -> LispResult<std::cell::RefMut<'_, Kind>> {
+        if self.closed.get() {
+            return Err(LispError::new(LispErrorKind::Io, format!("{self} is closed")));
+        }
+        return Ok(self.kind.borrow_mut());
+    }
+
+    fn with_input<T>(&self, read: impl FnOnce(&mut Input) -> LispResult<T>) -> LispResult<T> {
+        match &mut *self.open_kind()? {
+            Kind::Input(input) => read(input),
+            Kind::Output(_) => Err(LispError::type_mismatch(format!("expecting an input port, got {self}"))),
+        }
+    }
+
+    fn write_error(&self, err: io::Error) -> LispError {
+        return LispError::new(LispErrorKind::Io, format!("can not write to {}: {err}", self.name));
+    }
+}
+
+impl Input {
+    fn new(text: &str, source: Option<Source>) -> Self {
+        return Input { text: text.chars().collect(), position: 0, source };
+    }
+
+    /// Reads another line from the source, returning whether there was one.
+    fn fill(&mut self) -> LispResult<bool> {
+        let mut line = String::new();
+        let read = match &mut self.source {
+            None => return Ok(false),
+            Some(Source::Stdin) => io::stdin().read_line(&mut line),
+            Some(Source::Reader(reader)) => reader.read_line(&mut line),
+        };
+        let read = read.map_err(|err| LispError::new(LispErrorKind::Io, format!("can not read input: {err}")))?;
+        if read == 0 {
+            self.source = None;
+            return Ok(false);
+        }
+        // what was read before is not needed again
+        self.text.drain(..self.position);
+        self.position = 0;
+        self.text.extend(line.chars());
+        return Ok(true);
+    }
+
+    /// The character `offset` characters after the next one to be read.
+    fn peek(&mut self, offset: usize) -> LispResult<Option<char>> {
+        while self.position + offset >= self.text.len() {
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+        return Ok(Some(self.text[self.position + offset]));
+    }
+
+    fn take(&mut self, count: usize) -> String {
+        let taken = self.text[self.position..self.position + count].iter().collect();
+        self.position += count;
+        return taken;
+    }
+}
+
+// -------------- READER --------------
+// `read` finds where the next datum ends before handing it to the tokenizer,
+// so that it reads no further than it has to and never gives the tokenizer an
+// unterminated string.
+
+/// The offset of the first character from `offset` on that is neither
+/// whitespace nor part of a comment, or `None` at the end of the input.
+fn skip_atmosphere(input: &mut Input, mut offset: usize) -> LispResult<Option<usize>> {
+    loop {
+        match input.peek(offset)? {
+            None => return Ok(None),
+            Some(';') => {
+                while !matches!(input.peek(offset)?, None | Some('\n')) {
+                    offset += 1;
+                }
+            },
+            Some(next) if next.is_whitespace() => offset += 1,
+            Some(_) => return Ok(Some(offset)),
+        }
+    }
+}
+
+/// The offset just past the end of the datum starting at `start`.
+fn datum_end(input: &mut Input, start: usize) -> LispResult<usize> {
+    match input.peek(start)? {
+        Some('\'') => match skip_atmosphere(input, start + 1)? {
+            Some(quoted) => datum_end(input, quoted),
+            None => Err(unexpected_end()),
+        },
+        Some('(' | '[') => {
+            let mut offset = start + 1;
+            loop {
+                match skip_atmosphere(input, offset)? {
+                    None => return Err(unexpected_end()),
+                    Some(next) if matches!(input.peek(next)?, Some(')' | ']')) => return Ok(next + 1),
+                    Some(next) => offset = datum_end(input, next)?,
+                }
+            }
+        },
+        Some(close @ (')' | ']')) => Err(LispError::syntax(format!("unexpected {close}"))),
+        Some('"') => {
+            let mut offset = start + 1;
+            loop {
+                match input.peek(offset)? {
+                    None => return Err(unexpected_end()),
+                    Some('"') => return Ok(offset + 1),
+                    Some('\\') => match input.peek(offset + 1)? {
+                        Some('n' | 't' | '"' | '\\') => offset += 2,
+                        Some(other) => {
+                            return Err(LispError::syntax(format!("unknown escape sequence in string literal: \\{other}")));
+                        },
+                        None => return Err(unexpected_end()),
+                    },
+                    Some(_) => offset += 1,
+                }
+            }
+        },
+        _ => {
+            let mut offset = start;
+            while let Some(next) = input.peek(offset)? {
+                if next.is_whitespace() || matches!(next, '(' | ')' | '[' | ']' | '\'' | '"' | ';') {
+                    break;
+                }
+                offset += 1;
+            }
+            Ok(offset)
+        },
+    }
+}
+
+fn unexpected_end() -> LispError {
+    return LispError::syntax("unexpected end of input in datum");
+}
+
+
+// -------------- CURRENT PORTS --------------
+
+/// The ports that reading and writing procedures use when they are not given
+/// one, and what they may do with files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Io {
+    pub input: PortRef,
+    pub output: PortRef,
+    pub file_access: FileAccess,
+    /// The directory files must be inside of to be opened, if any.
+    pub allowed_directory: Option<PathBuf>,
+}
+
+/// Standard input and output, with unrestricted file access.
+impl Default for Io {
+    fn default() -> Self {
+        let (input, output) = CONSOLE.with(|console| console.clone());
+        return Io { input, output, file_access: FileAccess::default(), allowed_directory: None };
+    }
+}
+
+impl Io {
+    pub fn with_input(self, input: PortRef) -> Self {
+        return Io { input, ..self };
+    }
+
+    pub fn with_output(self, output: PortRef) -> Self {
+        return Io { output, ..self };
+    }
+
+    pub fn with_file_access(self, file_access: FileAccess) -> Self {
+        return Io { file_access, ..self };
+    }
+
+    pub fn with_allowed_directory(self, directory: impl Into<PathBuf>) -> Self {
+        return Io { allowed_directory: Some(directory.into()), ..self };
+    }
+}
+
+thread_local! {
+    // shared so that what one port reads ahead is not lost to the others
+    static CONSOLE: (PortRef, PortRef) = (
+        PortRef::new("console", Kind::Input(Input::new("", Some(Source::Stdin)))),
+        PortRef::new("console", Kind::Output(Output::Stdout)),
+    );
+    static CURRENT: RefCell<Io> = RefCell::new(Io::default());
+}
+
+/// Runs `evaluation` with `io` as its current ports and file access.
+pub fn with_io<T>(io: &Io, evaluation: impl FnOnce() -> T) -> T {
+    let outer = CURRENT.with(|current| current.replace(io.clone()));
+    let result = evaluation();
+    CURRENT.with(|current| *current.borrow_mut() = outer);
+    return result;
+}
+
+/// Runs `evaluation` with its output going to a new string port, returning
+/// what it wrote along with its result.
+pub fn with_output_to_string<T>(evaluation: impl FnOnce() -> T) -> (T, String) {
+    let port = PortRef::output_string();
+    let outer = CURRENT.with(|current| std::mem::replace(&mut current.borrow_mut().output, port.clone()));
+    let result = evaluation();
+    CURRENT.with(|current| current.borrow_mut().output = outer);
+    return (result, port.0.written().unwrap_or_default());
+}
+
+pub fn current_input() -> PortRef {
+    return CURRENT.with(|current| current.borrow().input.clone());
+}
+
+pub fn current_output() -> PortRef {
+    return CURRENT.with(|current| current.borrow().output.clone());
+}
+
+/// Fails with an `Io` error unless the current file access allows at least
+/// `needed` for the file at `path`, and the file is inside the allowed
+/// directory if there is one.
+pub fn check_file_access(path: &Path, needed: FileAccess) -> LispResult<()> {
+    let (allowed, directory) = CURRENT.with(|current| {
+        let io = current.borrow();
+        return (io.file_access, io.allowed_directory.clone());
+    });
+    if allowed < needed {
+        let purpose = match needed {
+            FileAccess::ReadWrite => "writing",
+            _ => "reading",
+        };
+        return Err(access_denied(format!("can not open {} for {purpose}", path.display())));
+    }
+    match directory {
+        Some(directory) if !is_inside(path, &directory) => {
+            Err(access_denied(format!("{} is outside of {}", path.display(), directory.display())))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Whether `path` is inside `directory` once links and `..` are resolved. A
+/// file that does not exist yet is judged by the directory it would be in,
+/// while a link to nothing is never inside, since opening it for writing
+/// would create the file it points to.
+fn is_inside(path: &Path, directory: &Path) -> bool {
+    let Ok(directory) = directory.canonicalize() else { return false };
+    let resolved = match path.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(_) if path.symlink_metadata().is_ok() => return false,
+        Err(_) => {
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else { return false };
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            match parent.canonicalize() {
+                Ok(parent) => parent.join(name),
+                Err(_) => return false,
+            }
+        },
+    };
+    return resolved.starts_with(directory);
+}
+
+fn access_denied(reason: String) -> LispError {
+    return LispError::new(LispErrorKind::Io, format!("file access denied: {reason}"));
+}
+
+fn open_error(path: &Path, err: io::Error) -> LispError {
+    return LispError::new(LispErrorKind::Io, format!("can not open {}: {err}", path.display()));
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn datum(source: &str) -> LispOutput {
+        return LispOutput::from_datum(&parse(&tokenize(source)));
+    }
+
+    #[test]
+    fn reading_lines_and_characters() {
+        let port = PortRef::input_string("ab\r\ncd\n\nlast");
+        assert_eq!(Ok(Some('a')), port.0.peek_char());
+        assert_eq!(Ok(Some('a')), port.0.read_char());
+        assert_eq!(Ok(Some("b".to_string())), port.0.read_line());
+        assert_eq!(Ok(Some("cd".to_string())), port.0.read_line());
+        assert_eq!(Ok(Some("".to_string())), port.0.read_line());
+        assert_eq!(Ok(Some("last".to_string())), port.0.read_line());
+        assert_eq!(Ok(None), port.0.read_line());
+        assert_eq!(Ok(None), port.0.read_char());
+    }
+
+    #[test]
+    fn reading_data() {
+        let port = PortRef::input_string("42 foo ; a comment\n (1 (\"a b\" 'x)\n 2) \"q\\\"\" 'y");
+        assert_eq!(Ok(Some(LispOutput::Integer(42))), port.0.read());
+        assert_eq!(Ok(Some(LispOutput::Symbol("foo".to_string()))), port.0.read());
+        assert_eq!(Ok(Some(datum("(1 (\"a b\" 'x) 2)"))), port.0.read());
+        assert_eq!(Ok(Some(LispOutput::String("q\"".to_string()))), port.0.read());
+        assert_eq!(Ok(Some(datum("'y"))), port.0.read());
+        assert_eq!(Ok(None), port.0.read());
+        assert_eq!(Ok(Some(datum("(1 (2))"))), PortRef::input_string("[1 (2)]").0.read());
+        // the rest of the line is left for the next read
+        let port = PortRef::input_string("(a) rest");
+        port.0.read().unwrap();
+        assert_eq!(Ok(Some(" rest".to_string())), port.0.read_line());
+    }
+
+    #[test]
+    fn malformed_data_are_syntax_errors() {
+        for source in ["(1 2", ")", "]", "[1 2)", "\"open", "\"\\q\"", "'"] {
+            let err = PortRef::input_string(source).0.read().unwrap_err();
+            assert_eq!(LispErrorKind::Syntax, err.kind, "{source}");
+        }
+    }
+
+    #[test]
+    fn ports_have_a_direction() {
+        let input = PortRef::input_string("");
+        let output = PortRef::output_string();
+        assert_eq!(LispErrorKind::Type, input.0.write_str("x").unwrap_err().kind);
+        assert_eq!(LispErrorKind::Type, output.0.read_char().unwrap_err().kind);
+        assert_eq!("#[input port string]", input.0.to_string());
+
+        output.0.write_str("some ").unwrap();
+        output.0.write_str("text").unwrap();
+        assert_eq!(Some("some text".to_string()), output.0.written());
+
+        output.0.close().unwrap();
+        output.0.close().unwrap();
+        let err = output.0.write_str("more").unwrap_err();
+        assert_eq!("io error: #[output port string] is closed", err.to_string());
+    }
+
+    #[test]
+    fn file_access_is_checked() {
+        let path = std::env::temp_dir().join(format!("lisp-port-test-{}.txt", std::process::id()));
+        let io = Io::default();
+
+        with_io(&io.clone().with_file_access(FileAccess::ReadOnly), || {
+            let err = PortRef::open_output_file(&path).unwrap_err();
+            assert_eq!(format!("io error: file access denied: can not open {} for writing", path.display()), err.to_string());
+        });
+        with_io(&io, || {
+            let port = PortRef::open_output_file(&path).unwrap();
+            port.0.write_str("(written)").unwrap();
+            port.0.close().unwrap();
+        });
+        with_io(&io.clone().with_file_access(FileAccess::ReadOnly), || {
+            let port = PortRef::open_input_file(&path).unwrap();
+            assert_eq!(Ok(Some(datum("(written)"))), port.0.read());
+        });
+        with_io(&io.with_file_access(FileAccess::Denied), || {
+            assert_eq!(LispErrorKind::Io, PortRef::open_input_file(&path).unwrap_err().kind);
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(LispErrorKind::Io, PortRef::open_input_file(&path).unwrap_err().kind);
+    }
+
+    #[test]
+    fn files_are_kept_inside_the_allowed_directory() {
+        let directory = std::env::temp_dir().join(format!("lisp-port-jail-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("inner")).unwrap();
+        let io = Io::default().with_allowed_directory(&directory);
+
+        with_io(&io, || {
+            let port = PortRef::open_output_file(&directory.join("inner/new.txt")).unwrap();
+            port.0.close().unwrap();
+            assert!(PortRef::open_input_file(&directory.join("inner/../inner/new.txt")).is_ok());
+
+            let outside = directory.join("../outside.txt");
+            let err = PortRef::open_output_file(&outside).unwrap_err();
+            assert_eq!(format!("io error: file access denied: {} is outside of {}", outside.display(), directory.display()), err.to_string());
+            assert!(PortRef::open_input_file(Path::new("/etc/hostname")).is_err());
+            assert!(PortRef::open_input_file(&directory.join("missing/file.txt")).is_err());
+        });
+        assert!(!directory.join("../outside.txt").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_links_do_not_leave_the_allowed_directory() {
+        let directory = std::env::temp_dir().join(format!("lisp-port-link-jail-{}", std::process::id()));
+        let target = std::env::temp_dir().join(format!("lisp-port-link-escape-{}.txt", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::os::unix::fs::symlink(&target, directory.join("dangling")).unwrap();
+        let io = Io::default().with_allowed_directory(&directory);
+
+        with_io(&io, || {
+            let err = PortRef::open_output_file(&directory.join("dangling")).unwrap_err();
+            assert!(err.to_string().contains("is outside of"), "{err}");
+        });
+        assert!(!target.exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+;; The standard prelude, evaluated into the built-ins of every environment.
+;;
+;; Like map, filter and reduce, procedures taking a list and a procedure take
+;; the list first. Bump the version whenever a definition is added, removed or
+;; changes behaviour. Procedures walking lists call themselves only in tail
+;; position, building their results backwards where need be, so that lists
+;; longer than the stack is deep are no trouble.
+
+(define prelude-version "1.0.0")
+
+(define (not x) (equal? x #f))
+
+(define (null? x) (equal? x nil))
+
+(define (identity x) x)
+
+;; (fold-left '(1 2 3) f init) is (f (f (f init 1) 2) 3)
+(define (fold-left lst f acc)
+  (if (null? lst)
+      acc
+      (fold-left (cdr lst) f (f acc (car lst)))))
+
+;; (fold-right '(1 2 3) f init) is (f 1 (f 2 (f 3 init)))
+(define (fold-right lst f acc)
+  (fold-left (reverse lst) (lambda (acc x) (f x acc)) acc))
+
+(define (for-each lst f)
+  (if (null? lst)
+      nil
+      (let ((ignored (f (car lst))))
+        (for-each (cdr lst) f))))
+
+(define (reverse lst)
+  (fold-left lst (lambda (acc x) (cons x acc)) nil))
+
+(define (last lst)
+  (if (null? (cdr lst))
+      (car lst)
+      (last (cdr lst))))
+
+(define (take lst n)
+  (let loop ((lst lst) (n n) (acc nil))
+    (if (> n 0)
+        (loop (cdr lst) (- n 1) (cons (car lst) acc))
+        (reverse acc))))
+
+(define (drop lst n)
+  (if (> n 0)
+      (drop (cdr lst) (- n 1))
+      lst))
+
+;; the tail of lst starting at the first element equal to x, or #f
+(define (member x lst)
+  (if (null? lst)
+      #f
+      (if (equal? x (car lst))
+          lst
+          (member x (cdr lst)))))
+
+;; the first (key value ...) entry of alist with the given key, or #f
+(define (assoc key alist)
+  (if (null? alist)
+      #f
+      (if (equal? key (car (car alist)))
+          (car alist)
+          (assoc key (cdr alist)))))
+
+;; pairs up the elements of two lists, as long as the shorter one lasts
+(define (zip first second)
+  (let loop ((first first) (second second) (acc nil))
+    (if (or (null? first) (null? second))
+        (reverse acc)
+        (loop (cdr first) (cdr second) (cons (list (car first) (car second)) acc)))))
+
+;; the integers from start up to but not including end
+(define (range start end #!optional (step 1))
+  (let loop ((n start) (acc nil))
+    (if (if (> step 0) (< n end) (> n end))
+        (loop (+ n step) (cons n acc))
+        (reverse acc))))
+
+;; count integers starting at start, built from the last one down
+(define (iota count #!optional (start 0) (step 1))
+  (let loop ((i count) (n (+ start (* step (- count 1)))) (acc nil))
+    (if (> i 0)
+        (loop (- i 1) (- n step) (cons n acc))
+        acc)))
+
+(define (any lst pred)
+  (if (null? lst)
+      #f
+      (if (pred (car lst))
+          #t
+          (any (cdr lst) pred))))
+
+(define (every lst pred)
+  (if (null? lst)
+      #t
+      (if (pred (car lst))
+          (every (cdr lst) pred)
+          #f)))
+
+;; a list of the elements satisfying pred and a list of the others
+(define (partition lst pred)
+  (fold-right lst
+              (lambda (x parts)
+                (if (pred x)
+                    (list (cons x (car parts)) (car (cdr parts)))
+                    (list (car parts) (cons x (car (cdr parts))))))
+              (list nil nil)))
+
+(define (flatten lst)
+  (reverse (let walk ((lst lst) (acc nil))
+             (fold-left lst
+                        (lambda (acc x)
+                          (if (list? x)
+                              (walk x acc)
+                              (cons x acc)))
+                        acc))))
+
+;; ((compose f g) x ...) is (f (g x ...))
+(define (compose . fs)
+  (if (null? fs)
+      identity
+      (if (null? (cdr fs))
+          (car fs)
+          (let ((f (car fs))
+                (g (apply compose (cdr fs))))
+            (lambda args (f (apply g args)))))))
@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::analyze::{analyze, Node};
+use crate::evaluate::Environment;
+use crate::interrupt;
+use crate::limits::{with_limits, EvalLimits};
+use crate::machine::execute;
+use crate::parser::parse_all_with_spans;
+use crate::resolve::resolve;
+use crate::tokenizer::tokenize_with_spans;
+
+
+/// The procedures of the standard library that are written in Lisp.
+pub const SOURCE: &str = include_str!("prelude.lisp");
+
+/// The version of the prelude, which it binds to `prelude-version`.
+pub const VERSION: &str = "1.0.0";
+
+thread_local! {
+    // parsed once per thread, since every environment evaluates it
+    static NODES: Vec<Rc<Node>> = parse();
+}
+
+fn parse() -> Vec<Rc<Node>> {
+    let (tokens, spans) = tokenize_with_spans(SOURCE);
+    let expressions = parse_all_with_spans(&tokens, &spans).expect("the prelude parses");
+    return expressions.iter()
+        .map(|(tree, _)| resolve(&analyze(tree).expect("the prelude is well formed")))
+        .collect();
+}
+
+/// Defines the procedures of the prelude in `env`. This happens outside the
+/// limits of any evaluation in progress and cannot be interrupted, so that
+/// `make-environment` always gets a complete set of built-ins.
+pub fn evaluate_into(env: &Rc<RefCell<Environment>>) {
+    NODES.with(|nodes| {
+        interrupt::uninterruptible(|| with_limits(&EvalLimits::default(), || {
+            for node in nodes {
+                execute(node.clone(), env.clone())?;
+            }
+            return Ok(());
+        })).expect("the prelude evaluates without errors");
+    });
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate::{evaluate, evaluate_compiled, Engine, LispOutput};
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    /// Checks written in Lisp, which evaluate to a list of the ones that
+    /// failed.
+    const TESTS: &str = include_str!("prelude_tests.lisp");
+
+    #[test]
+    fn prelude_tests_pass() {
+        let engines: [Engine; 2] = [evaluate, evaluate_compiled];
+        for engine in engines {
+            let mut env = Rc::new(RefCell::new(Environment::global_env()));
+            let (tokens, spans) = tokenize_with_spans(TESTS);
+            let mut failures = LispOutput::Void;
+            for (tree, _) in parse_all_with_spans(&tokens, &spans).unwrap() {
+                failures = engine(&tree, &mut env).unwrap();
+            }
+            assert_eq!("()", failures.to_string(), "failed prelude tests");
+        }
+    }
+
+    #[test]
+    fn prelude_is_versioned() {
+        let mut env = Rc::new(RefCell::new(Environment::global_env()));
+        let version = evaluate(&parse(&tokenize("prelude-version")), &mut env);
+        assert_eq!(Ok(LispOutput::String(VERSION.to_string())), version);
+    }
+
+    #[test]
+    fn user_definitions_shadow_the_prelude() {
+        let mut env = Rc::new(RefCell::new(Environment::global_env()));
+        let mut eval = |source: &str| evaluate(&parse(&tokenize(source)), &mut env);
+        eval("(define (null? x) #t)").unwrap();
+        assert_eq!(Ok(LispOutput::Bool(true)), eval("(null? (list 1))"));
+        // the prelude keeps using its own definitions
+        assert_eq!(eval("(list 3 2 1)"), eval("(reverse (list 1 2 3))"));
+    }
+}
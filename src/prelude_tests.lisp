@@ -0,0 +1,73 @@
+;; Tests of the prelude. Evaluates to a list of (name actual expected) for
+;; each check that failed.
+
+(define failures nil)
+
+(define (check name actual expected)
+  (if (equal? actual expected)
+      #t
+      (set! failures (append failures (list (list name actual expected))))))
+
+(check 'not (list (not #f) (not #t) (not 0)) (list #t #f #f))
+(check 'null? (list (null? nil) (null? (list 1)) (null? 0)) (list #t #f #f))
+(check 'identity (identity "same") "same")
+
+(check 'fold-left (fold-left (list 1 2 3) (lambda (acc x) (- acc x)) 10) 4)
+(check 'fold-left-order (fold-left (list 1 2 3) (lambda (acc x) (cons x acc)) nil) (list 3 2 1))
+(check 'fold-left-empty (fold-left nil + 7) 7)
+(check 'fold-right (fold-right (list 1 2 3) (lambda (x acc) (- x acc)) 0) 2)
+(check 'fold-right-order (fold-right (list 1 2 3) cons nil) (list 1 2 3))
+
+(define visited nil)
+(check 'for-each (for-each (list 1 2 3) (lambda (x) (set! visited (cons x visited)))) nil)
+(check 'for-each-order visited (list 3 2 1))
+
+(check 'reverse (reverse (list 1 2 3)) (list 3 2 1))
+(check 'reverse-empty (reverse nil) nil)
+(check 'last (last (list 1 2 3)) 3)
+(check 'take (take (list 1 2 3 4) 2) (list 1 2))
+(check 'take-none (take (list 1 2) 0) nil)
+(check 'drop (drop (list 1 2 3 4) 2) (list 3 4))
+(check 'drop-all (drop (list 1 2) 2) nil)
+
+(check 'member (member 2 (list 1 2 3)) (list 2 3))
+(check 'member-strings (member "b" (list "a" "b")) (list "b"))
+(check 'member-missing (member 4 (list 1 2 3)) #f)
+(check 'assoc (assoc 'b '((a 1) (b 2) (b 3))) '(b 2))
+(check 'assoc-missing (assoc 'c '((a 1))) #f)
+
+(check 'zip (zip (list 1 2 3) '(a b)) '((1 a) (2 b)))
+(check 'range (range 0 5) (list 0 1 2 3 4))
+(check 'range-step (range 1 10 3) (list 1 4 7))
+(check 'range-down (range 5 0 -2) (list 5 3 1))
+(check 'range-empty (range 3 3) nil)
+(check 'iota (iota 4) (list 0 1 2 3))
+(check 'iota-start-step (iota 3 10 -5) (list 10 5 0))
+
+(define (even? x) (equal? x (* 2 (/ x 2))))
+(check 'any (any (list 1 3 4) even?) #t)
+(check 'any-none (any (list 1 3) even?) #f)
+(check 'any-empty (any nil even?) #f)
+(check 'every (every (list 2 4) even?) #t)
+(check 'every-not (every (list 2 3) even?) #f)
+(check 'every-empty (every nil even?) #t)
+(check 'partition (partition (range 0 6) even?) (list (list 0 2 4) (list 1 3 5)))
+
+(check 'flatten (flatten (list 1 (list 2 (list 3 4)) nil 5)) (list 1 2 3 4 5))
+(check 'compose ((compose (lambda (x) (* x 2)) (lambda (x y) (+ x y))) 3 4) 14)
+(check 'compose-none ((compose) 5) 5)
+(check 'compose-order ((compose car reverse) (list 1 2 3)) 3)
+
+;; lists longer than the stack is deep
+(define long (iota 5000))
+(check 'long-iota (list (length long) (car long) (last long)) (list 5000 0 4999))
+(check 'long-range (length (range 0 5000)) 5000)
+(check 'long-map (length (map long identity)) 5000)
+(check 'long-filter (length (filter long even?)) 2500)
+(check 'long-fold-right (car (fold-right long cons nil)) 0)
+(check 'long-take (last (take long 4999)) 4998)
+(check 'long-zip (length (zip long long)) 5000)
+(check 'long-flatten (length (flatten (list long long))) 10000)
+(check 'long-equal (equal? (iota 5000) long) #t)
+
+failures
@@ -0,0 +1,253 @@
+use std::rc::Rc;
+
+use crate::analyze::{collect_definitions, GuardClause, Lambda, Node, Reference, Slots};
+
+
+/// Gives every variable in `node` a lexical address, so that the `machine`
+/// finds it in a slot of a known frame instead of searching frames by name.
+///
+/// The frames of `lambda` and the `let` forms get a slot for each internal
+/// definition made in them as well, so that `define` keeps adding to the
+/// innermost frame. Code using `the-environment` is left as it is, since
+/// `eval` may add variables to any of its frames.
+pub fn resolve(node: &Rc<Node>) -> Rc<Node> {
+    if uses_the_environment(node) {
+        return node.clone();
+    }
+    return Resolver { frames: Vec::new() }.node(node);
+}
+
+fn uses_the_environment(node: &Node) -> bool {
+    let any = |nodes: &[Rc<Node>]| nodes.iter().any(|node| uses_the_environment(node));
+    match node {
+        Node::TheEnvironment => true,
+        Node::Constant(_) | Node::Variable(..) | Node::Del(_) | Node::Import(_) | Node::DefineLibrary(_) => false,
+        Node::Define(_, value) | Node::SetBang(_, _, value) => uses_the_environment(value),
+        Node::Lambda(lambda) => lambda_uses_the_environment(lambda),
+        Node::If(condition, consequent, alternative) => {
+            [condition, consequent, alternative].into_iter().any(|node| uses_the_environment(node))
+        },
+        Node::And(nodes) | Node::Or(nodes) | Node::Application(nodes, _) => any(nodes),
+        Node::Let { inits, body, .. } => any(inits) || uses_the_environment(body),
+        Node::NamedLet { lambda, inits, .. } => any(inits) || lambda_uses_the_environment(lambda),
+        Node::LetStar { bindings, body, .. } | Node::Letrec { bindings, body, .. } => {
+            bindings.iter().any(|(_, init)| uses_the_environment(init)) || uses_the_environment(body)
+        },
+        Node::Guard { clauses, body, .. } => {
+            uses_the_environment(body)
+                || clauses.iter().any(|(test, expr)| {
+                    test.as_deref().is_some_and(uses_the_environment) || uses_the_environment(expr)
+                })
+        },
+    }
+}
+
+fn lambda_uses_the_environment(lambda: &Lambda) -> bool {
+    return uses_the_environment(&lambda.body)
+        || lambda.parameters.defaults().into_iter().flatten().any(|default| uses_the_environment(default));
+}
+
+/// The slots of a frame: its bindings, then the definitions made in `nodes`
+/// that are not among them.
+fn frame_slots<'a>(bindings: impl IntoIterator<Item = &'a String>, nodes: &[&Node]) -> Slots {
+    let mut names: Vec<String> = bindings.into_iter().cloned().collect();
+    let mut definitions = Vec::new();
+    for node in nodes {
+        collect_definitions(node, &mut definitions);
+    }
+    for definition in definitions {
+        if !names.contains(definition) {
+            names.push(definition.clone());
+        }
+    }
+    return names.into();
+}
+
+struct Resolver {
+    /// The slots of the frames around the code being resolved, innermost last.
+    frames: Vec<Slots>,
+}
+
+impl Resolver {
+    fn reference(&self, var: &str) -> Reference {
+        for (depth, slots) in self.frames.iter().rev().enumerate() {
+            if let Some(index) = slots.iter().position(|name| name == var) {
+                return Reference::Local { depth, index };
+            }
+        }
+        return Reference::Free { depth: self.frames.len() };
+    }
+
+    /// Resolves `node` inside a new frame with `slots`.
+    fn in_frame(&mut self, slots: &Slots, node: &Rc<Node>) -> Rc<Node> {
+        self.frames.push(slots.clone());
+        let node = self.node(node);
+        self.frames.pop();
+        return node;
+    }
+
+    fn nodes(&mut self, nodes: &[Rc<Node>]) -> Vec<Rc<Node>> {
+        return nodes.iter().map(|node| self.node(node)).collect();
+    }
+
+    fn node(&mut self, node: &Rc<Node>) -> Rc<Node> {
+        let resolved = match &**node {
+            // the body of a library is resolved when the library is defined
+            Node::Constant(_) | Node::Del(_) | Node::TheEnvironment
+                | Node::Import(_) | Node::DefineLibrary(_) => return node.clone(),
+            Node::Variable(var, _) => Node::Variable(var.clone(), self.reference(var)),
+            Node::Define(var, value) => Node::Define(var.clone(), self.node(value)),
+            Node::SetBang(var, _, value) => Node::SetBang(var.clone(), self.reference(var), self.node(value)),
+            Node::Lambda(lambda) => Node::Lambda(self.lambda(lambda)),
+            Node::If(condition, consequent, alternative) => {
+                Node::If(self.node(condition), self.node(consequent), self.node(alternative))
+            },
+            Node::And(clauses) => Node::And(self.nodes(clauses)),
+            Node::Or(clauses) => Node::Or(self.nodes(clauses)),
+            Node::Application(nodes, span) => Node::Application(self.nodes(nodes), *span),
+            Node::Let { names, inits, body, .. } => {
+                let slots = frame_slots(names, &[body]);
+                Node::Let {
+                    names: names.clone(),
+                    inits: self.nodes(inits),
+                    body: self.in_frame(&slots, body),
+                    slots,
+                }
+            },
+            Node::NamedLet { name, lambda, inits, slots } => {
+                self.frames.push(slots.clone());
+                let lambda = self.lambda(lambda);
+                self.frames.pop();
+                Node::NamedLet {
+                    name: name.clone(),
+                    lambda,
+                    inits: self.nodes(inits),
+                    slots: slots.clone(),
+                }
+            },
+            Node::LetStar { bindings, body, .. } => {
+                // each initializer is resolved in the frames of the bindings
+                // before it
+                let mut resolved = Vec::new();
+                let mut slots = Vec::new();
+                for (index, (var, init)) in bindings.iter().enumerate() {
+                    resolved.push((var.clone(), self.node(init)));
+                    let next = match bindings.get(index + 1) {
+                        Some((_, next)) => next,
+                        None => body,
+                    };
+                    let frame = frame_slots([var], &[next]);
+                    self.frames.push(frame.clone());
+                    slots.push(frame);
+                }
+                let body = self.node(body);
+                for _ in bindings {
+                    self.frames.pop();
+                }
+                Node::LetStar { bindings: resolved, body, slots }
+            },
+            Node::Letrec { bindings, body, sequential, .. } => {
+                let scope_nodes: Vec<&Node> = bindings.iter().map(|(_, init)| &**init).chain([&**body]).collect();
+                let slots = frame_slots(bindings.iter().map(|(var, _)| var), &scope_nodes);
+                self.frames.push(slots.clone());
+                let bindings = bindings.iter().map(|(var, init)| (var.clone(), self.node(init))).collect();
+                let body = self.node(body);
+                self.frames.pop();
+                Node::Letrec { bindings, body, sequential: *sequential, slots }
+            },
+            Node::Guard { var, clauses, body, .. } => {
+                let scope_nodes: Vec<&Node> = clauses.iter()
+                    .flat_map(|(test, expr)| test.iter().chain([expr]))
+                    .map(|node| &**node)
+                    .collect();
+                let slots = frame_slots([var], &scope_nodes);
+                let body = self.node(body);
+                self.frames.push(slots.clone());
+                let clauses: Vec<GuardClause> = clauses.iter()
+                    .map(|(test, expr)| (test.as_ref().map(|test| self.node(test)), self.node(expr)))
+                    .collect();
+                self.frames.pop();
+                Node::Guard { var: var.clone(), clauses, body, slots }
+            },
+        };
+        return Rc::new(resolved);
+    }
+
+    fn lambda(&mut self, lambda: &Rc<Lambda>) -> Rc<Lambda> {
+        let defaults = lambda.parameters.defaults();
+        let scope_nodes: Vec<&Node> = defaults.iter().flatten().map(|default| &***default).chain([&*lambda.body]).collect();
+        let slots = frame_slots(lambda.parameters.names(), &scope_nodes);
+
+        self.frames.push(slots.clone());
+        let parameters = lambda.parameters.map_defaults(|default| self.node(default));
+        let body = self.node(&lambda.body);
+        self.frames.pop();
+
+        return Rc::new(Lambda {
+            name: lambda.name.clone(),
+            parameters,
+            body,
+            slots,
+        });
+    }
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::analyze;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn resolve_source(source: &str) -> Rc<Node> {
+        return resolve(&analyze(&parse(&tokenize(source))).unwrap());
+    }
+
+    fn lambda_of(node: &Node) -> &Rc<Lambda> {
+        match node {
+            Node::Lambda(lambda) => lambda,
+            _ => panic!("expected a lambda, got {node:?}"),
+        }
+    }
+
+    #[test]
+    fn parameters_get_addresses() {
+        let node = resolve_source("(lambda (x y) (lambda (z) (list x z)))");
+        let inner = lambda_of(&lambda_of(&node).body);
+        let expected = Node::Application(vec![
+            Rc::new(Node::Variable("list".to_string(), Reference::Free { depth: 2 })),
+            Rc::new(Node::Variable("x".to_string(), Reference::Local { depth: 1, index: 0 })),
+            Rc::new(Node::Variable("z".to_string(), Reference::Local { depth: 0, index: 0 })),
+        ], None);
+        assert_eq!(expected, *inner.body);
+    }
+
+    #[test]
+    fn internal_definitions_get_slots() {
+        let node = resolve_source("(lambda (x) (begin (define y x) (define x 2) y))");
+        let lambda = lambda_of(&node);
+        assert_eq!(vec!["x".to_string(), "y".to_string()], lambda.slots.to_vec());
+    }
+
+    #[test]
+    fn let_star_binds_one_frame_per_binding() {
+        let node = resolve_source("(let* ((a 1) (b a)) b)");
+        match &*node {
+            Node::LetStar { bindings, body, slots } => {
+                assert_eq!(Node::Variable("a".to_string(), Reference::Local { depth: 0, index: 0 }), *bindings[1].1);
+                assert_eq!(Node::Variable("b".to_string(), Reference::Local { depth: 0, index: 0 }), **body);
+                assert_eq!(2, slots.len());
+            },
+            _ => panic!("expected let*"),
+        }
+    }
+
+    #[test]
+    fn code_using_the_environment_is_left_alone() {
+        let node = analyze(&parse(&tokenize("(let ((x 1)) (begin x (the-environment)))"))).unwrap();
+        assert_eq!(node, resolve(&node));
+    }
+}
@@ -0,0 +1,677 @@
+//! Conversions between Lisp values and Rust types implementing serde's
+//! `Serialize` and `Deserialize`.
+//!
+//! - integers and booleans map to themselves, floating point numbers to whole
+//!   integers, and strings and characters to strings
+//! - sequences and tuples map to lists, and `()` and unit structs to void
+//! - `None` maps to `#f` and `Some(value)` to the value, so `Some(false)` reads
+//!   back as `None`
+//! - structs and maps map to association lists of `(key value)` lists, with
+//!   field names as symbols
+//! - unit variants map to their name as a symbol, and other variants to a
+//!   `(name payload)` list whose payload is mapped like a newtype, tuple or
+//!   struct would be
+
+use std::fmt;
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::{ser, Serialize};
+
+use crate::convert::IntoLisp;
+use crate::evaluate::{LispList, LispOutput};
+use crate::lisp_error::{LispError, LispResult};
+
+
+/// Converts `value` to the Lisp value it maps to.
+pub fn to_lisp<T: Serialize + ?Sized>(value: &T) -> LispResult {
+    return value.serialize(Serializer);
+}
+
+/// Converts a Lisp value to the Rust value that maps to it.
+pub fn from_lisp<T: DeserializeOwned>(value: LispOutput) -> LispResult<T> {
+    return T::deserialize(Deserializer::new(value));
+}
+
+impl ser::Error for LispError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        return LispError::type_mismatch(message.to_string());
+    }
+}
+
+impl de::Error for LispError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        return LispError::type_mismatch(message.to_string());
+    }
+}
+
+fn list(items: Vec<LispOutput>) -> LispOutput {
+    return LispOutput::List(Box::new(LispList::build(items.into_iter())));
+}
+
+fn tagged(tag: &str, payload: LispOutput) -> LispOutput {
+    return list(vec![LispOutput::Symbol(tag.to_string()), payload]);
+}
+
+
+// -------------- SERIALIZER --------------
+/// Serializes Rust values to Lisp values.
+pub struct Serializer;
+
+/// Collects the items of a sequence, tuple or tuple variant.
+pub struct SerializeList {
+    tag: Option<&'static str>,
+    items: Vec<LispOutput>,
+}
+
+/// Collects the entries of a map, struct or struct variant.
+pub struct SerializeEntries {
+    tag: Option<&'static str>,
+    entries: Vec<LispOutput>,
+    key: Option<LispOutput>,
+}
+
+impl SerializeList {
+    fn new(tag: Option<&'static str>, len: Option<usize>) -> Self {
+        return SerializeList { tag, items: Vec::with_capacity(len.unwrap_or(0)) };
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> LispResult<()> {
+        self.items.push(to_lisp(value)?);
+        return Ok(());
+    }
+
+    fn finish(self) -> LispResult {
+        let items = list(self.items);
+        return Ok(match self.tag {
+            Some(tag) => tagged(tag, items),
+            None => items,
+        });
+    }
+}
+
+impl SerializeEntries {
+    fn new(tag: Option<&'static str>, len: Option<usize>) -> Self {
+        return SerializeEntries { tag, entries: Vec::with_capacity(len.unwrap_or(0)), key: None };
+    }
+
+    fn push(&mut self, key: LispOutput, value: LispOutput) {
+        self.entries.push(list(vec![key, value]));
+    }
+
+    fn push_field<T: Serialize + ?Sized>(&mut self, field: &'static str, value: &T) -> LispResult<()> {
+        self.push(LispOutput::Symbol(field.to_string()), to_lisp(value)?);
+        return Ok(());
+    }
+
+    fn finish(self) -> LispResult {
+        let entries = list(self.entries);
+        return Ok(match self.tag {
+            Some(tag) => tagged(tag, entries),
+            None => entries,
+        });
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = LispOutput;
+    type Error = LispError;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeEntries;
+    type SerializeStruct = SerializeEntries;
+    type SerializeStructVariant = SerializeEntries;
+
+    fn serialize_bool(self, value: bool) -> LispResult {
+        return Ok(LispOutput::Bool(value));
+    }
+
+    fn serialize_i8(self, value: i8) -> LispResult {
+        return self.serialize_i64(value.into());
+    }
+
+    fn serialize_i16(self, value: i16) -> LispResult {
+        return self.serialize_i64(value.into());
+    }
+
+    fn serialize_i32(self, value: i32) -> LispResult {
+        return self.serialize_i64(value.into());
+    }
+
+    fn serialize_i64(self, value: i64) -> LispResult {
+        return Ok(LispOutput::Integer(value));
+    }
+
+    fn serialize_u8(self, value: u8) -> LispResult {
+        return self.serialize_i64(value.into());
+    }
+
+    fn serialize_u16(self, value: u16) -> LispResult {
+        return self.serialize_i64(value.into());
+    }
+
+    fn serialize_u32(self, value: u32) -> LispResult {
+        return self.serialize_i64(value.into());
+    }
+
+    fn serialize_u64(self, value: u64) -> LispResult {
+        return match i64::try_from(value) {
+            Ok(value) => self.serialize_i64(value),
+            Err(_) => Err(LispError::type_mismatch(format!("{value} is too large for an integer"))),
+        };
+    }
+
+    fn serialize_f32(self, value: f32) -> LispResult {
+        return self.serialize_f64(value.into());
+    }
+
+    fn serialize_f64(self, value: f64) -> LispResult {
+        return value.into_lisp();
+    }
+
+    fn serialize_char(self, value: char) -> LispResult {
+        return Ok(LispOutput::String(value.to_string()));
+    }
+
+    fn serialize_str(self, value: &str) -> LispResult {
+        return Ok(LispOutput::String(value.to_string()));
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> LispResult {
+        return Ok(list(value.iter().map(|byte| LispOutput::Integer((*byte).into())).collect()));
+    }
+
+    fn serialize_none(self) -> LispResult {
+        return Ok(LispOutput::Bool(false));
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> LispResult {
+        return value.serialize(self);
+    }
+
+    fn serialize_unit(self) -> LispResult {
+        return Ok(LispOutput::Void);
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> LispResult {
+        return self.serialize_unit();
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> LispResult {
+        return Ok(LispOutput::Symbol(variant.to_string()));
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> LispResult {
+        return value.serialize(self);
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> LispResult {
+        return Ok(tagged(variant, to_lisp(value)?));
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> LispResult<SerializeList> {
+        return Ok(SerializeList::new(None, len));
+    }
+
+    fn serialize_tuple(self, len: usize) -> LispResult<SerializeList> {
+        return Ok(SerializeList::new(None, Some(len)));
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> LispResult<SerializeList> {
+        return Ok(SerializeList::new(None, Some(len)));
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> LispResult<SerializeList> {
+        return Ok(SerializeList::new(Some(variant), Some(len)));
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> LispResult<SerializeEntries> {
+        return Ok(SerializeEntries::new(None, len));
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> LispResult<SerializeEntries> {
+        return Ok(SerializeEntries::new(None, Some(len)));
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> LispResult<SerializeEntries> {
+        return Ok(SerializeEntries::new(Some(variant), Some(len)));
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = LispOutput;
+    type Error = LispError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> LispResult<()> {
+        return self.push(value);
+    }
+
+    fn end(self) -> LispResult {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = LispOutput;
+    type Error = LispError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> LispResult<()> {
+        return self.push(value);
+    }
+
+    fn end(self) -> LispResult {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = LispOutput;
+    type Error = LispError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> LispResult<()> {
+        return self.push(value);
+    }
+
+    fn end(self) -> LispResult {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = LispOutput;
+    type Error = LispError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> LispResult<()> {
+        return self.push(value);
+    }
+
+    fn end(self) -> LispResult {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeMap for SerializeEntries {
+    type Ok = LispOutput;
+    type Error = LispError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> LispResult<()> {
+        self.key = Some(to_lisp(key)?);
+        return Ok(());
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> LispResult<()> {
+        let key = self.key.take().ok_or_else(|| LispError::type_mismatch("map value without a key"))?;
+        self.push(key, to_lisp(value)?);
+        return Ok(());
+    }
+
+    fn end(self) -> LispResult {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeStruct for SerializeEntries {
+    type Ok = LispOutput;
+    type Error = LispError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, field: &'static str, value: &T) -> LispResult<()> {
+        return self.push_field(field, value);
+    }
+
+    fn end(self) -> LispResult {
+        return self.finish();
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeEntries {
+    type Ok = LispOutput;
+    type Error = LispError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, field: &'static str, value: &T) -> LispResult<()> {
+        return self.push_field(field, value);
+    }
+
+    fn end(self) -> LispResult {
+        return self.finish();
+    }
+}
+
+
+// -------------- DESERIALIZER --------------
+/// Deserializes Rust values from a Lisp value.
+pub struct Deserializer {
+    value: LispOutput,
+}
+
+impl Deserializer {
+    pub fn new(value: LispOutput) -> Self {
+        return Deserializer { value };
+    }
+
+    fn unexpected(&self, what: &str) -> LispError {
+        return LispError::type_mismatch(format!("expecting {what}, got {}", self.value));
+    }
+
+    fn items(self, what: &str) -> LispResult<Vec<LispOutput>> {
+        return match self.value {
+            LispOutput::List(list) => Ok(list.to_vec()),
+            _ => Err(self.unexpected(what)),
+        };
+    }
+
+    /// The `(key value)` entries of an association list.
+    fn entries(self) -> LispResult<Vec<(LispOutput, LispOutput)>> {
+        return self.items("an association list")?.into_iter().map(|entry| {
+            let entry = Deserializer::new(entry);
+            match entry.items("a (key value) entry")?.as_slice() {
+                [key, value] => Ok((key.clone(), value.clone())),
+                [..] => Err(LispError::type_mismatch("expecting a (key value) entry")),
+            }
+        }).collect();
+    }
+}
+
+impl<'de> IntoDeserializer<'de, LispError> for LispOutput {
+    type Deserializer = Deserializer;
+
+    fn into_deserializer(self) -> Deserializer {
+        return Deserializer::new(self);
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = LispError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> LispResult<V::Value> {
+        match self.value {
+            LispOutput::Void => visitor.visit_unit(),
+            LispOutput::Integer(num) => visitor.visit_i64(num),
+            LispOutput::Bool(bool_val) => visitor.visit_bool(bool_val),
+            LispOutput::String(string) | LispOutput::Symbol(string) | LispOutput::Keyword(string) => {
+                visitor.visit_string(string)
+            },
+            LispOutput::List(_) => self.deserialize_seq(visitor),
+            _ => Err(self.unexpected("data")),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> LispResult<V::Value> {
+        return self.deserialize_f64(visitor);
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> LispResult<V::Value> {
+        return match self.value {
+            LispOutput::Integer(num) => visitor.visit_f64(num as f64),
+            _ => Err(self.unexpected("a number")),
+        };
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> LispResult<V::Value> {
+        return match self.value {
+            LispOutput::Bool(false) => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        };
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> LispResult<V::Value> {
+        return match self.value {
+            LispOutput::Void => visitor.visit_unit(),
+            _ => Err(self.unexpected("void")),
+        };
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> LispResult<V::Value> {
+        return self.deserialize_unit(visitor);
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> LispResult<V::Value> {
+        return visitor.visit_newtype_struct(self);
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> LispResult<V::Value> {
+        let mut items = SeqDeserializer::new(self.items("a list")?.into_iter());
+        let value = visitor.visit_seq(&mut items)?;
+        items.end()?;
+        return Ok(value);
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> LispResult<V::Value> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> LispResult<V::Value> {
+        return self.deserialize_seq(visitor);
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> LispResult<V::Value> {
+        let mut entries = MapDeserializer::new(self.entries()?.into_iter());
+        let value = visitor.visit_map(&mut entries)?;
+        entries.end()?;
+        return Ok(value);
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> LispResult<V::Value> {
+        return self.deserialize_map(visitor);
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> LispResult<V::Value> {
+        let (tag, payload) = match self.value {
+            LispOutput::Symbol(tag) => (tag, None),
+            LispOutput::List(_) => match self.items("a variant")?.as_slice() {
+                [LispOutput::Symbol(tag), payload] => (tag.clone(), Some(payload.clone())),
+                _ => return Err(LispError::type_mismatch("expecting a (variant payload) list")),
+            },
+            _ => return Err(self.unexpected("a variant")),
+        };
+        return visitor.visit_enum(Variant { tag, payload });
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char str string
+        bytes byte_buf identifier ignored_any
+    }
+}
+
+/// An enum variant being deserialized: its name, and its payload unless it is
+/// a unit variant.
+struct Variant {
+    tag: String,
+    payload: Option<LispOutput>,
+}
+
+impl<'de> de::EnumAccess<'de> for Variant {
+    type Error = LispError;
+    type Variant = Self;
+
+    fn variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> LispResult<(S::Value, Self)> {
+        let tag = seed.deserialize(Deserializer::new(LispOutput::Symbol(self.tag.clone())))?;
+        return Ok((tag, self));
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant {
+    type Error = LispError;
+
+    fn unit_variant(self) -> LispResult<()> {
+        return match self.payload {
+            None => Ok(()),
+            Some(_) => Err(LispError::type_mismatch(format!("expecting {} to have no payload", self.tag))),
+        };
+    }
+
+    fn newtype_variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> LispResult<S::Value> {
+        return seed.deserialize(self.payload()?);
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> LispResult<V::Value> {
+        return de::Deserializer::deserialize_seq(self.payload()?, visitor);
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> LispResult<V::Value> {
+        return de::Deserializer::deserialize_map(self.payload()?, visitor);
+    }
+}
+
+impl Variant {
+    fn payload(self) -> LispResult<Deserializer> {
+        return match self.payload {
+            Some(payload) => Ok(Deserializer::new(payload)),
+            None => Err(LispError::type_mismatch(format!("expecting {} to have a payload", self.tag))),
+        };
+    }
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use crate::interpreter::Interpreter;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(i64),
+        Rectangle(i64, i64),
+        Polygon { sides: u8, closed: bool },
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Meters(u32);
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        name: String,
+        initial: char,
+        scale: f64,
+        width: Meters,
+        shapes: Vec<Shape>,
+        parent: Option<Box<Drawing>>,
+        tags: BTreeMap<String, i32>,
+        origin: (i64, i64),
+        visible: bool,
+        finished: (),
+    }
+
+    fn drawing() -> Drawing {
+        let parent = Drawing {
+            name: "base".to_string(),
+            initial: 'b',
+            scale: 1.0,
+            width: Meters(5),
+            shapes: vec![],
+            parent: None,
+            tags: BTreeMap::new(),
+            origin: (0, 0),
+            visible: false,
+            finished: (),
+        };
+        return Drawing {
+            name: "house".to_string(),
+            initial: 'h',
+            scale: 3.0,
+            width: Meters(12),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(4),
+                Shape::Rectangle(2, -3),
+                Shape::Polygon { sides: 6, closed: true },
+            ],
+            parent: Some(Box::new(parent)),
+            tags: BTreeMap::from([("doors".to_string(), 2), ("windows".to_string(), 6)]),
+            origin: (-1, 7),
+            visible: true,
+            finished: (),
+        };
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + fmt::Debug>(value: T) {
+        assert_eq!(Ok(&value), from_lisp::<T>(to_lisp(&value).unwrap()).as_ref());
+    }
+
+    #[test]
+    fn values_round_trip() {
+        round_trip(drawing());
+        round_trip(vec![Some(1u64), None, Some(3)]);
+        round_trip(BTreeMap::from([(1i64, "one".to_string()), (2, "two".to_string())]));
+        round_trip((true, -5i8, "text".to_string()));
+        round_trip(Shape::Polygon { sides: 3, closed: false });
+    }
+
+    #[test]
+    fn values_map_to_lisp_data() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_global("drawing", to_lisp(&drawing()).unwrap());
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+        assert_eq!("(name \"house\")", eval("(car drawing)"));
+        assert_eq!("(Empty (Circle 4) (Rectangle (2 -3)) (Polygon ((sides 6) (closed #t))))", eval("(car (cdr (list-ref drawing 4)))"));
+        assert_eq!("((\"doors\" 2) (\"windows\" 6))", eval("(car (cdr (list-ref drawing 6)))"));
+        assert_eq!("#f", eval("(car (cdr (list-ref (car (cdr (list-ref drawing 5))) 5)))"));
+    }
+
+    #[test]
+    fn lisp_data_deserializes_to_rust_values() {
+        let mut interpreter = Interpreter::new();
+        let shapes = interpreter.eval_str("(list 'Empty (list 'Circle 1) (list 'Polygon '((closed #f) (sides 4))))").unwrap();
+        assert_eq!(
+            Ok(vec![Shape::Empty, Shape::Circle(1), Shape::Polygon { sides: 4, closed: false }]),
+            from_lisp(shapes)
+        );
+    }
+
+    #[test]
+    fn mismatched_values_are_type_errors() {
+        let err = from_lisp::<Vec<i64>>(LispOutput::String("list".to_string())).unwrap_err();
+        assert_eq!("type error: expecting a list, got \"list\"", err.report());
+        assert!(from_lisp::<Shape>(LispOutput::Symbol("Hexagon".to_string())).is_err());
+        assert!(from_lisp::<(i64, i64)>(to_lisp(&(1, 2, 3)).unwrap()).is_err());
+        assert!(from_lisp::<u8>(LispOutput::Integer(300)).is_err());
+        assert!(to_lisp(&u64::MAX).is_err());
+        assert!(to_lisp(&0.5).is_err());
+    }
+}
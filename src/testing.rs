@@ -0,0 +1,437 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::analyze::{analyze_with_spans, Lambda, Node};
+use crate::built_in_functions::begin_procedure;
+use crate::convert::{NativeFunction, Optional, Symbol};
+use crate::evaluate::LispOutput;
+use crate::functions::{BuiltInFunction, LispFunction, LispFunctionCall, Parameters};
+use crate::interpreter::Interpreter;
+use crate::lisp_error::{Attachment, ErrorObject, LispError, LispErrorKind, LispResult};
+use crate::lisp_expression::{LispExpression, SpanTree};
+use crate::port::PortRef;
+
+pub mod report;
+
+
+/// Files of tests are those whose names end with this.
+pub const TEST_FILE_SUFFIX: &str = "_test.lisp";
+
+thread_local! {
+    // the tests of the file being run by `run_file`, if any
+    static REGISTRY: RefCell<Option<Registry>> = const { RefCell::new(None) };
+}
+
+
+// -------------- FORMS --------------
+// `define-test`, `test-group` and `assert-error` delay evaluating their bodies
+// by wrapping them in procedures, which are passed to built-ins that are part
+// of the code rather than looked up, so that they cannot be shadowed.
+
+/// `(define-test name body ...)`, where the name is a string or a symbol.
+pub fn analyze_define_test(expressions: &[LispExpression], spans: Option<&SpanTree>) -> LispResult<Node> {
+    return named_thunk_call(expressions, spans, "define-test", native(define_test_func));
+}
+
+/// `(test-group name body ...)`, which names the tests defined by its body.
+pub fn analyze_test_group(expressions: &[LispExpression], spans: Option<&SpanTree>) -> LispResult<Node> {
+    return named_thunk_call(expressions, spans, "test-group", native(test_group_func));
+}
+
+/// `(assert-error expression kind)`, where the optional kind is the symbol
+/// `error-object-kind` would give for the error.
+pub fn analyze_assert_error(expressions: &[LispExpression], spans: Option<&SpanTree>) -> LispResult<Node> {
+    let kind = match expressions {
+        [_, _] => None,
+        [_, _, LispExpression::Symbol(kind)] => Some(kind),
+        _ => return Err(LispError::syntax("expecting assert-error to be given an expression and optionally a kind")),
+    };
+    let mut arguments = vec![
+        Rc::new(Node::Constant(native(assert_error_func))),
+        thunk(None, &expressions[1..2], spans, 1)?,
+    ];
+    if let Some(kind) = kind {
+        arguments.push(Rc::new(Node::Constant(LispOutput::Symbol(kind.clone()))));
+    }
+    return Ok(Node::Application(arguments, spans.map(|spans| spans.span)));
+}
+
+fn named_thunk_call(
+    expressions: &[LispExpression],
+    spans: Option<&SpanTree>,
+    form: &str,
+    procedure: LispOutput,
+) -> LispResult<Node> {
+    let name = match expressions.get(1) {
+        Some(LispExpression::String(name) | LispExpression::Symbol(name)) => name,
+        _ => return Err(LispError::syntax(format!("expecting {form} to be given a name"))),
+    };
+    if expressions.len() < 3 {
+        return Err(LispError::syntax(format!("expecting {form} to be given a body")));
+    }
+    return Ok(Node::Application(vec![
+        Rc::new(Node::Constant(procedure)),
+        Rc::new(Node::Constant(LispOutput::String(name.clone()))),
+        thunk(Some(name), &expressions[2..], spans, 2)?,
+    ], spans.map(|spans| spans.span)));
+}
+
+/// A procedure of no arguments evaluating `body` in turn, where `first` is
+/// the index of the body in the form, for finding its spans.
+fn thunk(name: Option<&String>, body: &[LispExpression], spans: Option<&SpanTree>, first: usize) -> LispResult<Rc<Node>> {
+    let mut nodes = body.iter().enumerate()
+        .map(|(index, expr)| analyze_with_spans(expr, spans.and_then(|spans| spans.child(first + index))))
+        .collect::<LispResult<Vec<_>>>()?;
+    let body = match nodes.len() {
+        1 => nodes.remove(0),
+        _ => {
+            nodes.insert(0, Rc::new(Node::Constant(begin_procedure())));
+            Rc::new(Node::Application(nodes, None))
+        },
+    };
+    return Ok(Rc::new(Node::Lambda(Rc::new(Lambda {
+        name: name.cloned(),
+        parameters: Parameters::parse(&LispExpression::List(Vec::new()))?,
+        body,
+        slots: Rc::new([]),
+    }))));
+}
+
+fn native<Args>(function: impl NativeFunction<Args>) -> LispOutput {
+    return LispOutput::Lambda(LispFunction::BuiltInFunction(BuiltInFunction::native(function)));
+}
+
+
+// -------------- ASSERTIONS --------------
+
+/// An `Assertion` error whose irritants are kept apart from its message, so
+/// that a failed `assert-equal` can be reported with the values it compared.
+fn assertion_failed(message: String, irritants: Vec<LispOutput>) -> LispError {
+    let raised = LispOutput::ErrorObject(Rc::new(ErrorObject {
+        kind: LispErrorKind::Assertion,
+        message: message.clone(),
+        irritants,
+        backtrace: Vec::new(),
+    }));
+    return LispError::new(LispErrorKind::Assertion, message).attach(Attachment::Raised(raised));
+}
+
+/// `(assert-equal expected actual)` fails unless the values are `equal?`.
+pub fn assert_equal_func(expected: LispOutput, actual: LispOutput) -> LispResult<()> {
+    if expected == actual {
+        return Ok(());
+    }
+    return Err(assertion_failed(format!("expected {expected}, got {actual}"), vec![expected, actual]));
+}
+
+/// `(assert-true value)` fails unless the value is `#t`.
+pub fn assert_true_func(value: LispOutput) -> LispResult<()> {
+    match value {
+        LispOutput::Bool(true) => Ok(()),
+        _ => Err(assertion_failed(format!("expected #t, got {value}"), Vec::new())),
+    }
+}
+
+fn assert_error_func(thunk: LispFunction, kind: Optional<Symbol>) -> LispResult<()> {
+    let err = match thunk.call(Vec::new()) {
+        Ok(value) => return Err(assertion_failed(format!("expected an error, got {value}"), Vec::new())),
+        Err(err) if err.as_jump().is_some() || err.kind.stops_evaluation() => return Err(err),
+        Err(err) => err,
+    };
+    match kind.0 {
+        Some(Symbol(kind)) if kind != err.kind.name() => {
+            Err(assertion_failed(format!("expected an error of kind {kind}, got {err}"), Vec::new()))
+        },
+        _ => Ok(()),
+    }
+}
+
+
+// -------------- REGISTRY --------------
+
+/// What `define-test` does while a file is being run.
+#[derive(Clone, Copy)]
+enum Mode {
+    /// Only record the names of the tests.
+    Discover,
+    /// Run the test with this index and skip the others.
+    Run(usize),
+}
+
+struct Registry {
+    mode: Mode,
+    /// The names of the enclosing test groups, outermost first.
+    groups: Vec<String>,
+    /// The full names of the tests defined so far.
+    tests: Vec<String>,
+    /// The result of the test that was run.
+    outcome: Option<LispResult<()>>,
+}
+
+/// What to do with a test as it is defined.
+enum Action {
+    Skip,
+    /// Run it, failing the evaluation if it fails.
+    Run,
+    /// Run it, recording its outcome in the registry.
+    Record,
+}
+
+/// Outside of `run_file` tests are run as soon as they are defined.
+fn define_test_func(name: String, thunk: LispFunction) -> LispResult<()> {
+    let action = REGISTRY.with(|registry| match &mut *registry.borrow_mut() {
+        None => Action::Run,
+        Some(registry) => {
+            let index = registry.tests.len();
+            registry.tests.push(registry.groups.iter().chain([&name]).cloned().collect::<Vec<_>>().join(" / "));
+            match registry.mode {
+                Mode::Run(target) if target == index => Action::Record,
+                _ => Action::Skip,
+            }
+        },
+    });
+    match action {
+        Action::Skip => Ok(()),
+        Action::Run => thunk.call(Vec::new()).map(|_| ()),
+        Action::Record => {
+            let outcome = thunk.call(Vec::new()).map(|_| ());
+            if let Err(err) = &outcome {
+                if err.as_jump().is_some() || err.kind.stops_evaluation() {
+                    return outcome;
+                }
+            }
+            REGISTRY.with(|registry| {
+                if let Some(registry) = &mut *registry.borrow_mut() {
+                    registry.outcome = Some(outcome);
+                }
+            });
+            Ok(())
+        },
+    }
+}
+
+fn test_group_func(name: String, thunk: LispFunction) -> LispResult {
+    let in_runner = REGISTRY.with(|registry| match &mut *registry.borrow_mut() {
+        Some(registry) => {
+            registry.groups.push(name);
+            true
+        },
+        None => false,
+    });
+    let result = thunk.call(Vec::new());
+    if in_runner {
+        REGISTRY.with(|registry| registry.borrow_mut().as_mut().map(|registry| registry.groups.pop()));
+    }
+    return result;
+}
+
+
+// -------------- RUNNER --------------
+
+/// Why a test failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub message: String,
+    /// The printed expected and actual values of a failed `assert-equal`.
+    pub values: Option<(String, String)>,
+    /// What the test wrote to the current output port.
+    pub output: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    /// The name of the test, after those of its groups.
+    pub name: String,
+    pub failure: Option<Failure>,
+}
+
+/// The results of the tests of one file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// An error evaluating the file outside of its tests shows up as a
+    /// failed test named `top level`.
+    pub results: Vec<TestResult>,
+}
+
+impl FileReport {
+    pub fn failures(&self) -> usize {
+        return self.results.iter().filter(|result| result.failure.is_some()).count();
+    }
+}
+
+/// The test files in `directory` and its subdirectories, in order of their
+/// paths.
+pub fn discover(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(discover(&path)?);
+        } else if path.file_name().is_some_and(|name| name.to_string_lossy().ends_with(TEST_FILE_SUFFIX)) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    return Ok(files);
+}
+
+/// Runs the tests of every test file in `directory`.
+pub fn run_directory(directory: &Path, compiled: bool) -> io::Result<Vec<FileReport>> {
+    return Ok(discover(directory)?.iter().map(|path| run_file(path, compiled)).collect());
+}
+
+/// Runs each test defined in the file at `path` on its own, evaluating the
+/// whole file in a new interpreter for each of them so that tests cannot see
+/// what the others did. Libraries are looked for next to the file.
+pub fn run_file(path: &Path, compiled: bool) -> FileReport {
+    let (registry, evaluated, output) = evaluate(path, compiled, Mode::Discover);
+    let mut results: Vec<TestResult> = registry.tests.into_iter().enumerate().map(|(index, name)| {
+        let (registry, evaluated, output) = evaluate(path, compiled, Mode::Run(index));
+        let failure = match (registry.outcome, evaluated) {
+            (Some(Ok(())), _) => None,
+            (Some(Err(err)), _) | (None, Err(err)) => Some(failure(&err, output)),
+            // only code that behaves differently from one run to the next
+            (None, Ok(_)) => Some(Failure { message: "the test was not defined again".to_string(), values: None, output }),
+        };
+        return TestResult { name, failure };
+    }).collect();
+
+    if let Err(err) = evaluated {
+        results.push(TestResult { name: "top level".to_string(), failure: Some(failure(&err, output)) });
+    }
+    return FileReport { path: path.to_path_buf(), results };
+}
+
+fn evaluate(path: &Path, compiled: bool, mode: Mode) -> (Registry, LispResult, String) {
+    let output = PortRef::output_string();
+    let mut interpreter = Interpreter::new().compiled(compiled).with_output(output.clone());
+    if let Some(directory) = path.parent() {
+        interpreter.add_library_path(directory);
+    }
+    let registry = Registry { mode, groups: Vec::new(), tests: Vec::new(), outcome: None };
+    let outer = REGISTRY.with(|current| current.replace(Some(registry)));
+    let evaluated = interpreter.eval_file(path);
+    let registry = REGISTRY.with(|current| current.replace(outer)).expect("the registry is still in place");
+    return (registry, evaluated, output.0.written().unwrap_or_default());
+}
+
+fn failure(err: &LispError, output: String) -> Failure {
+    let values = match err.condition() {
+        LispOutput::ErrorObject(error) if error.kind == LispErrorKind::Assertion => match error.irritants.as_slice() {
+            [expected, actual] => Some((expected.to_string(), actual.to_string())),
+            _ => None,
+        },
+        _ => None,
+    };
+    return Failure { message: err.report(), values, output };
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate::{evaluate, evaluate_compiled, Engine, Environment};
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    const ENGINES: [Engine; 2] = [evaluate, evaluate_compiled];
+
+    fn eval_each(sources: &[&str]) -> Vec<Vec<LispResult>> {
+        return ENGINES.iter().map(|engine| {
+            let mut env = Rc::new(RefCell::new(Environment::global_env()));
+            return sources.iter().map(|source| engine(&parse(&tokenize(source)), &mut env)).collect();
+        }).collect();
+    }
+
+    #[test]
+    fn assertions() {
+        for results in eval_each(&[
+            "(assert-equal (list 1 2) (list 1 2))",
+            "(assert-equal 1 2)",
+            "(assert-true (equal? 1 1))",
+            "(assert-true 1)",
+            "(assert-error (car nil))",
+            "(assert-error (car nil) type)",
+            "(assert-error (+ 1 2))",
+            "(assert-error (car nil) arity)",
+            "(guard (e (#t (error-object-irritants e))) (assert-equal \"a\" \"b\"))",
+        ]) {
+            assert_eq!(Ok(LispOutput::Void), results[0]);
+            assert_eq!("assertion error: expected 1, got 2", results[1].clone().unwrap_err().to_string());
+            assert_eq!(Ok(LispOutput::Void), results[2]);
+            assert_eq!("assertion error: expected #t, got 1", results[3].clone().unwrap_err().to_string());
+            assert_eq!(Ok(LispOutput::Void), results[4]);
+            assert_eq!(Ok(LispOutput::Void), results[5]);
+            assert_eq!("assertion error: expected an error, got 3", results[6].clone().unwrap_err().to_string());
+            assert_eq!(
+                "assertion error: expected an error of kind arity, got type error: lisp list is empty!",
+                results[7].clone().unwrap_err().to_string()
+            );
+            let irritants = LispOutput::from_datum(&parse(&tokenize("(\"a\" \"b\")")));
+            assert_eq!(Ok(irritants), results[8]);
+        }
+    }
+
+    #[test]
+    fn tests_outside_a_runner_run_at_once() {
+        for results in eval_each(&[
+            "(define runs 0)",
+            "(define-test counts (set! runs (+ runs 1)) (assert-equal 1 runs))",
+            "(test-group outer (define-test inner (set! runs (+ runs 1))))",
+            "runs",
+            "(define-test fails (assert-true #f))",
+            "(define-test)",
+            "(test-group name)",
+            "(assert-error)",
+        ]) {
+            assert_eq!(Ok(LispOutput::Void), results[1]);
+            assert_eq!(Ok(LispOutput::Integer(2)), results[3]);
+            assert_eq!(LispErrorKind::Assertion, results[4].clone().unwrap_err().kind);
+            for result in &results[5..] {
+                assert_eq!(LispErrorKind::Syntax, result.clone().unwrap_err().kind);
+            }
+        }
+    }
+
+    #[test]
+    fn running_test_files() {
+        let directory = std::env::temp_dir().join(format!("lisp-testing-{}", std::process::id()));
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        fs::write(directory.join("math_test.lisp"), "
+            (define counter 0)
+            (define-test first (set! counter (+ counter 1)) (assert-equal 1 counter))
+            (test-group group
+              (define-test second (display \"ran\") (assert-equal 1 counter)))
+            (define-test last (assert-equal (list 1 2) (list 1 3)))").unwrap();
+        fs::write(directory.join("nested/broken_test.lisp"), "(define-test fine (assert-true #t)) (car 5)").unwrap();
+        fs::write(directory.join("helpers.lisp"), "(car 5)").unwrap();
+
+        for compiled in [false, true] {
+            let reports = run_directory(&directory, compiled).unwrap();
+            let paths: Vec<&PathBuf> = reports.iter().map(|report| &report.path).collect();
+            assert_eq!(vec![&directory.join("math_test.lisp"), &directory.join("nested/broken_test.lisp")], paths);
+
+            let results = &reports[0].results;
+            let names: Vec<&str> = results.iter().map(|result| &result.name[..]).collect();
+            assert_eq!(vec!["first", "group / second", "last"], names);
+            // each test sees the file as it was before any test ran
+            assert_eq!(None, results[0].failure);
+            let failure = results[1].failure.clone().unwrap();
+            assert_eq!(Some(("1".to_string(), "0".to_string())), failure.values);
+            assert_eq!("ran", failure.output);
+            assert_eq!(Some(("(1 2)".to_string(), "(1 3)".to_string())), results[2].failure.clone().unwrap().values);
+
+            let results = &reports[1].results;
+            assert_eq!(None, results[0].failure);
+            assert_eq!("top level", results[1].name);
+            assert!(results[1].failure.clone().unwrap().message.starts_with("type error"));
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt::Write;
+
+use super::{FileReport, TestResult};
+
+
+/// How `render` lays out the results of tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// For people reading a terminal.
+    Text,
+    /// The Test Anything Protocol, version 13.
+    Tap,
+    /// The JUnit XML format read by continuous integration services.
+    Junit,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Format::Text),
+            "tap" => Some(Format::Tap),
+            "junit" => Some(Format::Junit),
+            _ => None,
+        }
+    }
+}
+
+pub fn render(reports: &[FileReport], format: Format) -> String {
+    return match format {
+        Format::Text => text(reports),
+        Format::Tap => tap(reports),
+        Format::Junit => junit(reports),
+    };
+}
+
+/// The printed expected and actual values one above the other, with a caret
+/// under the first character where they differ.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let same = expected.chars().zip(actual.chars()).take_while(|(expected, actual)| expected == actual).count();
+    return format!("expected: {expected}\nactual:   {actual}\n          {}^", " ".repeat(same));
+}
+
+/// The message of a failed test followed by the diff of the values it
+/// compared, if any.
+fn details(result: &TestResult) -> String {
+    let Some(failure) = &result.failure else { return String::new() };
+    let mut details = failure.message.clone();
+    if let Some((expected, actual)) = &failure.values {
+        details.push('\n');
+        details.push_str(&diff(expected, actual));
+    }
+    return details;
+}
+
+fn indent(text: &str, prefix: &str) -> String {
+    return text.lines().map(|line| format!("{prefix}{line}\n")).collect();
+}
+
+fn text(reports: &[FileReport]) -> String {
+    let mut out = String::new();
+    for report in reports {
+        let _ = writeln!(out, "{}", report.path.display());
+        for result in &report.results {
+            match &result.failure {
+                None => {
+                    let _ = writeln!(out, "  ok    {}", result.name);
+                },
+                Some(failure) => {
+                    let _ = writeln!(out, "  FAIL  {}", result.name);
+                    out.push_str(&indent(&details(result), "        "));
+                    if !failure.output.is_empty() {
+                        out.push_str("        output:\n");
+                        out.push_str(&indent(&failure.output, "          "));
+                    }
+                },
+            }
+        }
+    }
+    let total: usize = reports.iter().map(|report| report.results.len()).sum();
+    let failed: usize = reports.iter().map(FileReport::failures).sum();
+    let _ = writeln!(out, "{total} tests, {} passed, {failed} failed", total - failed);
+    return out;
+}
+
+fn tap(reports: &[FileReport]) -> String {
+    let results: Vec<(&FileReport, &TestResult)> = reports.iter()
+        .flat_map(|report| report.results.iter().map(move |result| (report, result)))
+        .collect();
+    let mut out = format!("TAP version 13\n1..{}\n", results.len());
+    for (number, (report, result)) in results.iter().enumerate() {
+        let status = if result.failure.is_some() { "not ok" } else { "ok" };
+        let _ = writeln!(out, "{status} {} - {}: {}", number + 1, report.path.display(), result.name);
+        if let Some(failure) = &result.failure {
+            out.push_str("  ---\n  message: |\n");
+            out.push_str(&indent(&failure.message, "    "));
+            if let Some((expected, actual)) = &failure.values {
+                let _ = writeln!(out, "  expected: {expected:?}\n  actual: {actual:?}");
+            }
+            if !failure.output.is_empty() {
+                out.push_str("  output: |\n");
+                out.push_str(&indent(&failure.output, "    "));
+            }
+            out.push_str("  ...\n");
+        }
+    }
+    return out;
+}
+
+fn escape_xml(text: &str) -> String {
+    return text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;");
+}
+
+fn junit(reports: &[FileReport]) -> String {
+    let total: usize = reports.iter().map(|report| report.results.len()).sum();
+    let failed: usize = reports.iter().map(FileReport::failures).sum();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(out, "<testsuites tests=\"{total}\" failures=\"{failed}\">");
+    for report in reports {
+        let suite = escape_xml(&report.path.display().to_string());
+        let _ = writeln!(
+            out,
+            "  <testsuite name=\"{suite}\" tests=\"{}\" failures=\"{}\">",
+            report.results.len(),
+            report.failures(),
+        );
+        for result in &report.results {
+            let name = escape_xml(&result.name);
+            let Some(failure) = &result.failure else {
+                let _ = writeln!(out, "    <testcase classname=\"{suite}\" name=\"{name}\"/>");
+                continue;
+            };
+            let _ = writeln!(out, "    <testcase classname=\"{suite}\" name=\"{name}\">");
+            let message = failure.message.lines().next().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "      <failure message=\"{}\">{}</failure>",
+                escape_xml(message),
+                escape_xml(&details(result)),
+            );
+            if !failure.output.is_empty() {
+                let _ = writeln!(out, "      <system-out>{}</system-out>", escape_xml(&failure.output));
+            }
+            out.push_str("    </testcase>\n");
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    return out;
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::testing::Failure;
+
+    fn reports() -> Vec<FileReport> {
+        return vec![FileReport {
+            path: PathBuf::from("math_test.lisp"),
+            results: vec![
+                TestResult { name: "adds".to_string(), failure: None },
+                TestResult {
+                    name: "lists / <appends>".to_string(),
+                    failure: Some(Failure {
+                        message: "assertion error: expected (1 2 3), got (1 5 3)".to_string(),
+                        values: Some(("(1 2 3)".to_string(), "(1 5 3)".to_string())),
+                        output: "printed\n".to_string(),
+                    }),
+                },
+            ],
+        }];
+    }
+
+    #[test]
+    fn diffs_point_at_the_first_difference() {
+        assert_eq!("expected: (1 2 3)\nactual:   (1 5 3)\n             ^", diff("(1 2 3)", "(1 5 3)"));
+        assert_eq!("expected: ab\nactual:   abc\n            ^", diff("ab", "abc"));
+    }
+
+    #[test]
+    fn text_reports() {
+        let expected = "\
+math_test.lisp
+  ok    adds
+  FAIL  lists / <appends>
+        assertion error: expected (1 2 3), got (1 5 3)
+        expected: (1 2 3)
+        actual:   (1 5 3)
+                     ^
+        output:
+          printed
+2 tests, 1 passed, 1 failed
+";
+        assert_eq!(expected, render(&reports(), Format::Text));
+    }
+
+    #[test]
+    fn tap_reports() {
+        let expected = "\
+TAP version 13
+1..2
+ok 1 - math_test.lisp: adds
+not ok 2 - math_test.lisp: lists / <appends>
+  ---
+  message: |
+    assertion error: expected (1 2 3), got (1 5 3)
+  expected: \"(1 2 3)\"
+  actual: \"(1 5 3)\"
+  output: |
+    printed
+  ...
+";
+        assert_eq!(expected, render(&reports(), Format::Tap));
+    }
+
+    #[test]
+    fn junit_reports() {
+        let junit = render(&reports(), Format::Junit);
+        assert!(junit.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites tests=\"2\" failures=\"1\">\n"));
+        assert!(junit.contains("<testcase classname=\"math_test.lisp\" name=\"adds\"/>"));
+        assert!(junit.contains("<testcase classname=\"math_test.lisp\" name=\"lists / &lt;appends&gt;\">"));
+        assert!(junit.contains("<failure message=\"assertion error: expected (1 2 3), got (1 5 3)\">"));
+        assert!(junit.contains("<system-out>printed\n</system-out>"));
+        assert!(junit.ends_with("  </testsuite>\n</testsuites>\n"));
+    }
+}
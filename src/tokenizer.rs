@@ -1,49 +1,147 @@
+use crate::lisp_error::{LispError, LispResult};
+use crate::lisp_expression::Span;
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 pub enum LispToken {
     Integer(i64),
     Symbol(String),
+    String(String),
     LeftParen,
     RightParen,
+    /// `[` and `]`, which make lists like parentheses, such as the annotated
+    /// parameters `[x : Int]`.
+    LeftBracket,
+    RightBracket,
+    Quote,
 }
 
 
-pub fn tokenize(source: &str) -> Vec<LispToken> {
-    let mut source_without_comments = String::new();
-    // remove comments before separating source by parenthesis
-    for line in source.split("\n") {
-        for line_char in line.chars() {
-            if line_char == ';' {
-                break;
-            }
-            source_without_comments.push(line_char)
+fn word_to_token(word: &str) -> LispToken {
+    if let Ok(num) = word.parse::<i64>() {
+        return LispToken::Integer(num);
+    }
+    return LispToken::Symbol(word.to_string());
+}
+
+/// Reads the rest of a string literal whose opening quote is at `start`.
+fn read_string(chars: &mut Cursor, start: Span) -> LispResult<String> {
+    let unterminated = || LispError::syntax("unterminated string literal").with_span(Some(start));
+    let mut string = String::new();
+    loop {
+        let (line, column) = (chars.line, chars.column);
+        match chars.next() {
+            None => return Err(unterminated()),
+            Some('"') => return Ok(string),
+            Some('\\') => match chars.next() {
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some(escaped @ ('"' | '\\')) => string.push(escaped),
+                Some(other) => {
+                    let span = Span { line, column, end_line: chars.line, end_column: chars.column };
+                    return Err(LispError::syntax(format!("unknown escape sequence in string literal: \\{other}")).with_span(Some(span)));
+                },
+                None => return Err(unterminated()),
+            },
+            Some(string_char) => string.push(string_char),
         }
     }
+}
 
-    // replace parenthesis with space-padded parenthesis to make splitting string easier
-    let words = source_without_comments[..]
-                    .replace("(", " ( ")
-                    .replace(")", " ) ");
+/// Characters of the source along with the position of the next one.
+struct Cursor<'a> {
+    chars: std::str::Chars<'a>,
+    line: usize,
+    column: usize,
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.chars.next()?;
+        if next == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        return Some(next);
+    }
+}
+
+pub fn tokenize(source: &str) -> Vec<LispToken> {
+    return tokenize_with_spans(source).0;
+}
 
-    let words = words.split_whitespace();
+/// Tokenizes `source`, also returning the span of every token. Panics on
+/// malformed string literals, so it is only for sources known to be well
+/// formed, such as the prelude; anything else goes through
+/// `try_tokenize_with_spans`.
+pub fn tokenize_with_spans(source: &str) -> (Vec<LispToken>, Vec<Span>) {
+    return try_tokenize_with_spans(source).unwrap_or_else(|err| panic!("{}", err.message));
+}
 
+/// Tokenizes `source` like `tokenize_with_spans`, reporting malformed string
+/// literals as syntax errors with their spans.
+pub fn try_tokenize_with_spans(source: &str) -> LispResult<(Vec<LispToken>, Vec<Span>)> {
     let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    let mut word = String::new();
+    let mut word_span = Span::default();
+    let mut chars = Cursor { chars: source.chars(), line: 1, column: 1 };
 
-    for word in words {
-        match word {
-            "(" => tokens.push(LispToken::LeftParen),
-            ")" => tokens.push(LispToken::RightParen),
+    // words end at whitespace, parentheses, brackets, quotes, strings and comments
+    loop {
+        let (line, column) = (chars.line, chars.column);
+        let source_char = match chars.next() {
+            Some(source_char) => source_char,
+            None => break,
+        };
+        let token = match source_char {
+            '(' => Some(LispToken::LeftParen),
+            ')' => Some(LispToken::RightParen),
+            '[' => Some(LispToken::LeftBracket),
+            ']' => Some(LispToken::RightBracket),
+            '\'' => Some(LispToken::Quote),
+            '"' => {
+                let start = Span { line, column, end_line: chars.line, end_column: chars.column };
+                Some(LispToken::String(read_string(&mut chars, start)?))
+            },
+            ';' => {
+                // comments run until the end of the line
+                chars.by_ref().find(|comment_char| *comment_char == '\n');
+                None
+            },
+            _ if source_char.is_whitespace() => None,
             _ => {
-                let expression = word.parse::<i64>();
-                if expression.is_ok() {
-                    tokens.push(LispToken::Integer(expression.unwrap()));
-                } else {
-                    tokens.push(LispToken::Symbol(word.to_string()));
+                if word.is_empty() {
+                    word_span.line = line;
+                    word_span.column = column;
                 }
+                word.push(source_char);
+                word_span.end_line = chars.line;
+                word_span.end_column = chars.column;
+                continue;
             },
+        };
+
+        if !word.is_empty() {
+            tokens.push(word_to_token(&word));
+            spans.push(word_span);
+            word.clear();
+        }
+        if let Some(token) = token {
+            tokens.push(token);
+            spans.push(Span { line, column, end_line: chars.line, end_column: chars.column });
         }
     }
-    return tokens;
+
+    if !word.is_empty() {
+        tokens.push(word_to_token(&word));
+        spans.push(word_span);
+    }
+    return Ok((tokens, spans));
 }
 
 
@@ -63,6 +161,7 @@ mod tests {
     fn single_characters() {
         assert_eq!(vec![LispToken::LeftParen], tokenize("("));
         assert_eq!(vec![LispToken::RightParen], tokenize(")"));
+        assert_eq!(vec![LispToken::LeftBracket, LispToken::Symbol("x".to_string()), LispToken::RightBracket], tokenize("[x]"));
         assert_eq!(vec![LispToken::Integer(0)], tokenize("0"));
         assert_eq!(vec![LispToken::Symbol("x".to_string())], tokenize("x"));
     }
@@ -139,4 +238,70 @@ mod tests {
         assert_eq!(expected_tokens, tokenize(add_one_function_with_comments));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn newlines_separate_tokens() {
+        let expected_tokens = vec![
+            LispToken::Symbol("x".to_string()),
+            LispToken::Integer(2),
+        ];
+
+        assert_eq!(expected_tokens, tokenize("x\n2"));
+        assert_eq!(expected_tokens, tokenize("x ; comment\n2"));
+    }
+
+    #[test]
+    fn quote_shorthand() {
+        let expected_tokens = vec![
+            LispToken::Quote,
+            LispToken::LeftParen,
+            LispToken::Quote,
+            LispToken::Symbol("x".to_string()),
+            LispToken::RightParen,
+        ];
+
+        assert_eq!(expected_tokens, tokenize("'('x)"));
+    }
+
+    #[test]
+    fn string_literals() {
+        let expected_tokens = vec![
+            LispToken::LeftParen,
+            LispToken::Symbol("error".to_string()),
+            LispToken::String("bad (thing); \"here\"\n".to_string()),
+            LispToken::Integer(1),
+            LispToken::RightParen,
+        ];
+
+        assert_eq!(expected_tokens, tokenize("(error \"bad (thing); \\\"here\\\"\\n\" 1) ; comment"));
+        assert_eq!(vec![LispToken::String(String::new())], tokenize("\"\""));
+    }
+
+    #[test]
+    #[should_panic]
+    fn unterminated_string_literal() {
+        tokenize("\"never closed");
+    }
+
+    #[test]
+    fn malformed_strings_have_spans() {
+        let err = try_tokenize_with_spans("(f\n  \"never closed)").unwrap_err();
+        assert_eq!("unterminated string literal", err.message);
+        assert_eq!(Some(Span { line: 2, column: 3, end_line: 2, end_column: 4 }), err.span());
+
+        let err = try_tokenize_with_spans("\"a\\qb\"").unwrap_err();
+        assert_eq!("unknown escape sequence in string literal: \\q", err.message);
+        assert_eq!(Some(Span { line: 1, column: 3, end_line: 1, end_column: 5 }), err.span());
+    }
+
+    #[test]
+    fn token_spans() {
+        let (tokens, spans) = tokenize_with_spans("(car\n  \"a b\") ; done\nx");
+
+        assert_eq!(5, tokens.len());
+        assert_eq!(Span { line: 1, column: 1, end_line: 1, end_column: 2 }, spans[0]);
+        assert_eq!(Span { line: 1, column: 2, end_line: 1, end_column: 5 }, spans[1]);
+        assert_eq!(Span { line: 2, column: 3, end_line: 2, end_column: 8 }, spans[2]);
+        assert_eq!(Span { line: 2, column: 8, end_line: 2, end_column: 9 }, spans[3]);
+        assert_eq!(Span { line: 3, column: 1, end_line: 3, end_column: 2 }, spans[4]);
+    }
+}
@@ -0,0 +1,457 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::analyze::{Node, Reference};
+use crate::convert::Rest;
+use crate::evaluate::LispOutput;
+use crate::functions::{Arity, BuiltInFunction, LispFunction};
+use crate::lisp_error::{LispError, LispResult};
+use crate::lisp_expression::{LispExpression, SpanTree};
+
+pub mod check;
+
+
+/// Separates a name from its type in annotations, `[x : Int]`, and starts
+/// declarations, `(: name Type)`.
+pub const ANNOTATION: &str = ":";
+
+const OPTIONAL_MARKER: &str = "#!optional";
+const REST_MARKER: &str = "#!rest";
+
+/// The types of the optional annotations checked by `lisp check`:
+///
+/// - `Int`, `Bool`, `String`, `Symbol`, `Keyword` and `Void`, the type of what
+///   `display` and the like return;
+/// - `(Listof Type)`, or `(List Type)`, lists whose elements all have the
+///   type;
+/// - `(-> Parameter ... Result)`, procedures, where the parameters may have
+///   `#!optional` and `#!rest` sections as in parameter lists;
+/// - `(U Type ...)`, the values of any of the types;
+/// - `Any`, which is what code without annotations has, and agrees with every
+///   other type.
+///
+/// There are no vector types, the language having no vectors: `(Vectorof
+/// Type)` is an error saying so, where other unknown types are only unknown.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    /// The type of no value at all, such as the elements of the empty list.
+    Nothing,
+    Int,
+    Bool,
+    String,
+    Symbol,
+    Keyword,
+    Void,
+    List(Box<Type>),
+    Function(Rc<FunctionType>),
+    /// Always of two or more types, none of them a union.
+    Union(Vec<Type>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionType {
+    pub required: Vec<Type>,
+    pub optional: Vec<Type>,
+    /// The type of each of the arguments past the others.
+    pub rest: Option<Type>,
+    pub result: Type,
+}
+
+impl FunctionType {
+    pub fn arity(&self) -> Arity {
+        let min = self.required.len();
+        return match self.rest {
+            Some(_) => Arity::at_least(min),
+            None => Arity::between(min, min + self.optional.len()),
+        };
+    }
+
+    /// The type of the argument at `index`, if there can be one there.
+    pub fn parameter(&self, index: usize) -> Option<&Type> {
+        return self.required.iter().chain(&self.optional).nth(index).or(self.rest.as_ref());
+    }
+}
+
+const NAMED_TYPES: [(&str, Type); 8] = [
+    ("Any", Type::Any),
+    ("Nothing", Type::Nothing),
+    ("Int", Type::Int),
+    ("Bool", Type::Bool),
+    ("String", Type::String),
+    ("Symbol", Type::Symbol),
+    ("Keyword", Type::Keyword),
+    ("Void", Type::Void),
+];
+
+/// Whether a procedure taking `arity` can be given any number of arguments
+/// that `expected` allows.
+fn covers(arity: Arity, expected: Arity) -> bool {
+    return arity.min <= expected.min && arity.max.is_none_or(|max| expected.max.is_some_and(|expected| expected <= max));
+}
+
+fn unknown(expression: &LispExpression) -> LispError {
+    return LispError::syntax(format!("unknown type {}", LispOutput::from_datum(expression)));
+}
+
+impl Type {
+    pub fn function(required: Vec<Type>, optional: Vec<Type>, rest: Option<Type>, result: Type) -> Self {
+        return Type::Function(Rc::new(FunctionType { required, optional, rest, result }));
+    }
+
+    pub fn list(element: Type) -> Self {
+        return Type::List(Box::new(element));
+    }
+
+    /// The type written as `expression`.
+    pub fn parse(expression: &LispExpression) -> LispResult<Self> {
+        let items = match expression {
+            LispExpression::Symbol(name) => {
+                return NAMED_TYPES.iter().find(|(named, _)| named == name).map(|(_, named)| named.clone()).ok_or_else(|| unknown(expression));
+            },
+            LispExpression::List(items) => items,
+            _ => return Err(unknown(expression)),
+        };
+        let Some(LispExpression::Symbol(constructor)) = items.first() else { return Err(unknown(expression)) };
+        return match (constructor.as_str(), &items[1..]) {
+            ("Listof" | "List", [element]) => Ok(Type::list(Type::parse(element)?)),
+            (list @ ("Listof" | "List"), _) => Err(LispError::syntax(format!("expecting {list} to be given the type of the elements"))),
+            (vector @ ("Vectorof" | "Vector"), _) => Err(LispError::syntax(format!("{vector} is not a type, there are no vectors, only lists of type (Listof Type)"))),
+            ("U", []) => Err(LispError::syntax("expecting U to be given at least one type")),
+            ("U", members) => Ok(Type::union(members.iter().map(Type::parse).collect::<LispResult<Vec<_>>>()?)),
+            ("->", []) => Err(LispError::syntax("expecting -> to be given the type of the result")),
+            ("->", [parameters @ .., result]) => Self::parse_function(parameters, result),
+            _ => Err(unknown(expression)),
+        };
+    }
+
+    fn parse_function(parameters: &[LispExpression], result: &LispExpression) -> LispResult<Self> {
+        let (mut required, mut optional, mut rest) = (Vec::new(), Vec::new(), None);
+        let mut section = None;
+        for parameter in parameters {
+            match parameter {
+                LispExpression::Symbol(marker) if marker == OPTIONAL_MARKER && section.is_none() => section = Some(OPTIONAL_MARKER),
+                LispExpression::Symbol(marker) if marker == REST_MARKER && section != Some(REST_MARKER) => section = Some(REST_MARKER),
+                _ => match section {
+                    None => required.push(Type::parse(parameter)?),
+                    Some(OPTIONAL_MARKER) => optional.push(Type::parse(parameter)?),
+                    _ if rest.is_none() => rest = Some(Type::parse(parameter)?),
+                    _ => return Err(LispError::syntax("expecting a single type after #!rest")),
+                },
+            }
+        }
+        if section == Some(REST_MARKER) && rest.is_none() {
+            return Err(LispError::syntax("expecting a type after #!rest"));
+        }
+        return Ok(Type::function(required, optional, rest, Type::parse(result)?));
+    }
+
+    /// The type of the values of any of `types`.
+    pub fn union(types: impl IntoIterator<Item = Type>) -> Self {
+        let mut members: Vec<Type> = Vec::new();
+        for member in types {
+            let flattened = match member {
+                Type::Union(inner) => inner,
+                Type::Any => return Type::Any,
+                Type::Nothing => continue,
+                member => vec![member],
+            };
+            for member in flattened {
+                if !members.contains(&member) {
+                    members.push(member);
+                }
+            }
+        }
+        return match members.len() {
+            0 => Type::Nothing,
+            1 => members.remove(0),
+            _ => Type::Union(members),
+        };
+    }
+
+    /// Whether a value of type `actual` can be used where one of this type
+    /// is expected. `Any` agrees with everything either way round, the type
+    /// checker leaving such values to the contracts.
+    pub fn accepts(&self, actual: &Type) -> bool {
+        return match (self, actual) {
+            (Type::Any, _) | (_, Type::Any) | (_, Type::Nothing) => true,
+            (_, Type::Union(members)) => members.iter().all(|member| self.accepts(member)),
+            (Type::Union(members), _) => members.iter().any(|member| member.accepts(actual)),
+            (Type::List(expected), Type::List(actual)) => expected.accepts(actual),
+            (Type::Function(expected), Type::Function(actual)) => {
+                // every argument the expected type allows must suit the actual one
+                let positions = expected.required.len() + expected.optional.len() + usize::from(expected.rest.is_some());
+                let parameters = (0..positions).all(|index| match (expected.parameter(index), actual.parameter(index)) {
+                    (Some(expected), Some(actual)) => actual.accepts(expected),
+                    _ => true,
+                });
+                covers(actual.arity(), expected.arity()) && parameters && expected.result.accepts(&actual.result)
+            },
+            (expected, actual) => expected == actual,
+        };
+    }
+
+    /// Whether `value` is of this type, as far as can be told without calling
+    /// it: procedures only need to take the arguments the type says.
+    pub fn admits(&self, value: &LispOutput) -> bool {
+        return match (self, value) {
+            (Type::Any, _) => true,
+            (Type::Int, LispOutput::Integer(_))
+                | (Type::Bool, LispOutput::Bool(_))
+                | (Type::String, LispOutput::String(_))
+                | (Type::Symbol, LispOutput::Symbol(_))
+                | (Type::Keyword, LispOutput::Keyword(_))
+                | (Type::Void, LispOutput::Void) => true,
+            (Type::List(element), LispOutput::List(list)) => list.iter().all(|value| element.admits(value)),
+            (Type::Function(function), LispOutput::Lambda(procedure)) => covers(procedure.arity(), function.arity()),
+            (Type::Union(members), value) => members.iter().any(|member| member.admits(value)),
+            _ => false,
+        };
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Nothing => write!(f, "Nothing"),
+            Type::Int => write!(f, "Int"),
+            Type::Bool => write!(f, "Bool"),
+            Type::String => write!(f, "String"),
+            Type::Symbol => write!(f, "Symbol"),
+            Type::Keyword => write!(f, "Keyword"),
+            Type::Void => write!(f, "Void"),
+            Type::List(element) => write!(f, "(Listof {element})"),
+            Type::Function(function) => {
+                write!(f, "(->")?;
+                for parameter in &function.required {
+                    write!(f, " {parameter}")?;
+                }
+                if !function.optional.is_empty() {
+                    write!(f, " {OPTIONAL_MARKER}")?;
+                    for parameter in &function.optional {
+                        write!(f, " {parameter}")?;
+                    }
+                }
+                if let Some(rest) = &function.rest {
+                    write!(f, " {REST_MARKER} {rest}")?;
+                }
+                write!(f, " {})", function.result)
+            },
+            Type::Union(members) => {
+                write!(f, "(U")?;
+                for member in members {
+                    write!(f, " {member}")?;
+                }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+
+// -------------- ANNOTATIONS --------------
+/// The parts of `[name : Type]` or `[name : Type default]`, an annotated
+/// parameter.
+pub fn annotated_parameter(parameter: &LispExpression) -> Option<(&LispExpression, &LispExpression, Option<&LispExpression>)> {
+    let LispExpression::List(parts) = parameter else { return None };
+    return match &parts[..] {
+        [name, LispExpression::Symbol(colon), annotation] if colon == ANNOTATION => Some((name, annotation, None)),
+        [name, LispExpression::Symbol(colon), annotation, default] if colon == ANNOTATION => Some((name, annotation, Some(default))),
+        _ => None,
+    };
+}
+
+/// A parameter list without its annotations, which become `(name default)`
+/// or just the name, and the types of the parameters that had one.
+pub fn split_parameters(parameters: &LispExpression) -> LispResult<(LispExpression, Vec<(String, Type)>)> {
+    let LispExpression::List(items) = parameters else { return Ok((parameters.clone(), Vec::new())) };
+    let mut contracts = Vec::new();
+    let mut plain = Vec::new();
+    for parameter in items {
+        let Some((name, annotation, default)) = annotated_parameter(parameter) else {
+            plain.push(parameter.clone());
+            continue;
+        };
+        let LispExpression::Symbol(variable) = name else {
+            return Err(LispError::syntax("expecting the annotated parameter to be a symbol"));
+        };
+        contracts.push((variable.clone(), Type::parse(annotation)?));
+        plain.push(match default {
+            Some(default) => LispExpression::List(vec![name.clone(), default.clone()]),
+            None => name.clone(),
+        });
+    }
+    return Ok((LispExpression::List(plain), contracts));
+}
+
+/// Where the body of `(define (name parameter ...) body)` or `(lambda
+/// parameters body)` starts: after the annotation of the result, if the form
+/// has one, `(lambda parameters : Type body)`.
+pub fn body_index(form: &[LispExpression]) -> usize {
+    let procedure = match form.first() {
+        Some(LispExpression::Symbol(head)) if head == "lambda" => true,
+        Some(LispExpression::Symbol(head)) if head == "define" => matches!(form.get(1), Some(LispExpression::List(_))),
+        _ => false,
+    };
+    let annotated = procedure && form.len() == 5 && form[2] == LispExpression::Symbol(ANNOTATION.to_string());
+    return if annotated { 4 } else { 2 };
+}
+
+/// A procedure form whose result was annotated, with the annotation taken out.
+pub struct Annotated {
+    pub form: Vec<LispExpression>,
+    pub spans: Option<SpanTree>,
+    pub result: Type,
+}
+
+/// The procedure form `form` without the annotation of its result, if it has
+/// one. `None` for anything else.
+pub fn split_result(form: &[LispExpression], spans: Option<&SpanTree>) -> LispResult<Option<Annotated>> {
+    if body_index(form) == 2 {
+        return Ok(None);
+    }
+    let result = Type::parse(&form[3])?;
+    fn without<T: Clone>(items: &[T]) -> Vec<T> {
+        return [0, 1, 4].iter().filter_map(|&index| items.get(index).cloned()).collect();
+    }
+    let spans = spans.map(|spans| SpanTree { span: spans.span, children: without(&spans.children) });
+    return Ok(Some(Annotated { form: without(form), spans, result }));
+}
+
+
+// -------------- CONTRACTS --------------
+/// `body` checking, before it runs, that the parameters with a type have a
+/// value of that type, and that its value has the type of the result, if
+/// there is one. Failed checks are `Type` errors naming the procedure.
+///
+/// The checks are made on every call, not only on calls from code without
+/// annotations: values of type `Any` pass between annotated procedures as
+/// well, and the checker leaves those to the contracts.
+///
+/// The body of a procedure with a result type is not in tail position, the
+/// result being checked once it returns: a loop written as a tail call to
+/// itself takes stack, and counts against `max_depth`, every time round.
+/// Annotating only the parameters keeps such loops in constant space.
+pub fn with_contracts(name: Option<&String>, parameters: Vec<(String, Type)>, result: Option<Type>, body: Rc<Node>) -> Rc<Node> {
+    let procedure = name.map_or("anonymous procedure".to_string(), |name| name.clone());
+    let body = match result {
+        Some(expected) => {
+            let message = format!("contract violation: {procedure} promised a result of type {expected}");
+            Rc::new(Node::Application(vec![contract(expected, message), body], None))
+        },
+        None => body,
+    };
+    if parameters.is_empty() {
+        return body;
+    }
+    let mut check = Vec::new();
+    let mut expectations = Vec::new();
+    for (parameter, expected) in parameters {
+        check.push(Rc::new(Node::Variable(parameter.clone(), Reference::Dynamic)));
+        expectations.push((expected.clone(), format!("contract violation: {procedure} expects {parameter} to be {expected}")));
+    }
+    check.insert(0, parameter_contracts(expectations));
+    // the check holds or fails, leaving the body in tail position
+    let check = Rc::new(Node::Application(check, None));
+    return Rc::new(Node::If(check, body, Rc::new(Node::Constant(LispOutput::Void))));
+}
+
+/// A procedure returning its argument if it is of type `expected`, failing
+/// with `message` otherwise.
+fn contract(expected: Type, message: String) -> Rc<Node> {
+    let check = move |value: LispOutput| -> LispResult {
+        if expected.admits(&value) {
+            return Ok(value);
+        }
+        return Err(LispError::type_mismatch(format!("{message}, got {value}")));
+    };
+    return Rc::new(Node::Constant(LispOutput::Lambda(LispFunction::BuiltInFunction(BuiltInFunction::native(check)))));
+}
+
+/// A procedure returning true if each of its arguments is of the type
+/// expected of it, failing with the message of the first that is not
+/// otherwise.
+fn parameter_contracts(expectations: Vec<(Type, String)>) -> Rc<Node> {
+    let check = move |values: Rest<LispOutput>| -> LispResult<bool> {
+        for ((expected, message), value) in expectations.iter().zip(values.0) {
+            if !expected.admits(&value) {
+                return Err(LispError::type_mismatch(format!("{message}, got {value}")));
+            }
+        }
+        return Ok(true);
+    };
+    return Rc::new(Node::Constant(LispOutput::Lambda(LispFunction::BuiltInFunction(BuiltInFunction::native(check)))));
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::tokenizer::tokenize;
+
+    fn parsed(source: &str) -> Type {
+        return Type::parse(&parse(&tokenize(source))).unwrap();
+    }
+
+    #[test]
+    fn parsing_and_printing() {
+        for source in ["Int", "(Listof (U Int String))", "(-> Int #!optional Bool #!rest String Void)", "(-> Any)"] {
+            assert_eq!(source, parsed(source).to_string());
+        }
+        assert_eq!(Type::Int, parsed("(U Int Int Nothing)"));
+        assert_eq!(Type::Any, parsed("(U Int Any)"));
+        assert_eq!("(U Int String Bool)", parsed("(U (U Int String) Bool)").to_string());
+        assert_eq!(parsed("(Listof Int)"), parsed("(List Int)"));
+        let err = Type::parse(&parse(&tokenize("(Vectorof Int)"))).unwrap_err();
+        assert_eq!("Vectorof is not a type, there are no vectors, only lists of type (Listof Type)", err.message);
+        for source in ["Integer", "(Listof)", "(List Int String)", "Listof", "(U)", "(->)", "(-> #!rest Int)", "(-> #!rest Int Int Int)", "1"] {
+            assert!(Type::parse(&parse(&tokenize(source))).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn acceptance() {
+        let accepts = |expected: &str, actual: &str| parsed(expected).accepts(&parsed(actual));
+        assert!(accepts("Int", "Int"));
+        assert!(!accepts("Int", "String"));
+        assert!(accepts("Int", "Any") && accepts("String", "Any") && accepts("Any", "Int"));
+        assert!(accepts("(U Int String)", "Int"));
+        assert!(!accepts("Int", "(U Int String)"));
+        assert!(accepts("(Listof (U Int String))", "(Listof Int)"));
+        assert!(accepts("(Listof Int)", "(Listof Nothing)"));
+        // procedures taking more kinds of arguments, and more of them, will do
+        assert!(accepts("(-> Int (U Int String))", "(-> (U Int String) Int)"));
+        assert!(!accepts("(-> (U Int String) Int)", "(-> Int Int)"));
+        assert!(accepts("(-> Int Int Int)", "(-> #!rest Int Int)"));
+        assert!(!accepts("(-> #!rest Int Int)", "(-> Int Int Int)"));
+        assert!(accepts("(-> Int Int)", "(-> Int #!optional Int Int)"));
+        assert!(!accepts("(-> Int Int)", "(-> Int Int Int)"));
+    }
+
+    #[test]
+    fn admitted_values() {
+        assert!(parsed("(Listof Int)").admits(&LispOutput::from_datum(&parse(&tokenize("(1 2)")))));
+        assert!(!parsed("(Listof Int)").admits(&LispOutput::from_datum(&parse(&tokenize("(1 a)")))));
+        assert!(parsed("(U Int Void)").admits(&LispOutput::Void));
+        assert!(!parsed("Bool").admits(&LispOutput::Integer(1)));
+    }
+
+    #[test]
+    fn annotations() {
+        let (plain, contracts) = split_parameters(&parse(&tokenize("(a [b : Int] #!optional [c : String \"c\"])"))).unwrap();
+        assert_eq!(parse(&tokenize("(a b #!optional (c \"c\"))")), plain);
+        assert_eq!(vec![("b".to_string(), Type::Int), ("c".to_string(), Type::String)], contracts);
+
+        let LispExpression::List(form) = parse(&tokenize("(define (f [x : Int]) : Int x)")) else { unreachable!() };
+        assert_eq!(4, body_index(&form));
+        let annotated = split_result(&form, None).unwrap().unwrap();
+        assert_eq!(parse(&tokenize("(define (f [x : Int]) x)")), LispExpression::List(annotated.form));
+        assert_eq!(Type::Int, annotated.result);
+        let LispExpression::List(form) = parse(&tokenize("(define f : Int)")) else { unreachable!() };
+        assert!(split_result(&form, None).unwrap().is_none());
+    }
+}
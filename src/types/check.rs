@@ -0,0 +1,638 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::analyze::analyze_with_spans;
+use crate::functions::{Parameters, KEYWORD_PREFIX};
+use crate::lisp_expression::{LispExpression, Span, SpanTree};
+use crate::parser::{parse, parse_all_with_spans};
+use crate::tokenizer::{tokenize, try_tokenize_with_spans};
+use crate::types::{self, FunctionType, Type, ANNOTATION};
+
+/// The types of the built-ins `check` knows about; the others are `Any`.
+/// Those building lists have their results worked out from their arguments.
+const BUILT_INS: &[(&str, &str)] = &[
+    ("#t", "Bool"),
+    ("#f", "Bool"),
+    ("nil", "(Listof Nothing)"),
+    ("+", "(-> #!rest Int Int)"),
+    ("*", "(-> #!rest Int Int)"),
+    ("-", "(-> Int #!rest Int Int)"),
+    ("/", "(-> Int Int #!rest Int Int)"),
+    ("<", "(-> #!rest Int Bool)"),
+    ("<=", "(-> #!rest Int Bool)"),
+    (">", "(-> #!rest Int Bool)"),
+    (">=", "(-> #!rest Int Bool)"),
+    ("equal?", "(-> #!rest Any Bool)"),
+    ("list", "(-> #!rest Any (Listof Any))"),
+    ("cons", "(-> Any (Listof Any) (Listof Any))"),
+    ("car", "(-> (Listof Any) Any)"),
+    ("cdr", "(-> (Listof Any) (Listof Any))"),
+    ("list?", "(-> Any Bool)"),
+    ("length", "(-> (Listof Any) Int)"),
+    ("list-ref", "(-> (Listof Any) Int Any)"),
+    ("append", "(-> #!rest (Listof Any) (Listof Any))"),
+    ("map", "(-> (Listof Any) (-> Any Any) (Listof Any))"),
+    ("filter", "(-> (Listof Any) (-> Any Any) (Listof Any))"),
+    ("begin", "(-> Any #!rest Any Any)"),
+    ("error", "(-> String #!rest Any Nothing)"),
+    ("display", "(-> Any #!optional Any Void)"),
+    ("write", "(-> Any #!optional Any Void)"),
+    ("newline", "(-> #!optional Any Void)"),
+];
+
+/// A mismatch between the types of a program, or a form `check` could not
+/// read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message);
+    }
+}
+
+/// Checks the annotated parts of `source` without evaluating it, returning
+/// the errors in the order they appear.
+///
+/// The checking is gradual: what has no annotation, and cannot be worked out
+/// from literals and the built-ins, is `Any`, which agrees with every type
+/// and is left to the contracts at run time. Variables declared with `(:
+/// name Type)` at the top level have that type, and so do procedures'
+/// unannotated parameters and results where the declaration gives them one.
+pub fn check(source: &str) -> Vec<TypeError> {
+    let parsed = try_tokenize_with_spans(source).and_then(|(tokens, spans)| parse_all_with_spans(&tokens, &spans));
+    let forms = match parsed {
+        Ok(forms) => forms,
+        Err(err) => {
+            let span = err.span().unwrap_or(Span { line: 1, column: 1, end_line: 1, end_column: 1 });
+            return vec![TypeError { span, message: err.message }];
+        },
+    };
+
+    let mut checker = Checker::new();
+    let mut analyzed = Vec::new();
+    for (form, spans) in &forms {
+        match analyze_with_spans(form, Some(spans)) {
+            Ok(_) => analyzed.push((form, spans)),
+            Err(err) => checker.error(err.span().unwrap_or(spans.span), err.message),
+        }
+    }
+    for (form, _) in &analyzed {
+        checker.find_assignments(form);
+        checker.declare(form);
+    }
+    // procedures may be called above their definitions
+    for (form, spans) in &analyzed {
+        if let LispExpression::List(items) = form {
+            checker.bind_procedure(items, spans);
+        }
+    }
+    for (form, spans) in &analyzed {
+        checker.infer(form, spans);
+    }
+
+    // the procedures defined at the top level have their signatures read twice
+    let mut errors = checker.errors;
+    errors.sort_by_key(|error| (error.span.line, error.span.column));
+    errors.dedup();
+    return errors;
+}
+
+fn head(items: &[LispExpression]) -> Option<&str> {
+    return match items.first() {
+        Some(LispExpression::Symbol(head)) => Some(head.as_str()),
+        _ => None,
+    };
+}
+
+/// The type of the elements of a list of type `list`.
+fn element(list: &Type) -> Type {
+    return match list {
+        Type::List(element) => element.as_ref().clone(),
+        Type::Nothing => Type::Nothing,
+        _ => Type::Any,
+    };
+}
+
+/// The type of `datum`, quoted.
+fn datum(quoted: &LispExpression) -> Type {
+    return match quoted {
+        LispExpression::Integer(_) => Type::Int,
+        LispExpression::String(_) => Type::String,
+        LispExpression::Symbol(keyword) if keyword.starts_with(KEYWORD_PREFIX) => Type::Keyword,
+        LispExpression::Symbol(_) => Type::Symbol,
+        LispExpression::List(items) => Type::list(Type::union(items.iter().map(datum))),
+    };
+}
+
+/// What `(name arguments ...)` returns when `name` is the built-in building
+/// a list, or `result` otherwise.
+fn built_in_result(name: &str, arguments: &[Type], result: Type) -> Type {
+    let argument = |index: usize| arguments.get(index).cloned().unwrap_or(Type::Any);
+    return match name {
+        "list" => Type::list(Type::union(arguments.iter().cloned())),
+        "cons" => Type::list(Type::union([argument(0), element(&argument(1))])),
+        "car" | "list-ref" => element(&argument(0)),
+        "cdr" | "filter" => Type::list(element(&argument(0))),
+        "append" => Type::list(Type::union(arguments.iter().map(element))),
+        "map" => match argument(1) {
+            Type::Function(function) => Type::list(function.result.clone()),
+            _ => Type::list(Type::Any),
+        },
+        "begin" => arguments.last().cloned().unwrap_or(Type::Any),
+        _ => result,
+    };
+}
+
+/// A procedure's type, and the types of the variables its parameters bind.
+struct Signature {
+    procedure: Type,
+    variables: Vec<(String, Type)>,
+    /// The defaults of the parameters, with the types they should have.
+    defaults: Vec<(Type, LispExpression, SpanTree)>,
+    result: Option<Type>,
+}
+
+struct Checker {
+    built_ins: HashMap<&'static str, Type>,
+    scopes: Vec<HashMap<String, Type>>,
+    /// The types given by `(: name Type)` at the top level.
+    declared: HashMap<String, Type>,
+    /// The variables some `set!` changes, which are only as narrow as their
+    /// annotations.
+    assigned: HashSet<String>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        let built_ins = BUILT_INS.iter()
+            .map(|(name, annotation)| (*name, Type::parse(&parse(&tokenize(annotation))).expect("the built-in types parse")))
+            .collect();
+        return Checker {
+            built_ins,
+            scopes: vec![HashMap::new()],
+            declared: HashMap::new(),
+            assigned: HashSet::new(),
+            errors: Vec::new(),
+        };
+    }
+
+    fn error(&mut self, span: Span, message: impl Into<String>) {
+        self.errors.push(TypeError { span, message: message.into() });
+    }
+
+    /// Reports `actual` at `span` unless `expected` accepts it.
+    fn expect(&mut self, expected: &Type, actual: &Type, span: Span) {
+        if !expected.accepts(actual) {
+            self.error(span, format!("expected {expected}, got {actual}"));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        if let Some(found) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return found.clone();
+        }
+        if let Some(declared) = self.declared.get(name) {
+            return declared.clone();
+        }
+        return self.built_ins.get(name).cloned().unwrap_or(Type::Any);
+    }
+
+    fn is_built_in(&self, name: &str) -> bool {
+        return self.built_ins.contains_key(name) && self.scopes.iter().all(|scope| !scope.contains_key(name));
+    }
+
+    /// The declared type of `name`, if it is being defined at the top level.
+    fn declaration(&self, name: &str) -> Option<Type> {
+        return if self.scopes.len() == 1 { self.declared.get(name).cloned() } else { None };
+    }
+
+    fn bind(&mut self, name: &str, inferred: Type) {
+        let bound = match self.declaration(name) {
+            Some(declared) => declared,
+            None if self.assigned.contains(name) => Type::Any,
+            None => inferred,
+        };
+        self.scopes.last_mut().expect("there is always a scope").insert(name.to_string(), bound);
+    }
+
+    fn find_assignments(&mut self, expression: &LispExpression) {
+        let LispExpression::List(items) = expression else { return };
+        if let (Some("set!"), Some(LispExpression::Symbol(name))) = (head(items), items.get(1)) {
+            self.assigned.insert(name.clone());
+        }
+        if head(items) != Some("quote") {
+            items.iter().for_each(|item| self.find_assignments(item));
+        }
+    }
+
+    fn declare(&mut self, form: &LispExpression) {
+        let LispExpression::List(items) = form else { return };
+        if let [LispExpression::Symbol(colon), LispExpression::Symbol(name), annotation] = &items[..] {
+            if colon == ANNOTATION {
+                if let Ok(declared) = Type::parse(annotation) {
+                    self.declared.insert(name.clone(), declared);
+                }
+            }
+        }
+    }
+
+    /// Binds the procedure `items` defines, if it is a definition of one, to
+    /// its type, returning the rest of what is needed to check its body.
+    fn bind_procedure(&mut self, items: &[LispExpression], spans: &SpanTree) -> Option<(Signature, Vec<LispExpression>, SpanTree)> {
+        if head(items) != Some("define") {
+            return None;
+        }
+        let (items, spans, result) = match types::split_result(items, Some(spans)) {
+            Ok(Some(types::Annotated { form, spans: Some(spans), result })) => (form, spans, Some(result)),
+            Ok(_) => (items.to_vec(), spans.clone(), None),
+            Err(_) => return None,
+        };
+        let (name, parameters, parameter_spans, body, body_spans) = match (items.get(1), items.get(2)) {
+            (Some(LispExpression::List(signature)), Some(body)) => {
+                let Some(LispExpression::Symbol(name)) = signature.first() else { return None };
+                let parameter_spans = SpanTree { span: spans.children[1].span, children: spans.children[1].children[1..].to_vec() };
+                (name, LispExpression::List(signature[1..].to_vec()), parameter_spans, body.clone(), spans.children[2].clone())
+            },
+            (Some(LispExpression::Symbol(name)), Some(LispExpression::List(lambda))) if head(lambda) == Some("lambda") => {
+                let (lambda, lambda_spans, result) = match types::split_result(lambda, Some(&spans.children[2])) {
+                    Ok(Some(types::Annotated { form, spans: Some(spans), result })) => (form, spans, Some(result)),
+                    Ok(_) => (lambda.clone(), spans.children[2].clone(), None),
+                    Err(_) => return None,
+                };
+                let [_, parameters, body] = &lambda[..] else { return None };
+                let signature = self.signature(Some(name), parameters, &lambda_spans.children[1], result, self.declaration(name));
+                self.bind(name, signature.procedure.clone());
+                return Some((signature, vec![body.clone()], lambda_spans.children[2].clone()));
+            },
+            _ => return None,
+        };
+        let signature = self.signature(Some(name), &parameters, &parameter_spans, result, self.declaration(name));
+        self.bind(name, signature.procedure.clone());
+        return Some((signature, vec![body], body_spans));
+    }
+
+    /// The signature of a procedure with `parameters`, where missing
+    /// annotations are filled in from its `declared` type.
+    fn signature(&mut self, name: Option<&String>, parameters: &LispExpression, spans: &SpanTree, result: Option<Type>, declared: Option<Type>) -> Signature {
+        let declared_function = match &declared {
+            Some(Type::Function(function)) => Some(function.clone()),
+            _ => None,
+        };
+        let result = result.or_else(|| declared_function.as_ref().map(|function| function.result.clone()));
+        let mut function = FunctionType { required: Vec::new(), optional: Vec::new(), rest: None, result: result.clone().unwrap_or(Type::Any) };
+        let mut variables = Vec::new();
+        let mut defaults = Vec::new();
+
+        let items = match parameters {
+            LispExpression::List(items) => items.clone(),
+            // (lambda arguments body) takes a list of all the arguments
+            rest => vec![LispExpression::Symbol(".".to_string()), rest.clone()],
+        };
+        let item_spans = match parameters {
+            LispExpression::List(_) => spans.children.clone(),
+            _ => vec![spans.clone(), spans.clone()],
+        };
+        let mut section = "";
+        let mut keywords = false;
+        for (parameter, parameter_spans) in items.iter().zip(&item_spans) {
+            if let LispExpression::Symbol(marker) = parameter {
+                if Parameters::is_marker(marker) {
+                    section = marker;
+                    keywords |= marker == "#!key";
+                    continue;
+                }
+            }
+            let (variable, annotation, default) = match parameter {
+                LispExpression::Symbol(variable) => (variable, None, None),
+                LispExpression::List(pair) => match (types::annotated_parameter(parameter), &pair[..]) {
+                    (Some((LispExpression::Symbol(variable), annotation, default)), _) => {
+                        let annotation = Type::parse(annotation).unwrap_or(Type::Any);
+                        (variable, Some(annotation), default.map(|default| (default, &parameter_spans.children[3])))
+                    },
+                    (None, [LispExpression::Symbol(variable), default]) => (variable, None, Some((default, &parameter_spans.children[1]))),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let position = function.required.len() + function.optional.len();
+            let fallback = declared_function.as_ref().and_then(|declared| match section {
+                "#!rest" | "." => declared.rest.clone().map(Type::list),
+                _ => declared.parameter(position).cloned(),
+            });
+            let parameter_type = annotation.or(fallback).unwrap_or(match section {
+                "#!rest" | "." => Type::list(Type::Any),
+                _ => Type::Any,
+            });
+            match section {
+                "" => function.required.push(parameter_type.clone()),
+                "#!optional" => function.optional.push(parameter_type.clone()),
+                "#!rest" | "." => function.rest = Some(element(&parameter_type)),
+                _ => {},
+            }
+            if let Some((default, default_spans)) = default {
+                defaults.push((parameter_type.clone(), default.clone(), default_spans.clone()));
+            }
+            variables.push((variable.clone(), parameter_type));
+        }
+
+        // keyword arguments are not described by procedure types
+        let procedure = if keywords { Type::Any } else { Type::Function(function.into()) };
+        if let (Some(declared), Some(name)) = (&declared, name) {
+            if !declared.accepts(&procedure) {
+                self.error(spans.span, format!("{name} is declared as {declared} but defined as {procedure}"));
+            }
+        }
+        return Signature { procedure, variables, defaults, result };
+    }
+
+    /// Checks the body of a procedure with `signature`, whose name is
+    /// `name`, if it has one.
+    fn check_body(&mut self, name: Option<&String>, signature: Signature, body: &[LispExpression], body_spans: &[SpanTree]) {
+        for (expected, default, spans) in &signature.defaults {
+            let actual = self.infer(default, spans);
+            self.expect(expected, &actual, spans.span);
+        }
+        self.scopes.push(HashMap::new());
+        for (variable, parameter_type) in &signature.variables {
+            self.scopes.last_mut().expect("there is always a scope").insert(variable.clone(), parameter_type.clone());
+        }
+        let mut actual = Type::Void;
+        for (expression, spans) in body.iter().zip(body_spans) {
+            actual = self.infer(expression, spans);
+        }
+        if let (Some(expected), Some(spans)) = (&signature.result, body_spans.last()) {
+            if !expected.accepts(&actual) {
+                let procedure = name.map_or("anonymous procedure".to_string(), |name| name.clone());
+                self.error(spans.span, format!("{procedure} promised a result of type {expected}, got {actual}"));
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn infer(&mut self, expression: &LispExpression, spans: &SpanTree) -> Type {
+        return match expression {
+            LispExpression::Integer(_) => Type::Int,
+            LispExpression::String(_) => Type::String,
+            LispExpression::Symbol(keyword) if keyword.starts_with(KEYWORD_PREFIX) => Type::Keyword,
+            LispExpression::Symbol(name) => self.lookup(name),
+            LispExpression::List(items) => self.infer_list(items, spans),
+        };
+    }
+
+    fn infer_list(&mut self, items: &[LispExpression], spans: &SpanTree) -> Type {
+        let children = &spans.children[..];
+        return match head(items) {
+            None if items.is_empty() => Type::Any,
+            Some("quote") => items.get(1).map_or(Type::Any, datum),
+            Some("define") => {
+                if let Some((signature, body, body_spans)) = self.bind_procedure(items, spans) {
+                    let name = match &items[1] {
+                        LispExpression::List(signature) => head(signature).map(str::to_string),
+                        LispExpression::Symbol(name) => Some(name.clone()),
+                        _ => None,
+                    };
+                    self.check_body(name.as_ref(), signature, &body, std::slice::from_ref(&body_spans));
+                } else if let (Some(LispExpression::Symbol(name)), Some(value)) = (items.get(1), items.get(2)) {
+                    let actual = self.infer(value, &children[2]);
+                    if let Some(declared) = self.declaration(name) {
+                        self.expect(&declared, &actual, children[2].span);
+                    }
+                    self.bind(name, actual);
+                }
+                Type::Void
+            },
+            Some("lambda") => {
+                let (lambda, lambda_spans, result) = match types::split_result(items, Some(spans)) {
+                    Ok(Some(types::Annotated { form, spans: Some(spans), result })) => (form, spans, Some(result)),
+                    _ => (items.to_vec(), spans.clone(), None),
+                };
+                let [_, parameters, body] = &lambda[..] else { return Type::Any };
+                let signature = self.signature(None, parameters, &lambda_spans.children[1], result, None);
+                let procedure = signature.procedure.clone();
+                self.check_body(None, signature, std::slice::from_ref(body), &lambda_spans.children[2..]);
+                procedure
+            },
+            Some("if") => {
+                self.infer(&items[1], &children[1]);
+                let consequent = self.infer(&items[2], &children[2]);
+                let alternative = self.infer(&items[3], &children[3]);
+                Type::union([consequent, alternative])
+            },
+            Some(form @ ("and" | "or")) => {
+                let types: Vec<Type> = items[1..].iter().zip(&children[1..]).map(|(item, spans)| self.infer(item, spans)).collect();
+                match (form, types.last()) {
+                    (_, None) => Type::Bool,
+                    ("and", Some(last)) => Type::union([Type::Bool, last.clone()]),
+                    _ => Type::union(types),
+                }
+            },
+            Some("set!") => {
+                let actual = self.infer(&items[2], &children[2]);
+                if let LispExpression::Symbol(name) = &items[1] {
+                    let known = self.lookup(name);
+                    self.expect(&known, &actual, children[2].span);
+                }
+                Type::Void
+            },
+            Some("let") if matches!(items.get(1), Some(LispExpression::Symbol(_))) => {
+                let LispExpression::Symbol(name) = &items[1] else { return Type::Any };
+                let pairs = bindings(&items[2], &children[2]);
+                for (_, init, init_spans) in &pairs {
+                    self.infer(init, init_spans);
+                }
+                self.scopes.push(HashMap::new());
+                let procedure = Type::function(vec![Type::Any; pairs.len()], Vec::new(), None, Type::Any);
+                self.bind(name, procedure);
+                for (variable, _, _) in &pairs {
+                    self.bind(variable, Type::Any);
+                }
+                self.infer(&items[3], &children[3]);
+                self.scopes.pop();
+                Type::Any
+            },
+            Some(form @ ("let" | "let*" | "letrec" | "letrec*")) => {
+                let pairs = bindings(&items[1], &children[1]);
+                let inits: Vec<Type> = match form {
+                    "let" => pairs.iter().map(|(_, init, init_spans)| self.infer(init, init_spans)).collect(),
+                    _ => Vec::new(),
+                };
+                self.scopes.push(HashMap::new());
+                if form.starts_with("letrec") {
+                    for (variable, _, _) in &pairs {
+                        self.bind(variable, Type::Any);
+                    }
+                }
+                for (index, (variable, init, init_spans)) in pairs.iter().enumerate() {
+                    let init = match inits.get(index) {
+                        Some(init) => init.clone(),
+                        None => self.infer(init, init_spans),
+                    };
+                    self.bind(variable, init);
+                }
+                let body = self.infer(&items[2], &children[2]);
+                self.scopes.pop();
+                body
+            },
+            Some("guard") => {
+                let body = self.infer(&items[2], &children[2]);
+                let mut results = vec![body];
+                if let LispExpression::List(spec) = &items[1] {
+                    self.scopes.push(HashMap::new());
+                    if let Some(LispExpression::Symbol(variable)) = spec.first() {
+                        self.bind(variable, Type::Any);
+                    }
+                    for (clause, clause_spans) in spec.iter().zip(&children[1].children).skip(1) {
+                        let LispExpression::List(clause) = clause else { continue };
+                        if clause.first() != Some(&LispExpression::Symbol("else".to_string())) {
+                            self.infer(&clause[0], &clause_spans.children[0]);
+                        }
+                        results.push(self.infer(&clause[1], &clause_spans.children[1]));
+                    }
+                    self.scopes.pop();
+                }
+                Type::union(results)
+            },
+            Some(":" | "del") => Type::Void,
+            Some(form) if crate::lsp::document::SPECIAL_FORMS.contains(&form) => Type::Any,
+            _ => self.infer_application(items, spans),
+        };
+    }
+
+    fn infer_application(&mut self, items: &[LispExpression], spans: &SpanTree) -> Type {
+        let children = &spans.children[..];
+        let operator = self.infer(&items[0], &children[0]);
+        let arguments: Vec<Type> = items[1..].iter().zip(&children[1..]).map(|(item, spans)| self.infer(item, spans)).collect();
+        let function = match &operator {
+            Type::Function(function) => function.clone(),
+            Type::Any | Type::Nothing => return Type::Any,
+            Type::Union(members) if members.iter().any(|member| matches!(member, Type::Function(_) | Type::Any)) => return Type::Any,
+            _ => {
+                self.error(children[0].span, format!("{operator} is not a procedure"));
+                return Type::Any;
+            },
+        };
+
+        let keywords = items[1..].iter().any(|item| matches!(item, LispExpression::Symbol(keyword) if keyword.starts_with(KEYWORD_PREFIX)));
+        if !keywords {
+            let arity = function.arity();
+            if !arity.accepts(arguments.len()) {
+                let name = match &items[0] {
+                    LispExpression::Symbol(name) => name.clone(),
+                    _ => "the procedure".to_string(),
+                };
+                self.error(spans.span, format!("{name} expects {arity}, got {}", arguments.len()));
+            }
+            for (index, (actual, spans)) in arguments.iter().zip(&children[1..]).enumerate() {
+                if let Some(expected) = function.parameter(index) {
+                    self.expect(expected, actual, spans.span);
+                }
+            }
+        }
+        return match &items[0] {
+            LispExpression::Symbol(name) if self.is_built_in(name) => built_in_result(name, &arguments, function.result.clone()),
+            _ => function.result.clone(),
+        };
+    }
+}
+
+/// The variables, initial values and their spans of the bindings of a let
+/// form.
+fn bindings<'a>(bindings: &'a LispExpression, spans: &'a SpanTree) -> Vec<(&'a String, &'a LispExpression, &'a SpanTree)> {
+    let LispExpression::List(pairs) = bindings else { return Vec::new() };
+    return pairs.iter().zip(&spans.children)
+        .filter_map(|(pair, pair_spans)| match pair {
+            LispExpression::List(pair) => match &pair[..] {
+                [LispExpression::Symbol(variable), init] => Some((variable, init, &pair_spans.children[1])),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+}
+
+
+// ============== TESTS ===============
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<String> {
+        return check(source).iter().map(|error| error.to_string()).collect();
+    }
+
+    #[test]
+    fn unannotated_code_passes() {
+        let source = "
+            (define (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))
+            (define total 0)
+            (set! total \"changed\")
+            (let loop ((i 0)) (if (< i 3) (loop (+ i 1)) i))
+            (map (list 1 2) (lambda (x) (+ x 1)))
+        ";
+        assert_eq!(Vec::<String>::new(), messages(source));
+    }
+
+    #[test]
+    fn arguments_and_results() {
+        let source = "(define (add [x : Int] [y : Int]) : Int (+ x y))
+(add 1 \"2\")
+(define (greet [name : String]) : Int name)
+(add 1)
+(+ 1 (car (list \"a\" \"b\")))
+(1 2)";
+        assert_eq!(vec![
+            "2:8: expected Int, got String",
+            "3:39: greet promised a result of type Int, got String",
+            "4:1: add expects 2 arguments, got 1",
+            "5:6: expected Int, got String",
+            "6:2: Int is not a procedure",
+        ], messages(source));
+    }
+
+    #[test]
+    fn declarations() {
+        let source = "(: limit Int)
+(define limit \"ten\")
+(: twice (-> Int Int))
+(define (twice x) (* 2 x))
+(twice #t)
+(: pair (-> Int Int Int))
+(define (pair a) a)
+(: count Int)
+(set! count (quote many))";
+        assert_eq!(vec![
+            "2:15: expected Int, got String",
+            "5:8: expected Int, got Bool",
+            "7:9: pair is declared as (-> Int Int Int) but defined as (-> Int Int)",
+            "9:13: expected Int, got Symbol",
+        ], messages(source));
+    }
+
+    #[test]
+    fn optional_rest_and_procedures() {
+        let source = "(define (f #!optional [s : String 1] #!rest [ns : (Listof Int)]) : Int (car ns))
+(f \"a\" 1 \"b\")
+(define (apply-to [g : (-> Int Int)] [x : Int]) : Int (g x))
+(apply-to (lambda ([s : String]) : Int 1) 2)
+(apply-to (lambda (x) x) 2)";
+        assert_eq!(vec![
+            "1:35: expected String, got Int",
+            "2:10: expected Int, got String",
+            "4:11: expected (-> Int Int), got (-> String Int)",
+        ], messages(source));
+    }
+
+    #[test]
+    fn syntax_and_annotation_errors() {
+        assert_eq!(vec!["1:1: missing right parenthesis while trying to parse expression"], messages("(define (f x) x"));
+        let errors = check("(define (f [x : Integer]) x)");
+        assert_eq!(1, errors.len());
+        assert!(errors[0].message.contains("unknown type Integer"), "{}", errors[0]);
+    }
+}
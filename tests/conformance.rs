@@ -0,0 +1,195 @@
+//! Runs the golden files in `tests/conformance`. Every top-level form of a
+//! file is evaluated in order by an `Interpreter`, with both engines, and
+//! checked against the annotation in the comment that follows it, if any:
+//!
+//! ```lisp
+//! (+ 1 2 3)        ; => 6
+//! (car nil)        ; error: type
+//! (define x 1)
+//! ```
+//!
+//! `; => ` is followed by the printed value of the form and `; error: ` by the
+//! kind of error it must fail with. A form without an annotation must not fail.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lisp::lisp_expression::{LispExpression, Span};
+use lisp::parser::parse_all_with_spans;
+use lisp::tokenizer::try_tokenize_with_spans;
+use lisp::{Interpreter, LispResult};
+
+
+/// The engines, by name, and whether the interpreter compiles for them.
+const ENGINES: [(&str, bool); 2] = [("evaluate", false), ("evaluate_compiled", true)];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expected {
+    Value(String),
+    Error(String),
+}
+
+/// An annotation comment, found at `line` and `column` of its file.
+#[derive(Debug)]
+struct Annotation {
+    line: usize,
+    column: usize,
+    expected: Expected,
+}
+
+/// The annotations of `source`, in order. Semicolons inside strings do not
+/// start comments.
+fn annotations(source: &str) -> Vec<Annotation> {
+    let mut annotations = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let mut in_string = false;
+        let mut escaped = false;
+        let comment = text.char_indices().find(|(_, c)| {
+            match (in_string, escaped, c) {
+                (true, true, _) => escaped = false,
+                (true, false, '\\') => escaped = true,
+                (_, false, '"') => in_string = !in_string,
+                (false, _, ';') => return true,
+                _ => {},
+            }
+            return false;
+        });
+        let Some((offset, _)) = comment else { continue };
+        let comment = text[offset..].trim_start_matches(';').trim();
+        let expected = if let Some(value) = comment.strip_prefix("=>") {
+            Expected::Value(value.trim().to_string())
+        } else if let Some(kind) = comment.strip_prefix("error:") {
+            Expected::Error(kind.trim().to_string())
+        } else {
+            continue;
+        };
+        let column = text[..offset].chars().count() + 1;
+        annotations.push(Annotation { line: index + 1, column, expected });
+    }
+    return annotations;
+}
+
+/// Pairs each form with the annotation that follows it, before the next form
+/// starts.
+fn annotate(forms: &[(LispExpression, Span)], annotations: Vec<Annotation>) -> Result<Vec<Option<Expected>>, String> {
+    let mut expected = vec![None; forms.len()];
+    for annotation in annotations {
+        let at = (annotation.line, annotation.column);
+        let form = forms.iter().rposition(|(_, span)| (span.end_line, span.end_column) <= at);
+        let Some(form) = form else {
+            return Err(format!("line {}: annotation before any form", annotation.line));
+        };
+        if expected[form].is_some() {
+            return Err(format!("line {}: second annotation of the form on line {}", annotation.line, forms[form].1.line));
+        }
+        expected[form] = Some(annotation.expected);
+    }
+    return Ok(expected);
+}
+
+/// The top-level forms of `source` with their spans.
+fn forms(source: &str) -> LispResult<Vec<(LispExpression, Span)>> {
+    let (tokens, spans) = try_tokenize_with_spans(source)?;
+    let forms = parse_all_with_spans(&tokens, &spans)?;
+    return Ok(forms.into_iter().map(|(form, spans)| (form, spans.span)).collect());
+}
+
+/// The text of `source` that `span` covers.
+fn text(lines: &[Vec<char>], span: &Span) -> String {
+    let mut text = String::new();
+    for line in span.line..=span.end_line {
+        let chars = &lines[line - 1];
+        let start = if line == span.line { span.column - 1 } else { 0 };
+        let end = if line == span.end_line { span.end_column - 1 } else { chars.len() };
+        text.extend(&chars[start..end]);
+        if line != span.end_line {
+            text.push('\n');
+        }
+    }
+    return text;
+}
+
+/// Evaluates the golden file at `path` with every engine, returning a line for
+/// each form that did not do what its annotation says.
+fn run_file(path: &Path) -> Vec<String> {
+    let source = fs::read_to_string(path).unwrap();
+    let forms = match forms(&source) {
+        Ok(forms) => forms,
+        Err(err) => return vec![format!("{}: {err}", path.display())],
+    };
+    let annotations = annotations(&source);
+    if annotations.is_empty() {
+        return vec![format!("{}: no annotations", path.display())];
+    }
+    let expected = match annotate(&forms, annotations) {
+        Ok(expected) => expected,
+        Err(message) => return vec![format!("{}: {message}", path.display())],
+    };
+
+    let lines: Vec<Vec<char>> = source.lines().map(|line| line.chars().collect()).collect();
+    let mut failures = Vec::new();
+    for (engine_name, compiled) in ENGINES {
+        let mut interpreter = Interpreter::new().compiled(compiled);
+        for ((_, span), expected) in forms.iter().zip(&expected) {
+            let result = interpreter.eval_str(&text(&lines, span));
+            let mismatch = match (expected, &result) {
+                (None, Ok(_)) => None,
+                (None, Err(err)) => Some(format!("unexpected {err}")),
+                (Some(Expected::Value(value)), Ok(actual)) if *value == actual.to_string() => None,
+                (Some(Expected::Value(value)), Ok(actual)) => Some(format!("expected {value}, got {actual}")),
+                (Some(Expected::Value(value)), Err(err)) => Some(format!("expected {value}, got {err}")),
+                (Some(Expected::Error(kind)), Err(err)) if kind == err.kind.name() => None,
+                (Some(Expected::Error(kind)), Err(err)) => Some(format!("expected an error of kind {kind}, got {err}")),
+                (Some(Expected::Error(kind)), Ok(actual)) => Some(format!("expected an error of kind {kind}, got {actual}")),
+            };
+            if let Some(mismatch) = mismatch {
+                failures.push(format!("{}:{} ({engine_name}): {mismatch}", path.display(), span.line));
+            }
+        }
+    }
+    return failures;
+}
+
+fn golden_files() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("conformance");
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lisp"))
+        .collect();
+    paths.sort();
+    return paths;
+}
+
+#[test]
+fn golden_files_conform() {
+    let paths = golden_files();
+    assert!(!paths.is_empty(), "no golden files found");
+    let failures: Vec<String> = paths.iter().flat_map(|path| run_file(path)).collect();
+    let mut report = String::new();
+    for failure in &failures {
+        let _ = writeln!(report, "{failure}");
+    }
+    assert!(failures.is_empty(), "{} golden file checks failed:\n{report}", failures.len());
+}
+
+#[test]
+fn annotations_follow_their_forms() {
+    let source = "(define s \"a ; => 1\")\n(+ 1 2) ; => 3\n(car nil)\n;; error: type\n(list 1 2)";
+    let expected = annotate(&forms(source).unwrap(), annotations(source)).unwrap();
+    assert_eq!(
+        vec![None, Some(Expected::Value("3".to_string())), Some(Expected::Error("type".to_string())), None],
+        expected,
+    );
+    for source in ["; => 1\n(+ 1 0)", "1 ; => 1\n; => 2"] {
+        assert!(annotate(&forms(source).unwrap(), annotations(source)).is_err());
+    }
+}
+
+#[test]
+fn forms_are_cut_from_their_source() {
+    let source = "(define (f x)\n  (+ x 1)) (f 1) ; => 2\n\"a ; b\"";
+    let lines: Vec<Vec<char>> = source.lines().map(|line| line.chars().collect()).collect();
+    let texts: Vec<String> = forms(source).unwrap().iter().map(|(_, span)| text(&lines, span)).collect();
+    assert_eq!(vec!["(define (f x)\n  (+ x 1))", "(f 1)", "\"a ; b\""], texts);
+}
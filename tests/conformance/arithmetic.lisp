@@ -0,0 +1,14 @@
+;; Integer arithmetic and comparisons.
+
+(+ 1 2 3)               ; => 6
+(+)                     ; => 0
+(- 10 4 3)              ; => 3
+(* 2 3 4)               ; => 24
+(/ 20 2 5)              ; => 2
+(/ 7 2)                 ; => 3
+(/ 1 0)                 ; error: division-by-zero
+(+ 1 "two")             ; error: type
+(< 1 2 3)               ; => #t
+(< 1 3 2)               ; => #f
+(>= 3 3 1)              ; => #t
+(equal? 2 (+ 1 1))      ; => #t
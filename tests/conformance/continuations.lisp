@@ -0,0 +1,13 @@
+;; First-class continuations and dynamic-wind.
+
+(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))              ; => 3
+(call/ec (lambda (escape) (begin (escape 'out) 'in)))   ; => out
+
+(define trail nil)
+(define (note x) (set! trail (cons x trail)))
+(call/cc (lambda (k)
+  (dynamic-wind
+    (lambda () (note 'before))
+    (lambda () (k 'escaped))
+    (lambda () (note 'after)))))                        ; => escaped
+(reverse trail)                                         ; => (before after)
@@ -0,0 +1,17 @@
+;; Raising and handling errors.
+
+(error "something broke" 1 2)                           ; error: user
+(raise 'oops)                                           ; error: raise
+(guard (e (#t (error-object-message e)))
+  (error "caught" 1))                                   ; => "caught"
+(guard (e (#t (error-object-irritants e)))
+  (error "caught" 1 2))                                 ; => (1 2)
+(guard (e ((equal? e 'oops) "symbol"))
+  (raise 'oops))                                        ; => "symbol"
+(guard (e ((equal? e 'other) "no"))
+  (raise 'oops))                                        ; error: raise
+(guard (e (#t (error-object-kind e)))
+  (car nil))                                            ; => type
+(with-exception-handler
+  (lambda (e) 10)
+  (lambda () (+ 1 (raise-continuable 'more))))          ; => 11
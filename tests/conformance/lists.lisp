@@ -0,0 +1,27 @@
+;; Lists and the procedures over them.
+
+(list 1 2 3)                            ; => (1 2 3)
+(list)                                  ; => ()
+(cons 1 (list 2 3))                     ; => (1 2 3)
+(car (list 1 2 3))                      ; => 1
+(cdr (list 1 2 3))                      ; => (2 3)
+(car nil)                               ; error: type
+(length (list 1 2 3))                   ; => 3
+(list-ref (list 1 2 3) 1)               ; => 2
+(list-ref (list 1 2 3) 3)               ; error: index-out-of-bounds
+(append (list 1 2) (list 3) nil)        ; => (1 2 3)
+(list? (list 1))                        ; => #t
+(list? 1)                               ; => #f
+'(1 (2 "three") four)                   ; => (1 (2 "three") four)
+
+(map (list 1 2 3) (lambda (x) (* x x)))             ; => (1 4 9)
+(filter (list 1 2 3 4) (lambda (x) (> x 2)))        ; => (3 4)
+(reduce (list 1 2 3 4) + 0)                         ; => 10
+(equal? (list 1 (list 2)) (list 1 (list 2)))        ; => #t
+
+;; from the prelude
+(reverse (list 1 2 3))                  ; => (3 2 1)
+(fold-left (list 1 2 3) - 10)           ; => 4
+(range 1 4)                             ; => (1 2 3)
+(zip (list 1 2) (list 3 4))             ; => ((1 3) (2 4))
+(flatten (list 1 (list 2 (list 3))))    ; => (1 2 3)
@@ -0,0 +1,24 @@
+;; Closures, recursion and higher-order procedures.
+
+(define (make-counter)
+  (let ((count 0))
+    (lambda () (begin (set! count (+ count 1)) count))))
+(define counter (make-counter))
+(counter)                               ; => 1
+(counter)                               ; => 2
+((make-counter))                        ; => 1
+
+(define (fact n) (if (< n 2) 1 (* n (fact (- n 1)))))
+(fact 10)                               ; => 3628800
+
+;; tail calls do not grow the stack
+(define (count-down n) (if (equal? n 0) 'done (count-down (- n 1))))
+(count-down 100000)                     ; => done
+
+(apply + (list 1 2 3))                  ; => 6
+(procedure-arity car)                   ; => (1 1)
+((compose (lambda (x) (* x 2)) (lambda (x) (+ x 1))) 5)
+                                        ; => 12
+(begin 1 2 3)                           ; => 3
+(eval '(+ 1 2) (interaction-environment))
+                                        ; => 3
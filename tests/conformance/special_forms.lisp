@@ -0,0 +1,38 @@
+;; The special forms.
+
+(define x 10)
+x                                       ; => 10
+(define (square n) (* n n))
+(square 7)                              ; => 49
+(set! x 11)
+x                                       ; => 11
+(set! undefined-variable 1)             ; error: unbound-variable
+undefined-variable                      ; error: unbound-variable
+
+(if (< 1 2) "yes" "no")                 ; => "yes"
+(if 0 "yes" "no")                       ; => "no"
+(if #t 1)                               ; error: syntax
+
+(and)                                   ; => #t
+(and #t (< 1 2))                        ; => #t
+(and #f (car nil))                      ; => #f
+(or)                                    ; => #f
+(or #f (< 2 1))                         ; => #f
+
+(let ((a 1) (b 2)) (+ a b))             ; => 3
+(let ((a 1) (a 2)) a)                   ; error: syntax
+(let* ((a 1) (b (+ a 1))) (* a b))      ; => 2
+(let loop ((n 5) (acc 1))
+  (if (< n 2) acc (loop (- n 1) (* acc n))))
+                                        ; => 120
+
+(quote (a b))                           ; => (a b)
+'sym                                    ; => sym
+((lambda (a b) (list b a)) 1 2)         ; => (2 1)
+((lambda (a . rest) rest) 1 2 3)        ; => (2 3)
+((lambda (a) a))                        ; error: arity
+(lambda)                                ; error: syntax
+
+(define y 1)
+(del y)
+y                                       ; error: unbound-variable
@@ -0,0 +1,121 @@
+//! Replays the recorded sessions in `tests/dap` against the debug adapter,
+//! which debugs the programs kept next to them. Sessions are written as in
+//! `tests/lsp`, one message per line after `-->` for what the editor sends
+//! and `<--` for what the adapter must answer, with `${dir}` standing for
+//! the directory the sessions are in:
+//!
+//! ```text
+//! # a comment
+//! --> {"seq":1,"type":"request","command":"launch","arguments":{"program":"${dir}/squares.lisp"}}
+//! <-- {"type":"response","request_seq":1,"command":"launch","success":true,"seq":1}
+//! ```
+
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use lisp::dap::serve;
+use lisp::json::{read_message, write_message, Json};
+
+
+/// The adapter's output, kept around after the adapter is done with it.
+#[derive(Default, Clone)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.0.borrow_mut().write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+/// The messages of a session: what the editor sends, and what the adapter
+/// must answer with the line it is recorded on.
+struct Session {
+    sent: Vec<Json>,
+    expected: Vec<(usize, Json)>,
+}
+
+fn parse_session(session: &str) -> Result<Session, String> {
+    let mut sent = Vec::new();
+    let mut expected = Vec::new();
+    for (index, line) in session.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (marker, text) = line.split_at(line.len().min(3));
+        let message = Json::parse(text).map_err(|err| format!("line {}: {err}", index + 1))?;
+        match marker {
+            "-->" => sent.push(message),
+            "<--" => expected.push((index + 1, message)),
+            _ => return Err(format!("line {}: expected --> or <--", index + 1)),
+        }
+    }
+    return Ok(Session { sent, expected });
+}
+
+fn directory() -> PathBuf {
+    return Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("dap");
+}
+
+/// Replays the session at `path`, returning a line for each message the
+/// adapter did not send as recorded.
+fn run_session(path: &Path) -> Vec<String> {
+    // the directory as it is spelled inside a JSON string
+    let quoted = Json::from(directory().display().to_string().as_str()).to_string();
+    let session = fs::read_to_string(path).unwrap().replace("${dir}", &quoted[1..quoted.len() - 1]);
+    let Session { sent, expected } = match parse_session(&session) {
+        Ok(session) => session,
+        Err(message) => return vec![format!("{}: {message}", path.display())],
+    };
+
+    let mut input = Vec::new();
+    for message in &sent {
+        write_message(&mut input, message).unwrap();
+    }
+    let output = Shared::default();
+    if let Err(err) = serve(Cursor::new(input), output.clone()) {
+        return vec![format!("{}: {err}", path.display())];
+    }
+    let mut received = Cursor::new(output.0.take());
+    let mut failures = Vec::new();
+    for (line, expected) in expected {
+        match read_message(&mut received).unwrap() {
+            Some(actual) if actual == expected => {},
+            Some(actual) => failures.push(format!("{}:{line}: expected {expected}, got {actual}", path.display())),
+            None => failures.push(format!("{}:{line}: expected {expected}, got nothing", path.display())),
+        }
+    }
+    while let Some(extra) = read_message(&mut received).unwrap() {
+        failures.push(format!("{}: unexpected {extra}", path.display()));
+    }
+    return failures;
+}
+
+fn sessions() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "session"))
+        .collect();
+    paths.sort();
+    return paths;
+}
+
+#[test]
+fn recorded_sessions_replay() {
+    let paths = sessions();
+    assert!(!paths.is_empty(), "no sessions found");
+    let failures: Vec<String> = paths.iter().flat_map(|path| run_session(path)).collect();
+    let mut report = String::new();
+    for failure in &failures {
+        let _ = writeln!(report, "{failure}");
+    }
+    assert!(failures.is_empty(), "{} messages differ:\n{report}", failures.len());
+}
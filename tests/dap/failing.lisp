@@ -0,0 +1,2 @@
+(display "hi")
+(car (quote ()))
@@ -0,0 +1,7 @@
+(define (square x) (* x x))
+(define numbers (list 1 2 3))
+(define add5 (let ((n 5)) (lambda (x) (+ x n))))
+(display (square 4))
+(newline)
+(define total (+ (square 2) (length numbers)))
+(display (add5 total))
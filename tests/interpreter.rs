@@ -0,0 +1,276 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lisp::port::{FileAccess, PortRef};
+use lisp::{Arity, Capabilities, Capability, EvalLimits, Interpreter, LispErrorKind, LispList, LispOutput};
+
+
+fn integer_list(items: &[i64]) -> LispOutput {
+    return LispOutput::List(Box::new(LispList::build(items.iter().map(|item| LispOutput::Integer(*item)))));
+}
+
+fn interpreters() -> [Interpreter; 2] {
+    return [Interpreter::new(), Interpreter::new().compiled(true)];
+}
+
+#[test]
+fn eval_str_keeps_definitions() {
+    for mut interpreter in interpreters() {
+        interpreter.eval_str("(define (square x) (* x x))").unwrap();
+        assert_eq!(Ok(LispOutput::Integer(49)), interpreter.eval_str("(square 7)"));
+    }
+}
+
+#[test]
+fn eval_str_evaluates_every_expression() {
+    for mut interpreter in interpreters() {
+        let result = interpreter.eval_str("(define a 1) (define b 2) (list a b)");
+        assert_eq!(Ok(integer_list(&[1, 2])), result);
+        assert_eq!(Ok(LispOutput::Void), interpreter.eval_str(""));
+    }
+}
+
+#[test]
+fn malformed_source_is_a_syntax_error() {
+    let mut interpreter = Interpreter::new();
+    assert_eq!(LispErrorKind::Syntax, interpreter.eval_str("(+ 1").unwrap_err().kind);
+    assert_eq!(LispErrorKind::Syntax, interpreter.eval_str(")").unwrap_err().kind);
+    assert_eq!(LispErrorKind::Syntax, interpreter.eval_str("\"abc").unwrap_err().kind);
+    assert_eq!(LispErrorKind::Syntax, interpreter.eval_str("\"a\\q\"").unwrap_err().kind);
+    let read = interpreter.eval_str("(read (open-input-string \"\\\"a\\\\q\\\"\"))");
+    assert_eq!(LispErrorKind::Syntax, read.unwrap_err().kind);
+    assert_eq!(Ok(LispOutput::Integer(3)), interpreter.eval_str("(+ 1 2)"));
+}
+
+#[test]
+fn eval_file_runs_a_program() {
+    let path = std::env::temp_dir().join(format!("lisp-interpreter-test-{}.lisp", std::process::id()));
+    std::fs::write(&path, "(define (fact n)\n  (if (< n 2) 1 (* n (fact (- n 1)))))\n(fact 5)\n").unwrap();
+
+    for mut interpreter in interpreters() {
+        assert_eq!(Ok(LispOutput::Integer(120)), interpreter.eval_file(&path));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn eval_file_reports_missing_files() {
+    let mut interpreter = Interpreter::new();
+    let err = interpreter.eval_file("/nonexistent/program.lisp").unwrap_err();
+    assert_eq!(LispErrorKind::Io, err.kind);
+}
+
+#[test]
+fn libraries_are_imported_from_the_library_path() {
+    let directory = std::env::temp_dir().join(format!("lisp-interpreter-libraries-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("shapes")).unwrap();
+    std::fs::write(
+        directory.join("shapes/square.sld"),
+        "(define-library (shapes square) (export area) (begin (define (area side) (* side side))))",
+    ).unwrap();
+
+    for interpreter in interpreters() {
+        let mut interpreter = interpreter.with_library_path(&directory);
+        interpreter.eval_str("(import (prefix (shapes square) square-))").unwrap();
+        assert_eq!(Ok(LispOutput::Integer(9)), interpreter.eval_str("(square-area 3)"));
+    }
+    assert_eq!(LispErrorKind::Library, Interpreter::new().eval_str("(import (shapes square))").unwrap_err().kind);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn ports_read_and_write() {
+    for interpreter in interpreters() {
+        let output = PortRef::output_string();
+        let input = PortRef::input_string("first line\n(+ 1 2) x");
+        let mut interpreter = interpreter.with_input(input).with_output(output.clone());
+
+        interpreter.eval_str("(display \"a \") (write \"b\") (display (list 1 \"c\")) (newline)").unwrap();
+        assert_eq!(Some("a \"b\"(1 c)\n".to_string()), output.0.written());
+
+        assert_eq!(Ok(LispOutput::String("first line".to_string())), interpreter.eval_str("(read-line)"));
+        assert_eq!(Ok(LispOutput::Integer(3)), interpreter.eval_str("(eval (read))"));
+        assert_eq!(Ok(LispOutput::String(" ".to_string())), interpreter.eval_str("(read-char)"));
+        assert_eq!(Ok(LispOutput::String("x".to_string())), interpreter.eval_str("(peek-char)"));
+        assert_eq!(Ok(LispOutput::Symbol("x".to_string())), interpreter.eval_str("(read)"));
+        assert_eq!(Ok(LispOutput::Bool(true)), interpreter.eval_str("(eof-object? (read-line))"));
+
+        assert_eq!(
+            Ok(LispOutput::String("(1 2) 3".to_string())),
+            interpreter.eval_str("(with-output-to-string (lambda () (begin (write (list 1 2)) (display \" \") (display 3))))")
+        );
+        assert_eq!(Ok(integer_list(&[1, 2])), interpreter.eval_str("(define in (open-input-string \"1 2\")) (list (read in) (read in))"));
+        interpreter.eval_str("(close-port in)").unwrap();
+        assert_eq!(LispErrorKind::Io, interpreter.eval_str("(read in)").unwrap_err().kind);
+        assert_eq!(LispErrorKind::Type, interpreter.eval_str("(display 1 (open-input-string \"\"))").unwrap_err().kind);
+    }
+}
+
+#[test]
+fn file_access_is_a_setting_of_the_interpreter() {
+    let path = std::env::temp_dir().join(format!("lisp-interpreter-ports-{}.txt", std::process::id()));
+    let source = format!(
+        "(call-with-output-file {path:?} (lambda (port) (write '(saved \"data\") port)))",
+        path = path.display().to_string()
+    );
+    let read_back = format!("(read (open-input-file {:?}))", path.display().to_string());
+
+    for interpreter in interpreters() {
+        let mut interpreter = interpreter.with_file_access(FileAccess::ReadOnly);
+        let err = interpreter.eval_str(&source).unwrap_err();
+        assert_eq!(LispErrorKind::Io, err.kind);
+        assert!(err.message.starts_with("file access denied"), "{}", err.message);
+
+        interpreter.set_file_access(FileAccess::ReadWrite);
+        interpreter.eval_str(&source).unwrap();
+        interpreter.set_file_access(FileAccess::ReadOnly);
+        assert_eq!(interpreter.eval_str("'(saved \"data\")"), interpreter.eval_str(&read_back));
+
+        interpreter.set_file_access(FileAccess::Denied);
+        assert_eq!(LispErrorKind::Io, interpreter.eval_str(&read_back).unwrap_err().kind);
+        let load = format!("(load {:?})", path.display().to_string());
+        assert_eq!(LispErrorKind::Io, interpreter.eval_str(&load).unwrap_err().kind);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn sandboxed_interpreters_withhold_capabilities() {
+    let directory = std::env::temp_dir().join(format!("lisp-interpreter-sandbox-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(
+        directory.join("clock.sld"),
+        "(define-library (clock) (export now) (begin (define (now) (current-second))))",
+    ).unwrap();
+    let capabilities = Capabilities::all().without(Capability::Time);
+
+    for compiled in [false, true] {
+        let mut interpreter = Interpreter::sandboxed(capabilities).compiled(compiled).with_library_path(&directory);
+        assert_eq!(Ok(LispOutput::Integer(3)), interpreter.eval_str("(length (list 1 2 3))"));
+        assert_eq!(LispErrorKind::CapabilityDenied, interpreter.eval_str("(current-second)").unwrap_err().kind);
+        // the libraries it imports are no more capable than it is
+        interpreter.eval_str("(import (clock))").unwrap();
+        assert_eq!(LispErrorKind::CapabilityDenied, interpreter.eval_str("(now)").unwrap_err().kind);
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn sandboxes_without_the_filesystem_import_nothing_from_files() {
+    let directory = std::env::temp_dir().join(format!("lisp-interpreter-no-files-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("secret.sld"), "(define-library (secret) (export x) (begin (define x 1)))").unwrap();
+
+    for compiled in [false, true] {
+        let capabilities = Capabilities::only(&[Capability::Pure]);
+        let mut interpreter = Interpreter::sandboxed(capabilities).compiled(compiled).with_library_path(&directory);
+        assert_eq!(LispErrorKind::CapabilityDenied, interpreter.eval_str("(open-input-file \"x\")").unwrap_err().kind);
+        let err = interpreter.eval_str("(import (secret))").unwrap_err();
+        assert_eq!(LispErrorKind::CapabilityDenied, err.kind);
+        assert_eq!(LispErrorKind::UnboundVariable, interpreter.eval_str("x").unwrap_err().kind);
+        // libraries defined in the code itself need no files
+        interpreter.eval_str("(define-library (inline) (export y) (begin (define y 2)))").unwrap();
+        interpreter.eval_str("(import (inline))").unwrap();
+        assert_eq!(Ok(LispOutput::Integer(2)), interpreter.eval_str("y"));
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn imports_stay_inside_the_allowed_directory() {
+    let directory = std::env::temp_dir().join(format!("lisp-interpreter-jail-{}", std::process::id()));
+    let (jail, outside) = (directory.join("jail"), directory.join("outside"));
+    std::fs::create_dir_all(&jail).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("secret.sld"), "(define-library (secret) (export x) (begin (define x 1)))").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(outside.join("secret.sld"), jail.join("linked.sld")).unwrap();
+
+    for compiled in [false, true] {
+        let mut interpreter = Interpreter::new().compiled(compiled).with_allowed_directory(&jail).with_library_path(&jail);
+        let err = interpreter.eval_str("(import (.. outside secret))").unwrap_err();
+        assert_eq!(LispErrorKind::Library, err.kind);
+        assert_eq!("library (.. outside secret) can not be loaded from a file, .. is not a file name", err.message);
+        assert_eq!(LispErrorKind::Library, interpreter.eval_str("(import (a/b))").unwrap_err().kind);
+        #[cfg(unix)]
+        assert_eq!(LispErrorKind::Io, interpreter.eval_str("(import (linked))").unwrap_err().kind);
+        assert_eq!(LispErrorKind::UnboundVariable, interpreter.eval_str("x").unwrap_err().kind);
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn calling_lisp_functions_from_rust() {
+    for mut interpreter in interpreters() {
+        interpreter.eval_str("(define (add a b) (+ a b))").unwrap();
+        let result = interpreter.call_function("add", vec![LispOutput::Integer(2), LispOutput::Integer(3)]);
+        assert_eq!(Ok(LispOutput::Integer(5)), result);
+
+        let err = interpreter.call_function("missing", vec![]).unwrap_err();
+        assert_eq!(LispErrorKind::UnboundVariable, err.kind);
+        interpreter.eval_str("(define not-a-function 1)").unwrap();
+        assert_eq!(LispErrorKind::Type, interpreter.call_function("not-a-function", vec![]).unwrap_err().kind);
+    }
+}
+
+#[test]
+fn globals_are_shared_with_lisp() {
+    for mut interpreter in interpreters() {
+        interpreter.define_global("answer", LispOutput::Integer(42));
+        assert_eq!(Ok(LispOutput::Integer(43)), interpreter.eval_str("(+ answer 1)"));
+
+        interpreter.eval_str("(define greeting \"hello\")").unwrap();
+        assert_eq!(Some(LispOutput::String("hello".to_string())), interpreter.get_global("greeting"));
+        assert_eq!(None, interpreter.get_global("undefined"));
+    }
+}
+
+#[test]
+fn registered_builtins_are_callable() {
+    for mut interpreter in interpreters() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        interpreter.register_builtin("double", Arity::exactly(1), move |args| {
+            counter.set(counter.get() + 1);
+            match &args[0] {
+                LispOutput::Integer(num) => Ok(LispOutput::Integer(num * 2)),
+                _ => Ok(LispOutput::Bool(false)),
+            }
+        });
+
+        assert_eq!(Ok(integer_list(&[2, 4, 6])), interpreter.eval_str("(map (list 1 2 3) double)"));
+        assert_eq!(3, calls.get());
+        assert_eq!(LispErrorKind::Arity, interpreter.eval_str("(double 1 2)").unwrap_err().kind);
+    }
+}
+
+#[test]
+fn typed_functions_convert_their_arguments() {
+    for mut interpreter in interpreters() {
+        interpreter.register_function("repeat", |num: i64, times: i64| -> Result<Vec<i64>, String> {
+            match usize::try_from(times) {
+                Ok(times) => Ok(vec![num; times]),
+                Err(_) => Err(format!("can not repeat {times} times")),
+            }
+        });
+
+        assert_eq!(Ok(integer_list(&[7, 7, 7])), interpreter.eval_str("(repeat 7 3)"));
+        assert_eq!(LispErrorKind::Type, interpreter.eval_str("(repeat 7 \"3\")").unwrap_err().kind);
+        assert_eq!(LispErrorKind::Arity, interpreter.eval_str("(repeat 7)").unwrap_err().kind);
+        assert_eq!(
+            Ok(LispOutput::String("can not repeat -1 times".to_string())),
+            interpreter.eval_str("(guard (e (#t (error-object-message e))) (repeat 7 -1))")
+        );
+    }
+}
+
+#[test]
+fn limits_apply_to_every_evaluation() {
+    for interpreter in interpreters() {
+        let mut interpreter = interpreter.with_limits(EvalLimits::default().with_fuel(10000));
+        interpreter.eval_str("(define (spin) (spin))").unwrap();
+        assert_eq!(LispErrorKind::OutOfFuel, interpreter.eval_str("(spin)").unwrap_err().kind);
+        assert_eq!(LispErrorKind::OutOfFuel, interpreter.call_function("spin", vec![]).unwrap_err().kind);
+        assert_eq!(Ok(LispOutput::Integer(3)), interpreter.eval_str("(+ 1 2)"));
+    }
+}
@@ -0,0 +1,118 @@
+//! Replays the recorded sessions in `tests/lsp` against the language server.
+//! A session is a list of messages, one JSON object per line, each marked
+//! with who sends it:
+//!
+//! ```text
+//! # a comment
+//! --> {"jsonrpc":"2.0","id":1,"method":"shutdown"}
+//! <-- {"jsonrpc":"2.0","id":1,"result":null}
+//! ```
+//!
+//! Every `-->` message is sent to the server and the `<--` messages are what
+//! it must answer, in order. The order of the fields of an object does not
+//! matter.
+
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use lisp::json::{read_message, write_message, Json};
+use lisp::lsp::serve;
+
+
+/// The server's output, kept around after the server is done with it.
+#[derive(Default, Clone)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.0.borrow_mut().write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+/// The messages of a session: what the client sends, and what the server
+/// must answer with the line it is recorded on.
+struct Session {
+    sent: Vec<Json>,
+    expected: Vec<(usize, Json)>,
+}
+
+fn parse_session(session: &str) -> Result<Session, String> {
+    let mut sent = Vec::new();
+    let mut expected = Vec::new();
+    for (index, line) in session.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (marker, text) = line.split_at(line.len().min(3));
+        let message = Json::parse(text).map_err(|err| format!("line {}: {err}", index + 1))?;
+        match marker {
+            "-->" => sent.push(message),
+            "<--" => expected.push((index + 1, message)),
+            _ => return Err(format!("line {}: expected --> or <--", index + 1)),
+        }
+    }
+    return Ok(Session { sent, expected });
+}
+
+/// Replays the session at `path`, returning a line for each message the
+/// server did not send as recorded.
+fn run_session(path: &Path) -> Vec<String> {
+    let session = fs::read_to_string(path).unwrap();
+    let Session { sent, expected } = match parse_session(&session) {
+        Ok(session) => session,
+        Err(message) => return vec![format!("{}: {message}", path.display())],
+    };
+
+    let mut input = Vec::new();
+    for message in &sent {
+        write_message(&mut input, message).unwrap();
+    }
+    let output = Shared::default();
+    if let Err(err) = serve(Cursor::new(input), output.clone()) {
+        return vec![format!("{}: {err}", path.display())];
+    }
+    let mut received = Cursor::new(output.0.take());
+    let mut failures = Vec::new();
+    for (line, expected) in expected {
+        match read_message(&mut received).unwrap() {
+            Some(actual) if actual == expected => {},
+            Some(actual) => failures.push(format!("{}:{line}: expected {expected}, got {actual}", path.display())),
+            None => failures.push(format!("{}:{line}: expected {expected}, got nothing", path.display())),
+        }
+    }
+    while let Some(extra) = read_message(&mut received).unwrap() {
+        failures.push(format!("{}: unexpected {extra}", path.display()));
+    }
+    return failures;
+}
+
+fn sessions() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("lsp");
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "session"))
+        .collect();
+    paths.sort();
+    return paths;
+}
+
+#[test]
+fn recorded_sessions_replay() {
+    let paths = sessions();
+    assert!(!paths.is_empty(), "no sessions found");
+    let failures: Vec<String> = paths.iter().flat_map(|path| run_session(path)).collect();
+    let mut report = String::new();
+    for failure in &failures {
+        let _ = writeln!(report, "{failure}");
+    }
+    assert!(failures.is_empty(), "{} messages differ:\n{report}", failures.len());
+}